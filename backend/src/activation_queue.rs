@@ -0,0 +1,242 @@
+// lwas_economy/src/payments/activation_queue.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Bounded in-process retry queue for activations that failed to persist
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::config::env_parse;
+use crate::stripe_handler::SubscriptionManager;
+
+/// Longest wait between two attempts
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingActivation {
+    pub email: String,
+    pub stripe_customer_id: Option<String>,
+    pub stripe_subscription_id: Option<String>,
+    pub plan: String,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// UTM / referrer tags from the checkout session
+    #[serde(default)]
+    pub attribution: HashMap<String, String>,
+}
+
+impl PendingActivation {
+    pub fn new(
+        email: &str,
+        stripe_customer_id: Option<String>,
+        stripe_subscription_id: Option<String>,
+        plan: &str,
+    ) -> Self {
+        Self {
+            email: email.to_string(),
+            stripe_customer_id,
+            stripe_subscription_id,
+            plan: plan.to_string(),
+            attempts: 0,
+            next_attempt_at: Utc::now(),
+            last_error: None,
+            attribution: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ActivationQueue {
+    pending: Arc<Mutex<VecDeque<PendingActivation>>>,
+    capacity: usize,
+    base_backoff_secs: i64,
+    max_attempts: u32,
+}
+
+impl ActivationQueue {
+    /// `ACTIVATION_QUEUE_MAX` (1000), `ACTIVATION_RETRY_BASE_SECS` (5), `ACTIVATION_MAX_ATTEMPTS` (10)
+    pub fn from_env() -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            capacity: env_parse("ACTIVATION_QUEUE_MAX", 1000usize),
+            base_backoff_secs: env_parse("ACTIVATION_RETRY_BASE_SECS", 5i64).max(1),
+            max_attempts: env_parse("ACTIVATION_MAX_ATTEMPTS", 10u32).max(1),
+        }
+    }
+
+    /// O(1) - Queue a failed activation; false when the queue is full
+    pub async fn enqueue(&self, mut activation: PendingActivation, error: String) -> bool {
+        let mut pending = self.pending.lock().await;
+        if pending.len() >= self.capacity {
+            println!(
+                "[ACTIVATION] ❌ Retry queue full ({}), dropping activation for {}",
+                self.capacity, activation.email
+            );
+            return false;
+        }
+        activation.attempts += 1;
+        activation.next_attempt_at = Utc::now() + self.backoff(activation.attempts);
+        activation.last_error = Some(error);
+        println!(
+            "[ACTIVATION] ⏳ Queued {} for retry at {} (attempt {})",
+            activation.email, activation.next_attempt_at, activation.attempts
+        );
+        pending.push_back(activation);
+        true
+    }
+
+    /// O(1) - base * 2^(attempts-1), capped
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1i64 << attempts.saturating_sub(1).min(20);
+        Duration::seconds((self.base_backoff_secs * factor).min(MAX_BACKOFF_SECS))
+    }
+
+    /// O(n) - Copy for snapshots
+    pub async fn snapshot(&self) -> Vec<PendingActivation> {
+        self.pending.lock().await.iter().cloned().collect()
+    }
+
+    /// O(n) - Re-queue entries loaded from a snapshot
+    pub async fn restore(&self, activations: Vec<PendingActivation>) {
+        let mut pending = self.pending.lock().await;
+        for activation in activations.into_iter().take(self.capacity) {
+            pending.push_back(activation);
+        }
+    }
+
+    /// O(n) - Retry every due entry once; failures go back with a longer backoff
+    pub async fn drain(&self, subscriptions: &SubscriptionManager) {
+        let now = Utc::now();
+        let due: Vec<PendingActivation> = {
+            let mut pending = self.pending.lock().await;
+            let (due, waiting): (Vec<_>, Vec<_>) =
+                pending.drain(..).partition(|a| a.next_attempt_at <= now);
+            pending.extend(waiting);
+            due
+        };
+
+        for activation in due {
+            match subscriptions.try_activate(&activation).await {
+                Ok(_) => println!(
+                    "[ACTIVATION] ✅ Retried activation for {} succeeded (attempt {})",
+                    activation.email,
+                    activation.attempts + 1
+                ),
+                Err(e) if activation.attempts + 1 >= self.max_attempts => println!(
+                    "[ACTIVATION] ❌ Giving up on {} after {} attempts: {}",
+                    activation.email,
+                    activation.attempts + 1,
+                    e
+                ),
+                Err(e) => {
+                    self.enqueue(activation, e).await;
+                }
+            }
+        }
+    }
+
+    /// Background drain loop (`ACTIVATION_RETRY_TICK_SECS`, default 5)
+    pub fn spawn_drain(self, subscriptions: SubscriptionManager) {
+        let tick =
+            std::time::Duration::from_secs(env_parse("ACTIVATION_RETRY_TICK_SECS", 5u64).max(1));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(tick);
+            loop {
+                ticker.tick().await;
+                self.drain(&subscriptions).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serve_fake_redis;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn queued_activation_is_retried_once_due() {
+        let subscriptions = SubscriptionManager::new();
+        let queue = ActivationQueue::from_env();
+        let activation = PendingActivation::new("buyer@x.com", None, Some("sub_1".into()), "basic");
+        assert!(queue.enqueue(activation, "write failed".into()).await);
+
+        // Not due yet: the backoff keeps it queued
+        queue.drain(&subscriptions).await;
+        assert_eq!(queue.snapshot().await.len(), 1);
+        assert!(subscriptions.get("buyer@x.com").await.is_none());
+
+        queue.pending.lock().await[0].next_attempt_at = Utc::now();
+        queue.drain(&subscriptions).await;
+
+        assert!(queue.snapshot().await.is_empty());
+        assert!(subscriptions.get("buyer@x.com").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn requeued_failures_back_off_longer() {
+        let queue = ActivationQueue::from_env();
+        let activation = PendingActivation::new("buyer@x.com", None, None, "basic");
+        assert!(queue.enqueue(activation, "write failed".into()).await);
+        let first = queue.snapshot().await.remove(0);
+        assert_eq!(first.attempts, 1);
+
+        let retry = queue.pending.lock().await.pop_front().unwrap();
+        assert!(queue.enqueue(retry, "write failed again".into()).await);
+        let second = queue.snapshot().await.remove(0);
+        assert_eq!(second.attempts, 2);
+        assert!(second.next_attempt_at > first.next_attempt_at);
+        assert_eq!(second.last_error.as_deref(), Some("write failed again"));
+    }
+
+    #[tokio::test]
+    async fn failed_persist_is_queued_and_a_later_drain_succeeds() {
+        // Reserve a port, then leave it closed so the first write is refused
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let subscriptions =
+            SubscriptionManager::new().with_redis(Some(format!("redis://127.0.0.1:{}", port)));
+        let queue = ActivationQueue::from_env();
+
+        let activation = PendingActivation::new("buyer@x.com", None, Some("sub_1".into()), "basic");
+        let error = subscriptions
+            .try_activate(&activation)
+            .await
+            .expect_err("Redis is down, activation must not count as persisted");
+        assert!(queue.enqueue(activation, error).await);
+
+        let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        tokio::spawn(serve_fake_redis(listener, b"+OK\r\n"));
+        // Skip the backoff
+        queue.pending.lock().await[0].next_attempt_at = Utc::now();
+        queue.drain(&subscriptions).await;
+
+        assert!(queue.snapshot().await.is_empty());
+        assert!(subscriptions.get("buyer@x.com").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn failures_back_off_and_requeue() {
+        // Nothing can listen on port 0, so every Redis call is refused
+        let subscriptions =
+            SubscriptionManager::new().with_redis(Some("redis://127.0.0.1:0".to_string()));
+        let queue = ActivationQueue::from_env();
+        let activation = PendingActivation::new("buyer@x.com", None, None, "basic");
+        assert!(queue.enqueue(activation, "Redis write failed".into()).await);
+
+        queue.pending.lock().await[0].next_attempt_at = Utc::now();
+        queue.drain(&subscriptions).await;
+
+        let pending = queue.snapshot().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].attempts, 2);
+        assert!(pending[0].next_attempt_at > Utc::now());
+    }
+}
@@ -0,0 +1,53 @@
+// lwas_economy/src/payments/admin.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Admin API guard (shared-secret header)
+
+use axum::http::{HeaderMap, StatusCode};
+
+/// O(n) - Compare without short-circuiting on the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Shared-secret guard for the admin API (`X-Admin-Token`). Without a
+/// configured token the admin API is disabled entirely.
+#[derive(Clone, Default)]
+pub struct AdminAuth {
+    token: Option<String>,
+}
+
+impl AdminAuth {
+    /// O(1) - Token from `ADMIN_API_TOKEN`; blank counts as unset
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("ADMIN_API_TOKEN").ok())
+    }
+
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            token: token.filter(|t| !t.trim().is_empty()),
+        }
+    }
+
+    /// O(n) - `X-Admin-Token` must match the configured token
+    pub fn require(&self, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+        let Some(expected) = &self.token else {
+            println!("[ADMIN] 🚫 Admin request refused: ADMIN_API_TOKEN not configured");
+            return Err((StatusCode::FORBIDDEN, "Admin API disabled"));
+        };
+
+        let presented = headers
+            .get("x-admin-token")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        if !constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+            println!("[ADMIN] 🚫 Invalid or missing X-Admin-Token");
+            return Err((StatusCode::UNAUTHORIZED, "Unauthorized"));
+        }
+
+        Ok(())
+    }
+}
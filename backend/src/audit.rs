@@ -0,0 +1,638 @@
+// lwas_economy/src/payments/audit.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Audit trail: stamps every entry with the emitting node / region, links it into
+// a SHA-256 hash chain and writes it to stdout plus the durable sinks (JSON lines
+// file, PostgreSQL)
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::config::env_flag;
+
+/// Which deployment wrote an entry (multi-service / multi-region setups)
+struct AuditOrigin {
+    node: String,
+    region: Option<String>,
+}
+
+impl AuditOrigin {
+    /// O(1) - `SERVICE_NODE_ID` (default `payment_gateway`) and optional `SERVICE_REGION`
+    fn from_env() -> Self {
+        Self {
+            node: std::env::var("SERVICE_NODE_ID")
+                .ok()
+                .filter(|n| !n.trim().is_empty())
+                .unwrap_or_else(|| "payment_gateway".to_string()),
+            region: std::env::var("SERVICE_REGION")
+                .ok()
+                .filter(|r| !r.trim().is_empty()),
+        }
+    }
+}
+
+/// Every error `record` returns starts with this, so callers can tell a failed
+/// audit (retry the delivery) from a failed business handler
+const FAILURE_PREFIX: &str = "Audit append failed";
+
+/// O(1) - True for errors produced by `record`
+pub fn is_failure(error: &str) -> bool {
+    error.starts_with(FAILURE_PREFIX)
+}
+
+/// O(1) - `mode` value shared by Stripe and PayPal entries so analysis can
+/// filter on one field regardless of provider
+pub fn mode_label(livemode: bool) -> &'static str {
+    if livemode {
+        "live"
+    } else {
+        "test"
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// HASH CHAIN
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// `prev_hash` of the first entry when there is no earlier chain to continue
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// O(n) - Hex SHA-256 of an entry's fields, `prev_hash` included and
+/// `veritas_hash` (the result itself) left out. Objects serialize with sorted
+/// keys, so an entry read back from a sink hashes the same as when written.
+fn chain_hash(fields: &serde_json::Value) -> String {
+    let mut fields = fields.clone();
+    if let Some(map) = fields.as_object_mut() {
+        map.remove("veritas_hash");
+    }
+    hex::encode(Sha256::digest(fields.to_string()))
+}
+
+/// O(n) - Confirm consecutive entries form an unbroken chain: every entry's
+/// `veritas_hash` matches its contents and its `prev_hash` is the previous
+/// entry's `veritas_hash`. The first entry's `prev_hash` is taken as given, so
+/// any contiguous run of the trail can be checked. Err names the first bad entry.
+pub fn verify_chain(entries: &[serde_json::Value]) -> Result<(), String> {
+    let mut previous: Option<&str> = None;
+    for (index, entry) in entries.iter().enumerate() {
+        let hash = entry["veritas_hash"]
+            .as_str()
+            .ok_or_else(|| format!("entry {}: no veritas_hash", index))?;
+        let prev_hash = entry["prev_hash"]
+            .as_str()
+            .ok_or_else(|| format!("entry {}: no prev_hash", index))?;
+        if previous.is_some_and(|expected| expected != prev_hash) {
+            return Err(format!(
+                "entry {}: prev_hash does not match entry {}",
+                index,
+                index - 1
+            ));
+        }
+        if chain_hash(entry) != hash {
+            return Err(format!(
+                "entry {}: contents do not match veritas_hash",
+                index
+            ));
+        }
+        previous = Some(hash);
+    }
+    Ok(())
+}
+
+/// O(n) - Check the chain already in `AUDIT_LOG_PATH` and return its last hash,
+/// so entries written by this process continue it across restarts
+fn resume_chain(path: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let entries: Vec<serde_json::Value> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let last = entries.last()?["veritas_hash"].as_str()?.to_string();
+    match verify_chain(&entries) {
+        Ok(()) => println!(
+            "[AUDIT] 🔗 Hash chain intact across {} entries in {}",
+            entries.len(),
+            path
+        ),
+        Err(e) => println!("[AUDIT] 🚨 Hash chain BROKEN in {}: {}", path, e),
+    }
+    Some(last)
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// SINKS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// One audit record and the console tag it is printed under
+pub struct AuditEntry {
+    /// `AUDIT`, `AUDIT:PAYPAL`
+    pub tag: &'static str,
+    pub fields: serde_json::Value,
+}
+
+impl AuditEntry {
+    fn text(&self, key: &str) -> Option<&str> {
+        self.fields[key].as_str()
+    }
+
+    /// O(1) - Charged amount in minor units, whichever field the provider's entry uses
+    fn amount_minor(&self) -> Option<i64> {
+        self.fields["amount_cents"]
+            .as_i64()
+            .or_else(|| self.fields["amount_minor"].as_i64())
+            .or_else(|| self.fields["amount"]["minor"].as_i64())
+    }
+}
+
+pub trait AuditLog: Send + Sync {
+    /// Named in append errors (`file`, `postgres`)
+    fn name(&self) -> &'static str;
+    /// Whether entries must reach this sink in chain order (read back line by
+    /// line); such sinks are appended while the chain is held
+    fn ordered(&self) -> bool {
+        true
+    }
+    fn append<'a>(&'a self, entry: &'a AuditEntry) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// Console only; always on, never durable
+pub struct StdoutAuditLog;
+
+impl AuditLog for StdoutAuditLog {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn append<'a>(&'a self, entry: &'a AuditEntry) -> BoxFuture<'a, Result<(), String>> {
+        println!("[{}] 📝 {}", entry.tag, entry.fields);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// JSON lines appended to `AUDIT_LOG_PATH`, synced to disk per entry
+pub struct FileAuditLog {
+    path: String,
+}
+
+impl AuditLog for FileAuditLog {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn append<'a>(&'a self, entry: &'a AuditEntry) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .map_err(|e| format!("{} ({})", self.path, e))?;
+            let line = format!("{}\n", entry.fields);
+            file.write_all(line.as_bytes())
+                .await
+                .map_err(|e| format!("{} ({})", self.path, e))?;
+            file.sync_data()
+                .await
+                .map_err(|e| format!("{} ({})", self.path, e))
+        })
+    }
+}
+
+/// Rows in `audit_events` (`DATABASE_URL`); the table is created on first use
+pub struct PostgresAuditLog {
+    pool: PgPool,
+    schema: OnceCell<()>,
+}
+
+impl PostgresAuditLog {
+    /// O(1) - No connection is made until the first entry
+    pub fn connect_lazy(database_url: &str) -> Result<Self, String> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect_lazy(database_url)
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            pool,
+            schema: OnceCell::new(),
+        })
+    }
+
+    async fn ensure_schema(&self) -> Result<(), sqlx::Error> {
+        self.schema
+            .get_or_try_init(|| async {
+                sqlx::query(
+                    "CREATE TABLE IF NOT EXISTS audit_events (
+                        id BIGSERIAL PRIMARY KEY,
+                        ts TIMESTAMPTZ NOT NULL,
+                        event_type TEXT NOT NULL,
+                        email TEXT,
+                        amount_cents BIGINT,
+                        veritas_hash TEXT,
+                        provider TEXT NOT NULL,
+                        entry JSONB NOT NULL
+                    )",
+                )
+                .execute(&self.pool)
+                .await
+                .map(|_| ())
+            })
+            .await
+            .map(|_| ())
+    }
+}
+
+impl AuditLog for PostgresAuditLog {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    /// Rows link by `prev_hash`, so a slow insert doesn't hold up the chain
+    fn ordered(&self) -> bool {
+        false
+    }
+
+    fn append<'a>(&'a self, entry: &'a AuditEntry) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.ensure_schema().await.map_err(|e| e.to_string())?;
+            let ts = entry
+                .text("timestamp")
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map_or_else(Utc::now, |t| t.with_timezone(&Utc));
+            sqlx::query(
+                "INSERT INTO audit_events
+                    (ts, event_type, email, amount_cents, veritas_hash, provider, entry)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(ts)
+            .bind(entry.text("event").unwrap_or_default())
+            .bind(entry.text("email"))
+            .bind(entry.amount_minor())
+            .bind(entry.text("veritas_hash"))
+            .bind(entry.text("provider").unwrap_or_default())
+            .bind(sqlx::types::Json(&entry.fields))
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// AUDIT TRAIL
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Stdout plus whichever durable sinks are configured; shared by Stripe and PayPal
+pub struct AuditTrail {
+    origin: AuditOrigin,
+    stdout: StdoutAuditLog,
+    durable: Vec<Box<dyn AuditLog>>,
+    /// Compliance mode: an entry that can't be appended fails the caller
+    require_durability: bool,
+    /// `veritas_hash` of the last recorded entry; the write lock is held while
+    /// ordered sinks append so they receive the chain in order
+    last_hash: Arc<RwLock<String>>,
+}
+
+impl AuditTrail {
+    /// `AUDIT_LOG_PATH` (JSON lines), `DATABASE_URL` (PostgreSQL) and
+    /// `REQUIRE_AUDIT_DURABILITY`; misconfiguration is reported at boot
+    pub fn from_env() -> Self {
+        let require_durability = env_flag("REQUIRE_AUDIT_DURABILITY");
+        let mode = if require_durability {
+            "required"
+        } else {
+            "best-effort"
+        };
+
+        let mut durable: Vec<Box<dyn AuditLog>> = Vec::new();
+        let mut last_hash = None;
+        if let Some(path) = std::env::var("AUDIT_LOG_PATH")
+            .ok()
+            .filter(|p| !p.trim().is_empty())
+        {
+            println!("[AUDIT] 📒 Appending to {} ({})", path, mode);
+            last_hash = resume_chain(&path);
+            durable.push(Box::new(FileAuditLog { path }));
+        }
+        if let Some(url) = std::env::var("DATABASE_URL")
+            .ok()
+            .filter(|u| !u.trim().is_empty())
+        {
+            match PostgresAuditLog::connect_lazy(&url) {
+                Ok(log) => {
+                    println!("[AUDIT] 🐘 Writing to PostgreSQL audit_events ({})", mode);
+                    durable.push(Box::new(log));
+                }
+                Err(e) => println!("[AUDIT] ❌ DATABASE_URL rejected: {}", e),
+            }
+        }
+        if require_durability && durable.is_empty() {
+            println!(
+                "[AUDIT] ❌ REQUIRE_AUDIT_DURABILITY is set without AUDIT_LOG_PATH or DATABASE_URL; every audited event will fail"
+            );
+        }
+
+        Self {
+            origin: AuditOrigin::from_env(),
+            stdout: StdoutAuditLog,
+            durable,
+            require_durability,
+            last_hash: Arc::new(RwLock::new(
+                last_hash.unwrap_or_else(|| GENESIS_HASH.to_string()),
+            )),
+        }
+    }
+
+    /// Fixed sinks instead of the environment
+    #[cfg(test)]
+    pub fn with_sinks(durable: Vec<Box<dyn AuditLog>>, require_durability: bool) -> Self {
+        Self {
+            origin: AuditOrigin::from_env(),
+            stdout: StdoutAuditLog,
+            durable,
+            require_durability,
+            last_hash: Arc::new(RwLock::new(GENESIS_HASH.to_string())),
+        }
+    }
+
+    /// O(1) - Whether a failed append fails the caller (`REQUIRE_AUDIT_DURABILITY`)
+    pub fn requires_durability(&self) -> bool {
+        self.require_durability
+    }
+
+    /// O(k) - Chain one audit entry onto the last (`prev_hash`, `veritas_hash`) and
+    /// emit it under `tag` to every sink. Err only under `REQUIRE_AUDIT_DURABILITY`;
+    /// otherwise a failed append is logged. The chain advances once any sink
+    /// keeps the entry; an entry no sink kept leaves it as is.
+    pub async fn record(
+        &self,
+        tag: &'static str,
+        mut fields: serde_json::Value,
+    ) -> Result<(), String> {
+        let mut last_hash = self.last_hash.write().await;
+        let previous = last_hash.clone();
+        let mut hash = None;
+        if let Some(map) = fields.as_object_mut() {
+            map.insert("node".to_string(), self.origin.node.clone().into());
+            if let Some(region) = &self.origin.region {
+                map.insert("region".to_string(), region.clone().into());
+            }
+            map.insert("prev_hash".to_string(), previous.clone().into());
+            let digest = chain_hash(&fields);
+            fields["veritas_hash"] = digest.clone().into();
+            hash = Some(digest);
+        }
+        let entry = AuditEntry { tag, fields };
+
+        self.stdout.append(&entry).await?;
+        if self.durable.is_empty() && self.require_durability {
+            return Err(format!(
+                "{}: no AUDIT_LOG_PATH or DATABASE_URL configured",
+                FAILURE_PREFIX
+            ));
+        }
+        let (ordered, unordered): (Vec<_>, Vec<_>) =
+            self.durable.iter().partition(|sink| sink.ordered());
+        let mut persisted = self.durable.is_empty();
+        let mut failures = Vec::new();
+        for sink in ordered {
+            match sink.append(&entry).await {
+                Ok(()) => persisted = true,
+                Err(e) => failures.push(format!("{}: {} {}", FAILURE_PREFIX, sink.name(), e)),
+            }
+        }
+        // Linked before the unordered sinks are written so later entries don't wait on them
+        if let Some(hash) = &hash {
+            *last_hash = hash.clone();
+        }
+        drop(last_hash);
+        for sink in unordered {
+            match sink.append(&entry).await {
+                Ok(()) => persisted = true,
+                Err(e) => failures.push(format!("{}: {} {}", FAILURE_PREFIX, sink.name(), e)),
+            }
+        }
+        if let (false, Some(hash)) = (persisted, &hash) {
+            // No sink kept it: unlink it unless a later entry already chained on
+            let mut last_hash = self.last_hash.write().await;
+            if *last_hash == *hash {
+                *last_hash = previous;
+            }
+        }
+
+        if self.require_durability && !failures.is_empty() {
+            return Err(failures.swap_remove(0));
+        }
+        for e in failures {
+            println!("[AUDIT] ⚠️ {} (best-effort, continuing)", e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::RecordingAuditLog;
+
+    fn recorded_trail(node: &str, region: Option<&str>) -> (AuditTrail, RecordingAuditLog) {
+        let sink = RecordingAuditLog::default();
+        let mut trail = AuditTrail::with_sinks(vec![Box::new(sink.clone())], true);
+        trail.origin = AuditOrigin {
+            node: node.to_string(),
+            region: region.map(str::to_string),
+        };
+        (trail, sink)
+    }
+
+    #[tokio::test]
+    async fn entries_carry_the_configured_node_and_region() {
+        let (trail, sink) = recorded_trail("gateway-eu-2", Some("eu-west-1"));
+        trail
+            .record("AUDIT", serde_json::json!({ "event": "payment_succeeded" }))
+            .await
+            .unwrap();
+        let (trail_without_region, bare) = recorded_trail("gateway-us-1", None);
+        trail_without_region
+            .record(
+                "AUDIT:PAYPAL",
+                serde_json::json!({ "event": "capture.completed" }),
+            )
+            .await
+            .unwrap();
+
+        let entry = sink.entries.lock().unwrap()[0].clone();
+        assert_eq!(entry["node"], "gateway-eu-2");
+        assert_eq!(entry["region"], "eu-west-1");
+        let entry = bare.entries.lock().unwrap()[0].clone();
+        assert_eq!(entry["node"], "gateway-us-1");
+        assert!(entry.get("region").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_append_fails_only_under_required_durability() {
+        let entry = serde_json::json!({ "event": "payment_succeeded" });
+        let unwritable = |require_durability| {
+            let file = FileAuditLog {
+                path: "/nonexistent/audit/trail.jsonl".to_string(),
+            };
+            AuditTrail::with_sinks(vec![Box::new(file)], require_durability)
+        };
+
+        let error = unwritable(true)
+            .record("AUDIT", entry.clone())
+            .await
+            .unwrap_err();
+        assert!(is_failure(&error), "{}", error);
+        assert!(unwritable(false)
+            .record("AUDIT", entry.clone())
+            .await
+            .is_ok());
+        let nowhere = AuditTrail::with_sinks(Vec::new(), true);
+        assert!(is_failure(
+            &nowhere.record("AUDIT", entry).await.unwrap_err()
+        ));
+    }
+
+    fn unwritable_file() -> Box<dyn AuditLog> {
+        Box::new(FileAuditLog {
+            path: "/nonexistent/audit/trail.jsonl".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn entry_kept_by_any_sink_advances_the_chain() {
+        let sink = RecordingAuditLog::default();
+        let trail = AuditTrail::with_sinks(vec![Box::new(sink.clone()), unwritable_file()], true);
+        let first = trail
+            .record("AUDIT", serde_json::json!({ "event": "payment_succeeded" }))
+            .await;
+        assert!(is_failure(&first.unwrap_err()));
+        let _ = trail
+            .record("AUDIT", serde_json::json!({ "event": "refund" }))
+            .await;
+
+        let entries = sink.entries.lock().unwrap().clone();
+        assert_eq!(entries[1]["prev_hash"], entries[0]["veritas_hash"]);
+        assert_eq!(verify_chain(&entries), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn entry_no_sink_kept_leaves_the_chain_as_is() {
+        let trail = AuditTrail::with_sinks(vec![unwritable_file()], false);
+        trail
+            .record("AUDIT", serde_json::json!({ "event": "payment_succeeded" }))
+            .await
+            .unwrap();
+        assert_eq!(*trail.last_hash.read().await, GENESIS_HASH);
+    }
+
+    /// Unordered sink whose appends wait for a permit
+    struct StalledAuditLog(Arc<tokio::sync::Semaphore>);
+
+    impl AuditLog for StalledAuditLog {
+        fn name(&self) -> &'static str {
+            "stalled"
+        }
+
+        fn ordered(&self) -> bool {
+            false
+        }
+
+        fn append<'a>(&'a self, _entry: &'a AuditEntry) -> BoxFuture<'a, Result<(), String>> {
+            Box::pin(async move {
+                self.0
+                    .acquire()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_unordered_sink_does_not_hold_up_the_chain() {
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        let sink = RecordingAuditLog::default();
+        let trail = Arc::new(AuditTrail::with_sinks(
+            vec![
+                Box::new(sink.clone()),
+                Box::new(StalledAuditLog(release.clone())),
+            ],
+            true,
+        ));
+        let stalled = tokio::spawn({
+            let trail = trail.clone();
+            async move {
+                trail
+                    .record("AUDIT", serde_json::json!({ "event": "payment_succeeded" }))
+                    .await
+            }
+        });
+        while sink.entries.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        let second = trail.record("AUDIT", serde_json::json!({ "event": "refund" }));
+        tokio::pin!(second);
+        tokio::select! {
+            _ = &mut second => panic!("second entry finished while its stalled sink was held"),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+        }
+        // The first append is still in flight, yet the second entry is already chained onto it
+        assert!(!stalled.is_finished());
+        let entries = sink.entries.lock().unwrap().clone();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1]["prev_hash"], entries[0]["veritas_hash"]);
+
+        release.add_permits(1);
+        stalled.await.unwrap().unwrap();
+        second.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tampered_middle_entry_breaks_the_chain() {
+        let (trail, sink) = recorded_trail("gateway-eu-2", None);
+        for (email, cents) in [("a@x.com", 900), ("b@x.com", 4900), ("c@x.com", 900)] {
+            trail
+                .record(
+                    "AUDIT",
+                    serde_json::json!({ "event": "payment_succeeded", "email": email, "amount_cents": cents }),
+                )
+                .await
+                .unwrap();
+        }
+        let entries = sink.entries.lock().unwrap().clone();
+        assert_eq!(entries[0]["prev_hash"], GENESIS_HASH);
+        assert_eq!(verify_chain(&entries), Ok(()));
+
+        let mut edited = entries.clone();
+        edited[1]["amount_cents"] = 1.into();
+        assert_eq!(
+            verify_chain(&edited),
+            Err("entry 1: contents do not match veritas_hash".to_string())
+        );
+
+        // Re-hashing the edit only moves the break to the next entry
+        edited[1]["veritas_hash"] = chain_hash(&edited[1]).into();
+        assert_eq!(
+            verify_chain(&edited),
+            Err("entry 2: prev_hash does not match entry 1".to_string())
+        );
+
+        let mut removed = entries.clone();
+        removed.remove(1);
+        assert!(verify_chain(&removed).is_err());
+        // Any contiguous run still checks out on its own
+        assert_eq!(verify_chain(&entries[1..]), Ok(()));
+    }
+}
@@ -0,0 +1,384 @@
+// lwas_economy/src/payments/catalog.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Pricing Catalog (single source of truth for plans & prices)
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::money::Money;
+use crate::stripe_handler::SubscriptionPlan;
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// PLAN OFFERS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Plan assumed when neither metadata nor a known price names one
+pub const DEFAULT_PLAN_KEY: &str = "pro_monthly";
+
+/// Numeric quotas enforced by downstream services
+#[derive(Clone, Debug, Serialize)]
+pub struct PlanLimits {
+    pub api_calls_per_month: u64,
+    pub seats: u32,
+}
+
+impl PlanLimits {
+    /// O(1) - Built-in limits per tier role
+    fn default_for(role: &str) -> Self {
+        match role {
+            "enterprise" => Self {
+                api_calls_per_month: 1_000_000,
+                seats: 50,
+            },
+            "pro" => Self {
+                api_calls_per_month: 50_000,
+                seats: 5,
+            },
+            _ => Self {
+                api_calls_per_month: 1_000,
+                seats: 1,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlanOffer {
+    pub key: String,
+    pub display_name: String,
+    /// Minor units (cents)
+    pub amount: i64,
+    pub currency: String,
+    pub billing_periods: Vec<String>,
+    pub providers: Vec<String>,
+    /// Server-side only; checkout resolves it from the plan key
+    #[serde(skip)]
+    pub stripe_price_id: Option<String>,
+    /// Subscription plan a purchase of this offer grants
+    #[serde(skip)]
+    pub grants: SubscriptionPlan,
+    /// Filled from the tier the plan grants
+    pub limits: PlanLimits,
+}
+
+impl PlanOffer {
+    /// O(1) - Price as minor-unit money
+    pub fn price(&self) -> Money {
+        Money {
+            minor: self.amount,
+            currency: self.currency.to_ascii_uppercase(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PricingCatalog {
+    plans: Vec<PlanOffer>,
+    /// role -> limits
+    limits: HashMap<String, PlanLimits>,
+    /// Stripe price id -> plan key, for events that carry only the price
+    by_price: HashMap<String, String>,
+}
+
+impl PricingCatalog {
+    /// `PLAN_LIMITS` overrides the built-in tier limits, e.g.
+    /// `free=api_calls:500,seats:1;pro=api_calls:20000`, and `PLAN_PRICES` the
+    /// list prices, e.g. `basic=amount:1200;architect=amount:24900,currency:usd`
+    pub fn from_env() -> Self {
+        let limits = parse_limits(&std::env::var("PLAN_LIMITS").unwrap_or_default());
+        let tier_limits = |plan: &SubscriptionPlan| {
+            let role = plan.role();
+            limits
+                .get(role)
+                .cloned()
+                .unwrap_or_else(|| PlanLimits::default_for(role))
+        };
+        let stripe_price =
+            |var: &str| Some(std::env::var(var).unwrap_or_else(|_| "price_1OtH...".to_string()));
+
+        let catalog = Self {
+            plans: vec![
+                PlanOffer {
+                    key: "basic".to_string(),
+                    display_name: "Basic".to_string(),
+                    amount: 900,
+                    currency: "eur".to_string(),
+                    billing_periods: vec!["monthly".to_string()],
+                    providers: vec!["stripe".to_string()],
+                    stripe_price_id: stripe_price("STRIPE_PRICE_BASIC"),
+                    grants: SubscriptionPlan::Pro { monthly: true },
+                    limits: tier_limits(&SubscriptionPlan::Pro { monthly: true }),
+                },
+                PlanOffer {
+                    key: "premium".to_string(),
+                    display_name: "Premium".to_string(),
+                    amount: 2900,
+                    currency: "eur".to_string(),
+                    billing_periods: vec!["monthly".to_string()],
+                    providers: vec!["stripe".to_string()],
+                    stripe_price_id: stripe_price("STRIPE_PRICE_PREMIUM"),
+                    grants: SubscriptionPlan::Enterprise { monthly: true },
+                    limits: tier_limits(&SubscriptionPlan::Enterprise { monthly: true }),
+                },
+                PlanOffer {
+                    key: "architect".to_string(),
+                    display_name: "Veritas Architect Access".to_string(),
+                    amount: 19900,
+                    currency: "usd".to_string(),
+                    billing_periods: vec!["one_time".to_string()],
+                    providers: vec!["paypal".to_string()],
+                    stripe_price_id: None,
+                    grants: SubscriptionPlan::Enterprise { monthly: false },
+                    limits: tier_limits(&SubscriptionPlan::Enterprise { monthly: false }),
+                },
+            ],
+            limits,
+            by_price: HashMap::new(),
+        }
+        .with_prices(&std::env::var("PLAN_PRICES").unwrap_or_default())
+        .with_price_index();
+
+        // Checkout refuses these, so surface the misconfiguration at boot
+        for plan in &catalog.plans {
+            if let Err(e) = plan.price().check_minimum() {
+                println!("[CATALOG] ⚠️ Plan '{}': {}", plan.key, e);
+            }
+        }
+        catalog
+    }
+
+    /// O(n) - Replace the Stripe price ids of the named plans and re-index
+    #[cfg(test)]
+    pub fn with_stripe_prices(mut self, prices: &[(&str, &str)]) -> Self {
+        for (key, price_id) in prices {
+            if let Some(plan) = self.plans.iter_mut().find(|p| p.key == *key) {
+                plan.stripe_price_id = Some(price_id.to_string());
+            }
+        }
+        self.by_price.clear();
+        self.with_price_index()
+    }
+
+    /// O(n) - Apply `key=amount:N,currency:C;...` to the named plans; unset
+    /// fields keep the built-in price
+    pub fn with_prices(mut self, raw: &str) -> Self {
+        for entry in raw.split(';').filter(|e| !e.trim().is_empty()) {
+            let Some((key, pairs)) = entry.split_once('=') else {
+                println!("[CONFIG] ⚠️ Ignoring PLAN_PRICES entry '{}'", entry);
+                continue;
+            };
+            let key = key.trim();
+            let Some(plan) = self.plans.iter_mut().find(|p| p.key == key) else {
+                println!("[CONFIG] ⚠️ PLAN_PRICES names unknown plan '{}'", key);
+                continue;
+            };
+            for pair in pairs.split(',').filter(|p| !p.trim().is_empty()) {
+                let parsed = pair.split_once(':').and_then(|(name, value)| {
+                    let value = value.trim();
+                    match name.trim() {
+                        "amount" => value.parse().ok().map(|v| plan.amount = v),
+                        "currency" if value.len() == 3 => {
+                            plan.currency = value.to_ascii_lowercase();
+                            Some(())
+                        }
+                        _ => None,
+                    }
+                });
+                if parsed.is_none() {
+                    println!(
+                        "[CONFIG] ⚠️ Ignoring PLAN_PRICES entry '{}' for {}",
+                        pair, key
+                    );
+                }
+            }
+        }
+        self
+    }
+
+    /// O(n) - Build the price -> plan map; a price shared by two plans keeps the first
+    fn with_price_index(mut self) -> Self {
+        for plan in &self.plans {
+            let Some(price_id) = &plan.stripe_price_id else {
+                continue;
+            };
+            match self.by_price.get(price_id) {
+                Some(first) => println!(
+                    "[CATALOG] ⚠️ Price {} is set for both '{}' and '{}'; events map it to '{}'",
+                    price_id, first, plan.key, first
+                ),
+                None => {
+                    self.by_price.insert(price_id.clone(), plan.key.clone());
+                }
+            }
+        }
+        self
+    }
+
+    /// O(n) - Lookup by plan key (n is tiny)
+    pub fn get(&self, key: &str) -> Option<&PlanOffer> {
+        self.plans.iter().find(|p| p.key == key)
+    }
+
+    /// O(n) - Plan an offer key grants; canonical plan keys resolve to themselves
+    /// and anything else is `None`
+    pub fn resolve(&self, key: &str) -> Option<SubscriptionPlan> {
+        match self.get(key) {
+            Some(offer) => Some(offer.grants.clone()),
+            None => SubscriptionPlan::parse_key(key),
+        }
+    }
+
+    /// O(n) - Canonical key of the plan `key` grants; unknown keys are Free
+    pub fn plan_key(&self, key: &str) -> &'static str {
+        self.resolve(key).unwrap_or(SubscriptionPlan::Free).key()
+    }
+
+    /// O(1) - Plan sold under a Stripe price id
+    pub fn find_by_stripe_price(&self, price_id: &str) -> Option<&PlanOffer> {
+        self.by_price.get(price_id).and_then(|key| self.get(key))
+    }
+
+    /// O(1) - Plan key for a billed price; unknown or missing prices get `DEFAULT_PLAN_KEY`
+    pub fn plan_for_price(&self, price_id: Option<&str>) -> &str {
+        price_id
+            .and_then(|price| self.by_price.get(price))
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_PLAN_KEY)
+    }
+
+    pub fn plans(&self) -> &[PlanOffer] {
+        &self.plans
+    }
+
+    /// O(1) - Limits for a tier role; unknown roles get Free limits
+    pub fn limits_for(&self, role: &str) -> PlanLimits {
+        self.limits
+            .get(role)
+            .cloned()
+            .unwrap_or_else(|| PlanLimits::default_for(role))
+    }
+}
+
+/// O(n) - `role=api_calls:N,seats:N;...`; unset keys keep the tier default
+fn parse_limits(raw: &str) -> HashMap<String, PlanLimits> {
+    raw.split(';')
+        .filter_map(|entry| {
+            let (role, pairs) = entry.split_once('=')?;
+            let role = role.trim().to_lowercase();
+            let mut limits = PlanLimits::default_for(&role);
+            for pair in pairs.split(',').filter(|p| !p.trim().is_empty()) {
+                let parsed = pair.split_once(':').and_then(|(name, value)| {
+                    let value = value.trim();
+                    match name.trim() {
+                        "api_calls" => value.parse().ok().map(|v| limits.api_calls_per_month = v),
+                        "seats" => value.parse().ok().map(|v| limits.seats = v),
+                        _ => None,
+                    }
+                });
+                if parsed.is_none() {
+                    println!(
+                        "[CONFIG] ⚠️ Ignoring PLAN_LIMITS entry '{}' for {}",
+                        pair, role
+                    );
+                }
+            }
+            Some((role, limits))
+        })
+        .collect()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// PUBLIC ENDPOINT
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// GET /plans - Public pricing catalog for data-driven frontends
+pub async fn list_plans(State(catalog): State<Arc<PricingCatalog>>) -> Json<Vec<PlanOffer>> {
+    Json(catalog.plans().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn plans_lists_every_configured_plan_without_price_ids() {
+        let catalog = Arc::new(PricingCatalog::from_env());
+        let Json(plans) = list_plans(State(catalog.clone())).await;
+        let body = serde_json::to_value(&plans).unwrap();
+
+        let keys: Vec<&str> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["key"].as_str().unwrap())
+            .collect();
+        assert_eq!(keys, ["basic", "premium", "architect"]);
+        for plan in body.as_array().unwrap() {
+            assert!(plan.get("stripe_price_id").is_none());
+            assert!(plan["amount"].as_i64().unwrap() > 0);
+            assert!(!plan["providers"].as_array().unwrap().is_empty());
+            assert!(plan["limits"]["seats"].as_u64().unwrap() >= 1);
+        }
+    }
+
+    #[test]
+    fn plan_limits_override_only_the_named_fields() {
+        let limits = parse_limits("pro=api_calls:20000;free=seats:3,bogus:1");
+        assert_eq!(limits["pro"].api_calls_per_month, 20_000);
+        assert_eq!(limits["pro"].seats, 5);
+        assert_eq!(limits["free"].api_calls_per_month, 1_000);
+        assert_eq!(limits["free"].seats, 3);
+    }
+
+    #[test]
+    fn plan_prices_override_only_the_named_fields() {
+        let catalog = PricingCatalog::from_env()
+            .with_prices("basic=amount:1200;architect=currency:EUR,bogus:1;platinum=amount:1");
+        let basic = catalog.get("basic").unwrap();
+        assert_eq!((basic.amount, basic.currency.as_str()), (1200, "eur"));
+        let architect = catalog.get("architect").unwrap();
+        assert_eq!(
+            (architect.amount, architect.currency.as_str()),
+            (19900, "eur")
+        );
+        assert_eq!(catalog.get("premium").unwrap().amount, 2900);
+    }
+
+    #[test]
+    fn price_ids_map_back_to_their_plan() {
+        let catalog = PricingCatalog::from_env()
+            .with_stripe_prices(&[("basic", "price_basic_m"), ("premium", "price_premium_m")]);
+        assert_eq!(catalog.plan_for_price(Some("price_premium_m")), "premium");
+        assert_eq!(catalog.plan_for_price(Some("price_basic_m")), "basic");
+        assert_eq!(
+            catalog
+                .find_by_stripe_price("price_premium_m")
+                .map(|p| p.key.as_str()),
+            Some("premium")
+        );
+
+        assert_eq!(
+            catalog.plan_for_price(Some("price_retired")),
+            DEFAULT_PLAN_KEY
+        );
+        assert_eq!(catalog.plan_for_price(None), DEFAULT_PLAN_KEY);
+        assert!(catalog.find_by_stripe_price("price_retired").is_none());
+
+        // A price configured for two plans resolves to the first
+        let shared = PricingCatalog::from_env()
+            .with_stripe_prices(&[("basic", "price_same"), ("premium", "price_same")]);
+        assert_eq!(shared.plan_for_price(Some("price_same")), "basic");
+    }
+
+    #[test]
+    fn offers_resolve_to_the_plan_they_grant() {
+        let catalog = PricingCatalog::from_env();
+        assert_eq!(catalog.plan_key("basic"), "pro_monthly");
+        assert_eq!(catalog.plan_key("premium"), "enterprise_monthly");
+        assert_eq!(catalog.plan_key("pro_annual"), "pro_annual");
+        assert_eq!(catalog.plan_key("platinum"), "free");
+        assert!(catalog.resolve("platinum").is_none());
+    }
+}
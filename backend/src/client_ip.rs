@@ -0,0 +1,376 @@
+// lwas_economy/src/payments/client_ip.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Trusted client IP extraction from X-Forwarded-For / Forwarded chains
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::config::{env_flag, env_parse};
+
+/// Real client address, inserted as a request extension
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIpConfig {
+    /// Reverse proxies in front of us; the peer counts as the first one
+    pub trusted_proxies: usize,
+    /// Reject chains that are unparseable or shorter than the proxy count
+    pub strict: bool,
+}
+
+impl ClientIpConfig {
+    pub fn from_env() -> Self {
+        Self {
+            trusted_proxies: env_parse("TRUSTED_PROXY_COUNT", 0usize),
+            strict: env_flag("CLIENT_IP_STRICT"),
+        }
+    }
+
+    /// O(n) - Walk the hop chain (forwarded entries, then the peer) from the
+    /// right, skipping the trusted proxies; the next hop is the client
+    pub fn resolve(&self, headers: &HeaderMap, peer: IpAddr) -> Result<IpAddr, &'static str> {
+        if self.trusted_proxies == 0 {
+            return Ok(peer);
+        }
+
+        let mut hops = Vec::new();
+        for raw in forwarded_chain(headers) {
+            match parse_hop(&raw) {
+                Some(ip) => hops.push(ip),
+                None if self.strict => return Err("unparseable forwarded hop"),
+                None => {}
+            }
+        }
+        hops.push(peer);
+
+        match hops.len().checked_sub(self.trusted_proxies + 1) {
+            Some(index) => Ok(hops[index]),
+            None if self.strict => Err("forwarded chain shorter than trusted proxy count"),
+            // Fewer hops than proxies: the leftmost entry is the best guess
+            None => Ok(hops[0]),
+        }
+    }
+}
+
+/// O(n) - Hop entries, leftmost first. `Forwarded` (RFC 7239) wins over `X-Forwarded-For`.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<String> {
+    let forwarded: Vec<String> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| value.trim_matches('"').to_string())
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|hop| hop.trim().to_string())
+        .filter(|hop| !hop.is_empty())
+        .collect()
+}
+
+/// O(1) - Accepts `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` and `[2001:db8::1]:443`
+fn parse_hop(raw: &str) -> Option<IpAddr> {
+    if let Ok(ip) = raw.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = raw.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    raw.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+/// Middleware resolving `ClientIp` for handlers and audit logging
+pub async fn resolve_client_ip(
+    State(config): State<ClientIpConfig>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match config.resolve(request.headers(), peer.ip()) {
+        Ok(ip) => {
+            request.extensions_mut().insert(ClientIp(ip));
+            next.run(request).await
+        }
+        Err(reason) => {
+            println!(
+                "[CLIENT_IP] 🚫 Rejected request from {}: {}",
+                peer.ip(),
+                reason
+            );
+            (StatusCode::BAD_REQUEST, "Invalid forwarding headers").into_response()
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// WEBHOOK SOURCE ALLOWLIST
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// CIDR block, e.g. `3.18.12.63/32` or `2600:1f18::/36`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// O(1) - A bare address is treated as a single-host range
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        let (addr, prefix) = match raw.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+            None => (raw, None),
+        };
+        let network: IpAddr = addr.parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { network, prefix })
+    }
+
+    /// O(1) - Same family and same leading `prefix` bits
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip, width) = match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                (u32::from(net) as u128, u32::from(ip) as u128, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        if self.prefix == 0 {
+            return true;
+        }
+        let shift = width - self.prefix as u32;
+        (net >> shift) == (ip >> shift)
+    }
+}
+
+/// Optional check that a webhook really came from the provider's published ranges
+#[derive(Clone, Debug)]
+pub struct WebhookSourceFilter {
+    provider: &'static str,
+    /// `VERIFY_WEBHOOK_SOURCE_IP`; off by default
+    enabled: bool,
+    ranges: Vec<IpRange>,
+}
+
+impl WebhookSourceFilter {
+    /// `{PROVIDER}_WEBHOOK_IP_RANGES`, comma-separated CIDRs
+    pub fn from_env(provider: &'static str) -> Self {
+        let var = format!("{}_WEBHOOK_IP_RANGES", provider.to_ascii_uppercase());
+        let mut ranges = Vec::new();
+        for raw in std::env::var(&var).unwrap_or_default().split(',') {
+            if raw.trim().is_empty() {
+                continue;
+            }
+            match IpRange::parse(raw) {
+                Some(range) => ranges.push(range),
+                None => println!("[CONFIG] ⚠️ Ignoring invalid CIDR in {}: '{}'", var, raw),
+            }
+        }
+
+        let enabled = env_flag("VERIFY_WEBHOOK_SOURCE_IP");
+        if enabled && ranges.is_empty() {
+            println!(
+                "[CLIENT_IP] ⚠️ VERIFY_WEBHOOK_SOURCE_IP is on but {} is empty: all {} webhooks will be rejected",
+                var, provider
+            );
+        }
+        Self {
+            provider,
+            enabled,
+            ranges,
+        }
+    }
+
+    /// O(n) - Always true when verification is disabled
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        let allowed = self.ranges.iter().any(|r| r.contains(ip));
+        if !allowed {
+            println!(
+                "[CLIENT_IP] 🚫 {} webhook from {} is outside the allowed ranges",
+                self.provider, ip
+            );
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "10.0.0.1";
+
+    fn config(trusted_proxies: usize, strict: bool) -> ClientIpConfig {
+        ClientIpConfig {
+            trusted_proxies,
+            strict,
+        }
+    }
+
+    fn forwarded_for(chain: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", chain.parse().unwrap());
+        headers
+    }
+
+    fn resolve(config: ClientIpConfig, headers: &HeaderMap) -> Result<String, &'static str> {
+        config
+            .resolve(headers, PEER.parse().unwrap())
+            .map(|ip| ip.to_string())
+    }
+
+    #[test]
+    fn single_ip_is_the_client_behind_one_proxy() {
+        let headers = forwarded_for("203.0.113.7");
+        assert_eq!(resolve(config(1, false), &headers).unwrap(), "203.0.113.7");
+        // Without TRUSTED_PROXY_COUNT the header is ignored: the peer is the client
+        assert_eq!(resolve(config(0, false), &headers).unwrap(), PEER);
+    }
+
+    #[test]
+    fn multi_ip_chain_skips_only_the_trusted_hops() {
+        // Leftmost entry is whatever the client claimed; only trusted hops count
+        let headers = forwarded_for("1.1.1.1, 203.0.113.7, 198.51.100.2");
+        assert_eq!(resolve(config(1, false), &headers).unwrap(), "198.51.100.2");
+        assert_eq!(resolve(config(2, false), &headers).unwrap(), "203.0.113.7");
+        assert_eq!(resolve(config(0, false), &headers).unwrap(), PEER);
+    }
+
+    #[test]
+    fn missing_header_falls_back_to_the_peer() {
+        let headers = HeaderMap::new();
+        assert_eq!(resolve(config(0, false), &headers).unwrap(), PEER);
+        assert_eq!(resolve(config(1, false), &headers).unwrap(), PEER);
+        assert!(resolve(config(1, true), &headers).is_err());
+    }
+
+    #[test]
+    fn rotating_the_claimed_ip_does_not_change_the_client() {
+        let first = forwarded_for("1.1.1.1, 203.0.113.7");
+        let second = forwarded_for("9.9.9.9, 203.0.113.7");
+        assert_eq!(
+            resolve(config(1, false), &first),
+            resolve(config(1, false), &second)
+        );
+    }
+
+    #[test]
+    fn unparseable_hops_are_skipped_unless_strict() {
+        let headers = forwarded_for("junk, 203.0.113.7");
+        assert_eq!(resolve(config(1, false), &headers).unwrap(), "203.0.113.7");
+        assert!(resolve(config(1, true), &headers).is_err());
+    }
+
+    #[test]
+    fn forwarded_header_wins_and_accepts_ports_and_ipv6() {
+        let mut headers = forwarded_for("9.9.9.9");
+        headers.insert(
+            "forwarded",
+            r#"for=192.0.2.60:4711;proto=https, for="[2001:db8::7]:443""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(resolve(config(1, true), &headers).unwrap(), "2001:db8::7");
+        assert_eq!(resolve(config(2, true), &headers).unwrap(), "192.0.2.60");
+    }
+
+    #[tokio::test]
+    async fn middleware_hands_the_client_ip_to_handlers() {
+        use axum::{middleware, routing::get, Extension, Router};
+
+        let app = Router::new()
+            .route(
+                "/ip",
+                get(|Extension(ClientIp(ip)): Extension<ClientIp>| async move { ip.to_string() }),
+            )
+            .layer(middleware::from_fn_with_state(
+                config(1, true),
+                resolve_client_ip,
+            ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ip", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let client = reqwest::Client::new();
+        let hop = client
+            .get(&url)
+            .header("x-forwarded-for", "1.1.1.1, 203.0.113.7")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(hop.text().await.unwrap(), "203.0.113.7");
+
+        let spoofed = client
+            .get(&url)
+            .header("x-forwarded-for", "not-an-ip")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(spoofed.status(), 400);
+    }
+
+    #[test]
+    fn ranges_contain_only_their_own_addresses() {
+        let v4 = IpRange::parse("3.18.12.0/23").unwrap();
+        assert!(v4.contains("3.18.12.63".parse().unwrap()));
+        assert!(v4.contains("3.18.13.255".parse().unwrap()));
+        assert!(!v4.contains("3.18.14.1".parse().unwrap()));
+        assert!(!v4.contains("2600:1f18::1".parse().unwrap()));
+
+        let v6 = IpRange::parse("2600:1f18::/36").unwrap();
+        assert!(v6.contains("2600:1f18:0fff::1".parse().unwrap()));
+        assert!(!v6.contains("2600:1f18:1000::1".parse().unwrap()));
+
+        let host = IpRange::parse(" 54.187.174.169 ").unwrap();
+        assert!(host.contains("54.187.174.169".parse().unwrap()));
+        assert!(!host.contains("54.187.174.170".parse().unwrap()));
+        assert_eq!(IpRange::parse("10.0.0.0/33"), None);
+        assert_eq!(IpRange::parse("not-an-ip/8"), None);
+    }
+
+    #[test]
+    fn source_filter_rejects_out_of_range_ips_only_when_enabled() {
+        let filter = WebhookSourceFilter {
+            provider: "stripe",
+            enabled: true,
+            ranges: vec![IpRange::parse("3.18.12.0/24").unwrap()],
+        };
+        assert!(filter.allows("3.18.12.63".parse().unwrap()));
+        assert!(!filter.allows("203.0.113.7".parse().unwrap()));
+
+        let disabled = WebhookSourceFilter {
+            enabled: false,
+            ..filter
+        };
+        assert!(disabled.allows("203.0.113.7".parse().unwrap()));
+    }
+}
@@ -0,0 +1,29 @@
+// lwas_economy/src/payments/config.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Shared environment helpers for handler configs
+
+/// O(1) - Reads a boolean flag ("1", "true", "yes", "on"); unset means false
+pub fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| {
+            matches!(
+                v.trim().to_ascii_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        })
+        .unwrap_or(false)
+}
+
+/// O(1) - Parses `name` into `T`, falling back to `default` when unset or invalid
+pub fn env_parse<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(raw) => raw.trim().parse().unwrap_or_else(|_| {
+            println!(
+                "[CONFIG] ⚠️ Invalid value for {} ('{}'), using default",
+                name, raw
+            );
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,156 @@
+// lwas_economy/src/payments/connectivity.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Post-deploy credential check: one cheap authenticated call per provider
+
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::paypal_handler::PayPalState;
+use crate::stripe_handler::StripeWebhookState;
+
+pub struct ConnectivityState {
+    pub stripe: Arc<StripeWebhookState>,
+    pub paypal: Arc<PayPalState>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderProbe {
+    pub reachable: bool,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// O(1) - Time one probe call
+async fn probe<F, T, E>(call: F) -> ProviderProbe
+where
+    F: Future<Output = Result<T, E>>,
+    E: ToString,
+{
+    let started = Instant::now();
+    let result = call.await;
+    ProviderProbe {
+        reachable: result.is_ok(),
+        latency_ms: started.elapsed().as_millis(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// POST /admin/test-connectivity - Stripe `/v1/balance` and a fresh PayPal token,
+/// for each provider with real (non-placeholder) credentials
+pub async fn test_connectivity(
+    State(state): State<Arc<ConnectivityState>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = state.stripe.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let mut results = BTreeMap::new();
+    if !state.stripe.config.secret_key.contains("placeholder") {
+        results.insert("stripe", probe(state.stripe.ping()).await);
+    }
+    if !state.paypal.config.client_id.contains("placeholder") {
+        results.insert("paypal", probe(state.paypal.refresh_access_token()).await);
+    }
+
+    for (provider, result) in &results {
+        println!(
+            "[CONNECTIVITY] {} {} in {}ms{}",
+            if result.reachable { "✅" } else { "❌" },
+            provider,
+            result.latency_ms,
+            result
+                .error
+                .as_deref()
+                .map(|e| format!(": {}", e))
+                .unwrap_or_default()
+        );
+    }
+    Json(results).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::AdminAuth;
+    use crate::test_support::{MockResponse, MockServer};
+
+    /// Both providers with real-looking credentials, pointed at `api`
+    fn connectivity_against(api: &MockServer) -> Arc<ConnectivityState> {
+        let mut stripe = StripeWebhookState::new();
+        stripe.admin = AdminAuth::new(Some("test-admin-token".to_string()));
+        stripe.config.secret_key = "sk_test_real".to_string();
+        stripe.config.api_base = api.url.clone();
+        let mut paypal = PayPalState::new(
+            stripe.subscriptions.clone(),
+            stripe.domains.clone(),
+            stripe.license.clone(),
+            stripe.licenses.clone(),
+            stripe.audit.clone(),
+            stripe.catalog.clone(),
+        );
+        paypal.config.client_id = "client_real".to_string();
+        paypal.config.api_base = api.url.clone();
+        Arc::new(ConnectivityState {
+            stripe: Arc::new(stripe),
+            paypal: Arc::new(paypal),
+        })
+    }
+
+    async fn probes(state: Arc<ConnectivityState>) -> serde_json::Value {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "test-admin-token".parse().unwrap());
+        let response = test_connectivity(State(state), headers).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn both_providers_report_reachable_with_valid_credentials() {
+        let api = MockServer::start(|request| match request.path.as_str() {
+            "/v1/balance" => MockResponse::json(200, serde_json::json!({ "object": "balance" })),
+            "/v1/oauth2/token" => MockResponse::json(
+                200,
+                serde_json::json!({ "access_token": "tok", "expires_in": 3600 }),
+            ),
+            _ => MockResponse::json(404, serde_json::json!({})),
+        })
+        .await;
+
+        let body = probes(connectivity_against(&api)).await;
+        for provider in ["stripe", "paypal"] {
+            assert_eq!(body[provider]["reachable"], true, "{}", provider);
+            assert!(body[provider]["latency_ms"].is_u64());
+            assert!(body[provider].get("error").is_none());
+        }
+        let paths: Vec<String> = api.requests().into_iter().map(|r| r.path).collect();
+        assert!(paths.contains(&"/v1/balance".to_string()));
+        assert!(paths.contains(&"/v1/oauth2/token".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_are_reported_per_provider() {
+        let api = MockServer::start(|_| {
+            MockResponse::json(401, serde_json::json!({ "error": "invalid_client" }))
+        })
+        .await;
+
+        let body = probes(connectivity_against(&api)).await;
+        for provider in ["stripe", "paypal"] {
+            assert_eq!(body[provider]["reachable"], false, "{}", provider);
+            assert!(body[provider]["error"].as_str().unwrap().contains("401"));
+        }
+    }
+}
@@ -0,0 +1,236 @@
+// lwas_economy/src/payments/dead_letter.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Dead-letter store (Redis hash or In-Memory) for events that keep failing
+
+use chrono::{DateTime, Utc};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::config::env_parse;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub event_id: String,
+    pub event_type: String,
+    /// Raw payload exactly as received, for retries
+    pub payload: String,
+    pub error: String,
+    pub attempts: u32,
+    /// Failed with an error no retry can fix; dead-lettered regardless of `attempts`
+    #[serde(default)]
+    pub permanent: bool,
+    pub first_failed_at: DateTime<Utc>,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    /// Failed permanently, or transiently often enough that retries are
+    /// considered exhausted
+    pub fn is_dead(&self, threshold: u32) -> bool {
+        self.permanent || self.attempts >= threshold
+    }
+}
+
+#[derive(Clone)]
+pub struct DeadLetterStore {
+    /// Redis hash name, e.g. `deadletter:stripe`
+    hash: String,
+    /// Transient failures before an entry counts as dead-lettered
+    /// (`DEAD_LETTER_AFTER_ATTEMPTS`, 3); permanent failures count at once
+    pub threshold: u32,
+    redis_client: Option<redis::Client>,
+    fallback: Arc<RwLock<HashMap<String, DeadLetter>>>,
+    /// Most entries held in memory; the least recently failed is dropped to make room
+    fallback_capacity: usize,
+}
+
+impl DeadLetterStore {
+    /// Without Redis at most `DEAD_LETTER_FALLBACK_MAX` (default 1000)
+    /// entries are held in memory
+    pub fn new(provider: &str, redis_url: Option<String>) -> Self {
+        let redis_client = redis_url.and_then(|url| {
+            redis::Client::open(url)
+                .map_err(|e| println!("❌ Redis connect error: {}", e))
+                .ok()
+        });
+
+        Self {
+            hash: format!("deadletter:{}", provider),
+            threshold: env_parse("DEAD_LETTER_AFTER_ATTEMPTS", 3u32).max(1),
+            redis_client,
+            fallback: Arc::new(RwLock::new(HashMap::new())),
+            fallback_capacity: env_parse("DEAD_LETTER_FALLBACK_MAX", 1000usize).max(1),
+        }
+    }
+
+    /// O(1)
+    pub async fn get(&self, event_id: &str) -> Option<DeadLetter> {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let json: Option<String> = con.hget(&self.hash, event_id).await.unwrap_or(None);
+                return json.and_then(|j| serde_json::from_str(&j).ok());
+            }
+        }
+
+        self.fallback.read().await.get(event_id).cloned()
+    }
+
+    async fn put(&self, entry: &DeadLetter) {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let json = serde_json::to_string(entry).unwrap();
+                let _: () = con
+                    .hset(&self.hash, &entry.event_id, json)
+                    .await
+                    .unwrap_or(());
+                return;
+            }
+        }
+
+        let mut store = self.fallback.write().await;
+        if store.len() >= self.fallback_capacity && !store.contains_key(&entry.event_id) {
+            let oldest = store
+                .values()
+                .min_by_key(|e| e.last_failed_at)
+                .map(|e| e.event_id.clone());
+            if let Some(oldest) = oldest {
+                store.remove(&oldest);
+            }
+        }
+        store.insert(entry.event_id.clone(), entry.clone());
+    }
+
+    /// O(1) - Count one more failure; returns the updated entry.
+    /// A `permanent` failure is dead-lettered immediately and stays so.
+    pub async fn record_failure(
+        &self,
+        event_id: &str,
+        event_type: &str,
+        payload: &str,
+        error: &str,
+        permanent: bool,
+    ) -> DeadLetter {
+        let now = Utc::now();
+        let prior = self.get(event_id).await;
+        let was_dead = prior.as_ref().is_some_and(|p| p.is_dead(self.threshold));
+        let entry = match prior {
+            Some(mut prior) => {
+                prior.attempts += 1;
+                prior.error = error.to_string();
+                prior.permanent |= permanent;
+                prior.last_failed_at = now;
+                prior
+            }
+            None => DeadLetter {
+                event_id: event_id.to_string(),
+                event_type: event_type.to_string(),
+                payload: payload.to_string(),
+                error: error.to_string(),
+                attempts: 1,
+                permanent,
+                first_failed_at: now,
+                last_failed_at: now,
+            },
+        };
+        self.put(&entry).await;
+
+        if !was_dead && entry.is_dead(self.threshold) {
+            println!(
+                "[DEAD_LETTER] ☠️ {} ({}) dead-lettered after {} attempts: {}",
+                entry.event_id, entry.event_type, entry.attempts, entry.error
+            );
+        }
+        entry
+    }
+
+    /// O(1) - Drop the entry once the event finally succeeds
+    pub async fn remove(&self, event_id: &str) {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let _: () = con.hdel(&self.hash, event_id).await.unwrap_or(());
+                return;
+            }
+        }
+
+        self.fallback.write().await.remove(event_id);
+    }
+
+    /// O(n) - Permanent failures and entries past the threshold, oldest failure first
+    pub async fn list_dead(&self) -> Vec<DeadLetter> {
+        let mut entries: Vec<DeadLetter> = match &self.redis_client {
+            Some(client) => match client.get_multiplexed_async_connection().await {
+                Ok(mut con) => {
+                    let values: Vec<String> = con.hvals(&self.hash).await.unwrap_or_default();
+                    values
+                        .iter()
+                        .filter_map(|j| serde_json::from_str(j).ok())
+                        .collect()
+                }
+                Err(_) => self.fallback.read().await.values().cloned().collect(),
+            },
+            None => self.fallback.read().await.values().cloned().collect(),
+        };
+        entries.retain(|e| e.is_dead(self.threshold));
+        entries.sort_by_key(|e| e.first_failed_at);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn transient_failures_count_toward_the_threshold_and_permanent_ones_are_dead_at_once() {
+        let store = DeadLetterStore::new("test", None);
+        for attempt in 1..store.threshold {
+            let entry = store
+                .record_failure("evt_retried", "invoice.paid", "{}", "Redis down", false)
+                .await;
+            assert_eq!(entry.attempts, attempt);
+            assert!(store.list_dead().await.is_empty());
+        }
+        store
+            .record_failure("evt_retried", "invoice.paid", "{}", "Redis down", false)
+            .await;
+        let dead = store.list_dead().await;
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, store.threshold);
+
+        let rejected = store
+            .record_failure(
+                "evt_rejected",
+                "customer.subscription.updated",
+                "{}",
+                "No known subscription",
+                true,
+            )
+            .await;
+        assert_eq!(rejected.attempts, 1);
+        assert!(store
+            .list_dead()
+            .await
+            .iter()
+            .any(|e| e.event_id == "evt_rejected"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_drops_the_least_recently_failed_entry_when_full() {
+        let store = DeadLetterStore {
+            fallback_capacity: 2,
+            ..DeadLetterStore::new("test", None)
+        };
+        for id in ["evt_1", "evt_2", "evt_3"] {
+            store
+                .record_failure(id, "invoice.paid", "{}", "bad", true)
+                .await;
+        }
+
+        assert!(store.get("evt_1").await.is_none());
+        assert!(store.get("evt_2").await.is_some());
+        assert!(store.get("evt_3").await.is_some());
+    }
+}
@@ -0,0 +1,135 @@
+// lwas_economy/src/payments/domains.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Frontend domains for multi-site deployments: one primary plus an Origin allowlist
+
+use axum::http::{header, HeaderMap};
+
+const DEFAULT_DOMAIN: &str = "https://veritras.website";
+
+#[derive(Clone, Debug)]
+pub struct SiteDomains {
+    /// Fallback for unknown or missing origins (`DOMAIN`)
+    pub primary: String,
+    /// Extra frontends allowed to receive redirects (`ADDITIONAL_DOMAINS`, comma-separated)
+    pub additional: Vec<String>,
+}
+
+impl SiteDomains {
+    pub fn from_env() -> Self {
+        let primary = std::env::var("DOMAIN")
+            .ok()
+            .and_then(|d| normalize(&d))
+            .unwrap_or_else(|| DEFAULT_DOMAIN.to_string());
+        let additional: Vec<String> = std::env::var("ADDITIONAL_DOMAINS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(normalize)
+            .filter(|d| *d != primary)
+            .collect();
+
+        if !additional.is_empty() {
+            println!(
+                "[DOMAINS] 🌐 Primary {}, also serving {}",
+                primary,
+                additional.join(", ")
+            );
+        }
+        Self {
+            primary,
+            additional,
+        }
+    }
+
+    /// O(n) - Domain matching the request's `Origin`, else the primary
+    pub fn for_request(&self, headers: &HeaderMap) -> &str {
+        headers
+            .get(header::ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|origin| self.matching(origin))
+            .unwrap_or(&self.primary)
+    }
+
+    /// O(n) - Allowlisted domain equal to `origin` (scheme + host + port)
+    fn matching(&self, origin: &str) -> Option<&str> {
+        let origin = origin.trim().trim_end_matches('/');
+        std::iter::once(&self.primary)
+            .chain(self.additional.iter())
+            .find(|d| d.eq_ignore_ascii_case(origin))
+            .map(|d| d.as_str())
+    }
+}
+
+/// O(1) - Ensures a scheme and drops trailing slashes; None for blank entries
+fn normalize(raw: &str) -> Option<String> {
+    let domain = raw.trim().trim_end_matches('/');
+    if domain.is_empty() {
+        return None;
+    }
+    if domain.starts_with("http") {
+        return Some(domain.to_string());
+    }
+    let corrected = format!("https://{}", domain);
+    println!("[DOMAINS] ⚠️ Auto-correcting {} to: {}", domain, corrected);
+    Some(corrected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains() -> SiteDomains {
+        SiteDomains {
+            primary: "https://veritras.website".to_string(),
+            additional: vec![
+                "https://shop.example.com".to_string(),
+                "http://localhost:5173".to_string(),
+            ],
+        }
+    }
+
+    fn with_origin(origin: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ORIGIN, origin.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn allowlisted_origin_selects_its_own_domain() {
+        let domains = domains();
+        assert_eq!(
+            domains.for_request(&with_origin("https://Shop.example.com/")),
+            "https://shop.example.com"
+        );
+        assert_eq!(
+            domains.for_request(&with_origin("http://localhost:5173")),
+            "http://localhost:5173"
+        );
+    }
+
+    #[test]
+    fn unknown_or_missing_origin_falls_back_to_the_primary() {
+        let domains = domains();
+        assert_eq!(
+            domains.for_request(&with_origin("https://evil.example.com")),
+            "https://veritras.website"
+        );
+        // Same host on another port or scheme is a different origin
+        assert_eq!(
+            domains.for_request(&with_origin("http://shop.example.com")),
+            "https://veritras.website"
+        );
+        assert_eq!(
+            domains.for_request(&HeaderMap::new()),
+            "https://veritras.website"
+        );
+    }
+
+    #[test]
+    fn bare_hosts_get_https() {
+        assert_eq!(
+            normalize(" shop.example.com/ ").as_deref(),
+            Some("https://shop.example.com")
+        );
+        assert_eq!(normalize("  "), None);
+    }
+}
@@ -0,0 +1,40 @@
+// lwas_economy/src/payments/dunning.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Dunning grace period: past_due keeps access until DUNNING_GRACE_DAYS elapse.
+// The same sweep moves canceled subscriptions to Free per CANCEL_DOWNGRADE_POLICY.
+
+use crate::config::env_parse;
+use crate::stripe_handler::SubscriptionManager;
+
+/// Background sweep moving expired past_due subscriptions to unpaid, and canceled
+/// ones whose paid period is over to the Free plan.
+/// `DUNNING_GRACE_DAYS` (default 7), `DUNNING_CHECK_INTERVAL_SECS` (default 3600).
+pub fn spawn_grace_sweeper(subscriptions: SubscriptionManager) {
+    let grace_days: i64 = env_parse("DUNNING_GRACE_DAYS", 7);
+    let grace = chrono::Duration::days(grace_days.max(0));
+    let interval =
+        std::time::Duration::from_secs(env_parse("DUNNING_CHECK_INTERVAL_SECS", 3600u64).max(1));
+    println!(
+        "[DUNNING] ⏳ Grace period {} day(s), checked every {:?}",
+        grace_days, interval
+    );
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for email in subscriptions.expire_past_due(grace).await {
+                println!(
+                    "[DUNNING] 🔒 Grace period over for {}, access revoked (unpaid)",
+                    email
+                );
+            }
+            for email in subscriptions.downgrade_canceled().await {
+                println!(
+                    "[DUNNING] ⬇️ Paid period over for canceled {}, plan is now Free",
+                    email
+                );
+            }
+        }
+    });
+}
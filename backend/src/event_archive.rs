@@ -0,0 +1,121 @@
+// lwas_economy/src/payments/event_archive.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Raw webhook payload archive (Redis or In-Memory) for admin replays
+
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::config::env_parse;
+
+#[derive(Clone)]
+pub struct EventArchive {
+    /// Key namespace, e.g. `paypal` -> `raw:paypal:{event_id}`
+    provider: &'static str,
+    ttl_secs: u64,
+    redis_client: Option<redis::Client>,
+    /// Payloads with the time they were stored; expire like the Redis keys
+    fallback: Arc<RwLock<HashMap<String, (String, Instant)>>>,
+    /// Most payloads held in memory; the oldest is dropped to make room
+    fallback_capacity: usize,
+}
+
+impl EventArchive {
+    /// Payloads are kept for `RAW_EVENT_TTL_SECS` (default 7 days); without
+    /// Redis at most `RAW_EVENT_FALLBACK_MAX` (default 1000) are held in memory
+    pub fn new(provider: &'static str, redis_url: Option<String>) -> Self {
+        let redis_client = redis_url.and_then(|url| {
+            redis::Client::open(url)
+                .map_err(|e| println!("❌ Redis connect error: {}", e))
+                .ok()
+        });
+
+        Self {
+            provider,
+            ttl_secs: env_parse("RAW_EVENT_TTL_SECS", 7 * 86400),
+            redis_client,
+            fallback: Arc::new(RwLock::new(HashMap::new())),
+            fallback_capacity: env_parse("RAW_EVENT_FALLBACK_MAX", 1000usize).max(1),
+        }
+    }
+
+    fn key(&self, event_id: &str) -> String {
+        format!("raw:{}:{}", self.provider, event_id)
+    }
+
+    /// O(n) - Keep the payload exactly as received
+    pub async fn store(&self, event_id: &str, raw: &str) {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let _: () = con
+                    .set_ex(self.key(event_id), raw, self.ttl_secs)
+                    .await
+                    .unwrap_or(());
+                return;
+            }
+        }
+
+        let ttl = Duration::from_secs(self.ttl_secs);
+        let mut store = self.fallback.write().await;
+        store.retain(|_, (_, stored_at)| stored_at.elapsed() < ttl);
+        if store.len() >= self.fallback_capacity && !store.contains_key(event_id) {
+            let oldest = store
+                .iter()
+                .min_by_key(|(_, (_, stored_at))| *stored_at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                store.remove(&oldest);
+            }
+        }
+        store.insert(event_id.to_string(), (raw.to_string(), Instant::now()));
+    }
+
+    /// O(1)
+    pub async fn load(&self, event_id: &str) -> Option<String> {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                return con.get(self.key(event_id)).await.unwrap_or(None);
+            }
+        }
+
+        let store = self.fallback.read().await;
+        store
+            .get(event_id)
+            .filter(|(_, stored_at)| stored_at.elapsed() < Duration::from_secs(self.ttl_secs))
+            .map(|(raw, _)| raw.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_memory(ttl_secs: u64, capacity: usize) -> EventArchive {
+        EventArchive {
+            ttl_secs,
+            fallback_capacity: capacity,
+            ..EventArchive::new("paypal", None)
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_archive_drops_the_oldest_payload_when_full() {
+        let archive = in_memory(3600, 2);
+        archive.store("WH-1", "{1}").await;
+        archive.store("WH-2", "{2}").await;
+        archive.store("WH-3", "{3}").await;
+
+        assert!(archive.load("WH-1").await.is_none());
+        assert_eq!(archive.load("WH-2").await.as_deref(), Some("{2}"));
+        assert_eq!(archive.load("WH-3").await.as_deref(), Some("{3}"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_payloads_expire_after_the_ttl() {
+        let archive = in_memory(0, 10);
+        archive.store("WH-1", "{1}").await;
+        assert!(archive.load("WH-1").await.is_none());
+    }
+}
@@ -0,0 +1,187 @@
+// lwas_economy/src/payments/health.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Root /health (liveness) report and /ready (readiness) gate
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::env_flag;
+use crate::paypal_handler::PayPalState;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ProviderFlags {
+    /// Real (non-placeholder) credentials present
+    pub configured: bool,
+    pub live: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SystemHealth {
+    pub status: &'static str,
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    pub stripe: ProviderFlags,
+    pub paypal: ProviderFlags,
+}
+
+/// Fixed at startup; only the uptime moves
+pub struct HealthState {
+    pub started_at: Instant,
+    pub stripe: ProviderFlags,
+    pub paypal: ProviderFlags,
+}
+
+impl HealthState {
+    /// O(1)
+    pub fn report(&self) -> SystemHealth {
+        SystemHealth {
+            status: "ok",
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            stripe: self.stripe,
+            paypal: self.paypal,
+        }
+    }
+}
+
+/// GET /health
+pub async fn health_check(State(state): State<Arc<HealthState>>) -> Json<SystemHealth> {
+    Json(state.report())
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// READINESS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Flipped once startup checks pass; never flips back
+#[derive(Clone, Default)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn mark_ready(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Opt-in checks: `PAYPAL_PREWARM_TOKEN` fetches the PayPal token up front,
+/// `READY_REQUIRE_REDIS` requires a PING to `REDIS_URL` to succeed
+async fn startup_checks(paypal: &PayPalState) -> Result<(), String> {
+    if env_flag("PAYPAL_PREWARM_TOKEN") {
+        paypal
+            .get_access_token()
+            .await
+            .map_err(|e| format!("PayPal token prewarm: {}", e))?;
+    }
+    if env_flag("READY_REQUIRE_REDIS") {
+        let url = std::env::var("REDIS_URL").map_err(|_| "REDIS_URL is not set".to_string())?;
+        let client = redis::Client::open(url).map_err(|e| format!("Redis: {}", e))?;
+        let mut con = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| format!("Redis: {}", e))?;
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut con)
+            .await
+            .map_err(|e| format!("Redis PING: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Retry the startup checks in the background until they pass, then mark ready
+pub fn spawn_startup_checks(readiness: Readiness, paypal: Arc<PayPalState>) {
+    tokio::spawn(async move {
+        loop {
+            match startup_checks(&paypal).await {
+                Ok(()) => {
+                    readiness.mark_ready();
+                    println!("[READY] ✅ Startup checks passed, accepting traffic");
+                    return;
+                }
+                Err(e) => {
+                    println!("[READY] ⏳ Not ready: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    });
+}
+
+/// GET /ready - 503 until startup checks pass; /health stays the cheap liveness probe
+pub async fn ready_check(State(readiness): State<Readiness>) -> Response {
+    if readiness.is_ready() {
+        (StatusCode::OK, "ready").into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "starting").into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn health_serializes_version_uptime_and_provider_flags() {
+        let state = Arc::new(HealthState {
+            started_at: Instant::now() - Duration::from_secs(90),
+            stripe: ProviderFlags {
+                configured: true,
+                live: false,
+            },
+            paypal: ProviderFlags {
+                configured: false,
+                live: false,
+            },
+        });
+
+        let Json(report) = health_check(State(state)).await;
+        let body = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "status": "ok",
+                "version": env!("CARGO_PKG_VERSION"),
+                "uptime_secs": 90,
+                "stripe": { "configured": true, "live": false },
+                "paypal": { "configured": false, "live": false },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn ready_is_503_until_startup_checks_pass() {
+        let readiness = Readiness::default();
+        let response = ready_check(State(readiness.clone())).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let stripe = crate::stripe_handler::StripeWebhookState::new();
+        let paypal = PayPalState::new(
+            stripe.subscriptions.clone(),
+            stripe.domains.clone(),
+            stripe.license.clone(),
+            stripe.licenses.clone(),
+            stripe.audit.clone(),
+            stripe.catalog.clone(),
+        );
+        spawn_startup_checks(readiness.clone(), Arc::new(paypal));
+        for _ in 0..50 {
+            if readiness.is_ready() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let response = ready_check(State(readiness)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
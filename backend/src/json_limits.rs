@@ -0,0 +1,79 @@
+// lwas_economy/src/payments/json_limits.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Nesting-depth guard for untrusted JSON, checked before serde recurses into it
+
+use serde::de::DeserializeOwned;
+
+/// Provider payloads nest well under 10 levels
+pub const DEFAULT_MAX_JSON_DEPTH: usize = 32;
+
+/// O(n) - Deepest `{`/`[` nesting outside string literals, scanned iteratively
+/// so pathological input can't exhaust the stack. Stops early past `limit`.
+fn exceeds_depth(body: &[u8], limit: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in body {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > limit {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// O(n) - Reject bodies nested deeper than `max_depth`, then deserialize.
+/// Takes the raw bytes so callers can verify signatures over the same buffer.
+pub fn parse_bounded<T: DeserializeOwned>(body: &[u8], max_depth: usize) -> Result<T, String> {
+    if exceeds_depth(body, max_depth) {
+        return Err(format!("JSON nested deeper than {} levels", max_depth));
+    }
+    serde_json::from_slice(body).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(depth: usize) -> String {
+        format!(
+            r#"{{"id":"evt_deep","data":{}1{}}}"#,
+            "[".repeat(depth),
+            "]".repeat(depth)
+        )
+    }
+
+    #[test]
+    fn deeply_nested_json_is_rejected_before_parsing() {
+        let deep = nested(100_000);
+        assert_eq!(
+            parse_bounded::<serde_json::Value>(deep.as_bytes(), DEFAULT_MAX_JSON_DEPTH),
+            Err("JSON nested deeper than 32 levels".to_string())
+        );
+
+        let shallow = nested(DEFAULT_MAX_JSON_DEPTH - 1);
+        assert!(
+            parse_bounded::<serde_json::Value>(shallow.as_bytes(), DEFAULT_MAX_JSON_DEPTH).is_ok()
+        );
+
+        // Brackets inside strings (escaped quotes included) don't count
+        let quoted = format!(r#"{{"note":"\"{}"}}"#, "[".repeat(100));
+        assert!(parse_bounded::<serde_json::Value>(quoted.as_bytes(), 2).is_ok());
+    }
+}
@@ -0,0 +1,910 @@
+// lwas_economy/src/payments/license.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// ZKP License Key issuance (HMAC-derived, deterministic per purchase)
+
+use axum::{
+    extract::{Extension, Json, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha384, Sha512};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::admin::constant_time_eq;
+use crate::client_ip::ClientIp;
+use crate::config::env_parse;
+use crate::rate_limit::RateLimiter;
+use crate::stripe_handler::{SubscriptionManager, SubscriptionStatus};
+
+/// Shipped default; keys signed with it are forgeable by anyone reading the source
+pub const PLACEHOLDER_SECRET: &str = "veritas-zkp-default-secret-change-me";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LicenseError {
+    /// LICENSE_KEY_SECRET missing or left at the placeholder in live mode
+    InsecureSecret,
+    /// The MAC digest had fewer characters than a key needs (`KEY_CHARS`)
+    DigestTooShort(usize),
+}
+
+impl fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseError::InsecureSecret => write!(
+                f,
+                "LICENSE_KEY_SECRET is not configured; license issuance is disabled in live mode"
+            ),
+            LicenseError::DigestTooShort(len) => write!(
+                f,
+                "License digest has {} usable characters, {} are needed",
+                len, KEY_CHARS
+            ),
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// LICENSE ISSUER
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Payment provider a license was purchased through
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LicenseProvider {
+    #[default]
+    Stripe,
+    Paypal,
+}
+
+impl LicenseProvider {
+    /// O(1) - HMAC input for a purchase. Stripe ids are used bare so keys issued
+    /// before PayPal support keep verifying; other providers are namespaced.
+    fn derivation_input(self, purchase_id: &str) -> String {
+        match self {
+            LicenseProvider::Stripe => purchase_id.to_string(),
+            LicenseProvider::Paypal => format!("paypal:{}", purchase_id),
+        }
+    }
+}
+
+/// HMAC digest behind license keys (`LICENSE_HMAC_ALGORITHM`). Issuance and
+/// verification always use the configured one, so switching it invalidates
+/// earlier keys just like dropping their secret would.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HmacAlgorithm {
+    /// O(1) - `sha256` / `sha384` / `sha512` (case-insensitive, `hmac-` prefix allowed)
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim().to_ascii_lowercase();
+        match raw.strip_prefix("hmac-").unwrap_or(&raw) {
+            "sha256" => Some(HmacAlgorithm::Sha256),
+            "sha384" => Some(HmacAlgorithm::Sha384),
+            "sha512" => Some(HmacAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// O(n) - Raw MAC of `message` under `secret`
+    fn mac(self, secret: &str, message: &[u8]) -> Vec<u8> {
+        match self {
+            HmacAlgorithm::Sha256 => mac_with::<Hmac<Sha256>>(secret, message),
+            HmacAlgorithm::Sha384 => mac_with::<Hmac<Sha384>>(secret, message),
+            HmacAlgorithm::Sha512 => mac_with::<Hmac<Sha512>>(secret, message),
+        }
+    }
+}
+
+fn mac_with<M: Mac + hmac::digest::KeyInit>(secret: &str, message: &[u8]) -> Vec<u8> {
+    let mut mac =
+        <M as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// New keys are signed with the first secret; verification accepts any of them,
+/// so a secret can be rotated by prepending the new one to `LICENSE_KEY_SECRETS`.
+#[derive(Clone)]
+pub struct LicenseIssuer {
+    secrets: Vec<String>,
+    /// Live mode refuses the placeholder secret
+    live: bool,
+    algorithm: HmacAlgorithm,
+}
+
+impl LicenseIssuer {
+    pub fn from_env(live: bool) -> Self {
+        let secrets: Vec<String> = std::env::var("LICENSE_KEY_SECRETS")
+            .or_else(|_| std::env::var("LICENSE_KEY_SECRET"))
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let secrets = if secrets.is_empty() {
+            vec![PLACEHOLDER_SECRET.to_string()]
+        } else {
+            secrets
+        };
+
+        let algorithm = match std::env::var("LICENSE_HMAC_ALGORITHM") {
+            Ok(raw) => HmacAlgorithm::parse(&raw).unwrap_or_else(|| {
+                println!(
+                    "[CONFIG] ⚠️ Unknown LICENSE_HMAC_ALGORITHM '{}', using sha256",
+                    raw
+                );
+                HmacAlgorithm::Sha256
+            }),
+            Err(_) => HmacAlgorithm::Sha256,
+        };
+
+        let issuer = Self {
+            secrets,
+            live,
+            algorithm,
+        };
+        if !issuer.has_real_secret() {
+            if live {
+                println!("[LICENSE] ❌ LICENSE_KEY_SECRET unset/placeholder in LIVE mode: license issuance DISABLED");
+            } else {
+                println!("[LICENSE] ⚠️ Using placeholder LICENSE_KEY_SECRET (sandbox only)");
+            }
+        }
+        issuer
+    }
+
+    /// No secret, signing or retired, may be the shipped placeholder: any one of
+    /// them is enough to verify a forged key
+    fn has_real_secret(&self) -> bool {
+        self.secrets.iter().all(|s| s != PLACEHOLDER_SECRET)
+    }
+
+    fn usable_secrets(&self) -> Result<&[String], LicenseError> {
+        if self.live && !self.has_real_secret() {
+            return Err(LicenseError::InsecureSecret);
+        }
+        Ok(&self.secrets)
+    }
+
+    /// O(n) - Derive `VRT-XXXXX-XXXXX-XXXXX-XXXXX` from the purchase id
+    /// (Stripe session / payment intent, PayPal order)
+    pub fn generate_license_key(
+        &self,
+        provider: LicenseProvider,
+        purchase_id: &str,
+    ) -> Result<String, LicenseError> {
+        let secrets = self.usable_secrets()?;
+        derive_key(
+            self.algorithm,
+            &secrets[0],
+            &provider.derivation_input(purchase_id),
+        )
+    }
+
+    /// O(k·n) - True if `license_key` was issued for the purchase under the
+    /// current or any previous secret
+    pub fn verify_license_key(
+        &self,
+        license_key: &str,
+        provider: LicenseProvider,
+        purchase_id: &str,
+    ) -> Result<bool, LicenseError> {
+        let secrets = self.usable_secrets()?;
+        let input = provider.derivation_input(purchase_id);
+        for secret in secrets {
+            let expected = derive_key(self.algorithm, secret, &input)?;
+            if constant_time_eq(expected.as_bytes(), license_key.as_bytes()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// O(k·n) - Key for the purchase, plus whether `presented` (when given) matches it
+    pub fn issue_checked(
+        &self,
+        provider: LicenseProvider,
+        purchase_id: &str,
+        presented: Option<&str>,
+    ) -> Result<(String, bool), LicenseError> {
+        let key = self.generate_license_key(provider, purchase_id)?;
+        let presented_ok = match presented {
+            Some(presented) => self.verify_license_key(presented, provider, purchase_id)?,
+            None => true,
+        };
+        Ok((key, presented_ok))
+    }
+}
+
+/// Characters of the digest that make up a key's four groups
+const KEY_CHARS: usize = 20;
+
+fn derive_key(
+    algorithm: HmacAlgorithm,
+    secret: &str,
+    session_id: &str,
+) -> Result<String, LicenseError> {
+    format_key(&hex::encode_upper(
+        algorithm.mac(secret, session_id.as_bytes()),
+    ))
+}
+
+/// O(n) - `VRT-XXXXX-XXXXX-XXXXX-XXXXX` from the first `KEY_CHARS` alphanumerics
+/// of `digest`; Err instead of panicking when a digest is too short
+fn format_key(digest: &str) -> Result<String, LicenseError> {
+    let chars: Vec<char> = digest
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    if chars.len() < KEY_CHARS {
+        return Err(LicenseError::DigestTooShort(chars.len()));
+    }
+    let groups: Vec<String> = chars[..KEY_CHARS]
+        .chunks(5)
+        .map(|group| group.iter().collect())
+        .collect();
+    Ok(format!("VRT-{}", groups.join("-")))
+}
+
+/// O(1) - `VRT-` followed by four groups of five upper-case hex digits
+pub fn is_well_formed(license_key: &str) -> bool {
+    let Some(rest) = license_key.strip_prefix("VRT-") else {
+        return false;
+    };
+    let groups: Vec<&str> = rest.split('-').collect();
+    groups.len() == 4
+        && groups.iter().all(|g| {
+            g.len() == 5
+                && g.bytes()
+                    .all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b))
+        })
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// LICENSE REGISTRY
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LicenseRecord {
+    pub license_key: String,
+    /// Records from before PayPal support are all Stripe
+    #[serde(default)]
+    pub provider: LicenseProvider,
+    /// Checkout session, payment intent or PayPal order the key was derived from
+    pub purchase_id: String,
+    pub email: String,
+    pub plan: String,
+    pub issued_at: DateTime<Utc>,
+    pub revoked: bool,
+}
+
+/// Issued keys by value, so a key can be looked up without its purchase id.
+/// With Redis every change is written through to `license:{key}` and loaded
+/// back at boot, like subscriptions.
+#[derive(Clone, Default)]
+pub struct LicenseRegistry {
+    records: Arc<RwLock<HashMap<String, LicenseRecord>>>,
+    redis_client: Option<redis::Client>,
+}
+
+impl LicenseRegistry {
+    pub fn new(redis_url: Option<String>) -> Self {
+        let redis_client = redis_url.and_then(|url| {
+            redis::Client::open(url)
+                .map_err(|e| println!("❌ Redis connect error: {}", e))
+                .ok()
+        });
+        Self {
+            records: Arc::default(),
+            redis_client,
+        }
+    }
+
+    /// O(1) - Write one record through to Redis; on failure the in-memory copy
+    /// stands and the next change to it retries the write
+    async fn persist(&self, record: &LicenseRecord) {
+        let Some(client) = &self.redis_client else {
+            return;
+        };
+        let result = match client.get_multiplexed_async_connection().await {
+            Ok(mut con) => match serde_json::to_string(record) {
+                Ok(json) => con
+                    .set::<_, _, ()>(format!("license:{}", record.license_key), json)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            println!(
+                "[LICENSE] ⚠️ Redis write for {} failed: {}",
+                record.license_key, e
+            );
+        }
+    }
+
+    /// O(n) - Load every `license:*` record from Redis (at boot); returns how many
+    pub async fn load_persisted(&self) -> usize {
+        let Some(client) = &self.redis_client else {
+            return 0;
+        };
+        let mut con = match client.get_multiplexed_async_connection().await {
+            Ok(con) => con,
+            Err(e) => {
+                println!("[LICENSE] ❌ Redis unavailable, starting empty: {}", e);
+                return 0;
+            }
+        };
+
+        let mut keys: Vec<String> = Vec::new();
+        match con.clone().scan_match::<_, String>("license:*").await {
+            Ok(mut iter) => {
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+            }
+            Err(e) => {
+                println!("[LICENSE] ❌ Redis scan failed: {}", e);
+                return 0;
+            }
+        }
+
+        let mut store = self.records.write().await;
+        let mut loaded = 0;
+        for key in keys {
+            let json: Option<String> = con.get(&key).await.unwrap_or(None);
+            match json.map(|j| serde_json::from_str::<LicenseRecord>(&j)) {
+                Some(Ok(record)) => {
+                    store.insert(record.license_key.clone(), record);
+                    loaded += 1;
+                }
+                Some(Err(e)) => println!("[LICENSE] ⚠️ Skipping unreadable {}: {}", key, e),
+                None => {}
+            }
+        }
+        loaded
+    }
+
+    /// O(1) - Remember an issued key (re-issuing the same key keeps the first
+    /// record). False, and nothing stored, when there is no email to hold it.
+    pub async fn register(
+        &self,
+        license_key: &str,
+        provider: LicenseProvider,
+        purchase_id: &str,
+        email: &str,
+        plan: &str,
+    ) -> bool {
+        if email.trim().is_empty() {
+            println!(
+                "[LICENSE] ❌ Refusing to register {} for {} without an email",
+                license_key, purchase_id
+            );
+            return false;
+        }
+
+        let record = {
+            let mut store = self.records.write().await;
+            if store.contains_key(license_key) {
+                return true;
+            }
+            let record = LicenseRecord {
+                license_key: license_key.to_string(),
+                provider,
+                purchase_id: purchase_id.to_string(),
+                email: email.to_string(),
+                plan: plan.to_string(),
+                issued_at: Utc::now(),
+                revoked: false,
+            };
+            store.insert(license_key.to_string(), record.clone());
+            record
+        };
+        self.persist(&record).await;
+        true
+    }
+
+    /// O(1)
+    pub async fn get(&self, license_key: &str) -> Option<LicenseRecord> {
+        self.records.read().await.get(license_key).cloned()
+    }
+
+    /// O(n) - Revoke every key held by `email`; returns how many changed
+    pub async fn revoke_email(&self, email: &str) -> usize {
+        let changed: Vec<LicenseRecord> = {
+            let mut store = self.records.write().await;
+            store
+                .values_mut()
+                .filter(|r| r.email == email && !r.revoked)
+                .map(|record| {
+                    record.revoked = true;
+                    record.clone()
+                })
+                .collect()
+        };
+        for record in &changed {
+            self.persist(record).await;
+        }
+        if !changed.is_empty() {
+            println!(
+                "[LICENSE] 🚫 Revoked {} key(s) for {}",
+                changed.len(),
+                email
+            );
+        }
+        changed.len()
+    }
+
+    /// O(n) - Point every key held by `old` at `new`; returns how many moved
+    pub async fn reassign_email(&self, old: &str, new: &str) -> usize {
+        let changed: Vec<LicenseRecord> = {
+            let mut store = self.records.write().await;
+            store
+                .values_mut()
+                .filter(|r| r.email == old)
+                .map(|record| {
+                    record.email = new.to_string();
+                    record.clone()
+                })
+                .collect()
+        };
+        for record in &changed {
+            self.persist(record).await;
+        }
+        changed.len()
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// INTROSPECTION API
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Clone)]
+pub struct LicenseApiState {
+    pub registry: LicenseRegistry,
+    pub subscriptions: SubscriptionManager,
+    pub limiter: RateLimiter,
+}
+
+impl LicenseApiState {
+    pub fn new(registry: LicenseRegistry, subscriptions: SubscriptionManager) -> Self {
+        Self {
+            registry,
+            subscriptions,
+            limiter: RateLimiter::new(
+                env_parse("LICENSE_INTROSPECT_LIMIT", 30),
+                env_parse("LICENSE_INTROSPECT_WINDOW_SECS", 60),
+            )
+            .with_redis("license_introspect", std::env::var("REDIS_URL").ok()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntrospectRequest {
+    pub license_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntrospectResponse {
+    pub valid: bool,
+    pub plan: Option<String>,
+    /// End of the paid period; `None` for perpetual licenses
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+    pub provider: Option<LicenseProvider>,
+}
+
+/// POST /license/introspect - Validity, plan and expiry of a key, no purchase id needed
+pub async fn introspect_license(
+    State(state): State<Arc<LicenseApiState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    Json(request): Json<IntrospectRequest>,
+) -> Response {
+    if !state.limiter.check(&client_ip.to_string()).await {
+        println!("[LICENSE] 🚫 Introspection rate limited for {}", client_ip);
+        metrics::counter!("rate_limit_blocked_total", "limit" => "introspection").increment(1);
+        return (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+    }
+
+    let key = request.license_key.trim();
+    if !is_well_formed(key) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "valid": false, "error": "Malformed license key" })),
+        )
+            .into_response();
+    }
+
+    let Some(record) = state.registry.get(key).await else {
+        return Json(IntrospectResponse {
+            valid: false,
+            plan: None,
+            expires_at: None,
+            revoked: false,
+            provider: None,
+        })
+        .into_response();
+    };
+
+    // The subscription is the source of truth for the paid period
+    let subscription = state.subscriptions.get(&record.email).await;
+    let expires_at = subscription.as_ref().and_then(|s| s.current_period_end);
+    let expired = expires_at.is_some_and(|end| end < Utc::now())
+        || subscription.is_some_and(|s| s.status == SubscriptionStatus::Canceled);
+
+    Json(IntrospectResponse {
+        valid: !record.revoked && !expired,
+        plan: Some(record.plan),
+        expires_at,
+        revoked: record.revoked,
+        provider: Some(record.provider),
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serve_fake_redis;
+
+    fn issuer(secrets: &[&str], live: bool) -> LicenseIssuer {
+        LicenseIssuer {
+            secrets: secrets.iter().map(|s| s.to_string()).collect(),
+            live,
+            algorithm: HmacAlgorithm::Sha256,
+        }
+    }
+
+    #[test]
+    fn placeholder_secret_refuses_issuance_in_live_mode() {
+        let live = issuer(&[PLACEHOLDER_SECRET], true);
+        assert_eq!(
+            live.generate_license_key(LicenseProvider::Stripe, "cs_live_1"),
+            Err(LicenseError::InsecureSecret)
+        );
+        assert_eq!(
+            live.verify_license_key(
+                "VRT-00000-00000-00000-00000",
+                LicenseProvider::Stripe,
+                "cs_1"
+            ),
+            Err(LicenseError::InsecureSecret)
+        );
+
+        let sandbox = issuer(&[PLACEHOLDER_SECRET], false);
+        let key = sandbox
+            .generate_license_key(LicenseProvider::Stripe, "cs_test_1")
+            .unwrap();
+        assert_eq!(
+            sandbox.verify_license_key(&key, LicenseProvider::Stripe, "cs_test_1"),
+            Ok(true)
+        );
+        assert!(issuer(&["real-secret"], true)
+            .generate_license_key(LicenseProvider::Stripe, "cs_live_1")
+            .is_ok());
+    }
+
+    #[test]
+    fn placeholder_among_retired_secrets_refuses_live_verification() {
+        let live = issuer(&["new-secret", PLACEHOLDER_SECRET], true);
+        let forged = issuer(&[PLACEHOLDER_SECRET], false)
+            .generate_license_key(LicenseProvider::Stripe, "cs_live_1")
+            .unwrap();
+        assert_eq!(
+            live.verify_license_key(&forged, LicenseProvider::Stripe, "cs_live_1"),
+            Err(LicenseError::InsecureSecret)
+        );
+    }
+
+    #[test]
+    fn key_signed_under_a_retired_secret_still_verifies() {
+        let before = issuer(&["secret-2024"], true);
+        let old_key = before
+            .generate_license_key(LicenseProvider::Stripe, "cs_live_1")
+            .unwrap();
+
+        let rotated = issuer(&["secret-2025", "secret-2024"], true);
+        let new_key = rotated
+            .generate_license_key(LicenseProvider::Stripe, "cs_live_1")
+            .unwrap();
+        assert_ne!(new_key, old_key);
+        for key in [&old_key, &new_key] {
+            assert_eq!(
+                rotated.verify_license_key(key, LicenseProvider::Stripe, "cs_live_1"),
+                Ok(true)
+            );
+        }
+        assert_eq!(
+            rotated.issue_checked(LicenseProvider::Stripe, "cs_live_1", Some(&old_key)),
+            Ok((new_key, true))
+        );
+
+        let dropped = issuer(&["secret-2025"], true);
+        assert_eq!(
+            dropped.verify_license_key(&old_key, LicenseProvider::Stripe, "cs_live_1"),
+            Ok(false)
+        );
+    }
+
+    async fn introspect(
+        state: &Arc<LicenseApiState>,
+        license_key: &str,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = introspect_license(
+            State(state.clone()),
+            Extension(ClientIp("127.0.0.1".parse().unwrap())),
+            Json(IntrospectRequest {
+                license_key: license_key.to_string(),
+            }),
+        )
+        .await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    /// Registers a key for `email` on an active `basic` subscription
+    async fn licensed(state: &LicenseApiState, purchase_id: &str, email: &str) -> String {
+        let key = issuer(&["secret"], false)
+            .generate_license_key(LicenseProvider::Stripe, purchase_id)
+            .unwrap();
+        state
+            .subscriptions
+            .activate_subscription(email, None, None, "basic")
+            .await;
+        state
+            .registry
+            .register(&key, LicenseProvider::Stripe, purchase_id, email, "basic")
+            .await;
+        key
+    }
+
+    #[tokio::test]
+    async fn registry_refuses_a_key_without_an_email() {
+        let registry = LicenseRegistry::default();
+        assert!(
+            !registry
+                .register("VRT-NOBODY", LicenseProvider::Stripe, "cs_1", "  ", "basic")
+                .await
+        );
+        assert!(registry.get("VRT-NOBODY").await.is_none());
+        assert!(
+            registry
+                .register(
+                    "VRT-SOMEONE",
+                    LicenseProvider::Stripe,
+                    "cs_2",
+                    "a@x.io",
+                    "basic"
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn registry_writes_each_change_through_to_redis() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_fake_redis(listener, b"-ERR read only\r\n"));
+        let registry = LicenseRegistry::new(Some(url));
+
+        // A refused write leaves the in-memory record standing
+        assert!(
+            registry
+                .register(
+                    "VRT-KEPT",
+                    LicenseProvider::Stripe,
+                    "cs_1",
+                    "a@x.io",
+                    "basic"
+                )
+                .await
+        );
+        assert_eq!(registry.revoke_email("a@x.io").await, 1);
+        assert!(registry.get("VRT-KEPT").await.unwrap().revoked);
+        assert_eq!(registry.load_persisted().await, 0);
+    }
+
+    #[tokio::test]
+    async fn introspection_reports_valid_expired_revoked_and_malformed_keys() {
+        let state = LicenseApiState {
+            registry: LicenseRegistry::default(),
+            subscriptions: SubscriptionManager::new(),
+            limiter: RateLimiter::new(100, 60),
+        };
+        let valid = licensed(&state, "cs_valid", "valid@x.io").await;
+        let expired = licensed(&state, "cs_expired", "expired@x.io").await;
+        let revoked = licensed(&state, "cs_revoked", "revoked@x.io").await;
+
+        let mut lapsed = state.subscriptions.get("expired@x.io").await.unwrap();
+        lapsed.current_period_end = Some(Utc::now() - chrono::Duration::days(1));
+        state.subscriptions.upsert_subscription(lapsed).await;
+        state.registry.revoke_email("revoked@x.io").await;
+        let state = Arc::new(state);
+
+        let (status, body) = introspect(&state, &valid).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], true);
+        assert_eq!(body["plan"], "basic");
+        assert_eq!(body["provider"], "stripe");
+
+        let (_, body) = introspect(&state, &expired).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["revoked"], false);
+        assert!(body["expires_at"].is_string());
+
+        let (_, body) = introspect(&state, &revoked).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["revoked"], true);
+
+        let (status, body) = introspect(&state, "VRT-NOT-A-KEY").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["valid"], false);
+
+        let (_, body) = introspect(&state, "VRT-00000-00000-00000-00000").await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["plan"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn introspection_is_rate_limited_per_client() {
+        let state = Arc::new(LicenseApiState {
+            registry: LicenseRegistry::default(),
+            subscriptions: SubscriptionManager::new(),
+            limiter: RateLimiter::new(2, 60),
+        });
+        let key = "VRT-00000-00000-00000-00000";
+        assert_eq!(introspect(&state, key).await.0, StatusCode::OK);
+        assert_eq!(introspect(&state, key).await.0, StatusCode::OK);
+        assert_eq!(
+            introspect(&state, key).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[test]
+    fn introspection_rejection_is_counted_as_blocked() {
+        let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let state = Arc::new(LicenseApiState {
+                    registry: LicenseRegistry::default(),
+                    subscriptions: SubscriptionManager::new(),
+                    limiter: RateLimiter::new(1, 60),
+                });
+                for _ in 0..3 {
+                    introspect(&state, "VRT-00000-00000-00000-00000").await;
+                }
+            })
+        });
+
+        let rendered = handle.render();
+        assert!(
+            rendered
+                .lines()
+                .any(|line| line == "rate_limit_blocked_total{limit=\"introspection\"} 2"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn both_providers_issue_and_verify_in_the_same_format() {
+        let issuer = issuer(&["secret"], true);
+        let stripe = issuer
+            .generate_license_key(LicenseProvider::Stripe, "ORDER42")
+            .unwrap();
+        let paypal = issuer
+            .generate_license_key(LicenseProvider::Paypal, "ORDER42")
+            .unwrap();
+        assert!(is_well_formed(&stripe) && is_well_formed(&paypal));
+        // Namespaced: one provider's key never unlocks the other's purchase
+        assert_ne!(stripe, paypal);
+
+        for (provider, key, other) in [
+            (LicenseProvider::Stripe, &stripe, &paypal),
+            (LicenseProvider::Paypal, &paypal, &stripe),
+        ] {
+            assert_eq!(
+                issuer.verify_license_key(key, provider, "ORDER42"),
+                Ok(true)
+            );
+            assert_eq!(
+                issuer.verify_license_key(other, provider, "ORDER42"),
+                Ok(false)
+            );
+        }
+    }
+
+    #[test]
+    fn keys_round_trip_under_each_hmac_algorithm() {
+        assert_eq!(
+            HmacAlgorithm::parse(" HMAC-SHA384 "),
+            Some(HmacAlgorithm::Sha384)
+        );
+        assert_eq!(HmacAlgorithm::parse("md5"), None);
+
+        let algorithms = [
+            HmacAlgorithm::Sha256,
+            HmacAlgorithm::Sha384,
+            HmacAlgorithm::Sha512,
+        ];
+        let keys: Vec<String> = algorithms
+            .iter()
+            .map(|&algorithm| {
+                let signer = LicenseIssuer {
+                    algorithm,
+                    ..issuer(&["secret-2025"], true)
+                };
+                let key = signer
+                    .generate_license_key(LicenseProvider::Stripe, "cs_alg")
+                    .unwrap();
+                assert!(is_well_formed(&key));
+                assert_eq!(
+                    signer.verify_license_key(&key, LicenseProvider::Stripe, "cs_alg"),
+                    Ok(true)
+                );
+                key
+            })
+            .collect();
+
+        // Default stays SHA256; a key never verifies under another algorithm
+        assert_eq!(HmacAlgorithm::default(), HmacAlgorithm::Sha256);
+        assert_eq!(
+            keys[0],
+            issuer(&["secret-2025"], true)
+                .generate_license_key(LicenseProvider::Stripe, "cs_alg")
+                .unwrap()
+        );
+        let sha512 = LicenseIssuer {
+            algorithm: HmacAlgorithm::Sha512,
+            ..issuer(&["secret-2025"], true)
+        };
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(keys[1], keys[2]);
+        assert_eq!(
+            sha512.verify_license_key(&keys[0], LicenseProvider::Stripe, "cs_alg"),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn short_digest_is_an_error_not_a_panic() {
+        assert_eq!(
+            format_key("a1b2-c3d4-e5f6"),
+            Err(LicenseError::DigestTooShort(12))
+        );
+        assert_eq!(format_key(""), Err(LicenseError::DigestTooShort(0)));
+        // Non-alphanumerics don't count towards the twenty characters
+        assert_eq!(
+            format_key(&"-".repeat(64)),
+            Err(LicenseError::DigestTooShort(0))
+        );
+        assert_eq!(
+            format_key("0123456789abcdef0123").unwrap(),
+            "VRT-01234-56789-abcde-f0123"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// lwas_economy/src/payments/lifecycle.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// In-flight request tracking & graceful shutdown drain stats
+
+use axum::{extract::Request, extract::State, middleware::Next, response::Response};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// IN-FLIGHT COUNTER
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// O(1) - Requests currently being served
+    pub fn current(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Decrements on drop so cancelled/panicking requests are still released
+struct InFlightGuard(InFlight);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Middleware counting requests for the drain stats
+pub async fn track_in_flight(
+    State(in_flight): State<InFlight>,
+    request: Request,
+    next: Next,
+) -> Response {
+    in_flight.0.fetch_add(1, Ordering::SeqCst);
+    let _guard = InFlightGuard(in_flight);
+    next.run(request).await
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// DRAIN STATS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+pub struct DrainStart {
+    pub signal: &'static str,
+    pub in_flight: usize,
+    pub started_at: Instant,
+}
+
+/// Set once by the shutdown signal, read after the server has drained
+#[derive(Clone, Default)]
+pub struct ShutdownTracker {
+    pub in_flight: InFlight,
+    drain: Arc<OnceLock<DrainStart>>,
+}
+
+impl ShutdownTracker {
+    /// O(1) - Record which signal started the drain and what was in flight
+    pub fn begin_drain(&self, signal: &'static str) {
+        let _ = self.drain.set(DrainStart {
+            signal,
+            in_flight: self.in_flight.current(),
+            started_at: Instant::now(),
+        });
+    }
+
+    /// O(1) - Structured summary, emitted once the server stops accepting work
+    pub fn drain_report(&self) -> Option<serde_json::Value> {
+        let start = self.drain.get()?;
+        Some(serde_json::json!({
+            "signal": start.signal,
+            "in_flight_at_drain_start": start.in_flight,
+            "in_flight_remaining": self.in_flight.current(),
+            "drain_duration_ms": start.started_at.elapsed().as_millis() as u64,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use std::time::Duration;
+    use tokio::sync::{oneshot, Notify};
+
+    #[tokio::test]
+    async fn request_in_flight_at_shutdown_is_reported_once_drained() {
+        let tracker = ShutdownTracker::default();
+        let release = Arc::new(Notify::new());
+        let held = release.clone();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(move || {
+                    let held = held.clone();
+                    async move {
+                        held.notified().await;
+                        "done"
+                    }
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                tracker.in_flight.clone(),
+                track_in_flight,
+            ));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/slow", listener.local_addr().unwrap());
+        let (stop, stopped) = oneshot::channel::<()>();
+        let drain = tracker.clone();
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async move {
+                    stopped.await.ok();
+                    drain.begin_drain("SIGTERM");
+                })
+                .await
+        });
+
+        let request = tokio::spawn(reqwest::get(url));
+        while tracker.in_flight.current() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(tracker.drain_report().is_none());
+
+        stop.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        release.notify_one();
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        server.await.unwrap().unwrap();
+
+        let report = tracker.drain_report().unwrap();
+        assert_eq!(report["signal"], "SIGTERM");
+        assert_eq!(report["in_flight_at_drain_start"], 1);
+        assert_eq!(report["in_flight_remaining"], 0);
+        assert!(report["drain_duration_ms"].as_u64().unwrap() >= 50);
+    }
+}
@@ -3,19 +3,62 @@ use axum::{
     routing::{get, post},
     Router,
 };
+use catalog::list_plans;
 use dotenv::dotenv;
+use lifecycle::{track_in_flight, ShutdownTracker};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
+use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 
+mod activation_queue;
+mod admin;
+mod audit;
+mod catalog;
+mod checkout_link;
+mod client_ip;
+mod config;
+mod connectivity;
+mod dead_letter;
+mod domains;
+mod dunning;
+mod event_archive;
+mod health;
+mod json_limits;
+mod license;
+mod lifecycle;
+mod metadata;
+mod metrics;
+mod money;
+mod notifications;
 mod paypal_handler;
+mod provider_event;
+mod rate_limit;
+mod retry;
+mod security_headers;
+mod snapshot;
 mod stripe_handler;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod token;
+mod upstream;
+mod webhook_queue;
 
-use paypal_handler::{paypal_webhook_handler, start_checkout as paypal_checkout, PayPalState};
+use paypal_handler::{
+    authorize_capture as paypal_authorize_capture, paypal_webhook_handler,
+    replay_event as paypal_replay_event, start_checkout as paypal_checkout,
+    verify_order as paypal_verify_order, PayPalState,
+};
 use stripe_handler::{
-    create_portal_session, start_checkout_basic as stripe_checkout_basic,
-    start_checkout_premium as stripe_checkout_premium, stripe_webhook_handler, StripeWebhookState,
+    clear_idempotency, create_checkout_link, create_portal_session, create_refund,
+    export_subscriptions_ndjson, follow_checkout_link, get_limits, get_subscription,
+    import_subscriptions, issue_token, list_dead_letters, retry_dead_letter, simulate_lifecycle,
+    start_checkout_basic as stripe_checkout_basic,
+    start_checkout_premium as stripe_checkout_premium, stripe_webhook_handler, verify_session,
+    webhook_selftest, StripeWebhookState,
 };
 
 #[tokio::main]
@@ -23,25 +66,113 @@ async fn main() {
     // Load environment variables from .env if available
     dotenv().ok();
 
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+    // Initialize tracing (plus OTLP export under OTEL_ENABLED)
+    telemetry::init();
+
+    // Metrics recorder must exist before any state registers gauges
+    let metrics_handle = crate::metrics::install_recorder();
 
     // Load states
     let stripe_state = Arc::new(StripeWebhookState::new());
-    let paypal_state = Arc::new(PayPalState::new());
+    let paypal_state = Arc::new(PayPalState::new(
+        stripe_state.subscriptions.clone(),
+        stripe_state.domains.clone(),
+        stripe_state.license.clone(),
+        stripe_state.licenses.clone(),
+        stripe_state.audit.clone(),
+        stripe_state.catalog.clone(),
+    ));
+    let catalog = stripe_state.catalog.clone();
+    let health_state = Arc::new(health::HealthState {
+        started_at: std::time::Instant::now(),
+        stripe: health::ProviderFlags {
+            configured: !stripe_state.config.secret_key.contains("placeholder"),
+            live: stripe_state.config.is_live(),
+        },
+        paypal: health::ProviderFlags {
+            configured: !paypal_state.config.client_id.contains("placeholder"),
+            live: paypal_state.config.is_live(),
+        },
+    });
+    let connectivity_state = Arc::new(connectivity::ConnectivityState {
+        stripe: stripe_state.clone(),
+        paypal: paypal_state.clone(),
+    });
+    let license_state = Arc::new(license::LicenseApiState::new(
+        stripe_state.licenses.clone(),
+        stripe_state.subscriptions.clone(),
+    ));
+
+    let persisted = stripe_state.subscriptions.load_persisted().await;
+    if persisted > 0 {
+        println!(
+            "[SUBSCRIPTION] ✅ Loaded {} subscription(s) from Redis",
+            persisted
+        );
+    }
+    let licenses = stripe_state.licenses.load_persisted().await;
+    if licenses > 0 {
+        println!("[LICENSE] ✅ Loaded {} license(s) from Redis", licenses);
+    }
+
+    // Fill in from the last snapshot, then keep snapshotting
+    let snapshots = snapshot::SnapshotStore::from_env();
+    if let Some(store) = &snapshots {
+        store
+            .restore(&stripe_state.subscriptions, &stripe_state.activations)
+            .await;
+        store.clone().spawn(
+            stripe_state.subscriptions.clone(),
+            stripe_state.activations.clone(),
+        );
+    }
+    let subscriptions = stripe_state.subscriptions.clone();
+    let activations = stripe_state.activations.clone();
+    activations
+        .clone()
+        .spawn_drain(stripe_state.subscriptions.clone());
+    dunning::spawn_grace_sweeper(stripe_state.subscriptions.clone());
+    stripe_handler::spawn_webhook_workers(stripe_state.clone()).await;
 
     // Build Stripe sub-router
     let stripe_router = Router::new()
         .route("/webhook", post(stripe_webhook_handler))
         .route("/portal", post(create_portal_session))
+        .route("/refund", post(create_refund))
+        .route(
+            "/webhook/selftest",
+            get(webhook_selftest).post(webhook_selftest),
+        )
+        .route("/verify", get(verify_session))
+        .route("/token", post(issue_token))
+        .route("/limits", get(get_limits))
+        .route("/subscription", get(get_subscription))
+        .route("/admin/import", post(import_subscriptions))
+        .route(
+            "/admin/subscriptions.ndjson",
+            get(export_subscriptions_ndjson),
+        )
+        .route("/admin/simulate", post(simulate_lifecycle))
+        .route("/admin/idempotency/clear", post(clear_idempotency))
+        .route("/admin/dead-letter", get(list_dead_letters))
+        .route("/admin/dead-letter/:id/retry", post(retry_dead_letter))
         .route("/checkout/basic", get(stripe_checkout_basic)) // Basic plan
         .route("/checkout/premium", get(stripe_checkout_premium)) // Premium plan
+        .route("/checkout/link", post(create_checkout_link))
+        .route("/checkout/go", get(follow_checkout_link))
         .with_state(stripe_state);
 
+    // Readiness flips once the opt-in startup checks (token prewarm, Redis) pass
+    let readiness = health::Readiness::default();
+    health::spawn_startup_checks(readiness.clone(), paypal_state.clone());
+
     // Build PayPal sub-router
     let paypal_router = Router::new()
         .route("/webhook", post(paypal_webhook_handler))
         .route("/checkout", get(paypal_checkout))
+        .route("/verify", get(paypal_verify_order))
+        .route("/authorize-capture", post(paypal_authorize_capture))
+        .route("/admin/replay", post(paypal_replay_event))
         .with_state(paypal_state);
 
     // Combine into main app
@@ -55,11 +186,46 @@ async fn main() {
                 )
             }),
         )
+        .route("/plans", get(list_plans).with_state(catalog))
+        .route(
+            "/license/introspect",
+            post(license::introspect_license).with_state(license_state),
+        )
+        .route(
+            "/admin/test-connectivity",
+            post(connectivity::test_connectivity).with_state(connectivity_state),
+        )
         .nest("/stripe", stripe_router)
         .nest("/paypal", paypal_router)
-        .route("/health", get(|| async { StatusCode::OK }))
+        .route(
+            "/health",
+            get(health::health_check).with_state(health_state),
+        )
         .route("/healthz", get(|| async { StatusCode::OK }))
-        .layer(TraceLayer::new_for_http());
+        .route(
+            "/ready",
+            get(health::ready_check).with_state(readiness.clone()),
+        )
+        .route(
+            "/metrics",
+            get(crate::metrics::render_metrics).with_state(metrics_handle),
+        )
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::make_request_span));
+
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        client_ip::ClientIpConfig::from_env(),
+        client_ip::resolve_client_ip,
+    ));
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        security_headers::SecurityHeaders::from_env(),
+        security_headers::apply_security_headers,
+    ));
+
+    let shutdown = ShutdownTracker::default();
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        shutdown.in_flight.clone(),
+        track_in_flight,
+    ));
 
     // Get port from env or default to 3000
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
@@ -76,14 +242,40 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(
         listener,
-        app.layer(tower_http::cors::CorsLayer::permissive()),
+        app.layer(build_cors_layer())
+            .into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .with_graceful_shutdown(shutdown_signal())
+    .with_graceful_shutdown(shutdown_signal(shutdown.clone()))
     .await
     .unwrap();
+
+    if let Some(report) = shutdown.drain_report() {
+        println!("[SHUTDOWN] 🛑 {}", report);
+    }
+    if let Some(store) = &snapshots {
+        store.save(&subscriptions, &activations).await;
+    }
+    telemetry::shutdown();
 }
 
-async fn shutdown_signal() {
+/// Firefox caps preflight caching at 24h (Chromium at 2h); larger values are ignored
+const CORS_MAX_AGE_LIMIT_SECS: u64 = 86400;
+
+/// Permissive CORS with a configurable preflight cache (`CORS_MAX_AGE_SECS`, default 3600)
+fn build_cors_layer() -> CorsLayer {
+    let mut max_age = config::env_parse("CORS_MAX_AGE_SECS", 3600u64);
+    if max_age > CORS_MAX_AGE_LIMIT_SECS {
+        println!(
+            "[CONFIG] ⚠️ CORS_MAX_AGE_SECS={} exceeds what browsers honor, clamping to {}",
+            max_age, CORS_MAX_AGE_LIMIT_SECS
+        );
+        max_age = CORS_MAX_AGE_LIMIT_SECS;
+    }
+
+    CorsLayer::permissive().max_age(Duration::from_secs(max_age))
+}
+
+async fn shutdown_signal(tracker: ShutdownTracker) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -101,10 +293,52 @@ async fn shutdown_signal() {
     #[cfg(not(unix))]
     let terminate = std::future::pending::<()>();
 
-    tokio::select! {
-        _ = ctrl_c => {},
-        _ = terminate => {},
+    let signal = tokio::select! {
+        _ = ctrl_c => "SIGINT",
+        _ = terminate => "SIGTERM",
+    };
+
+    tracker.begin_drain(signal);
+    println!(
+        "{} received, draining {} in-flight request(s)",
+        signal,
+        tracker.in_flight.current()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// O(1) - `Access-Control-Max-Age` of one preflight through `build_cors_layer`
+    async fn preflight_max_age() -> String {
+        let app = Router::new()
+            .route("/plans", get(|| async { "ok" }))
+            .layer(build_cors_layer());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/plans", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let response = reqwest::Client::new()
+            .request(reqwest::Method::OPTIONS, url)
+            .header("Origin", "https://app.example.com")
+            .header("Access-Control-Request-Method", "GET")
+            .send()
+            .await
+            .unwrap();
+        response.headers()["access-control-max-age"]
+            .to_str()
+            .unwrap()
+            .to_string()
     }
 
-    println!("SIGTERM received, shutting down gracefully");
+    #[tokio::test]
+    async fn preflight_max_age_follows_the_setting_within_browser_bounds() {
+        std::env::set_var("CORS_MAX_AGE_SECS", "600");
+        assert_eq!(preflight_max_age().await, "600");
+
+        std::env::set_var("CORS_MAX_AGE_SECS", "604800");
+        assert_eq!(preflight_max_age().await, "86400");
+        std::env::remove_var("CORS_MAX_AGE_SECS");
+    }
 }
@@ -0,0 +1,117 @@
+// lwas_economy/src/payments/metadata.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Provider metadata limits & input sanitization
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// PROVIDER LIMITS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataLimits {
+    pub max_keys: usize,
+    pub max_key_len: usize,
+    pub max_value_len: usize,
+}
+
+/// Stripe rejects metadata beyond 50 keys, 40-char keys or 500-char values
+pub const STRIPE_METADATA_LIMITS: MetadataLimits = MetadataLimits {
+    max_keys: 50,
+    max_key_len: 40,
+    max_value_len: 500,
+};
+
+/// PayPal `purchase_units[].custom_id` limit
+pub const PAYPAL_CUSTOM_ID_MAX: usize = 127;
+
+/// PayPal `purchase_units[].description` limit
+pub const PAYPAL_DESCRIPTION_MAX: usize = 127;
+
+/// Marketing attribution accepted on checkout routes and carried in session metadata
+pub const ATTRIBUTION_KEYS: [&str; 4] = ["utm_source", "utm_medium", "utm_campaign", "referrer"];
+
+/// Attribution values are short tags or a URL; anything longer is noise
+pub const ATTRIBUTION_VALUE_MAX: usize = 200;
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// SANITIZATION
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// O(n) - Drop control characters and cut to `max` chars (never splits a char).
+/// Logs whenever the value had to be shortened.
+pub fn sanitize_value(value: &str, max: usize, label: &str) -> String {
+    let cleaned: String = value.chars().filter(|c| !c.is_control()).collect();
+    let char_count = cleaned.chars().count();
+    if char_count <= max {
+        return cleaned;
+    }
+
+    println!(
+        "[METADATA] ✂️ Truncated '{}' from {} to {} chars",
+        label, char_count, max
+    );
+    cleaned.chars().take(max).collect()
+}
+
+/// O(n) - Apply provider limits to key/value pairs, keeping the first `max_keys`
+pub fn sanitize_metadata(
+    entries: Vec<(String, String)>,
+    limits: MetadataLimits,
+) -> Vec<(String, String)> {
+    if entries.len() > limits.max_keys {
+        println!(
+            "[METADATA] ✂️ Dropping {} metadata keys over the limit of {}",
+            entries.len() - limits.max_keys,
+            limits.max_keys
+        );
+    }
+
+    entries
+        .into_iter()
+        .take(limits.max_keys)
+        .map(|(key, value)| {
+            let key = sanitize_value(&key, limits.max_key_len, "metadata key");
+            let value = sanitize_value(&value, limits.max_value_len, &key);
+            (key, value)
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_values_are_cut_to_the_limit() {
+        let value = "v".repeat(STRIPE_METADATA_LIMITS.max_value_len + 100);
+        let key = "k".repeat(STRIPE_METADATA_LIMITS.max_key_len + 10);
+        let sanitized = sanitize_metadata(vec![(key, value)], STRIPE_METADATA_LIMITS);
+        assert_eq!(sanitized.len(), 1);
+        assert_eq!(sanitized[0].0.len(), STRIPE_METADATA_LIMITS.max_key_len);
+        assert_eq!(sanitized[0].1.len(), STRIPE_METADATA_LIMITS.max_value_len);
+    }
+
+    #[test]
+    fn truncation_never_splits_a_character() {
+        let custom_id = format!("veritas_{}", "ü".repeat(PAYPAL_CUSTOM_ID_MAX));
+        let sanitized = sanitize_value(&custom_id, PAYPAL_CUSTOM_ID_MAX, "custom_id");
+        assert_eq!(sanitized.chars().count(), PAYPAL_CUSTOM_ID_MAX);
+        assert!(sanitized.starts_with("veritas_ü"));
+    }
+
+    #[test]
+    fn keys_past_the_limit_and_empty_keys_are_dropped() {
+        let mut entries: Vec<(String, String)> = (0..STRIPE_METADATA_LIMITS.max_keys + 5)
+            .map(|i| (format!("key_{}", i), "x".to_string()))
+            .collect();
+        entries[0].0 = "\u{7}".to_string();
+        let sanitized = sanitize_metadata(entries, STRIPE_METADATA_LIMITS);
+        assert_eq!(sanitized.len(), STRIPE_METADATA_LIMITS.max_keys - 1);
+        assert_eq!(sanitized[0].0, "key_1");
+    }
+
+    #[test]
+    fn control_characters_are_stripped() {
+        assert_eq!(sanitize_value("plan\r\nbasic\0", 50, "plan"), "planbasic");
+    }
+}
@@ -0,0 +1,95 @@
+// lwas_economy/src/payments/metrics.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Prometheus exposition for payment observability
+
+use axum::extract::State;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Upstream latency buckets (seconds): Stripe/PayPal calls sit well under 1s
+/// normally, so resolution is concentrated there with a tail up to the timeout
+const UPSTREAM_LATENCY_BUCKETS: &[f64] = &[0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Exporter with the histogram buckets configured
+fn prometheus_builder() -> PrometheusBuilder {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("upstream_request_duration_seconds".to_string()),
+            UPSTREAM_LATENCY_BUCKETS,
+        )
+        .expect("invalid upstream latency buckets")
+}
+
+/// Installs the global recorder; call once at startup before any metric is touched
+pub fn install_recorder() -> PrometheusHandle {
+    prometheus_builder()
+        .install_recorder()
+        .expect("failed to install Prometheus recorder")
+}
+
+/// GET /metrics - Prometheus text format
+pub async fn render_metrics(State(handle): State<PrometheusHandle>) -> String {
+    handle.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockResponse, MockServer};
+    use crate::upstream::UpstreamClient;
+
+    /// Rendered lines of `metric` whose labels include `operation`
+    fn samples<'a>(rendered: &'a str, metric: &str, operation: &str) -> Vec<&'a str> {
+        let label = format!("operation=\"{}\"", operation);
+        rendered
+            .lines()
+            .filter(|line| line.starts_with(metric) && line.contains(&label))
+            .collect()
+    }
+
+    #[test]
+    fn upstream_calls_are_timed_per_operation() {
+        let recorder = prometheus_builder().build_recorder();
+        let handle = recorder.handle();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // The local recorder covers everything polled on this thread
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let api =
+                    MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+                let stripe = UpstreamClient::from_env("stripe", false);
+                let paypal = UpstreamClient::from_env("paypal", false);
+                for _ in 0..2 {
+                    let request = stripe
+                        .client()
+                        .post(format!("{}/v1/checkout/sessions", api.url));
+                    stripe.send("create_checkout", request).await.unwrap();
+                }
+                let request = paypal
+                    .client()
+                    .post(format!("{}/v2/checkout/orders", api.url));
+                paypal.send("paypal_create_order", request).await.unwrap();
+            })
+        });
+
+        let rendered = handle.render();
+        let count = "upstream_request_duration_seconds_count";
+        let checkout = samples(&rendered, count, "create_checkout");
+        assert_eq!(checkout.len(), 1, "{}", rendered);
+        assert!(checkout[0].contains("provider=\"stripe\"") && checkout[0].ends_with(" 2"));
+        let order = samples(&rendered, count, "paypal_create_order");
+        assert!(order[0].contains("provider=\"paypal\"") && order[0].ends_with(" 1"));
+
+        // Configured buckets, not the exporter's summary default
+        let buckets = samples(
+            &rendered,
+            "upstream_request_duration_seconds_bucket",
+            "create_checkout",
+        );
+        assert_eq!(buckets.len(), UPSTREAM_LATENCY_BUCKETS.len() + 1);
+        assert!(buckets.iter().any(|line| line.contains("le=\"0.025\"")));
+    }
+}
@@ -0,0 +1,378 @@
+// lwas_economy/src/payments/money.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Minor-unit money shared by Stripe (integers) and PayPal (decimal strings)
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use serde::Serialize;
+
+use crate::config::env_parse;
+
+/// Amount in the currency's minor unit (cents for USD, yen for JPY)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Money {
+    pub minor: i64,
+    /// ISO 4217, upper case
+    pub currency: String,
+}
+
+/// O(1) - Decimal places of the currency's minor unit
+pub fn minor_unit_exponent(currency: &str) -> u32 {
+    match currency.to_ascii_uppercase().as_str() {
+        // Zero-decimal (Stripe's list, plus HUF/TWD which PayPal only accepts whole)
+        "BIF" | "CLP" | "DJF" | "GNF" | "HUF" | "JPY" | "KMF" | "KRW" | "MGA" | "PYG" | "RWF"
+        | "TWD" | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "JOD" | "KWD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// O(1) - Smallest amount the providers will charge, in minor units (Stripe's
+/// published minimums). `MIN_CHARGE_{CURRENCY}` overrides the table; unlisted
+/// currencies only need to be positive.
+pub fn minimum_charge(currency: &str) -> i64 {
+    let currency = currency.to_ascii_uppercase();
+    let default = match currency.as_str() {
+        "USD" | "EUR" | "AUD" | "CAD" | "CHF" | "NZD" | "SGD" | "BRL" | "INR" | "JPY" => 50,
+        "GBP" => 30,
+        "BGN" => 100,
+        "AED" | "MYR" | "PLN" | "RON" => 200,
+        "DKK" => 250,
+        "NOK" | "SEK" => 300,
+        "HKD" => 400,
+        "MXN" | "THB" => 1000,
+        "CZK" => 1500,
+        "HUF" => 175,
+        _ => 1,
+    };
+    env_parse(&format!("MIN_CHARGE_{}", currency), default)
+}
+
+impl Money {
+    /// O(1) - Rejects amounts the provider would refuse as too small
+    pub fn check_minimum(&self) -> Result<(), String> {
+        let minimum = Self {
+            minor: minimum_charge(&self.currency),
+            currency: self.currency.clone(),
+        };
+        if self.minor < minimum.minor {
+            return Err(format!(
+                "Amount {} is below the minimum charge of {}",
+                self, minimum
+            ));
+        }
+        Ok(())
+    }
+
+    /// O(n) - Parse a PayPal `value` ("199.00", "1500") into minor units,
+    /// scaling by the currency's own exponent (x100 for USD, x1 for JPY)
+    pub fn from_paypal_decimal(value: &str, currency: &str) -> Result<Self, String> {
+        let exponent = minor_unit_exponent(currency);
+        let (negative, digits) = match value.trim().strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, value.trim()),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+            return Err(format!("Invalid amount '{}'", value));
+        }
+        // Extra fractional digits are only tolerated when they are zeros ("1500.00" JPY)
+        let (kept, dropped) = fraction.split_at(fraction.len().min(exponent as usize));
+        if dropped.bytes().any(|b| b != b'0') {
+            return Err(format!(
+                "Amount '{}' has more than {} decimal(s) for {}",
+                value, exponent, currency
+            ));
+        }
+
+        let scale = 10i64.pow(exponent);
+        let whole: i64 = whole
+            .parse()
+            .map_err(|_| format!("Amount '{}' out of range", value))?;
+        let fraction: i64 = format!("{:0<width$}", kept, width = exponent as usize)
+            .parse()
+            .unwrap_or(0);
+        let minor = whole
+            .checked_mul(scale)
+            .and_then(|m| m.checked_add(fraction))
+            .ok_or_else(|| format!("Amount '{}' out of range", value))?;
+
+        Ok(Self {
+            minor: if negative { -minor } else { minor },
+            currency: currency.to_ascii_uppercase(),
+        })
+    }
+
+    /// O(1) - PayPal `amount` object: `{"currency_code": "USD", "value": "199.00"}`
+    pub fn from_paypal_amount(amount: &serde_json::Value) -> Result<Self, String> {
+        let currency = amount["currency_code"]
+            .as_str()
+            .ok_or("Missing currency_code")?;
+        let value = amount["value"].as_str().ok_or("Missing value")?;
+        Self::from_paypal_decimal(value, currency)
+    }
+
+    /// O(1) - PayPal `value` string ("199.00", "1500")
+    pub fn to_paypal_decimal(&self) -> String {
+        let exponent = minor_unit_exponent(&self.currency);
+        if exponent == 0 {
+            return self.minor.to_string();
+        }
+        let scale = 10i64.pow(exponent);
+        let sign = if self.minor < 0 { "-" } else { "" };
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            self.minor.abs() / scale,
+            self.minor.abs() % scale,
+            width = exponent as usize
+        )
+    }
+
+    /// O(1) - PayPal `amount` object
+    pub fn to_paypal_amount(&self) -> serde_json::Value {
+        serde_json::json!({
+            "currency_code": self.currency,
+            "value": self.to_paypal_decimal(),
+        })
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.to_paypal_decimal(), self.currency)
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// LOCALIZED DISPLAY
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Separators and symbol placement for human-readable amounts; machine-readable
+/// fields keep raw minor units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmountLocale {
+    group: &'static str,
+    decimal: char,
+    /// `1.234,56 €` rather than `€1,234.56`
+    symbol_after: bool,
+    /// Digits after the decimal point, capped at the currency's own
+    /// (`AMOUNT_DISPLAY_DECIMALS`); fewer rounds half away from zero
+    max_decimals: Option<u32>,
+}
+
+impl AmountLocale {
+    pub const EN: Self = Self {
+        group: ",",
+        decimal: '.',
+        symbol_after: false,
+        max_decimals: None,
+    };
+
+    /// O(1) - From a POSIX or BCP 47 tag (`de_DE.UTF-8`, `fr-FR`, `en`); only the
+    /// language decides, unknown languages are None
+    pub fn parse(tag: &str) -> Option<Self> {
+        let language = tag
+            .split(['_', '-', '.', '@'])
+            .next()?
+            .trim()
+            .to_ascii_lowercase();
+        let (group, decimal, symbol_after) = match language.as_str() {
+            "en" | "ja" | "zh" | "ko" | "c" | "posix" => (",", '.', false),
+            "de" | "es" | "it" | "pt" | "nl" | "tr" | "id" => (".", ',', true),
+            // Narrow no-break space, as CLDR formats French
+            "fr" => ("\u{202f}", ',', true),
+            "bg" | "ru" | "uk" | "pl" | "cs" | "sv" | "fi" | "nb" => ("\u{a0}", ',', true),
+            _ => return None,
+        };
+        Some(Self {
+            group,
+            decimal,
+            symbol_after,
+            max_decimals: None,
+        })
+    }
+
+    /// O(1) - Same separators, shown with at most `decimals` fractional digits
+    pub fn with_max_decimals(mut self, decimals: u32) -> Self {
+        self.max_decimals = Some(decimals);
+        self
+    }
+}
+
+static DISPLAY_LOCALE: OnceLock<AmountLocale> = OnceLock::new();
+
+/// O(1) - `LOCALE` (default `en`) plus optional `AMOUNT_DISPLAY_DECIMALS`
+pub fn display_locale() -> AmountLocale {
+    *DISPLAY_LOCALE.get_or_init(|| {
+        let locale = match std::env::var("LOCALE") {
+            Ok(tag) if !tag.trim().is_empty() => AmountLocale::parse(&tag).unwrap_or_else(|| {
+                println!(
+                    "[CONFIG] ⚠️ Unknown LOCALE '{}', formatting amounts as en",
+                    tag
+                );
+                AmountLocale::EN
+            }),
+            _ => AmountLocale::EN,
+        };
+        match std::env::var("AMOUNT_DISPLAY_DECIMALS") {
+            Ok(_) => locale.with_max_decimals(env_parse("AMOUNT_DISPLAY_DECIMALS", 2)),
+            Err(_) => locale,
+        }
+    })
+}
+
+/// O(1) - Symbol for the common currencies; others are shown by code
+fn currency_symbol(currency: &str) -> Option<&'static str> {
+    match currency {
+        "USD" => Some("$"),
+        "EUR" => Some("€"),
+        "GBP" => Some("£"),
+        "JPY" => Some("¥"),
+        "BGN" => Some("лв."),
+        "INR" => Some("₹"),
+        _ => None,
+    }
+}
+
+impl Money {
+    /// O(n) - Human-readable amount under `locale`: `$1,234.56`, `1.234,56 €`,
+    /// `CHF 1,234.56`
+    pub fn format_localized(&self, locale: AmountLocale) -> String {
+        let exponent = minor_unit_exponent(&self.currency);
+        let decimals = locale.max_decimals.map_or(exponent, |d| d.min(exponent));
+        let scale = 10u64.pow(exponent - decimals);
+        let shown = (self.minor.unsigned_abs() + scale / 2) / scale;
+        let unit = 10u64.pow(decimals);
+
+        let whole = (shown / unit).to_string();
+        let mut number = String::new();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i).is_multiple_of(3) {
+                number.push_str(locale.group);
+            }
+            number.push(digit);
+        }
+        if decimals > 0 {
+            number.push(locale.decimal);
+            number.push_str(&format!(
+                "{:0width$}",
+                shown % unit,
+                width = decimals as usize
+            ));
+        }
+
+        let sign = if self.minor < 0 && shown > 0 { "-" } else { "" };
+        match (currency_symbol(&self.currency), locale.symbol_after) {
+            (Some(symbol), false) => format!("{}{}{}", sign, symbol, number),
+            (Some(symbol), true) => format!("{}{} {}", sign, number, symbol),
+            (None, false) => format!("{}{} {}", sign, self.currency, number),
+            (None, true) => format!("{}{} {}", sign, number, self.currency),
+        }
+    }
+
+    /// O(n) - `format_localized` under the configured `LOCALE`
+    pub fn localized(&self) -> String {
+        self.format_localized(display_locale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minor(value: &str, currency: &str) -> Result<i64, String> {
+        Money::from_paypal_decimal(value, currency).map(|m| m.minor)
+    }
+
+    #[test]
+    fn jpy_paypal_amounts_are_whole_yen() {
+        assert_eq!(minor("1500", "JPY"), Ok(1500));
+        assert_eq!(minor("1500.00", "jpy"), Ok(1500));
+        assert!(minor("1500.50", "JPY").is_err());
+        let yen = Money::from_paypal_amount(&serde_json::json!({
+            "currency_code": "JPY",
+            "value": "1500",
+        }))
+        .unwrap();
+        assert_eq!(yen.to_string(), "1500 JPY");
+        assert_eq!(yen.to_paypal_decimal(), "1500");
+    }
+
+    #[test]
+    fn usd_paypal_amounts_are_cents() {
+        assert_eq!(minor("199.00", "USD"), Ok(19_900));
+        assert_eq!(minor("199.5", "USD"), Ok(19_950));
+        assert_eq!(minor("7", "USD"), Ok(700));
+        assert_eq!(minor("-0.05", "USD"), Ok(-5));
+        assert!(minor("1.999", "USD").is_err());
+        assert!(minor("1e3", "USD").is_err());
+        let dollars = Money {
+            minor: 19_905,
+            currency: "USD".into(),
+        };
+        assert_eq!(dollars.to_string(), "199.05 USD");
+        assert_eq!(dollars.to_paypal_amount()["value"], "199.05");
+    }
+
+    #[test]
+    fn amounts_below_the_currency_minimum_are_refused() {
+        let money = |minor: i64, currency: &str| Money {
+            minor,
+            currency: currency.into(),
+        };
+        assert_eq!(
+            money(49, "EUR").check_minimum(),
+            Err("Amount 0.49 EUR is below the minimum charge of 0.50 EUR".to_string())
+        );
+        assert_eq!(money(50, "EUR").check_minimum(), Ok(()));
+        assert_eq!(money(900, "EUR").check_minimum(), Ok(()));
+
+        // Zero-decimal: 49 yen is below ¥50, and the message stays in whole yen
+        assert_eq!(
+            money(49, "JPY").check_minimum(),
+            Err("Amount 49 JPY is below the minimum charge of 50 JPY".to_string())
+        );
+        assert!(money(29, "GBP").check_minimum().is_err());
+        assert_eq!(money(30, "GBP").check_minimum(), Ok(()));
+
+        // Unlisted currencies only need to be positive
+        assert!(money(0, "ZAR").check_minimum().is_err());
+        assert_eq!(money(1, "ZAR").check_minimum(), Ok(()));
+    }
+
+    #[test]
+    fn amounts_follow_the_locale_separators_and_symbol_placement() {
+        let eur = Money {
+            minor: 123_456_789,
+            currency: "EUR".to_string(),
+        };
+        let en = AmountLocale::parse("en_US.UTF-8").unwrap();
+        let de = AmountLocale::parse("de-DE").unwrap();
+        let fr = AmountLocale::parse("fr_FR").unwrap();
+        assert_eq!(eur.format_localized(en), "€1,234,567.89");
+        assert_eq!(eur.format_localized(de), "1.234.567,89 €");
+        assert_eq!(eur.format_localized(fr), "1\u{202f}234\u{202f}567,89 €");
+        assert_eq!(AmountLocale::parse("xx"), None);
+
+        // Codes stand in for unlisted symbols; JPY has no minor digits to show
+        let chf = Money {
+            minor: -150_000,
+            currency: "CHF".to_string(),
+        };
+        assert_eq!(chf.format_localized(en), "-CHF 1,500.00");
+        assert_eq!(chf.format_localized(de), "-1.500,00 CHF");
+        let jpy = Money {
+            minor: 4900,
+            currency: "JPY".to_string(),
+        };
+        assert_eq!(jpy.format_localized(de), "4.900 ¥");
+
+        // Display rounding leaves the minor units alone
+        assert_eq!(eur.format_localized(de.with_max_decimals(0)), "1.234.568 €");
+        assert_eq!(eur.minor, 123_456_789);
+    }
+}
@@ -0,0 +1,78 @@
+// lwas_economy/src/payments/notifications.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: BETA
+// Outbound notification hook (email relay / automation webhook)
+
+use chrono::Utc;
+use reqwest::Client;
+use uuid::Uuid;
+
+use crate::upstream::outbound_client;
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// NOTIFICATION HOOK
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Forwards customer-facing notifications to an external mailer or automation
+/// endpoint. Without `NOTIFICATION_WEBHOOK_URL` notifications are only logged.
+#[derive(Clone)]
+pub struct NotificationHook {
+    webhook_url: Option<String>,
+    http_client: Client,
+}
+
+impl NotificationHook {
+    pub fn from_env() -> Self {
+        Self {
+            webhook_url: std::env::var("NOTIFICATION_WEBHOOK_URL").ok(),
+            http_client: outbound_client(),
+        }
+    }
+
+    /// Posting to `url` instead of the environment's hook
+    #[cfg(test)]
+    pub fn with_url(url: &str) -> Self {
+        Self {
+            webhook_url: Some(url.to_string()),
+            http_client: outbound_client(),
+        }
+    }
+
+    /// O(1) - Fire a notification of `kind` for `email` with extra `data`.
+    /// Only the kind and notification id are logged: `data` may carry secrets
+    /// such as license keys.
+    pub async fn notify(
+        &self,
+        kind: &str,
+        email: &str,
+        data: serde_json::Value,
+    ) -> Result<(), String> {
+        let id = Uuid::new_v4();
+        let payload = serde_json::json!({
+            "id": id,
+            "kind": kind,
+            "email": email,
+            "data": data,
+            "timestamp": Utc::now().to_rfc3339(),
+        });
+
+        let Some(url) = &self.webhook_url else {
+            println!("[NOTIFY] ✉️ (no hook configured) '{}' {}", kind, id);
+            return Ok(());
+        };
+
+        let res = self
+            .http_client
+            .post(url)
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| format!("Notification request failed: {}", e))?;
+
+        if !res.status().is_success() {
+            return Err(format!("Notification hook returned {}", res.status()));
+        }
+
+        println!("[NOTIFY] ✉️ Sent '{}' {}", kind, id);
+        Ok(())
+    }
+}
@@ -3,16 +3,38 @@
 // PayPal Webhook Handler & Order Management
 
 use axum::{
-    extract::{Json, State},
+    body::Bytes,
+    extract::{Extension, Json, Query, State},
     http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Redirect},
+    response::{IntoResponse, Redirect, Response},
 };
 use base64::{engine::general_purpose, Engine as _};
 use chrono::{DateTime, Utc};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::admin::AdminAuth;
+use crate::audit::{self, AuditTrail};
+use crate::catalog::{PricingCatalog, DEFAULT_PLAN_KEY};
+use crate::client_ip::{ClientIp, WebhookSourceFilter};
+use crate::config::{env_flag, env_parse};
+use crate::domains::SiteDomains;
+use crate::event_archive::EventArchive;
+use crate::json_limits::{parse_bounded, DEFAULT_MAX_JSON_DEPTH};
+use crate::license::{LicenseIssuer, LicenseProvider, LicenseRegistry};
+use crate::metadata::{sanitize_value, PAYPAL_CUSTOM_ID_MAX, PAYPAL_DESCRIPTION_MAX};
+use crate::money::Money;
+use crate::provider_event::{self, Activation, ProviderEvent};
+use crate::retry::retry_async;
+use crate::upstream::{UpstreamClient, UpstreamError};
+
+use crate::stripe_handler::{
+    EventResult, IdempotencyStore, SubscriptionManager, SubscriptionPlan, SubscriptionStatus,
+    VerifyResponse,
+};
 
 // ═══════════════════════════════════════════════════════════════════════════════
 // PAYPAL CONFIGURATION
@@ -23,31 +45,109 @@ pub struct PayPalConfig {
     pub client_id: String,
     pub client_secret: String,
     pub mode: String, // "sandbox" or "live"
-    pub _webhook_id: String,
+    /// REST API root for `mode`; `PAYPAL_API_BASE` points sandbox mode at a
+    /// mock instead
+    pub api_base: String,
+    /// Id PayPal assigned our webhook registration, which signatures are
+    /// checked against (`PAYPAL_WEBHOOK_ID`)
+    pub webhook_id: Option<String>,
+    /// Check every webhook with PayPal's `verify-webhook-signature`
+    /// (`PAYPAL_VERIFY_WEBHOOK_SIGNATURE`; on by default once `PAYPAL_WEBHOOK_ID` is set)
+    pub verify_signature: bool,
+    /// Accept unsigned webhooks (`DEV_SKIP_SIGNATURE`, refused in live mode)
+    pub dev_skip_signature: bool,
+    /// PayPal billing plan id -> our plan key (`PAYPAL_PLAN_MAP=P-123=pro_monthly,P-456=enterprise_monthly`)
+    pub plan_map: HashMap<String, String>,
+    /// Source IP allowlist for webhooks (`VERIFY_WEBHOOK_SOURCE_IP`, `PAYPAL_WEBHOOK_IP_RANGES`)
+    pub webhook_sources: WebhookSourceFilter,
+    /// Deeper webhook bodies are rejected before parsing (`WEBHOOK_MAX_JSON_DEPTH`)
+    pub max_json_depth: usize,
 }
 
 impl PayPalConfig {
     pub fn from_env() -> Self {
+        let mode = std::env::var("PAYPAL_MODE").unwrap_or_else(|_| "sandbox".to_string());
+        let api_base = match std::env::var("PAYPAL_API_BASE") {
+            Ok(base) if !base.trim().is_empty() && mode != "live" => {
+                base.trim().trim_end_matches('/').to_string()
+            }
+            Ok(base) if !base.trim().is_empty() => {
+                println!("[CONFIG] ❌ PAYPAL_API_BASE is not allowed in live mode, ignoring");
+                default_api_base(&mode).to_string()
+            }
+            _ => default_api_base(&mode).to_string(),
+        };
+        let webhook_id = std::env::var("PAYPAL_WEBHOOK_ID")
+            .ok()
+            .filter(|id| !id.trim().is_empty());
         Self {
             client_id: std::env::var("PAYPAL_CLIENT_ID")
                 .unwrap_or_else(|_| "sb_client_id_placeholder".to_string()),
             client_secret: std::env::var("PAYPAL_CLIENT_SECRET")
                 .unwrap_or_else(|_| "sb_client_secret_placeholder".to_string()),
-            mode: std::env::var("PAYPAL_MODE").unwrap_or_else(|_| "sandbox".to_string()),
-            _webhook_id: std::env::var("PAYPAL_WEBHOOK_ID")
-                .unwrap_or_else(|_| "wh_id_placeholder".to_string()),
+            mode,
+            api_base,
+            verify_signature: env_parse("PAYPAL_VERIFY_WEBHOOK_SIGNATURE", webhook_id.is_some()),
+            webhook_id,
+            dev_skip_signature: env_flag("DEV_SKIP_SIGNATURE"),
+            plan_map: std::env::var("PAYPAL_PLAN_MAP")
+                .map(|raw| parse_plan_map(&raw))
+                .unwrap_or_default(),
+            webhook_sources: WebhookSourceFilter::from_env("paypal"),
+            max_json_depth: env_parse("WEBHOOK_MAX_JSON_DEPTH", DEFAULT_MAX_JSON_DEPTH).max(1),
         }
+        .validated()
     }
 
-    pub fn base_url(&self) -> &str {
-        if self.mode == "live" {
-            "https://api-m.paypal.com"
-        } else {
-            "https://api-m.sandbox.paypal.com"
+    /// O(1) - Unsigned webhooks are never accepted in live mode. Enforcing
+    /// signatures without a webhook id would refuse every delivery, so that
+    /// stops the boot instead.
+    fn validated(mut self) -> Self {
+        if self.dev_skip_signature {
+            if self.is_live() {
+                self.dev_skip_signature = false;
+                println!("[CONFIG] ❌ DEV_SKIP_SIGNATURE refused in PayPal live mode; signatures stay ENFORCED");
+            } else {
+                println!("[CONFIG] 🚧 DEV_SKIP_SIGNATURE ACTIVE: PayPal webhook signatures are NOT verified");
+            }
+        } else if self.verify_signature && self.webhook_id.is_none() {
+            panic!("PAYPAL_VERIFY_WEBHOOK_SIGNATURE needs PAYPAL_WEBHOOK_ID");
+        } else if !self.verify_signature {
+            println!("[CONFIG] ⚠️ PayPal webhook signatures are NOT verified; set PAYPAL_WEBHOOK_ID to enforce them");
         }
+        self
+    }
+
+    /// O(1) - Live mode moves real money
+    pub fn is_live(&self) -> bool {
+        self.mode == "live"
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.api_base
     }
 }
 
+/// O(1) - PayPal's REST API root for `mode`
+fn default_api_base(mode: &str) -> &'static str {
+    if mode == "live" {
+        "https://api-m.paypal.com"
+    } else {
+        "https://api-m.sandbox.paypal.com"
+    }
+}
+
+/// O(n) - Parses `plan_id=plan_key` pairs separated by commas
+fn parse_plan_map(raw: &str) -> HashMap<String, String> {
+    raw.split(',')
+        .filter_map(|pair| {
+            let (plan_id, plan_key) = pair.split_once('=')?;
+            Some((plan_id.trim().to_string(), plan_key.trim().to_string()))
+        })
+        .filter(|(plan_id, plan_key)| !plan_id.is_empty() && !plan_key.is_empty())
+        .collect()
+}
+
 // ═══════════════════════════════════════════════════════════════════════════════
 // PAYPAL EVENT TYPES
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -60,30 +160,125 @@ pub struct PayPalEvent {
     pub resource_type: String,
     pub resource: serde_json::Value,
     pub summary: Option<String>,
+    /// Not in PayPal's payload; stamped from `PAYPAL_MODE` after parsing, since
+    /// each mode has its own webhook registration
+    #[serde(skip)]
+    pub livemode: bool,
+}
+
+impl ProviderEvent for PayPalEvent {
+    fn provider(&self) -> &'static str {
+        "paypal"
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    fn created(&self) -> Option<i64> {
+        DateTime::parse_from_rfc3339(&self.create_time)
+            .ok()
+            .map(|t| t.timestamp())
+    }
+
+    fn livemode(&self) -> bool {
+        self.livemode
+    }
+}
+
+/// Order intent: capture immediately, or authorize now and capture later
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderIntent {
+    Capture,
+    Authorize,
+}
+
+impl OrderIntent {
+    /// O(1) - Parse the `intent` query value (case-insensitive, defaults to CAPTURE)
+    pub fn parse(raw: Option<&str>) -> Result<Self, String> {
+        match raw.map(|s| s.trim().to_ascii_uppercase()).as_deref() {
+            None | Some("") | Some("CAPTURE") => Ok(OrderIntent::Capture),
+            Some("AUTHORIZE") => Ok(OrderIntent::Authorize),
+            Some(other) => Err(format!("Unsupported intent '{}'", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderIntent::Capture => "CAPTURE",
+            OrderIntent::Authorize => "AUTHORIZE",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckoutParams {
+    pub intent: Option<String>,
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
 // PAYPAL STATE
 // ═══════════════════════════════════════════════════════════════════════════════
 
+/// Cached OAuth token with its expiry
+type CachedToken = Option<(String, DateTime<Utc>)>;
+
 #[derive(Clone)]
 pub struct PayPalState {
     pub config: PayPalConfig,
-    pub http_client: Client,
-    pub auth_token: Arc<RwLock<Option<(String, DateTime<Utc>)>>>,
+    pub http: UpstreamClient,
+    pub auth_token: Arc<RwLock<CachedToken>>,
+    /// Shared with the Stripe handler so both providers see one store
+    pub subscriptions: SubscriptionManager,
+    /// Processed event ids with their outcome (Redis or in-memory)
+    pub processed_events: IdempotencyStore,
+    /// Raw payloads as received, for admin replays
+    pub archive: EventArchive,
+    /// Shared with the Stripe handler; picks return/cancel URLs per Origin
+    pub domains: SiteDomains,
+    /// Shared with the Stripe handler so PayPal keys introspect like Stripe ones
+    pub license: LicenseIssuer,
+    pub licenses: LicenseRegistry,
+    /// Shared with the Stripe handler so both write one audit trail
+    pub audit: Arc<AuditTrail>,
+    /// Shared with the Stripe handler so both sell at the catalog's prices
+    pub catalog: Arc<PricingCatalog>,
+    /// Guards the admin routes (`ADMIN_API_TOKEN`)
+    pub admin: AdminAuth,
 }
 
 impl PayPalState {
-    pub fn new() -> Self {
+    pub fn new(
+        subscriptions: SubscriptionManager,
+        domains: SiteDomains,
+        license: LicenseIssuer,
+        licenses: LicenseRegistry,
+        audit: Arc<AuditTrail>,
+        catalog: Arc<PricingCatalog>,
+    ) -> Self {
+        let config = PayPalConfig::from_env();
         Self {
-            config: PayPalConfig::from_env(),
-            http_client: Client::new(),
+            http: UpstreamClient::from_env("paypal", config.is_live()),
+            config,
             auth_token: Arc::new(RwLock::new(None)),
+            subscriptions,
+            processed_events: IdempotencyStore::new(std::env::var("REDIS_URL").ok()),
+            archive: EventArchive::new("paypal", std::env::var("REDIS_URL").ok()),
+            domains,
+            license,
+            licenses,
+            audit,
+            catalog,
+            admin: AdminAuth::from_env(),
         }
     }
 
     /// Get valid access token (Cached or Refreshed)
-    pub async fn get_access_token(&self) -> Result<String, String> {
+    pub async fn get_access_token(&self) -> Result<String, UpstreamError> {
         // Check cache
         {
             let token_lock = self.auth_token.read().await;
@@ -94,24 +289,27 @@ impl PayPalState {
             }
         }
 
-        // Refresh token
+        self.refresh_access_token().await
+    }
+
+    /// O(1) - Fetch a new token from PayPal regardless of the cache, then cache it
+    pub async fn refresh_access_token(&self) -> Result<String, UpstreamError> {
         let auth_str = format!("{}:{}", self.config.client_id, self.config.client_secret);
         let auth_basic = general_purpose::STANDARD.encode(auth_str);
 
         let url = format!("{}/v1/oauth2/token", self.config.base_url());
         let params = [("grant_type", "client_credentials")];
 
-        let resp = self
-            .http_client
+        let request = self
+            .http
+            .client()
             .post(&url)
             .header("Authorization", format!("Basic {}", auth_basic))
-            .form(&params)
-            .send()
-            .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+            .form(&params);
+        let resp = self.http.send("paypal_auth", request).await?;
 
         if !resp.status().is_success() {
-            return Err(format!("Auth failed: {}", resp.status()));
+            return Err(format!("Auth failed: {}", resp.status()).into());
         }
 
         let body: serde_json::Value = resp
@@ -135,87 +333,483 @@ impl PayPalState {
     }
 }
 
+// ═══════════════════════════════════════════════════════════════════════════════
+// WEBHOOK SIGNATURE VERIFICATION
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Transmission headers PayPal signs each delivery with, in the field names
+/// `verify-webhook-signature` expects them under
+const TRANSMISSION_HEADERS: [(&str, &str); 5] = [
+    ("auth_algo", "paypal-auth-algo"),
+    ("cert_url", "paypal-cert-url"),
+    ("transmission_id", "paypal-transmission-id"),
+    ("transmission_sig", "paypal-transmission-sig"),
+    ("transmission_time", "paypal-transmission-time"),
+];
+
+/// O(n) - Request body for `POST /v1/notifications/verify-webhook-signature`.
+/// The event goes in as the exact bytes received, never re-encoded: PayPal
+/// checks the signature over them.
+fn signature_request(headers: &HeaderMap, webhook_id: &str, body: &[u8]) -> Result<String, String> {
+    let event = std::str::from_utf8(body).map_err(|_| "Body is not UTF-8".to_string())?;
+    let mut request = String::from("{");
+    for (field, header) in TRANSMISSION_HEADERS {
+        let value = headers
+            .get(header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| format!("Missing {} header", header))?;
+        request.push_str(&format!(
+            "\"{}\":{},",
+            field,
+            serde_json::Value::from(value)
+        ));
+    }
+    request.push_str(&format!(
+        "\"webhook_id\":{},\"webhook_event\":{}}}",
+        serde_json::Value::from(webhook_id),
+        event
+    ));
+    Ok(request)
+}
+
+/// O(1) - Ask PayPal whether `body` was signed for our webhook. Err is either a
+/// rejection or, when `UpstreamError::is_transient` says so, PayPal being unreachable.
+async fn verify_webhook_signature(
+    state: &PayPalState,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), UpstreamError> {
+    let webhook_id = state
+        .config
+        .webhook_id
+        .as_deref()
+        .ok_or("PAYPAL_WEBHOOK_ID not configured")?;
+    let request_body = signature_request(headers, webhook_id, body)?;
+
+    let token = state.get_access_token().await?;
+    let url = format!(
+        "{}/v1/notifications/verify-webhook-signature",
+        state.config.base_url()
+    );
+    let request = state
+        .http
+        .client()
+        .post(&url)
+        .bearer_auth(token)
+        .header("Content-Type", "application/json")
+        .body(request_body);
+    let resp = state.http.send("paypal_verify_webhook", request).await?;
+    let status = resp.status();
+    let reply: serde_json::Value = resp
+        .json()
+        .await
+        .map_err(|e| format!("JSON error: {}", e))?;
+    if !status.is_success() {
+        return Err(UpstreamError::status("PayPal", status, reply));
+    }
+    match reply["verification_status"].as_str() {
+        Some("SUCCESS") => Ok(()),
+        other => Err(format!("Verification status {}", other.unwrap_or("missing")).into()),
+    }
+}
+
 // ═══════════════════════════════════════════════════════════════════════════════
 // WEBHOOK HANDLER
 // ═══════════════════════════════════════════════════════════════════════════════
 
 pub async fn paypal_webhook_handler(
-    State(_state): State<Arc<PayPalState>>,
-    _headers: HeaderMap,
-    Json(event): Json<PayPalEvent>,
+    State(state): State<Arc<PayPalState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> impl IntoResponse {
+    if !state.config.webhook_sources.allows(client_ip) {
+        return (StatusCode::FORBIDDEN, "Source not allowed").into_response();
+    }
+
+    // PayPal signs the exact bytes it sent, so this runs on `body` before
+    // anything below parses or re-encodes the payload
+    if state.config.dev_skip_signature {
+        println!(
+            "[PAYPAL] 🚧 DEV_SKIP_SIGNATURE: accepting payload WITHOUT signature verification"
+        );
+    } else if state.config.verify_signature {
+        if let Err(e) = verify_webhook_signature(&state, &headers, &body).await {
+            metrics::counter!("webhook_signature_failures_total").increment(1);
+            if e.is_transient() {
+                println!(
+                    "[PAYPAL] ❌ Signature check unavailable ({}), asking PayPal to retry",
+                    e
+                );
+                return (StatusCode::SERVICE_UNAVAILABLE, "Verification unavailable")
+                    .into_response();
+            }
+            println!("[PAYPAL] ❌ Signature verification failed: {}", e);
+            return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+        }
+    }
+
+    let mut event: PayPalEvent = match parse_bounded(&body, state.config.max_json_depth) {
+        Ok(e) => e,
+        Err(e) => {
+            println!("[PAYPAL] ❌ Failed to parse event: {}", e);
+            return (StatusCode::BAD_REQUEST, "Invalid event").into_response();
+        }
+    };
+    event.livemode = state.config.is_live();
     println!("[PAYPAL] 📬 Received: {} ({})", event.event_type, event.id);
+    metrics::counter!(
+        "webhooks_received_total",
+        "provider" => "paypal",
+        "type" => event.event_type.clone()
+    )
+    .increment(1);
+
+    // Idempotency check - answer redeliveries with the original outcome
+    if let Some(prior) = state.processed_events.get(&event.id).await {
+        println!(
+            "[PAYPAL] ⚡ Event {} already processed at {} ({:?})",
+            event.id, prior.processed_at, prior.result
+        );
+        let body = match prior.result {
+            EventResult::Failed { .. } => "Already processed with error",
+            _ => "Already processed",
+        };
+        return (StatusCode::OK, body).into_response();
+    }
+
+    // Parsed above, so the bytes are valid UTF-8; only verified payloads are
+    // archived verbatim for replays
+    if !state.config.dev_skip_signature {
+        state
+            .archive
+            .store(&event.id, &String::from_utf8_lossy(&body))
+            .await;
+    }
+    let result = route_event(&state, &event).await;
+    if let Err(e) = &result {
+        if audit::is_failure(e) {
+            // Unmarked, so PayPal's redelivery is processed again
+            println!("[PAYPAL] ❌ {}, asking PayPal to retry", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Audit unavailable").into_response();
+        }
+    }
+
+    let event_result = match &result {
+        Ok(_) => EventResult::Processed,
+        Err(e) => EventResult::Failed { error: e.clone() },
+    };
+    state.processed_events.record(&event, event_result).await;
+
+    match result {
+        Ok(_) => (StatusCode::OK, "Received").into_response(),
+        Err(e) => {
+            println!("[PAYPAL] ❌ Processing error: {}", e);
+            (StatusCode::OK, "Processed with error").into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReplayRequest {
+    pub event_id: String,
+}
+
+/// POST /paypal/admin/replay - Re-run routing for an archived event
+pub async fn replay_event(
+    State(state): State<Arc<PayPalState>>,
+    headers: HeaderMap,
+    Json(request): Json<ReplayRequest>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let Some(raw) = state.archive.load(&request.event_id).await else {
+        return (StatusCode::NOT_FOUND, "Event not archived").into_response();
+    };
+    let mut event: PayPalEvent = match serde_json::from_str(&raw) {
+        Ok(e) => e,
+        Err(e) => {
+            println!(
+                "[PAYPAL] ❌ Archived event {} unreadable: {}",
+                request.event_id, e
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Archived event unreadable",
+            )
+                .into_response();
+        }
+    };
+
+    event.livemode = state.config.is_live();
+    println!("[PAYPAL] 🔁 Replaying {} ({})", event.event_type, event.id);
+    state.processed_events.forget(&event.id).await;
+    let result = route_event(&state, &event).await;
+
+    let event_result = match &result {
+        Ok(_) => EventResult::Processed,
+        Err(e) => EventResult::Failed { error: e.clone() },
+    };
+    let marker_error = state
+        .processed_events
+        .mark_processed(event.id.clone(), event_result, None)
+        .await
+        .err()
+        .map(|e| e.to_string());
 
-    // TODO: Implement signature verification using PayPal's 'verify-webhook-signature' API
-    // This is critical for production but omitted for brevity in this initial deployment.
-    // Ideally, we post the headers and body back to PayPal to verify.
+    Json(serde_json::json!({
+        "event_id": event.id,
+        "event_type": event.event_type,
+        "replayed": true,
+        "error": result.err(),
+        "marker_error": marker_error,
+    }))
+    .into_response()
+}
 
+/// Dispatch a PayPal event to its handler
+async fn route_event(state: &PayPalState, event: &PayPalEvent) -> Result<(), String> {
     match event.event_type.as_str() {
         "PAYMENT.CAPTURE.COMPLETED" => {
+            let amount = Money::from_paypal_amount(&event.resource["amount"])?;
             println!(
-                "[PAYPAL] 💰 Payment Captured: {:?}",
-                event.resource["amount"]
+                "[PAYPAL] 💰 Payment Captured: {} ({} minor units)",
+                amount.localized(),
+                amount.minor
             );
+            log_paypal_event(&state.audit, event, "capture.completed", &amount).await?;
             // Trigger logic: update DB, grant access, etc.
+            Ok(())
+        }
+        "PAYMENT.AUTHORIZATION.CREATED" => {
+            println!(
+                "[PAYPAL] 🔒 Payment Authorized: {:?} ({:?})",
+                event.resource["id"], event.resource["amount"]
+            );
+            Ok(())
+        }
+        "PAYMENT.AUTHORIZATION.VOIDED" => {
+            println!(
+                "[PAYPAL] 🚫 Authorization Voided: {:?}",
+                event.resource["id"]
+            );
+            Ok(())
         }
         "BILLING.SUBSCRIPTION.CREATED" => {
             println!(
                 "[PAYPAL] 📋 Subscription Created: {:?}",
                 event.resource["id"]
             );
+            Ok(())
         }
+        "BILLING.SUBSCRIPTION.ACTIVATED" => handle_subscription_activated(state, event).await,
+        "BILLING.SUBSCRIPTION.UPDATED" => handle_subscription_updated(state, event).await,
         "BILLING.SUBSCRIPTION.CANCELLED" => {
             println!(
                 "[PAYPAL] ❌ Subscription Cancelled: {:?}",
                 event.resource["id"]
             );
+            Ok(())
         }
         _ => {
             println!("[PAYPAL] ℹ️ Unhandled: {}", event.event_type);
+            Ok(())
         }
     }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// EVENT HANDLERS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// O(1) - Map a PayPal subscription status onto ours.
+/// APPROVAL_PENDING / APPROVED carry no billing state yet.
+fn map_subscription_status(status: &str) -> Option<SubscriptionStatus> {
+    match status {
+        "ACTIVE" => Some(SubscriptionStatus::Active),
+        "SUSPENDED" => Some(SubscriptionStatus::PastDue),
+        "CANCELLED" | "EXPIRED" => Some(SubscriptionStatus::Canceled),
+        _ => None,
+    }
+}
+
+/// First payment cleared on a PayPal subscription
+async fn handle_subscription_activated(
+    state: &PayPalState,
+    event: &PayPalEvent,
+) -> Result<(), String> {
+    let resource = &event.resource;
+    let email = resource["subscriber"]["email_address"]
+        .as_str()
+        .ok_or("Subscription has no subscriber email")?;
+    let paypal_plan_id = resource["plan_id"].as_str().unwrap_or_default();
+    let plan = state
+        .config
+        .plan_map
+        .get(paypal_plan_id)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_PLAN_KEY);
+
+    println!(
+        "[PAYPAL] ✅ Subscription Activated: {:?} ({}) for {}",
+        resource["id"], plan, email
+    );
+
+    let amount = &resource["billing_info"]["last_payment"]["amount"];
+    let amount = if amount.is_null() {
+        None
+    } else {
+        Some(Money::from_paypal_amount(amount)?)
+    };
+    let mut external_ids = serde_json::Map::new();
+    external_ids.insert("subscription".to_string(), resource["id"].clone());
+    external_ids.insert("plan".to_string(), paypal_plan_id.into());
+
+    // Later BILLING.SUBSCRIPTION.UPDATED events find the record by email
+    state
+        .subscriptions
+        .activate_subscription(email, None, None, plan)
+        .await;
+
+    provider_event::record_activation(
+        &state.audit,
+        event,
+        Activation {
+            email,
+            plan,
+            amount,
+            external_ids,
+            queued: false,
+        },
+    )
+    .await
+}
+
+/// Reflect plan/status changes made on the PayPal side in the shared store
+async fn handle_subscription_updated(
+    state: &PayPalState,
+    event: &PayPalEvent,
+) -> Result<(), String> {
+    let resource = &event.resource;
+    let email = resource["subscriber"]["email_address"]
+        .as_str()
+        .ok_or("Subscription has no subscriber email")?;
+    let paypal_plan_id = resource["plan_id"].as_str().unwrap_or_default();
+    let status = resource["status"].as_str().unwrap_or_default();
+
+    println!(
+        "[PAYPAL] 🔄 Subscription Updated: {:?} ({} / {}) for {}",
+        resource["id"], paypal_plan_id, status, email
+    );
+
+    let mut found = false;
+    if let Some(plan_key) = state.config.plan_map.get(paypal_plan_id) {
+        found |= state
+            .subscriptions
+            .change_plan(email, SubscriptionPlan::from_key(plan_key))
+            .await;
+    }
+    if let Some(status) = map_subscription_status(status) {
+        found |= state.subscriptions.update_status(email, status).await;
+    }
 
-    (StatusCode::OK, "Received").into_response()
+    if !found {
+        return Err(format!("No known subscription to update for {}", email));
+    }
+
+    Ok(())
 }
 
+/// Catalog plan sold through PayPal Checkout
+const PAYPAL_PLAN_KEY: &str = "architect";
+
+/// Currencies PayPal Checkout accepts
+const PAYPAL_CURRENCIES: [&str; 24] = [
+    "AUD", "BRL", "CAD", "CHF", "CNY", "CZK", "DKK", "EUR", "GBP", "HKD", "HUF", "ILS", "JPY",
+    "MXN", "MYR", "NOK", "NZD", "PHP", "PLN", "SEK", "SGD", "THB", "TWD", "USD",
+];
+
 /// O(log n) - Start PayPal Checkout (Create Order)
-pub async fn start_checkout(State(state): State<Arc<PayPalState>>) -> Redirect {
-    let domain = std::env::var("DOMAIN").unwrap_or_else(|_| "https://veritras.website".to_string());
+pub async fn start_checkout(
+    State(state): State<Arc<PayPalState>>,
+    headers: HeaderMap,
+    Query(params): Query<CheckoutParams>,
+) -> Response {
+    let domain = state.domains.for_request(&headers);
+
+    let intent = match OrderIntent::parse(params.intent.as_deref()) {
+        Ok(i) => i,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let Some(offer) = state.catalog.get(PAYPAL_PLAN_KEY) else {
+        println!(
+            "[PAYPAL] ❌ Plan '{}' missing from the catalog",
+            PAYPAL_PLAN_KEY
+        );
+        return Redirect::to("/error").into_response();
+    };
+    let price = offer.price();
+    if !PAYPAL_CURRENCIES.contains(&price.currency.as_str()) {
+        println!(
+            "[PAYPAL] ❌ Plan '{}' priced in unsupported {}",
+            offer.key, price.currency
+        );
+        return (StatusCode::BAD_REQUEST, "Currency not supported by PayPal").into_response();
+    }
+    if let Err(e) = price.check_minimum() {
+        println!("[PAYPAL] ❌ Order not chargeable: {}", e);
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
 
     // 1. Get Access Token
     let token = match state.get_access_token().await {
         Ok(t) => t,
         Err(e) => {
             println!("[PAYPAL] ❌ Auth Failed: {}", e);
-            return Redirect::to("/error");
+            return Redirect::to("/error").into_response();
         }
     };
 
+    // Authorized orders come back to the frontend; capturing them is an admin
+    // call to /paypal/authorize-capture
+    let return_url = match intent {
+        OrderIntent::Capture => format!("{}/paypal/success", domain),
+        OrderIntent::Authorize => format!("{}/paypal/success?intent=authorize", domain),
+    };
+
     // 2. Create Order
+    let custom_id = sanitize_value(
+        &format!("veritas_{}_{}", offer.key, Uuid::new_v4().simple()),
+        PAYPAL_CUSTOM_ID_MAX,
+        "custom_id",
+    );
+    let description = sanitize_value(&offer.display_name, PAYPAL_DESCRIPTION_MAX, "description");
     let order_payload = serde_json::json!({
-        "intent": "CAPTURE",
+        "intent": intent.as_str(),
         "purchase_units": [{
-            "amount": {
-                "currency_code": "USD",
-                "value": "199.00"
-            },
-            "description": "Veritas Architect Access"
+            "amount": price.to_paypal_amount(),
+            "description": description,
+            "custom_id": custom_id
         }],
         "application_context": {
-            "return_url": format!("{}/paypal/success", domain),
+            "return_url": return_url,
             "cancel_url": format!("{}/paypal/cancel", domain),
             "brand_name": "QANTUM NEXUS",
             "user_action": "PAY_NOW"
         }
     });
 
-    let client = &state.http_client;
-    let res = client
+    let request = state
+        .http
+        .client()
         .post(format!("{}/v2/checkout/orders", state.config.base_url()))
         .header("Authorization", format!("Bearer {}", token))
-        .json(&order_payload)
-        .send()
-        .await;
+        .json(&order_payload);
+    let res = state.http.send("paypal_create_order", request).await;
 
     // 3. Extract Approve Link
     match res {
@@ -226,7 +820,13 @@ pub async fn start_checkout(State(state): State<Arc<PayPalState>>) -> Redirect {
                         if link["rel"] == "approve" {
                             if let Some(href) = link["href"].as_str() {
                                 println!("[PAYPAL] 🔗 Redirecting to: {}", href);
-                                return Redirect::to(href);
+                                metrics::counter!(
+                                    "checkout_sessions_created_total",
+                                    "provider" => "paypal",
+                                    "plan" => offer.key.clone()
+                                )
+                                .increment(1);
+                                return Redirect::to(href).into_response();
                             }
                         }
                     }
@@ -238,5 +838,1222 @@ pub async fn start_checkout(State(state): State<Arc<PayPalState>>) -> Redirect {
     }
 
     println!("[PAYPAL] ⚠️ Fallback to placeholder");
-    Redirect::to("https://www.sandbox.paypal.com/checkoutnow?token=placeholder")
+    Redirect::to("https://www.sandbox.paypal.com/checkoutnow?token=placeholder").into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// AUTHORIZE / CAPTURE
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeCaptureRequest {
+    pub order_id: String,
+    /// Partial capture in the order's currency ("50.00"); full amount when absent.
+    /// Admin-only: a partial capture still completes the order for `/paypal/verify`
+    #[serde(default)]
+    pub amount: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthorizeCaptureResponse {
+    pub order_id: String,
+    pub authorization_id: String,
+    pub capture_id: Option<String>,
+    pub status: String,
+    /// The order had been captured before this request; nothing new was captured
+    pub already_captured: bool,
+}
+
+/// O(1) - POST to a PayPal endpoint with an empty JSON body
+async fn post_paypal(
+    state: &PayPalState,
+    operation: &'static str,
+    path: &str,
+    payload: serde_json::Value,
+) -> Result<serde_json::Value, UpstreamError> {
+    let token = state.get_access_token().await?;
+    let request = state
+        .http
+        .client()
+        .post(format!("{}{}", state.config.base_url(), path))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&payload);
+    send_paypal(state, operation, request).await
+}
+
+/// O(1) - GETs are idempotent, so transient failures are retried with backoff
+async fn get_paypal(
+    state: &PayPalState,
+    operation: &'static str,
+    path: &str,
+) -> Result<serde_json::Value, UpstreamError> {
+    retry_async(&state.http.retry, UpstreamError::is_transient, || async {
+        let token = state.get_access_token().await?;
+        let request = state
+            .http
+            .client()
+            .get(format!("{}{}", state.config.base_url(), path))
+            .header("Authorization", format!("Bearer {}", token));
+        send_paypal(state, operation, request).await
+    })
+    .await
+}
+
+async fn send_paypal(
+    state: &PayPalState,
+    operation: &'static str,
+    request: reqwest::RequestBuilder,
+) -> Result<serde_json::Value, UpstreamError> {
+    let res = state.http.send(operation, request).await?;
+
+    let status = res.status();
+    let body: serde_json::Value = res.json().await.map_err(|e| format!("JSON error: {}", e))?;
+    if !status.is_success() {
+        return Err(UpstreamError::status("PayPal", status, body));
+    }
+    Ok(body)
+}
+
+/// O(1) - Authorize an approved AUTHORIZE-intent order, returning the authorization id
+async fn authorize_order(state: &PayPalState, order_id: &str) -> Result<String, String> {
+    let body = post_paypal(
+        state,
+        "paypal_authorize",
+        &format!("/v2/checkout/orders/{}/authorize", order_id),
+        serde_json::json!({}),
+    )
+    .await?;
+    body["purchase_units"][0]["payments"]["authorizations"][0]["id"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| "Authorization id missing from PayPal response".to_string())
+}
+
+/// O(1) - Current order state (status, amount, existing authorizations/captures)
+async fn fetch_order(state: &PayPalState, order_id: &str) -> Result<serde_json::Value, String> {
+    get_paypal(
+        state,
+        "paypal_get_order",
+        &format!("/v2/checkout/orders/{}", order_id),
+    )
+    .await
+    .map_err(String::from)
+}
+
+/// O(1) - Id of the first payment of `kind` (`authorizations` / `captures`) on the order
+fn order_payment_id<'a>(order: &'a serde_json::Value, kind: &str) -> Option<&'a str> {
+    order["purchase_units"][0]["payments"][kind][0]["id"].as_str()
+}
+
+/// O(1) - Result of an earlier capture, so a repeat request succeeds instead of
+/// surfacing PayPal's ORDER_ALREADY_CAPTURED as a failure
+fn existing_capture(order_id: &str, order: &serde_json::Value) -> Option<AuthorizeCaptureResponse> {
+    if order["status"].as_str() != Some("COMPLETED") {
+        return None;
+    }
+    let capture = &order["purchase_units"][0]["payments"]["captures"][0];
+    let capture_id = capture["id"].as_str()?;
+    Some(AuthorizeCaptureResponse {
+        order_id: order_id.to_string(),
+        authorization_id: order_payment_id(order, "authorizations")
+            .unwrap_or_default()
+            .to_string(),
+        capture_id: Some(capture_id.to_string()),
+        status: capture["status"]
+            .as_str()
+            .unwrap_or("COMPLETED")
+            .to_string(),
+        already_captured: true,
+    })
+}
+
+/// O(n) - Authorized amount not yet taken by a completed or pending capture
+fn remaining_amount(order: &serde_json::Value) -> Result<Money, String> {
+    let unit = &order["purchase_units"][0];
+    let authorized = Money::from_paypal_amount(&unit["amount"])?;
+    let mut remaining = authorized.minor;
+    for capture in unit["payments"]["captures"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if !matches!(capture["status"].as_str(), Some("COMPLETED" | "PENDING")) {
+            continue;
+        }
+        let captured = Money::from_paypal_amount(&capture["amount"])?;
+        if captured.currency != authorized.currency {
+            return Err(format!(
+                "Capture in {} on a {} order",
+                captured.currency, authorized.currency
+            ));
+        }
+        remaining -= captured.minor;
+    }
+    Ok(Money {
+        minor: remaining.max(0),
+        currency: authorized.currency,
+    })
+}
+
+/// O(1) - Partial capture must be positive and within what is left of the
+/// authorization. Only the final capture of the remainder may fall below the
+/// minimum charge, or a small leftover could never be captured.
+fn validate_partial_capture(requested: &str, remaining: &Money) -> Result<Money, String> {
+    let amount = Money::from_paypal_decimal(requested, &remaining.currency)?;
+    if amount.minor <= 0 {
+        return Err(format!("Capture amount {} must be positive", amount));
+    }
+    if amount.minor > remaining.minor {
+        return Err(format!(
+            "Capture amount {} exceeds the remaining authorized {}",
+            amount, remaining
+        ));
+    }
+    if amount.minor < remaining.minor {
+        amount.check_minimum()?;
+    }
+    Ok(amount)
+}
+
+/// O(1) - Capture a previously created authorization, in full or for `amount`
+async fn capture_order(
+    state: &PayPalState,
+    authorization_id: &str,
+    amount: Option<&Money>,
+) -> Result<serde_json::Value, String> {
+    let payload = match amount {
+        Some(amount) => serde_json::json!({ "amount": amount.to_paypal_amount() }),
+        None => serde_json::json!({}),
+    };
+    post_paypal(
+        state,
+        "paypal_capture",
+        &format!("/v2/payments/authorizations/{}/capture", authorization_id),
+        payload,
+    )
+    .await
+    .map_err(String::from)
+}
+
+/// Admin: authorize an approved order and capture the funds in one step
+pub async fn authorize_capture(
+    State(state): State<Arc<PayPalState>>,
+    headers: HeaderMap,
+    Json(req): Json<AuthorizeCaptureRequest>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let order = match fetch_order(&state, &req.order_id).await {
+        Ok(o) => o,
+        Err(e) => {
+            println!(
+                "[PAYPAL] ❌ Order lookup failed for {}: {}",
+                req.order_id, e
+            );
+            return (StatusCode::BAD_GATEWAY, e).into_response();
+        }
+    };
+
+    // A partial capture may follow earlier ones until the authorization is used up
+    let partial = match &req.amount {
+        Some(requested) => {
+            let remaining = match remaining_amount(&order) {
+                Ok(r) => r,
+                Err(e) => return (StatusCode::BAD_GATEWAY, e).into_response(),
+            };
+            if remaining.minor == 0 {
+                None
+            } else {
+                match validate_partial_capture(requested, &remaining) {
+                    Ok(amount) => Some(amount),
+                    Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+                }
+            }
+        }
+        None => None,
+    };
+
+    if partial.is_none() {
+        if let Some(existing) = existing_capture(&req.order_id, &order) {
+            println!(
+                "[PAYPAL] ⚡ Order {} already captured ({:?})",
+                req.order_id, existing.capture_id
+            );
+            return Json(existing).into_response();
+        }
+    }
+
+    // An earlier attempt may have authorized without capturing; reuse it
+    let authorization_id = match order_payment_id(&order, "authorizations") {
+        Some(id) => id.to_string(),
+        None => match authorize_order(&state, &req.order_id).await {
+            Ok(id) => id,
+            Err(e) => {
+                println!("[PAYPAL] ❌ Authorize failed for {}: {}", req.order_id, e);
+                return (StatusCode::BAD_GATEWAY, e).into_response();
+            }
+        },
+    };
+    println!(
+        "[PAYPAL] 🔒 Order {} authorized ({})",
+        req.order_id, authorization_id
+    );
+
+    let capture = match capture_order(&state, &authorization_id, partial.as_ref()).await {
+        Ok(c) => c,
+        Err(e) => {
+            println!("[PAYPAL] ❌ Capture failed for {}: {}", authorization_id, e);
+            return (StatusCode::BAD_GATEWAY, e).into_response();
+        }
+    };
+
+    let response = AuthorizeCaptureResponse {
+        order_id: req.order_id,
+        authorization_id,
+        capture_id: capture["id"].as_str().map(|s| s.to_string()),
+        status: capture["status"].as_str().unwrap_or("UNKNOWN").to_string(),
+        already_captured: false,
+    };
+    println!(
+        "[PAYPAL] 💰 Captured {:?} ({})",
+        response.capture_id, response.status
+    );
+
+    Json(response).into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ORDER VERIFICATION
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyOrderQuery {
+    pub order_id: String,
+    /// Previously issued key to re-validate, as on `/stripe/verify`
+    pub license_key: Option<String>,
+}
+
+/// O(1) - Plan encoded in the order's `custom_id` (`veritas_{plan}_{uuid}`)
+fn order_plan(order: &serde_json::Value) -> Option<&str> {
+    order["purchase_units"][0]["custom_id"]
+        .as_str()?
+        .strip_prefix("veritas_")?
+        .rsplit_once('_')
+        .map(|(plan, _)| plan)
+}
+
+/// O(1) - Payment taken: the order is COMPLETED and, when it carries payments,
+/// one capture completed. APPROVED orders and AUTHORIZE orders that were only
+/// authorized (also COMPLETED) have not been paid yet.
+fn order_is_paid(order: &serde_json::Value) -> bool {
+    if order["status"].as_str() != Some("COMPLETED") {
+        return false;
+    }
+    match order["purchase_units"][0]["payments"]["captures"].as_array() {
+        Some(captures) => captures
+            .iter()
+            .any(|c| c["status"].as_str() == Some("COMPLETED")),
+        None => order_payment_id(order, "authorizations").is_none(),
+    }
+}
+
+/// GET /paypal/verify?order_id= - Confirm a paid order and hand out its license key
+pub async fn verify_order(
+    State(state): State<Arc<PayPalState>>,
+    Query(query): Query<VerifyOrderQuery>,
+) -> Response {
+    let order_id = query.order_id.trim();
+    if order_id.is_empty() || !order_id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return (StatusCode::BAD_REQUEST, "Invalid order id").into_response();
+    }
+
+    let order = match fetch_order(&state, order_id).await {
+        Ok(o) => o,
+        Err(e) => {
+            println!("[VERIFY] ❌ PayPal lookup failed for {}: {}", order_id, e);
+            return (StatusCode::BAD_GATEWAY, "Order lookup failed").into_response();
+        }
+    };
+
+    let plan = order_plan(&order).map(|p| p.to_string());
+    let email = order["payer"]["email_address"]
+        .as_str()
+        .map(|e| e.to_string());
+    let mut response = VerifyResponse {
+        valid: order_is_paid(&order),
+        plan,
+        email,
+        license_key: None,
+    };
+    if !response.valid {
+        println!(
+            "[VERIFY] ⚠️ PayPal order {} is {:?}",
+            order_id, order["status"]
+        );
+        return Json(response).into_response();
+    }
+
+    let issued = state.license.issue_checked(
+        LicenseProvider::Paypal,
+        order_id,
+        query.license_key.as_deref(),
+    );
+    match issued {
+        Ok((key, presented_ok)) => {
+            let registered = match response.email.as_deref() {
+                Some(email) => {
+                    state
+                        .licenses
+                        .register(
+                            &key,
+                            LicenseProvider::Paypal,
+                            order_id,
+                            email,
+                            response.plan.as_deref().unwrap_or_default(),
+                        )
+                        .await
+                }
+                None => false,
+            };
+            if !registered {
+                println!(
+                    "[VERIFY] ❌ PayPal order {} is paid but has no email",
+                    order_id
+                );
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(serde_json::json!({ "valid": false, "error": "Purchase has no email" })),
+                )
+                    .into_response();
+            }
+            response.valid = presented_ok;
+            response.license_key = Some(key);
+        }
+        Err(e) => {
+            println!("[VERIFY] ❌ License refused for {}: {}", order_id, e);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "valid": false, "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    }
+
+    println!(
+        "[VERIFY] ✅ PayPal order {} verified for {:?}",
+        order_id, response.email
+    );
+    Json(response).into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// AUDIT LOG
+// ═══════════════════════════════════════════════════════════════════════════════
+
+async fn log_paypal_event(
+    audit: &AuditTrail,
+    event: &PayPalEvent,
+    event_type: &str,
+    amount: &Money,
+) -> Result<(), String> {
+    let mut log_entry = provider_event::audit_entry(event, event_type);
+    log_entry["amount_minor"] = amount.minor.into();
+    log_entry["currency"] = amount.currency.clone().into();
+    log_entry["amount_display"] = amount.localized().into();
+
+    audit.record("AUDIT:PAYPAL", log_entry).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stripe_handler::StripeWebhookState;
+    use crate::test_support::{MockRequest, MockResponse, MockServer, RecordingAuditLog};
+
+    /// Key order and spacing a serde round trip would not preserve
+    const RAW_EVENT: &str = r#"{"resource_type":"sale",  "id":"WH-RAW-1","event_type":"TEST.EVENT.UNROUTED","create_time":"2024-01-01T00:00:00Z","resource":{"b":1,"a":2.50}}"#;
+
+    fn transmission_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (field, header) in TRANSMISSION_HEADERS {
+            headers.insert(header, format!("{}-value", field).parse().unwrap());
+        }
+        headers
+    }
+
+    fn paypal_state(webhook_id: Option<&str>, dev_skip_signature: bool) -> PayPalState {
+        let stripe = StripeWebhookState::new();
+        let mut state = PayPalState::new(
+            stripe.subscriptions.clone(),
+            stripe.domains.clone(),
+            stripe.license.clone(),
+            stripe.licenses.clone(),
+            stripe.audit.clone(),
+            stripe.catalog.clone(),
+        );
+        state.admin = AdminAuth::new(Some("test-admin-token".to_string()));
+        state.config.webhook_id = webhook_id.map(str::to_string);
+        state.config.verify_signature = webhook_id.is_some();
+        state.config.dev_skip_signature = dev_skip_signature;
+        state
+    }
+
+    fn paypal_event(id: &str, event_type: &str, resource: serde_json::Value) -> PayPalEvent {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "event_type": event_type,
+            "create_time": "2024-01-01T00:00:00Z",
+            "resource_type": "subscription",
+            "resource": resource,
+        }))
+        .unwrap()
+    }
+
+    async fn post_webhook(state: &Arc<PayPalState>, headers: HeaderMap, body: String) -> Response {
+        let client = ClientIp("127.0.0.1".parse().unwrap());
+        paypal_webhook_handler(
+            State(state.clone()),
+            Extension(client),
+            headers,
+            Bytes::from(body),
+        )
+        .await
+        .into_response()
+    }
+
+    async fn deliver(state: &Arc<PayPalState>, headers: HeaderMap) -> StatusCode {
+        post_webhook(state, headers, RAW_EVENT.to_string())
+            .await
+            .status()
+    }
+
+    /// Unsigned delivery of `event` (state built with the bypass): status and body
+    async fn deliver_event(state: &Arc<PayPalState>, event: &PayPalEvent) -> (StatusCode, String) {
+        let body = serde_json::to_string(event).unwrap();
+        let response = post_webhook(state, HeaderMap::new(), body).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[test]
+    fn signature_request_carries_the_raw_bytes() {
+        let request =
+            signature_request(&transmission_headers(), "WH-ID", RAW_EVENT.as_bytes()).unwrap();
+        assert!(request.ends_with(&format!("\"webhook_event\":{}}}", RAW_EVENT)));
+
+        let parsed: serde_json::Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(parsed["webhook_id"], "WH-ID");
+        assert_eq!(parsed["transmission_sig"], "transmission_sig-value");
+        assert_eq!(parsed["webhook_event"]["id"], "WH-RAW-1");
+        // A re-encoded event would have reordered the keys
+        assert_ne!(parsed["webhook_event"].to_string(), RAW_EVENT);
+    }
+
+    #[test]
+    fn signature_request_needs_every_transmission_header() {
+        for (_, header) in TRANSMISSION_HEADERS {
+            let mut headers = transmission_headers();
+            headers.remove(header);
+            let err = signature_request(&headers, "WH-ID", RAW_EVENT.as_bytes()).unwrap_err();
+            assert!(err.contains(header), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn unsigned_delivery_is_refused_and_not_archived() {
+        let state = Arc::new(paypal_state(Some("WH-ID"), false));
+        assert_eq!(
+            deliver(&state, HeaderMap::new()).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(state.archive.load("WH-RAW-1").await.is_none());
+        assert!(state.processed_events.get("WH-RAW-1").await.is_none());
+
+        // No webhook id to verify against: refused even with headers
+        let mut state = paypal_state(None, false);
+        state.config.verify_signature = true;
+        assert_eq!(
+            deliver(&Arc::new(state), transmission_headers()).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn signatures_go_unchecked_until_a_webhook_id_is_configured() {
+        let state = Arc::new(paypal_state(None, false));
+        assert!(!state.config.verify_signature);
+        assert_eq!(deliver(&state, HeaderMap::new()).await, StatusCode::OK);
+        assert!(state.archive.load("WH-RAW-1").await.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "PAYPAL_VERIFY_WEBHOOK_SIGNATURE needs PAYPAL_WEBHOOK_ID")]
+    fn enforcing_signatures_without_a_webhook_id_stops_the_boot() {
+        let mut config = paypal_state(None, false).config;
+        config.verify_signature = true;
+        config.validated();
+    }
+
+    #[tokio::test]
+    async fn skipped_verification_is_not_archived_for_replay() {
+        let state = Arc::new(paypal_state(None, true));
+        assert_eq!(deliver(&state, HeaderMap::new()).await, StatusCode::OK);
+        assert!(state.processed_events.get("WH-RAW-1").await.is_some());
+        assert!(state.archive.load("WH-RAW-1").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn subscription_updated_moves_plan_and_status() {
+        let mut state = paypal_state(None, false);
+        state
+            .config
+            .plan_map
+            .insert("P-PREMIUM".to_string(), "enterprise_monthly".to_string());
+        state
+            .subscriptions
+            .activate_subscription("pp@x.com", None, None, "basic")
+            .await;
+
+        let updated = paypal_event(
+            "WH-UPDATED-1",
+            "BILLING.SUBSCRIPTION.UPDATED",
+            serde_json::json!({
+                "id": "I-SUB1",
+                "plan_id": "P-PREMIUM",
+                "status": "SUSPENDED",
+                "subscriber": { "email_address": "pp@x.com" },
+            }),
+        );
+        route_event(&state, &updated).await.unwrap();
+
+        let sub = state.subscriptions.get("pp@x.com").await.unwrap();
+        assert_eq!(sub.plan, SubscriptionPlan::from_key("enterprise_monthly"));
+        assert_eq!(sub.status, SubscriptionStatus::PastDue);
+
+        let unknown = paypal_event(
+            "WH-UPDATED-2",
+            "BILLING.SUBSCRIPTION.UPDATED",
+            serde_json::json!({
+                "id": "I-SUB2",
+                "plan_id": "P-PREMIUM",
+                "status": "ACTIVE",
+                "subscriber": { "email_address": "nobody@x.com" },
+            }),
+        );
+        assert!(route_event(&state, &unknown).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn duplicates_answer_with_the_original_outcome() {
+        let state = Arc::new(paypal_state(None, true));
+        let succeeded = paypal_event("WH-DUP-OK", "TEST.EVENT.UNROUTED", serde_json::json!({}));
+        assert_eq!(
+            deliver_event(&state, &succeeded).await,
+            (StatusCode::OK, "Received".to_string())
+        );
+        assert_eq!(
+            deliver_event(&state, &succeeded).await,
+            (StatusCode::OK, "Already processed".to_string())
+        );
+
+        // No subscription to update: processed, with an error
+        let failed = paypal_event(
+            "WH-DUP-ERR",
+            "BILLING.SUBSCRIPTION.UPDATED",
+            serde_json::json!({
+                "id": "I-NONE",
+                "status": "ACTIVE",
+                "subscriber": { "email_address": "nobody@x.com" },
+            }),
+        );
+        assert_eq!(
+            deliver_event(&state, &failed).await,
+            (StatusCode::OK, "Processed with error".to_string())
+        );
+        assert_eq!(
+            deliver_event(&state, &failed).await,
+            (StatusCode::OK, "Already processed with error".to_string())
+        );
+        assert!(matches!(
+            state
+                .processed_events
+                .get("WH-DUP-ERR")
+                .await
+                .unwrap()
+                .result,
+            EventResult::Failed { .. }
+        ));
+    }
+
+    /// PayPal sandbox stand-in for one AUTHORIZE-intent order, `ORDER1`
+    fn paypal_api(request: &MockRequest) -> MockResponse {
+        let body = match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/v1/oauth2/token") => {
+                serde_json::json!({ "access_token": "tok", "expires_in": 3600 })
+            }
+            ("POST", "/v2/checkout/orders") => serde_json::json!({
+                "id": "ORDER1",
+                "links": [{ "rel": "approve", "href": "https://paypal.test/approve/ORDER1" }],
+            }),
+            ("GET", "/v2/checkout/orders/ORDER1") => serde_json::json!({
+                "id": "ORDER1",
+                "status": "APPROVED",
+                "purchase_units": [{ "amount": { "currency_code": "USD", "value": "199.00" } }],
+            }),
+            ("POST", "/v2/checkout/orders/ORDER1/authorize") => serde_json::json!({
+                "purchase_units": [{ "payments": { "authorizations": [{ "id": "AUTH1" }] } }],
+            }),
+            ("POST", "/v2/payments/authorizations/AUTH1/capture") => {
+                serde_json::json!({ "id": "CAP1", "status": "COMPLETED" })
+            }
+            ("POST", "/v1/notifications/verify-webhook-signature") => {
+                serde_json::json!({ "verification_status": "SUCCESS" })
+            }
+            _ => return MockResponse::json(404, serde_json::json!({ "name": "NOT_FOUND" })),
+        };
+        MockResponse::json(200, body)
+    }
+
+    async fn against(api: &MockServer) -> Arc<PayPalState> {
+        let mut state = paypal_state(None, false);
+        state.config.api_base = api.url.clone();
+        Arc::new(state)
+    }
+
+    #[test]
+    fn intent_param_parses_case_insensitively() {
+        assert_eq!(OrderIntent::parse(None), Ok(OrderIntent::Capture));
+        assert_eq!(OrderIntent::parse(Some("")), Ok(OrderIntent::Capture));
+        assert_eq!(
+            OrderIntent::parse(Some(" authorize ")),
+            Ok(OrderIntent::Authorize)
+        );
+        assert!(OrderIntent::parse(Some("SALE")).is_err());
+    }
+
+    #[test]
+    fn created_order_is_counted_under_its_plan() {
+        let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let api = MockServer::start(paypal_api).await;
+                let state = against(&api).await;
+                let params = CheckoutParams { intent: None };
+                start_checkout(State(state), HeaderMap::new(), Query(params)).await;
+            })
+        });
+
+        let rendered = handle.render();
+        let created: Vec<_> = rendered
+            .lines()
+            .filter(|line| line.starts_with("checkout_sessions_created_total"))
+            .collect();
+        assert_eq!(created.len(), 1, "{}", rendered);
+        assert!(created[0].contains("provider=\"paypal\""));
+        assert!(created[0].contains(&format!("plan=\"{}\"", PAYPAL_PLAN_KEY)));
+        assert!(created[0].ends_with(" 1"));
+    }
+
+    #[tokio::test]
+    async fn authorize_intent_creates_an_authorize_order() {
+        let api = MockServer::start(paypal_api).await;
+        let state = against(&api).await;
+
+        let params = CheckoutParams {
+            intent: Some("AUTHORIZE".to_string()),
+        };
+        let response = start_checkout(State(state.clone()), HeaderMap::new(), Query(params)).await;
+        assert_eq!(
+            response.headers()["location"],
+            "https://paypal.test/approve/ORDER1"
+        );
+
+        let order = api
+            .requests()
+            .into_iter()
+            .find(|r| r.path == "/v2/checkout/orders")
+            .unwrap();
+        assert_eq!(order.headers["authorization"], "Bearer tok");
+        let order = order.json();
+        assert_eq!(order["intent"], "AUTHORIZE");
+        assert!(order["application_context"]["return_url"]
+            .as_str()
+            .unwrap()
+            .ends_with("intent=authorize"));
+
+        let params = CheckoutParams {
+            intent: Some("SALE".to_string()),
+        };
+        let response = start_checkout(State(state), HeaderMap::new(), Query(params)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn checkout_validates_the_configured_price_and_currency() {
+        let api = MockServer::start(paypal_api).await;
+        for (prices, accepted) in [
+            ("architect=amount:40", false),
+            ("architect=amount:5000,currency:xof", false),
+            ("architect=amount:5000,currency:eur", true),
+        ] {
+            let mut state = paypal_state(None, false);
+            state.config.api_base = api.url.clone();
+            state.catalog = Arc::new(PricingCatalog::from_env().with_prices(prices));
+            let params = CheckoutParams { intent: None };
+            let response =
+                start_checkout(State(Arc::new(state)), HeaderMap::new(), Query(params)).await;
+            assert_eq!(
+                response.status() == StatusCode::BAD_REQUEST,
+                !accepted,
+                "{}",
+                prices
+            );
+        }
+        let orders: Vec<_> = api
+            .requests()
+            .into_iter()
+            .filter(|r| r.path == "/v2/checkout/orders")
+            .collect();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(
+            orders[0].json()["purchase_units"][0]["amount"]["value"],
+            "50.00"
+        );
+        assert_eq!(
+            orders[0].json()["purchase_units"][0]["amount"]["currency_code"],
+            "EUR"
+        );
+    }
+
+    #[test]
+    fn only_the_final_capture_may_fall_below_the_minimum() {
+        let order = serde_json::json!({
+            "purchase_units": [{
+                "amount": { "currency_code": "USD", "value": "199.00" },
+                "payments": { "captures": [
+                    { "status": "COMPLETED", "amount": { "currency_code": "USD", "value": "198.80" } },
+                    { "status": "DECLINED", "amount": { "currency_code": "USD", "value": "0.20" } },
+                ] },
+            }],
+        });
+        let remaining = remaining_amount(&order).unwrap();
+        assert_eq!(remaining.minor, 20);
+
+        assert_eq!(
+            validate_partial_capture("0.20", &remaining).unwrap().minor,
+            20
+        );
+        assert!(validate_partial_capture("0.10", &remaining).is_err());
+        assert!(validate_partial_capture("0.30", &remaining).is_err());
+    }
+
+    #[tokio::test]
+    async fn authorize_capture_authorizes_then_captures_in_full() {
+        let api = MockServer::start(paypal_api).await;
+        let state = against(&api).await;
+
+        let request = AuthorizeCaptureRequest {
+            order_id: "ORDER1".to_string(),
+            amount: None,
+        };
+        let response = authorize_capture(State(state), admin_headers(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["authorization_id"], "AUTH1");
+        assert_eq!(body["capture_id"], "CAP1");
+        assert_eq!(body["status"], "COMPLETED");
+        assert_eq!(body["already_captured"], false);
+
+        let calls: Vec<String> = api
+            .requests()
+            .into_iter()
+            .filter(|r| r.path != "/v1/oauth2/token")
+            .map(|r| format!("{} {}", r.method, r.path))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                "GET /v2/checkout/orders/ORDER1",
+                "POST /v2/checkout/orders/ORDER1/authorize",
+                "POST /v2/payments/authorizations/AUTH1/capture",
+            ]
+        );
+        let capture = api.requests().pop().unwrap();
+        assert_eq!(capture.json(), serde_json::json!({}));
+    }
+
+    #[tokio::test]
+    async fn replay_of_an_unarchived_event_is_not_found() {
+        let state = Arc::new(paypal_state(Some("WH-ID"), false));
+        let request = ReplayRequest {
+            event_id: "WH-NEVER".to_string(),
+        };
+        let response = replay_event(State(state), admin_headers(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn replay_reruns_the_handler_for_an_archived_event() {
+        let api = MockServer::start(paypal_api).await;
+        let mut state = paypal_state(Some("WH-ID"), false);
+        state.config.api_base = api.url.clone();
+        state
+            .subscriptions
+            .activate_subscription("replay@x.com", None, None, "basic")
+            .await;
+        let state = Arc::new(state);
+        let suspended = paypal_event(
+            "WH-REPLAY-1",
+            "BILLING.SUBSCRIPTION.UPDATED",
+            serde_json::json!({
+                "id": "I-SUB1",
+                "status": "SUSPENDED",
+                "subscriber": { "email_address": "replay@x.com" },
+            }),
+        );
+        let body = serde_json::to_string(&suspended).unwrap();
+        let response = post_webhook(&state, transmission_headers(), body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.archive.load("WH-REPLAY-1").await.is_some());
+        let status = || async {
+            state
+                .subscriptions
+                .get("replay@x.com")
+                .await
+                .unwrap()
+                .status
+        };
+        let after_delivery = status().await;
+        assert_ne!(after_delivery, SubscriptionStatus::Active);
+
+        state
+            .subscriptions
+            .update_status("replay@x.com", SubscriptionStatus::Active)
+            .await;
+        let mut admin = HeaderMap::new();
+        admin.insert("x-admin-token", "test-admin-token".parse().unwrap());
+        let request = ReplayRequest {
+            event_id: "WH-REPLAY-1".to_string(),
+        };
+        let response = replay_event(State(state.clone()), admin, Json(request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status().await, after_delivery);
+
+        let unknown = ReplayRequest {
+            event_id: "WH-NEVER".to_string(),
+        };
+        let response = replay_event(State(state.clone()), HeaderMap::new(), Json(unknown)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn admin_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "test-admin-token".parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn partial_capture_sends_the_amount_within_the_authorization() {
+        let api = MockServer::start(paypal_api).await;
+        let state = against(&api).await;
+        let capture = |amount: &str| {
+            authorize_capture(
+                State(state.clone()),
+                admin_headers(),
+                Json(AuthorizeCaptureRequest {
+                    order_id: "ORDER1".to_string(),
+                    amount: Some(amount.to_string()),
+                }),
+            )
+        };
+
+        assert_eq!(capture("250.00").await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(capture("0.00").await.status(), StatusCode::BAD_REQUEST);
+        assert!(api
+            .requests()
+            .iter()
+            .all(|r| r.method == "GET" || r.path == "/v1/oauth2/token"));
+
+        assert_eq!(capture("50").await.status(), StatusCode::OK);
+        let sent = api.requests().pop().unwrap();
+        assert_eq!(sent.path, "/v2/payments/authorizations/AUTH1/capture");
+        assert_eq!(
+            sent.json(),
+            serde_json::json!({ "amount": { "currency_code": "USD", "value": "50.00" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_capture_without_the_admin_token_is_refused() {
+        let api = MockServer::start(paypal_api).await;
+        let state = against(&api).await;
+
+        let request = AuthorizeCaptureRequest {
+            order_id: "ORDER1".to_string(),
+            amount: Some("0.01".to_string()),
+        };
+        let response = authorize_capture(State(state), HeaderMap::new(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(api.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_capture_without_the_admin_token_is_refused() {
+        let api = MockServer::start(paypal_api).await;
+        let state = against(&api).await;
+
+        let request = AuthorizeCaptureRequest {
+            order_id: "ORDER1".to_string(),
+            amount: None,
+        };
+        let response = authorize_capture(State(state), HeaderMap::new(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(api.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn already_captured_order_returns_its_capture_without_capturing_again() {
+        let api = MockServer::start(|request| match request.path.as_str() {
+            "/v2/checkout/orders/ORDER2" => MockResponse::json(
+                200,
+                serde_json::json!({
+                    "id": "ORDER2",
+                    "status": "COMPLETED",
+                    "purchase_units": [{
+                        "amount": { "currency_code": "USD", "value": "199.00" },
+                        "payments": {
+                            "authorizations": [{ "id": "AUTH2" }],
+                            "captures": [{ "id": "CAP2", "status": "COMPLETED" }],
+                        },
+                    }],
+                }),
+            ),
+            _ => paypal_api(request),
+        })
+        .await;
+        let state = against(&api).await;
+
+        let request = AuthorizeCaptureRequest {
+            order_id: "ORDER2".to_string(),
+            amount: None,
+        };
+        let response =
+            authorize_capture(State(state.clone()), admin_headers(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["capture_id"], "CAP2");
+        assert_eq!(body["authorization_id"], "AUTH2");
+        assert_eq!(body["already_captured"], true);
+        assert!(api
+            .requests()
+            .iter()
+            .all(|r| r.method == "GET" || r.path == "/v1/oauth2/token"));
+
+        // A fresh order goes on to authorize and capture
+        let request = AuthorizeCaptureRequest {
+            order_id: "ORDER1".to_string(),
+            amount: None,
+        };
+        let response = authorize_capture(State(state), admin_headers(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let capture = api.requests().pop().unwrap();
+        assert_eq!(capture.path, "/v2/payments/authorizations/AUTH1/capture");
+    }
+
+    /// `paypal_api` plus a completed `PAID1` and an unpaid `OPEN1`
+    fn orders_api(request: &MockRequest) -> MockResponse {
+        let order = |id: &str, status: &str| {
+            serde_json::json!({
+                "id": id,
+                "status": status,
+                "payer": { "email_address": "payer@x.com" },
+                "purchase_units": [{ "custom_id": "veritas_premium_7f3a" }],
+            })
+        };
+        match request.path.as_str() {
+            "/v2/checkout/orders/PAID1" => MockResponse::json(200, order("PAID1", "COMPLETED")),
+            "/v2/checkout/orders/OPEN1" => MockResponse::json(200, order("OPEN1", "CREATED")),
+            _ => paypal_api(request),
+        }
+    }
+
+    async fn verify(
+        state: &Arc<PayPalState>,
+        order_id: &str,
+        license_key: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let query = VerifyOrderQuery {
+            order_id: order_id.to_string(),
+            license_key: license_key.map(str::to_string),
+        };
+        let response = verify_order(State(state.clone()), Query(query)).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn verify_order_confirms_a_paid_order_and_issues_its_license() {
+        let api = MockServer::start(orders_api).await;
+        let state = against(&api).await;
+
+        let (status, body) = verify(&state, "PAID1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], true);
+        assert_eq!(body["plan"], "premium");
+        assert_eq!(body["email"], "payer@x.com");
+        let key = body["license_key"].as_str().unwrap().to_string();
+        let record = state.licenses.get(&key).await.unwrap();
+        assert_eq!(record.provider, LicenseProvider::Paypal);
+        assert_eq!(record.purchase_id, "PAID1");
+
+        let (_, body) = verify(&state, "OPEN1", None).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["license_key"], serde_json::Value::Null);
+
+        let (status, _) = verify(&state, "MISSING1", None).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let (status, _) = verify(&state, "../orders", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn verify_order_issues_no_license_before_capture() {
+        let api = MockServer::start(|request| {
+            let (status, payments) = match request.path.as_str() {
+                "/v2/checkout/orders/APPROVED1" => ("APPROVED", serde_json::json!({})),
+                "/v2/checkout/orders/AUTHONLY1" => (
+                    "COMPLETED",
+                    serde_json::json!({ "authorizations": [{ "id": "AUTH9" }] }),
+                ),
+                "/v2/checkout/orders/PENDING1" => (
+                    "COMPLETED",
+                    serde_json::json!({ "captures": [{ "id": "CAP9", "status": "PENDING" }] }),
+                ),
+                _ => return paypal_api(request),
+            };
+            MockResponse::json(
+                200,
+                serde_json::json!({
+                    "status": status,
+                    "payer": { "email_address": "payer@x.com" },
+                    "purchase_units": [{ "custom_id": "veritas_premium_7f3a", "payments": payments }],
+                }),
+            )
+        })
+        .await;
+        let state = against(&api).await;
+
+        for order_id in ["APPROVED1", "AUTHONLY1", "PENDING1"] {
+            let (status, body) = verify(&state, order_id, None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["valid"], false, "{}", order_id);
+            assert_eq!(body["license_key"], serde_json::Value::Null);
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_order_refuses_a_license_without_a_payer_email() {
+        let api = MockServer::start(|request| match request.path.as_str() {
+            "/v2/checkout/orders/NOMAIL1" => MockResponse::json(
+                200,
+                serde_json::json!({
+                    "status": "COMPLETED",
+                    "purchase_units": [{ "custom_id": "veritas_premium_7f3a" }],
+                }),
+            ),
+            _ => paypal_api(request),
+        })
+        .await;
+        let state = against(&api).await;
+
+        let (status, body) = verify(&state, "NOMAIL1", None).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["license_key"], serde_json::Value::Null);
+        let key = state
+            .license
+            .generate_license_key(LicenseProvider::Paypal, "NOMAIL1")
+            .unwrap();
+        assert!(state.licenses.get(&key).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn verify_order_checks_a_presented_license_key() {
+        let api = MockServer::start(orders_api).await;
+        let state = against(&api).await;
+        let (_, issued) = verify(&state, "PAID1", None).await;
+        let key = issued["license_key"].as_str().unwrap();
+
+        let (_, body) = verify(&state, "PAID1", Some(key)).await;
+        assert_eq!(body["valid"], true);
+        let stripe_key = state
+            .license
+            .generate_license_key(LicenseProvider::Stripe, "PAID1")
+            .unwrap();
+        let (_, body) = verify(&state, "PAID1", Some(&stripe_key)).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["license_key"], key);
+    }
+
+    #[tokio::test]
+    async fn subscription_activated_creates_the_local_subscription() {
+        let mut state = paypal_state(None, true);
+        state
+            .config
+            .plan_map
+            .insert("P-PREMIUM".to_string(), "premium".to_string());
+        let activated = paypal_event(
+            "WH-ACTIVATED-1",
+            "BILLING.SUBSCRIPTION.ACTIVATED",
+            serde_json::json!({
+                "id": "I-SUB1",
+                "plan_id": "P-PREMIUM",
+                "subscriber": { "email_address": "pp@x.com" },
+            }),
+        );
+        route_event(&state, &activated).await.unwrap();
+
+        let stored = state.subscriptions.get("pp@x.com").await.unwrap();
+        assert_eq!(stored.status, SubscriptionStatus::Active);
+        assert_eq!(stored.plan, SubscriptionPlan::from_key("premium"));
+    }
+
+    #[tokio::test]
+    async fn audit_entries_are_tagged_with_the_paypal_mode() {
+        let amount = Money::from_paypal_decimal("9.00", "EUR").unwrap();
+        for (id, livemode) in [("WH-MODE-LIVE", true), ("WH-MODE-TEST", false)] {
+            let sink = RecordingAuditLog::default();
+            let audit = AuditTrail::with_sinks(vec![Box::new(sink.clone())], true);
+            let mut event = paypal_event(id, "PAYMENT.CAPTURE.COMPLETED", serde_json::json!({}));
+            event.livemode = livemode;
+            log_paypal_event(&audit, &event, "capture.completed", &amount)
+                .await
+                .unwrap();
+
+            let entries = sink.entries.lock().unwrap().clone();
+            assert_eq!(entries[0]["paypal_event_id"], id);
+            assert_eq!(entries[0]["livemode"], livemode);
+            assert_eq!(entries[0]["mode"], if livemode { "live" } else { "test" });
+        }
+    }
 }
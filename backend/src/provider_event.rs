@@ -0,0 +1,166 @@
+// lwas_economy/src/payments/provider_event.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Provider-neutral view of a webhook event (Stripe, PayPal) for shared handling
+
+use chrono::Utc;
+
+use crate::audit::{self, AuditTrail};
+use crate::money::Money;
+
+pub trait ProviderEvent {
+    /// `stripe` / `paypal`
+    fn provider(&self) -> &'static str;
+    fn id(&self) -> &str;
+    fn event_type(&self) -> &str;
+    /// Unix seconds; None when the provider's timestamp can't be read
+    fn created(&self) -> Option<i64>;
+    fn livemode(&self) -> bool;
+    /// API version the payload was rendered with, where the provider has one
+    fn api_version(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// O(1) - Fields every audit entry carries; callers add their own on top
+pub fn audit_entry(event: &impl ProviderEvent, audit_event: &str) -> serde_json::Value {
+    let mut entry = serde_json::json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "provider": event.provider(),
+        "event": audit_event,
+        // Raw provider type, e.g. to tell invoice.paid from its payment_succeeded alias
+        "event_type": event.event_type(),
+        "event_created": event.created(),
+        "livemode": event.livemode(),
+        "mode": audit::mode_label(event.livemode()),
+    });
+    entry[format!("{}_event_id", event.provider())] = event.id().into();
+    entry
+}
+
+/// A subscription going live, whichever provider billed it
+pub struct Activation<'a> {
+    pub email: &'a str,
+    pub plan: &'a str,
+    /// None when the provider didn't report what was charged
+    pub amount: Option<Money>,
+    /// Provider-side ids (customer, subscription, session...), by name
+    pub external_ids: serde_json::Map<String, serde_json::Value>,
+    /// Persisting failed and the activation waits in the retry queue
+    pub queued: bool,
+}
+
+/// O(1) - Emit `subscription.activated` with the same fields for every provider,
+/// so activations can be counted without knowing each provider's entry shape.
+/// A queued one is `subscription.activation_queued` instead, so it isn't counted
+/// before it happens.
+pub async fn record_activation(
+    audit: &AuditTrail,
+    event: &impl ProviderEvent,
+    activation: Activation<'_>,
+) -> Result<(), String> {
+    let audit_event = if activation.queued {
+        "subscription.activation_queued"
+    } else {
+        "subscription.activated"
+    };
+    let mut entry = audit_entry(event, audit_event);
+    entry["email"] = activation.email.into();
+    entry["plan"] = activation.plan.into();
+    entry["amount_display"] = activation.amount.as_ref().map(Money::localized).into();
+    entry["amount"] = serde_json::json!(activation.amount);
+    entry["external_ids"] = activation.external_ids.into();
+    audit.record("AUDIT", entry).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paypal_handler::PayPalEvent;
+    use crate::stripe_handler::{EventResult, IdempotencyStore, StripeEvent};
+
+    fn stripe_event(livemode: bool) -> StripeEvent {
+        serde_json::from_value(serde_json::json!({
+            "id": "evt_mode",
+            "type": "invoice.paid",
+            "created": 1_700_000_000,
+            "livemode": livemode,
+            "data": { "object": { "id": "in_1" } },
+        }))
+        .unwrap()
+    }
+
+    /// `livemode` is stamped from `PAYPAL_MODE` after parsing, as the handler does
+    fn paypal_event(livemode: bool) -> PayPalEvent {
+        let mut event: PayPalEvent = serde_json::from_value(serde_json::json!({
+            "id": "WH-MODE",
+            "event_type": "PAYMENT.CAPTURE.COMPLETED",
+            "create_time": "2023-11-14T22:13:20Z",
+            "resource_type": "capture",
+            "resource": {},
+        }))
+        .unwrap();
+        event.livemode = livemode;
+        event
+    }
+
+    #[test]
+    fn entries_are_tagged_live_or_test_for_both_providers() {
+        for (entry, livemode) in [
+            (audit_entry(&stripe_event(true), "invoice.paid"), true),
+            (audit_entry(&stripe_event(false), "invoice.paid"), false),
+            (audit_entry(&paypal_event(true), "capture.completed"), true),
+            (
+                audit_entry(&paypal_event(false), "capture.completed"),
+                false,
+            ),
+        ] {
+            assert_eq!(entry["livemode"], livemode, "{}", entry);
+            assert_eq!(entry["mode"], if livemode { "live" } else { "test" });
+        }
+    }
+
+    #[tokio::test]
+    async fn both_providers_expose_the_same_view() {
+        let mut stripe = stripe_event(true);
+        stripe.api_version = Some("2024-06-20".to_string());
+        let paypal = paypal_event(false);
+
+        assert_eq!(
+            (stripe.provider(), stripe.id(), stripe.event_type()),
+            ("stripe", "evt_mode", "invoice.paid")
+        );
+        assert_eq!(stripe.created(), Some(1_700_000_000));
+        assert_eq!(stripe.api_version(), Some("2024-06-20"));
+        assert_eq!(
+            (paypal.provider(), paypal.id(), paypal.event_type()),
+            ("paypal", "WH-MODE", "PAYMENT.CAPTURE.COMPLETED")
+        );
+        assert_eq!(paypal.created(), Some(1_700_000_000));
+        assert_eq!(paypal.api_version(), None);
+
+        let mut unreadable = paypal_event(false);
+        unreadable.create_time = "yesterday".to_string();
+        assert_eq!(unreadable.created(), None);
+
+        // Shared logic keys off the trait: each provider's id names its own entry
+        let stripe_entry = audit_entry(&stripe, "invoice.paid");
+        let paypal_entry = audit_entry(&paypal, "capture.completed");
+        assert_eq!(stripe_entry["stripe_event_id"], "evt_mode");
+        assert_eq!(paypal_entry["paypal_event_id"], "WH-MODE");
+        assert_eq!(stripe_entry["event_created"], paypal_entry["event_created"]);
+
+        let idempotency = IdempotencyStore::new(None);
+        idempotency.record(&stripe, EventResult::Processed).await;
+        idempotency.record(&paypal, EventResult::Processed).await;
+        assert_eq!(
+            idempotency
+                .get("evt_mode")
+                .await
+                .unwrap()
+                .api_version
+                .as_deref(),
+            Some("2024-06-20")
+        );
+        assert_eq!(idempotency.get("WH-MODE").await.unwrap().api_version, None);
+    }
+}
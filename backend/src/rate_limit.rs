@@ -0,0 +1,331 @@
+// lwas_economy/src/payments/rate_limit.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Token-bucket rate limiting (per-IP / per-email), shared through Redis across replicas
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::config::env_parse;
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// TOKEN BUCKET
+// ═══════════════════════════════════════════════════════════════════════════════
+
+struct Bucket {
+    tokens: f64,
+    last_refill_ts: Instant,
+}
+
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+/// INCR, and start the window's TTL on its first hit, in one round trip
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local hits = redis.call('INCR', KEYS[1])
+if hits == 1 then redis.call('EXPIRE', KEYS[1], ARGV[1]) end
+return hits
+"#;
+
+/// Fixed-window counters every replica sees
+#[derive(Clone)]
+struct SharedCounter {
+    /// Keeps limiters that key on the same value (e.g. an IP) apart
+    scope: String,
+    client: redis::Client,
+    script: Arc<redis::Script>,
+    /// After a failure Redis is left alone until then (`RATE_LIMIT_REDIS_RETRY_SECS`,
+    /// default 5), so an outage costs one connect attempt, not one per request
+    retry_at: Arc<Mutex<Option<Instant>>>,
+    retry_after: Duration,
+}
+
+/// `capacity` requests per `window_secs`, refilled continuously. With Redis the
+/// limit is a fixed window shared by all replicas instead.
+#[derive(Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    /// Idle this long, a bucket is full again and can be dropped without changing behavior
+    window: Duration,
+    /// `RATE_LIMIT_MAX_KEYS` (default 100000); least recently used keys go first
+    max_tracked_keys: usize,
+    buckets: Arc<Mutex<Buckets>>,
+    shared: Option<SharedCounter>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, window_secs: u64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let window_secs = window_secs.max(1);
+        Self {
+            capacity,
+            refill_per_sec: capacity / window_secs as f64,
+            window: Duration::from_secs(window_secs),
+            max_tracked_keys: env_parse("RATE_LIMIT_MAX_KEYS", 100_000usize).max(1),
+            buckets: Arc::new(Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                last_sweep: Instant::now(),
+            })),
+            shared: None,
+        }
+    }
+
+    /// Count in Redis under `ratelimit:{scope}:{key}:{window}`; unchanged without `redis_url`
+    pub fn with_redis(mut self, scope: &str, redis_url: Option<String>) -> Self {
+        self.shared = redis_url
+            .and_then(|url| {
+                redis::Client::open(url)
+                    .map_err(|e| println!("❌ Redis connect error: {}", e))
+                    .ok()
+            })
+            .map(|client| SharedCounter {
+                scope: scope.to_string(),
+                client,
+                script: Arc::new(redis::Script::new(FIXED_WINDOW_SCRIPT)),
+                retry_at: Arc::new(Mutex::new(None)),
+                retry_after: Duration::from_secs(env_parse("RATE_LIMIT_REDIS_RETRY_SECS", 5)),
+            });
+        self
+    }
+
+    /// O(1) - Consume one request for `key`; false once it is over the limit.
+    /// Falls back to the local bucket while Redis is unreachable.
+    pub async fn check(&self, key: &str) -> bool {
+        if let Some(shared) = &self.shared {
+            let backing_off = shared
+                .retry_at
+                .lock()
+                .unwrap()
+                .is_some_and(|at| Instant::now() < at);
+            if !backing_off {
+                match self.check_shared(shared, key).await {
+                    Ok(allowed) => return allowed,
+                    Err(e) => {
+                        println!(
+                            "[RATE_LIMIT] ⚠️ Redis unavailable ({}), limiting {} locally for {:?}",
+                            e, shared.scope, shared.retry_after
+                        );
+                        *shared.retry_at.lock().unwrap() =
+                            Some(Instant::now() + shared.retry_after);
+                    }
+                }
+            }
+        }
+        self.check_local(key)
+    }
+
+    /// O(1) - One INCR on the current window's counter
+    async fn check_shared(&self, shared: &SharedCounter, key: &str) -> Result<bool, String> {
+        let window_secs = self.window.as_secs();
+        let window = Utc::now().timestamp() as u64 / window_secs;
+        let mut con = shared
+            .client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let hits: u64 = shared
+            .script
+            .key(format!("ratelimit:{}:{}:{}", shared.scope, key, window))
+            .arg(window_secs)
+            .invoke_async(&mut con)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(hits as f64 <= self.capacity)
+    }
+
+    /// O(1) amortized - Consume one token for `key`; false when the bucket is empty
+    fn check_local(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.last_sweep) >= self.window {
+            self.sweep_idle(&mut buckets, now);
+        }
+        if buckets.by_key.len() >= self.max_tracked_keys && !buckets.by_key.contains_key(key) {
+            self.sweep_idle(&mut buckets, now);
+            self.evict_least_recent(&mut buckets.by_key);
+        }
+
+        let bucket = buckets.by_key.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            last_refill_ts: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill_ts).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.last_refill_ts = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// O(n) - Drop buckets idle for a full window (at most once per window)
+    fn sweep_idle(&self, buckets: &mut Buckets, now: Instant) {
+        buckets
+            .by_key
+            .retain(|_, bucket| now.duration_since(bucket.last_refill_ts) < self.window);
+        buckets.last_sweep = now;
+    }
+
+    /// O(n) - Still at the cap: drop the least recently used tenth, so a flood of
+    /// new keys pays for one scan per batch rather than per request
+    fn evict_least_recent(&self, by_key: &mut HashMap<String, Bucket>) {
+        if by_key.len() < self.max_tracked_keys {
+            return;
+        }
+        let evict = (self.max_tracked_keys / 10).max(1);
+        let mut by_age: Vec<(Instant, String)> = by_key
+            .iter()
+            .map(|(key, bucket)| (bucket.last_refill_ts, key.clone()))
+            .collect();
+        by_age.select_nth_unstable_by_key(evict - 1, |(ts, _)| *ts);
+        for (_, key) in by_age.into_iter().take(evict) {
+            by_key.remove(&key);
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// CHECKOUT LIMITS (IP + EMAIL)
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Rotating IPs with one email (or one IP with many emails) trips either limiter
+#[derive(Clone)]
+pub struct CheckoutRateLimits {
+    per_ip: RateLimiter,
+    per_email: RateLimiter,
+}
+
+impl CheckoutRateLimits {
+    /// Shared through Redis when `redis_url` is set
+    pub fn from_env(redis_url: Option<String>) -> Self {
+        let window = env_parse("CHECKOUT_RATE_WINDOW_SECS", 60);
+        Self {
+            per_ip: RateLimiter::new(env_parse("CHECKOUT_IP_LIMIT", 10), window)
+                .with_redis("checkout_ip", redis_url.clone()),
+            per_email: RateLimiter::new(env_parse("CHECKOUT_EMAIL_LIMIT", 5), window)
+                .with_redis("checkout_email", redis_url),
+        }
+    }
+
+    /// O(1) - Both limits apply; rejected if either one trips
+    pub async fn check(&self, ip: &str, email: Option<&str>) -> Result<(), &'static str> {
+        let ip_ok = self.per_ip.check(ip).await;
+        let email_ok = match email {
+            Some(email) => self.per_email.check(&email.trim().to_lowercase()).await,
+            None => true,
+        };
+
+        match (ip_ok, email_ok) {
+            (false, _) => Err("ip"),
+            (_, false) => Err("email"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serve_fake_redis;
+    use tokio::net::TcpListener;
+
+    /// Limiter whose Redis answers every command with `reply`
+    async fn shared_limiter(capacity: u32, reply: &'static [u8]) -> RateLimiter {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_fake_redis(listener, reply));
+        RateLimiter::new(capacity, 60).with_redis("checkout_ip", Some(url))
+    }
+
+    fn limits(ip_limit: u32, email_limit: u32) -> CheckoutRateLimits {
+        CheckoutRateLimits {
+            per_ip: RateLimiter::new(ip_limit, 60),
+            per_email: RateLimiter::new(email_limit, 60),
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_limit_trips_across_different_emails() {
+        let limits = limits(2, 10);
+        assert_eq!(limits.check("203.0.113.7", Some("a@x.io")).await, Ok(()));
+        assert_eq!(limits.check("203.0.113.7", Some("b@x.io")).await, Ok(()));
+        assert_eq!(limits.check("203.0.113.7", Some("c@x.io")).await, Err("ip"));
+        assert_eq!(limits.check("203.0.113.8", Some("c@x.io")).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn email_limit_trips_across_rotating_ips() {
+        let limits = limits(10, 2);
+        assert_eq!(limits.check("203.0.113.1", Some("a@x.io")).await, Ok(()));
+        assert_eq!(limits.check("203.0.113.2", Some(" A@X.io")).await, Ok(()));
+        assert_eq!(
+            limits.check("203.0.113.3", Some("a@x.io")).await,
+            Err("email")
+        );
+        assert_eq!(limits.check("203.0.113.3", Some("b@x.io")).await, Ok(()));
+        assert_eq!(limits.check("203.0.113.3", None).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn shared_counter_decides_when_redis_answers() {
+        // Other replicas already used up the window
+        let exhausted = shared_limiter(2, b":3\r\n").await;
+        assert!(!exhausted.check("203.0.113.7").await);
+
+        let fresh = shared_limiter(2, b":1\r\n").await;
+        let shared = fresh.shared.as_ref().unwrap();
+        assert_eq!(fresh.check_shared(shared, "203.0.113.7").await, Ok(true));
+        assert!(fresh.check("203.0.113.7").await);
+        // Redis decided, so the local bucket was never touched
+        assert!(fresh.buckets.lock().unwrap().by_key.is_empty());
+    }
+
+    #[tokio::test]
+    async fn redis_outage_backs_off_to_the_local_bucket() {
+        // Nothing can listen on port 0, so the connect is refused
+        let limiter =
+            RateLimiter::new(1, 60).with_redis("checkout_ip", Some("redis://127.0.0.1:0".into()));
+        assert!(limiter.check("203.0.113.7").await);
+        assert!(!limiter.check("203.0.113.7").await);
+        let retry_at = limiter
+            .shared
+            .as_ref()
+            .unwrap()
+            .retry_at
+            .lock()
+            .unwrap()
+            .unwrap();
+        assert!(retry_at > Instant::now());
+
+        // While backing off a reachable Redis is not asked; after it, it decides again
+        let limiter = shared_limiter(1, b":5\r\n").await;
+        let shared = limiter.shared.as_ref().unwrap();
+        *shared.retry_at.lock().unwrap() = Some(Instant::now() + Duration::from_secs(60));
+        assert!(limiter.check("203.0.113.7").await);
+        *shared.retry_at.lock().unwrap() = Some(Instant::now());
+        assert!(!limiter.check("203.0.113.8").await);
+    }
+
+    #[tokio::test]
+    async fn flood_of_distinct_keys_stays_bounded() {
+        let mut limiter = RateLimiter::new(1, 60);
+        limiter.max_tracked_keys = 100;
+        assert!(limiter.check("198.51.100.1").await);
+
+        for n in 0..10_000 {
+            limiter.check(&format!("spoofed-{}", n)).await;
+            // Kept busy, the real client is never the one evicted, so it stays limited
+            assert!(!limiter.check("198.51.100.1").await);
+        }
+        assert!(limiter.buckets.lock().unwrap().by_key.len() <= 100);
+    }
+}
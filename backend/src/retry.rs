@@ -0,0 +1,147 @@
+// lwas_economy/src/payments/retry.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Exponential backoff with full jitter for transient failures
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::config::env_parse;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total tries including the first (at least 1)
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// `{prefix}_ATTEMPTS` (3), `{prefix}_BASE_MS` (200), `{prefix}_MAX_MS` (2000)
+    pub fn from_env(prefix: &str) -> Self {
+        Self {
+            max_attempts: env_parse(&format!("{}_ATTEMPTS", prefix), 3u32).max(1),
+            base_delay: Duration::from_millis(env_parse(&format!("{}_BASE_MS", prefix), 200u64)),
+            max_delay: Duration::from_millis(env_parse(&format!("{}_MAX_MS", prefix), 2000u64)),
+        }
+    }
+
+    /// O(1) - Upper bound before the retry following failed attempt `attempt`
+    /// (1-based): base * 2^(attempt-1), capped at `max_delay`
+    pub fn ceiling(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(20);
+        self.base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// O(1) - Full jitter: uniform in [0, ceiling]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.ceiling(attempt).as_millis() as u64;
+        Duration::from_millis(rand::random::<u64>() % (ceiling + 1))
+    }
+}
+
+/// Run `op` until it succeeds, fails with an error `retryable` rejects, or
+/// `policy.max_attempts` is used up; the last error is returned.
+pub async fn retry_async<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    retryable: impl Fn(&E) -> bool,
+    mut op: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.max_attempts && retryable(&e) => {
+                let delay = policy.delay(attempt);
+                println!(
+                    "[RETRY] 🔁 Attempt {}/{} failed ({}), retrying in {:?}",
+                    attempt, policy.max_attempts, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_millis(2000),
+        };
+        let ceilings: Vec<u128> = (1..=6).map(|n| policy.ceiling(n).as_millis()).collect();
+        assert_eq!(ceilings, [200, 400, 800, 1600, 2000, 2000]);
+        assert_eq!(policy.ceiling(u32::MAX), policy.max_delay);
+        for attempt in 1..=6 {
+            assert!(policy.delay(attempt) <= policy.ceiling(attempt));
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts_or_a_permanent_error() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), String> = retry_async(
+            &policy(3),
+            |_| true,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err("503".to_string())
+            },
+        )
+        .await;
+        assert_eq!(result, Err("503".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicU32::new(0);
+        let result: Result<(), String> = retry_async(
+            &policy(3),
+            |e: &String| e.starts_with('5'),
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err("400".to_string())
+            },
+        )
+        .await;
+        assert_eq!(result, Err("400".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A transient failure followed by success returns the value
+        let calls = AtomicU32::new(0);
+        let result = retry_async(
+            &policy(3),
+            |_: &String| true,
+            || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err("503".to_string()),
+                    _ => Ok("done"),
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Ok("done"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,115 @@
+// lwas_economy/src/payments/security_headers.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Baseline security headers (HSTS, nosniff, Referrer-Policy) on every response
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+use crate::config::env_parse;
+
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    /// `HSTS_MAX_AGE_SECS` (default 1 year, 0 disables), `HSTS_INCLUDE_SUBDOMAINS`
+    /// (default on), `REFERRER_POLICY` (default `strict-origin-when-cross-origin`,
+    /// empty disables). `X-Content-Type-Options: nosniff` is always sent.
+    pub fn from_env() -> Arc<Self> {
+        let mut headers = vec![(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )];
+
+        let max_age = env_parse("HSTS_MAX_AGE_SECS", 31_536_000u64);
+        if max_age > 0 {
+            let subdomains = std::env::var("HSTS_INCLUDE_SUBDOMAINS")
+                .map(|v| !matches!(v.trim(), "0" | "false" | "no" | "off"))
+                .unwrap_or(true);
+            let value = if subdomains {
+                format!("max-age={}; includeSubDomains", max_age)
+            } else {
+                format!("max-age={}", max_age)
+            };
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.push((header::STRICT_TRANSPORT_SECURITY, value));
+            }
+        }
+
+        let referrer = std::env::var("REFERRER_POLICY")
+            .unwrap_or_else(|_| "strict-origin-when-cross-origin".to_string());
+        if !referrer.trim().is_empty() {
+            match HeaderValue::from_str(referrer.trim()) {
+                Ok(value) => headers.push((header::REFERRER_POLICY, value)),
+                Err(_) => println!(
+                    "[CONFIG] ⚠️ Invalid REFERRER_POLICY '{}', header not sent",
+                    referrer
+                ),
+            }
+        }
+
+        Arc::new(Self { headers })
+    }
+}
+
+/// Adds the configured headers unless the handler already set them. CORS sits
+/// outside this layer, so preflight responses are produced before it runs.
+pub async fn apply_security_headers(
+    State(config): State<Arc<SecurityHeaders>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    for (name, value) in &config.headers {
+        if !headers.contains_key(name) {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+
+    #[tokio::test]
+    async fn responses_carry_the_security_headers() {
+        let app = Router::new()
+            .route("/plans", get(|| async { "[]" }))
+            .route(
+                "/embed",
+                get(|| async { ([(header::REFERRER_POLICY, "no-referrer")], "ok") }),
+            )
+            .layer(middleware::from_fn_with_state(
+                SecurityHeaders::from_env(),
+                apply_security_headers,
+            ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let response = reqwest::get(format!("{}/plans", base)).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(
+            headers["strict-transport-security"],
+            "max-age=31536000; includeSubDomains"
+        );
+        assert_eq!(
+            headers["referrer-policy"],
+            "strict-origin-when-cross-origin"
+        );
+
+        // A handler's own choice is kept
+        let response = reqwest::get(format!("{}/embed", base)).await.unwrap();
+        assert_eq!(response.headers()["referrer-policy"], "no-referrer");
+    }
+}
@@ -0,0 +1,255 @@
+// lwas_economy/src/payments/snapshot.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Subscription snapshots: timestamped files, LATEST pointer, bounded retention
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::activation_queue::{ActivationQueue, PendingActivation};
+use crate::config::env_parse;
+use crate::stripe_handler::{SubscriptionManager, UserSubscription};
+
+const FILE_PREFIX: &str = "subscriptions-";
+const FILE_SUFFIX: &str = ".json";
+const LATEST_POINTER: &str = "LATEST";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotData {
+    pub subscriptions: Vec<UserSubscription>,
+    /// Activations still waiting for a retry when the snapshot was taken
+    #[serde(default)]
+    pub pending_activations: Vec<PendingActivation>,
+}
+
+/// Early snapshots were a bare subscription array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotFormat {
+    Current(SnapshotData),
+    Legacy(Vec<UserSubscription>),
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    /// Number of snapshot files kept after each write (at least 1)
+    retain: usize,
+    interval: Duration,
+}
+
+impl SnapshotStore {
+    /// Enabled only when `SNAPSHOT_DIR` is set
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("SNAPSHOT_DIR").ok()?;
+        let store = Self {
+            dir: PathBuf::from(dir),
+            retain: env_parse("SNAPSHOT_RETAIN", 5usize).max(1),
+            interval: Duration::from_secs(env_parse("SNAPSHOT_INTERVAL_SECS", 300u64).max(1)),
+        };
+        println!(
+            "[SNAPSHOT] 💾 Writing to {} every {:?}, keeping {}",
+            store.dir.display(),
+            store.interval,
+            store.retain
+        );
+        Some(store)
+    }
+
+    /// O(n) - Load the snapshot LATEST points to into the manager and retry queue
+    pub async fn restore(
+        &self,
+        subscriptions: &SubscriptionManager,
+        activations: &ActivationQueue,
+    ) {
+        let store = self.clone();
+        let loaded = tokio::task::spawn_blocking(move || store.read_latest())
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+
+        match loaded {
+            Ok(Some(data)) => {
+                let pending = data.pending_activations.len();
+                // Records already loaded from Redis are newer than the snapshot
+                let mut count = 0;
+                for record in data.subscriptions {
+                    if subscriptions.insert_if_absent(record).await {
+                        count += 1;
+                    }
+                }
+                activations.restore(data.pending_activations).await;
+                println!(
+                    "[SNAPSHOT] ✅ Restored {} subscription(s), {} pending activation(s)",
+                    count, pending
+                );
+            }
+            Ok(None) => println!("[SNAPSHOT] ℹ️ No snapshot to restore"),
+            Err(e) => println!("[SNAPSHOT] ❌ Restore failed: {}", e),
+        }
+    }
+
+    /// O(n) - Write one snapshot, move LATEST to it, then prune
+    pub async fn save(&self, subscriptions: &SubscriptionManager, activations: &ActivationQueue) {
+        let data = SnapshotData {
+            subscriptions: subscriptions.all().await,
+            pending_activations: activations.snapshot().await,
+        };
+        let store = self.clone();
+        let written = tokio::task::spawn_blocking(move || store.write_snapshot(&data))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+
+        match written {
+            Ok(name) => println!("[SNAPSHOT] 💾 Wrote {}", name),
+            Err(e) => println!("[SNAPSHOT] ❌ Write failed: {}", e),
+        }
+    }
+
+    /// Periodic background snapshots
+    pub fn spawn(self, subscriptions: SubscriptionManager, activations: ActivationQueue) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                self.save(&subscriptions, &activations).await;
+            }
+        });
+    }
+
+    fn read_latest(&self) -> Result<Option<SnapshotData>, String> {
+        let pointer = self.dir.join(LATEST_POINTER);
+        let name = match fs::read_to_string(&pointer) {
+            Ok(name) => name.trim().to_string(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {}", pointer.display(), e)),
+        };
+        let raw = fs::read(self.dir.join(&name)).map_err(|e| format!("{}: {}", name, e))?;
+        match serde_json::from_slice(&raw).map_err(|e| format!("{}: {}", name, e))? {
+            SnapshotFormat::Current(data) => Ok(Some(data)),
+            SnapshotFormat::Legacy(subscriptions) => Ok(Some(SnapshotData {
+                subscriptions,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Crash-safe ordering: the snapshot and the pointer are each written to a
+    /// temp file, fsynced and renamed into place before anything is deleted,
+    /// so LATEST always names a complete file.
+    fn write_snapshot(&self, data: &SnapshotData) -> Result<String, String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+
+        let name = format!(
+            "{}{}{}",
+            FILE_PREFIX,
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+            FILE_SUFFIX
+        );
+        let json = serde_json::to_vec(data).map_err(|e| e.to_string())?;
+        write_atomic(&self.dir, &name, &json)?;
+        write_atomic(&self.dir, LATEST_POINTER, name.as_bytes())?;
+
+        self.prune(&name)?;
+        Ok(name)
+    }
+
+    /// O(n log n) - Keep the newest `retain` snapshots; never the one LATEST names
+    fn prune(&self, latest: &str) -> Result<(), String> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(|e| e.to_string())? {
+            let name = entry.map_err(|e| e.to_string())?.file_name();
+            let Some(name) = name.to_str() else { continue };
+            if name.ends_with(".tmp") {
+                // Leftover from an interrupted write; never referenced by LATEST
+                let _ = fs::remove_file(self.dir.join(name));
+            } else if name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX) {
+                snapshots.push(name.to_string());
+            }
+        }
+
+        // Timestamped names sort chronologically
+        snapshots.sort();
+        let excess = snapshots.len().saturating_sub(self.retain);
+        for name in snapshots.iter().take(excess).filter(|n| *n != latest) {
+            if let Err(e) = fs::remove_file(self.dir.join(name)) {
+                println!("[SNAPSHOT] ⚠️ Could not prune {}: {}", name, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// O(n) - temp file + fsync + rename, then fsync the directory entry
+fn write_atomic(dir: &Path, name: &str, bytes: &[u8]) -> Result<(), String> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    let mut file = fs::File::create(&tmp).map_err(|e| e.to_string())?;
+    file.write_all(bytes).map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())?;
+    fs::rename(&tmp, dir.join(name)).map_err(|e| e.to_string())?;
+
+    #[cfg(unix)]
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(retain: usize) -> SnapshotStore {
+        SnapshotStore {
+            dir: std::env::temp_dir().join(format!("snapshots-{}", uuid::Uuid::new_v4())),
+            retain,
+            interval: Duration::from_secs(300),
+        }
+    }
+
+    fn snapshot_files(store: &SnapshotStore) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&store.dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.starts_with(FILE_PREFIX))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn only_the_newest_snapshots_are_kept() {
+        let store = store(3);
+        let mut written = Vec::new();
+        for _ in 0..6 {
+            written.push(store.write_snapshot(&SnapshotData::default()).unwrap());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(snapshot_files(&store), written[3..]);
+        let latest = fs::read_to_string(store.dir.join(LATEST_POINTER)).unwrap();
+        assert_eq!(latest, written[5]);
+        assert!(store.read_latest().unwrap().is_some());
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn prune_never_deletes_the_file_latest_names() {
+        let store = store(1);
+        let latest = store.write_snapshot(&SnapshotData::default()).unwrap();
+        // Newer-sorting names (e.g. written by a replica with a fast clock)
+        let ahead = format!("{}99991231T000000.000Z{}", FILE_PREFIX, FILE_SUFFIX);
+        fs::write(store.dir.join(&ahead), b"{}").unwrap();
+        fs::write(store.dir.join("stray.json.tmp"), b"{").unwrap();
+
+        store.prune(&latest).unwrap();
+        assert_eq!(snapshot_files(&store), [latest, ahead]);
+        assert!(!store.dir.join("stray.json.tmp").exists());
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+}
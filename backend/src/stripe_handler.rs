@@ -3,20 +3,52 @@
 // Stripe Webhook Handler with Idempotency (Redis) & 0x4121 Verification
 
 use axum::{
-    extract::{Json, State},
-    http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Redirect},
+    body::{Body, Bytes},
+    extract::{Extension, Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
 };
 use chrono::{DateTime, Utc};
+use futures_util::stream;
 use hmac::{Hmac, Mac};
 use redis::AsyncCommands;
+use reqwest::{Method, RequestBuilder};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+use crate::activation_queue::{ActivationQueue, PendingActivation};
+use crate::admin::AdminAuth;
+use crate::audit::{self, AuditTrail};
+use crate::catalog::{PlanLimits, PricingCatalog, DEFAULT_PLAN_KEY};
+use crate::checkout_link::{CheckoutLinkClaims, CheckoutLinkSigner, LinkError};
+use crate::client_ip::{ClientIp, WebhookSourceFilter};
+use crate::config::{env_flag, env_parse};
+use crate::dead_letter::DeadLetterStore;
+use crate::domains::SiteDomains;
+use crate::json_limits::{parse_bounded, DEFAULT_MAX_JSON_DEPTH};
+use crate::license::{LicenseIssuer, LicenseProvider, LicenseRegistry};
+use crate::metadata::{
+    sanitize_metadata, sanitize_value, ATTRIBUTION_KEYS, ATTRIBUTION_VALUE_MAX,
+    STRIPE_METADATA_LIMITS,
+};
+use crate::money::Money;
+use crate::notifications::NotificationHook;
+use crate::provider_event::{self, Activation, ProviderEvent};
+use crate::rate_limit::CheckoutRateLimits;
+use crate::retry::retry_async;
+use crate::token::{EntitlementClaims, TokenIssuer};
+use crate::upstream::{UpstreamClient, UpstreamError};
+use crate::webhook_queue::{ProcessOutcome, QueuedEvent, WebhookQueue};
+
 // ═══════════════════════════════════════════════════════════════════════════════
 // STRIPE CONFIGURATION
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -27,8 +59,44 @@ pub struct StripeConfig {
     pub webhook_secret: String,
     pub _publishable_key: String,
     pub redis_url: Option<String>,
+    /// Status returned when an event handler fails on business logic.
+    /// 200 acks the event (Stripe stops retrying); 500 forces Stripe to
+    /// redeliver with exponential backoff for up to 3 days.
+    pub business_error_status: StatusCode,
+    /// Send a recovery link when a checkout session expires unpaid
+    pub abandonment_recovery: bool,
+    /// Public base URL recovery links point at (`CHECKOUT_RECOVERY_URL`);
+    /// recovery stays off until it is set
+    pub recovery_base_url: Option<String>,
+    /// Stripe API root (`STRIPE_API_BASE` points test keys at stripe-mock)
+    pub api_base: String,
+    /// Test Clock id; checkout customers are attached to it (sandbox only)
+    pub test_clock: Option<String>,
+    /// Pinned Stripe API version: sent as `Stripe-Version` on outbound calls,
+    /// and events rendered with another version are flagged
+    pub api_version: Option<String>,
+    /// Local development only: accept unsigned webhook payloads (never in live mode)
+    pub dev_skip_signature: bool,
+    /// When set, only these event types are routed; others are acked and counted
+    pub event_allowlist: Option<HashSet<String>>,
+    /// Also dedupe on `request.idempotency_key` (opt-in)
+    pub dedupe_by_request_key: bool,
+    /// Source IP allowlist for webhooks (`VERIFY_WEBHOOK_SOURCE_IP`, `STRIPE_WEBHOOK_IP_RANGES`)
+    pub webhook_sources: WebhookSourceFilter,
+    /// Deeper webhook bodies are rejected before parsing (`WEBHOOK_MAX_JSON_DEPTH`)
+    pub max_json_depth: usize,
+    /// Customer Portal configuration (`bpc_...`); Stripe's default when unset
+    pub portal_configuration: Option<String>,
+    /// Max age / skew of a signature's `t=` (`STRIPE_WEBHOOK_TOLERANCE`, default 300)
+    pub webhook_tolerance_secs: i64,
+    /// Account this deployment serves (`STRIPE_ACCOUNT_ID`, `acct_...`); when set,
+    /// events from another account or the other mode are rejected
+    pub account_id: Option<String>,
 }
 
+/// Stripe's API root, the only one live keys are sent to
+const STRIPE_API_BASE: &str = "https://api.stripe.com";
+
 impl StripeConfig {
     pub fn from_env() -> Self {
         Self {
@@ -39,6 +107,171 @@ impl StripeConfig {
             _publishable_key: std::env::var("STRIPE_PUBLISHABLE_KEY")
                 .unwrap_or_else(|_| "pk_test_placeholder".to_string()),
             redis_url: std::env::var("REDIS_URL").ok(),
+            business_error_status: parse_business_error_status(
+                std::env::var("WEBHOOK_BUSINESS_ERROR_STATUS")
+                    .ok()
+                    .as_deref(),
+            ),
+            abandonment_recovery: env_flag("CHECKOUT_RECOVERY_ENABLED"),
+            recovery_base_url: std::env::var("CHECKOUT_RECOVERY_URL")
+                .ok()
+                .map(|url| url.trim().trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+            api_base: std::env::var("STRIPE_API_BASE")
+                .ok()
+                .map(|base| base.trim().trim_end_matches('/').to_string())
+                .filter(|base| !base.is_empty())
+                .unwrap_or_else(|| STRIPE_API_BASE.to_string()),
+            test_clock: std::env::var("STRIPE_TEST_CLOCK").ok(),
+            api_version: std::env::var("STRIPE_API_VERSION").ok(),
+            dev_skip_signature: env_flag("DEV_SKIP_SIGNATURE"),
+            dedupe_by_request_key: env_flag("STRIPE_DEDUPE_BY_REQUEST_KEY"),
+            webhook_sources: WebhookSourceFilter::from_env("stripe"),
+            max_json_depth: env_parse("WEBHOOK_MAX_JSON_DEPTH", DEFAULT_MAX_JSON_DEPTH).max(1),
+            webhook_tolerance_secs: env_parse(
+                "STRIPE_WEBHOOK_TOLERANCE",
+                DEFAULT_SIGNATURE_TOLERANCE_SECS,
+            )
+            .max(1),
+            account_id: std::env::var("STRIPE_ACCOUNT_ID")
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty()),
+            portal_configuration: std::env::var("STRIPE_PORTAL_CONFIG_ID")
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty()),
+            event_allowlist: parse_event_allowlist(
+                std::env::var("STRIPE_EVENT_ALLOWLIST").ok().as_deref(),
+            ),
+        }
+        .validated()
+    }
+
+    /// O(1) - Live keys move real money
+    pub fn is_live(&self) -> bool {
+        self.secret_key.starts_with("sk_live_") || self.secret_key.starts_with("rk_live_")
+    }
+
+    /// O(1) - With `STRIPE_ACCOUNT_ID` set, reject events that belong elsewhere: a
+    /// Connect `account` other than ours, or a mode that doesn't match our keys
+    pub fn check_account(&self, event: &StripeEvent) -> Result<(), String> {
+        let Some(expected) = &self.account_id else {
+            return Ok(());
+        };
+        if let Some(account) = event.account.as_deref().filter(|a| a != expected) {
+            return Err(format!("account {} is not {}", account, expected));
+        }
+        if event.livemode != self.is_live() {
+            return Err(format!(
+                "{} event sent to a {} deployment",
+                audit::mode_label(event.livemode),
+                audit::mode_label(self.is_live())
+            ));
+        }
+        Ok(())
+    }
+
+    /// O(1) - Drop sandbox-only options when running against live keys
+    fn validated(mut self) -> Self {
+        if let Some(version) = &self.api_version {
+            if !is_valid_api_version(version) {
+                println!(
+                    "[CONFIG] ❌ STRIPE_API_VERSION '{}' is not YYYY-MM-DD[.release], ignoring",
+                    version
+                );
+                self.api_version = None;
+            }
+        }
+        if let Some(id) = &self.portal_configuration {
+            if !is_valid_portal_configuration(id) {
+                println!(
+                    "[CONFIG] ❌ STRIPE_PORTAL_CONFIG_ID '{}' is not a bpc_ id, using the default portal",
+                    id
+                );
+                self.portal_configuration = None;
+            }
+        }
+        if let Some(id) = &self.account_id {
+            if !is_valid_account_id(id) {
+                println!(
+                    "[CONFIG] ❌ STRIPE_ACCOUNT_ID '{}' is not an acct_ id, account guard disabled",
+                    id
+                );
+                self.account_id = None;
+            }
+        }
+        if self.abandonment_recovery && self.recovery_base_url.is_none() {
+            println!("[CONFIG] ❌ CHECKOUT_RECOVERY_ENABLED without CHECKOUT_RECOVERY_URL; no recovery links will be sent");
+        }
+        if self.is_live() && self.api_base != STRIPE_API_BASE {
+            println!("[CONFIG] ❌ STRIPE_API_BASE is not allowed with live keys, ignoring");
+            self.api_base = STRIPE_API_BASE.to_string();
+        }
+        if self.is_live() && self.test_clock.take().is_some() {
+            println!("[CONFIG] ❌ STRIPE_TEST_CLOCK is not allowed with live keys, ignoring");
+        }
+        if self.dev_skip_signature {
+            if self.is_live() {
+                self.dev_skip_signature = false;
+                println!("[CONFIG] ❌ DEV_SKIP_SIGNATURE refused with live keys; signatures stay ENFORCED");
+            } else {
+                println!("[CONFIG] 🚧🚧🚧 DEV_SKIP_SIGNATURE ACTIVE: webhook signatures are NOT verified 🚧🚧🚧");
+            }
+        }
+        self
+    }
+}
+
+/// O(1) - Stripe versions look like `2024-06-20` or `2024-09-30.acacia`
+fn is_valid_api_version(version: &str) -> bool {
+    let (date, release) = match version.split_once('.') {
+        Some((date, release)) => (date, Some(release)),
+        None => (version, None),
+    };
+    let date_ok = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok() && date.len() == 10;
+    let release_ok = release
+        .is_none_or(|r| !r.is_empty() && r.chars().all(|c| c.is_ascii_lowercase() || c == '_'));
+    date_ok && release_ok
+}
+
+/// O(n) - Account ids look like `acct_1Pabc...`
+fn is_valid_account_id(id: &str) -> bool {
+    id.strip_prefix("acct_")
+        .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// O(n) - Portal configuration ids look like `bpc_1Pabc...`
+fn is_valid_portal_configuration(id: &str) -> bool {
+    id.strip_prefix("bpc_")
+        .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// O(n) - Comma-separated event types; empty or unset means "route everything"
+fn parse_event_allowlist(raw: Option<&str>) -> Option<HashSet<String>> {
+    let types: HashSet<String> = raw?
+        .split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if types.is_empty() {
+        return None;
+    }
+    println!("[CONFIG] 🔐 Stripe event allowlist: {:?}", types);
+    Some(types)
+}
+
+/// O(1) - Accepts only 200 or 500; anything else falls back to 200
+fn parse_business_error_status(raw: Option<&str>) -> StatusCode {
+    match raw.map(str::trim) {
+        None | Some("") | Some("200") => StatusCode::OK,
+        Some("500") => StatusCode::INTERNAL_SERVER_ERROR,
+        Some(other) => {
+            println!(
+                "[CONFIG] ⚠️ WEBHOOK_BUSINESS_ERROR_STATUS must be 200 or 500 (got '{}'), using 200",
+                other
+            );
+            StatusCode::OK
         }
     }
 }
@@ -55,11 +288,196 @@ pub struct StripeEvent {
     pub created: i64,
     pub data: StripeEventData,
     pub livemode: bool,
+    /// API version the event object was rendered with (shapes differ across versions)
+    #[serde(default)]
+    pub api_version: Option<String>,
+    /// API request that triggered the event (absent for automatic events)
+    #[serde(default)]
+    pub request: Option<StripeEventRequest>,
+    /// Connected account the event happened on (Connect only)
+    #[serde(default)]
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeEventRequest {
+    pub id: Option<String>,
+    pub idempotency_key: Option<String>,
+}
+
+impl ProviderEvent for StripeEvent {
+    fn provider(&self) -> &'static str {
+        "stripe"
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    fn created(&self) -> Option<i64> {
+        Some(self.created)
+    }
+
+    fn livemode(&self) -> bool {
+        self.livemode
+    }
+
+    fn api_version(&self) -> Option<&str> {
+        self.api_version.as_deref()
+    }
+}
+
+impl StripeEvent {
+    /// O(1) - Dedupe key for logically identical deliveries under different event ids.
+    /// One API request can emit several event types, so the type is part of the key.
+    pub fn request_dedupe_key(&self) -> Option<String> {
+        let key = self.request.as_ref()?.idempotency_key.as_deref()?;
+        (!key.is_empty()).then(|| format!("reqkey:{}:{}", self.event_type, key))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Str,
+    Int,
+    Bool,
+    Object,
+}
+
+impl FieldKind {
+    fn matches(self, value: &serde_json::Value) -> bool {
+        match self {
+            FieldKind::Str => value.is_string(),
+            FieldKind::Int => value.is_i64(),
+            FieldKind::Bool => value.is_boolean(),
+            FieldKind::Object => value.is_object(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldKind::Str => "a string",
+            FieldKind::Int => "an integer",
+            FieldKind::Bool => "a boolean",
+            FieldKind::Object => "an object",
+        }
+    }
+}
+
+/// (field, kind, required) checked on `data.object` for the routed event types.
+/// Optional fields may be absent or null but must have the right type when set.
+fn object_schema(event_type: &str) -> &'static [(&'static str, FieldKind, bool)] {
+    use FieldKind::*;
+    match event_type {
+        "checkout.session.completed"
+        | "checkout.session.expired"
+        | "checkout.session.async_payment_succeeded"
+        | "checkout.session.async_payment_failed" => &[
+            ("id", Str, true),
+            ("status", Str, true),
+            ("payment_status", Str, false),
+            ("customer", Str, false),
+            ("customer_email", Str, false),
+            ("subscription", Str, false),
+            ("amount_total", Int, false),
+            ("metadata", Object, false),
+        ],
+        "payment_intent.succeeded" => &[
+            ("id", Str, true),
+            ("amount_received", Int, false),
+            ("receipt_email", Str, false),
+            ("invoice", Str, false),
+            ("metadata", Object, false),
+        ],
+        "invoice.paid" | "invoice.payment_succeeded" | "invoice.payment_failed" => &[
+            ("id", Str, true),
+            ("customer_email", Str, false),
+            ("amount_paid", Int, false),
+            ("lines", Object, false),
+        ],
+        "customer.subscription.created" | "customer.subscription.updated" => &[
+            ("id", Str, true),
+            ("status", Str, true),
+            ("customer", Str, false),
+            ("trial_end", Int, false),
+            ("current_period_end", Int, false),
+            ("metadata", Object, false),
+            ("items", Object, false),
+        ],
+        "customer.subscription.deleted" => &[("id", Str, true), ("customer_email", Str, false)],
+        "customer.updated" => &[("id", Str, true), ("email", Str, false)],
+        "setup_intent.succeeded" => &[
+            ("id", Str, true),
+            ("customer", Str, false),
+            ("payment_method", Str, false),
+            ("usage", Str, false),
+            ("metadata", Object, false),
+        ],
+        "charge.dispute.closed" => &[
+            ("id", Str, true),
+            ("status", Str, true),
+            ("charge", Str, false),
+            ("amount", Int, false),
+            ("evidence", Object, false),
+        ],
+        "charge.refunded" => &[
+            ("id", Str, true),
+            ("customer", Str, false),
+            ("receipt_email", Str, false),
+            ("amount_refunded", Int, false),
+            ("refunded", Bool, false),
+        ],
+        "charge.refund.updated" => &[
+            ("id", Str, true),
+            ("status", Str, true),
+            ("charge", Str, false),
+            ("amount", Int, false),
+        ],
+        _ => &[],
+    }
+}
+
+impl StripeEvent {
+    /// O(n) - Structural checks after parsing, before routing
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.id.starts_with("evt_") {
+            return Err(format!("id '{}' is not an event id", self.id));
+        }
+        if self.event_type.is_empty() {
+            return Err("type is empty".to_string());
+        }
+        let object = self
+            .data
+            .object
+            .as_object()
+            .ok_or("data.object must be an object")?;
+
+        for (field, kind, required) in object_schema(&self.event_type) {
+            match object.get(*field) {
+                None | Some(serde_json::Value::Null) if *required => {
+                    return Err(format!("data.object.{} is required", field));
+                }
+                None | Some(serde_json::Value::Null) => {}
+                Some(value) if !kind.matches(value) => {
+                    return Err(format!("data.object.{} must be {}", field, kind.name()));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StripeEventData {
     pub object: serde_json::Value,
+    /// Prior values of the fields an `*.updated` event changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_attributes: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,6 +490,194 @@ pub struct CheckoutSession {
     pub currency: Option<String>,
     pub status: String,
     pub metadata: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub customer_details: Option<CustomerDetails>,
+    #[serde(default)]
+    pub payment_status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomerDetails {
+    pub email: Option<String>,
+}
+
+impl CheckoutSession {
+    /// O(1) - Email entered at checkout, falling back to the collected details
+    pub fn email(&self) -> Option<&str> {
+        self.customer_email
+            .as_deref()
+            .or_else(|| self.customer_details.as_ref()?.email.as_deref())
+            .filter(|e| !e.is_empty())
+    }
+
+    /// O(1) - Plan key attached to the session at creation
+    pub fn plan(&self) -> Option<&str> {
+        self.metadata.as_ref()?.get("plan").map(|s| s.as_str())
+    }
+
+    /// O(1) - UTM / referrer tags attached to the session at creation
+    pub fn attribution(&self) -> HashMap<String, String> {
+        let Some(metadata) = &self.metadata else {
+            return HashMap::new();
+        };
+        ATTRIBUTION_KEYS
+            .iter()
+            .filter_map(|key| Some((key.to_string(), metadata.get(*key)?.clone())))
+            .collect()
+    }
+
+    /// O(1) - Completed and actually paid (or free)
+    pub fn is_paid(&self) -> bool {
+        self.status == "complete"
+            && matches!(
+                self.payment_status.as_deref(),
+                Some("paid") | Some("no_payment_required")
+            )
+    }
+
+    /// O(1) - Completed with a delayed method (SEPA, ACH...) whose funds have not
+    /// cleared; `async_payment_succeeded` / `_failed` follows
+    pub fn is_payment_pending(&self) -> bool {
+        self.payment_status.as_deref() == Some("unpaid")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentIntent {
+    pub id: String,
+    pub amount_received: Option<i64>,
+    pub currency: Option<String>,
+    pub customer: Option<String>,
+    pub receipt_email: Option<String>,
+    /// Set when the intent pays a subscription invoice
+    #[serde(default)]
+    pub invoice: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// Dispute object from charge.dispute.* events
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeDispute {
+    pub id: String,
+    /// `won` / `lost` once closed (also `warning_closed`, `needs_response`, ...)
+    pub status: String,
+    pub charge: Option<String>,
+    #[serde(default)]
+    pub amount: Option<i64>,
+    #[serde(default)]
+    pub evidence: serde_json::Value,
+}
+
+/// Charge object from charge.refunded (or fetched by id)
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeCharge {
+    pub id: String,
+    pub customer: Option<String>,
+    #[serde(default)]
+    pub receipt_email: Option<String>,
+    #[serde(default)]
+    pub billing_details: serde_json::Value,
+    #[serde(default)]
+    pub amount_refunded: Option<i64>,
+    /// True once the whole amount has been refunded
+    #[serde(default)]
+    pub refunded: bool,
+}
+
+/// Refund object from charge.refund.updated
+#[derive(Debug, Clone, Deserialize)]
+pub struct StripeRefund {
+    pub id: String,
+    /// `pending` / `succeeded` / `failed` / `canceled` / `requires_action`
+    pub status: String,
+    pub charge: Option<String>,
+}
+
+/// SetupIntent object from setup_intent.* events (card saved without a charge)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeSetupIntent {
+    pub id: String,
+    pub customer: Option<String>,
+    pub payment_method: Option<String>,
+    /// `off_session` when the card may be charged without the customer present
+    #[serde(default)]
+    pub usage: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// Customer object from customer.* events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeCustomer {
+    pub id: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+/// Subscription object from customer.subscription.* events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeSubscription {
+    pub id: String,
+    pub customer: Option<String>,
+    pub status: String,
+    #[serde(default)]
+    pub trial_end: Option<i64>,
+    #[serde(default)]
+    pub current_period_end: Option<i64>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub items: SubscriptionItems,
+}
+
+/// Stripe list object (`{"object": "list", "data": [...]}`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubscriptionItems {
+    #[serde(default)]
+    pub data: Vec<SubscriptionItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionItem {
+    pub price: Option<StripePrice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripePrice {
+    pub id: String,
+}
+
+impl StripeSubscription {
+    /// O(1) - Price of the first item (we sell single-item subscriptions)
+    pub fn price_id(&self) -> Option<&str> {
+        self.items
+            .data
+            .first()?
+            .price
+            .as_ref()
+            .map(|p| p.id.as_str())
+    }
+}
+
+/// O(1) - Stripe timestamps are unix seconds
+fn from_unix(ts: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(ts, 0)
+}
+
+impl PaymentIntent {
+    /// O(1) - Receipt email, falling back to an `email` metadata entry
+    pub fn email(&self) -> Option<&str> {
+        self.receipt_email
+            .as_deref()
+            .or_else(|| self.metadata.get("email").map(|s| s.as_str()))
+            .filter(|e| !e.is_empty())
+    }
+
+    /// O(1) - Plan key attached by the API/Elements integration
+    pub fn plan(&self) -> Option<&str> {
+        self.metadata.get("plan").map(|s| s.as_str())
+    }
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -82,6 +688,26 @@ pub struct CheckoutSession {
 pub struct IdempotencyStore {
     redis_client: Option<redis::Client>,
     processed_events_fallback: Arc<RwLock<HashMap<String, ProcessedEvent>>>,
+    /// Events a delivery is working on right now, and when the claim lapses
+    claims_fallback: Arc<std::sync::Mutex<HashMap<String, Instant>>>,
+    /// `WEBHOOK_CLAIM_TTL_SECS` (default 60): a claim outlives a crashed worker this long
+    claim_ttl: Duration,
+}
+
+/// A processed marker that could not be persisted
+#[derive(Debug)]
+pub enum StoreError {
+    Serialize(String),
+    Redis(String),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Serialize(e) => write!(f, "Idempotency record not serializable: {}", e),
+            StoreError::Redis(e) => write!(f, "Idempotency write to Redis failed: {}", e),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -89,15 +715,43 @@ pub struct ProcessedEvent {
     pub event_id: String,
     pub processed_at: DateTime<Utc>,
     pub result: EventResult,
+    #[serde(default)]
+    pub api_version: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EventResult {
-    Success { user_id: Uuid, plan: String },
-    Failed { error: String },
+    Success {
+        user_id: Uuid,
+        plan: String,
+    },
+    /// Handled successfully without touching a subscription
+    Processed,
+    Failed {
+        error: String,
+    },
     Duplicate,
 }
 
+impl EventResult {
+    /// O(1) - Failed outcomes may be retried on redelivery
+    pub fn is_failure(&self) -> bool {
+        matches!(self, EventResult::Failed { .. })
+    }
+
+    /// O(1) - Success naming the subscription an event left behind; Processed
+    /// when there is none (e.g. activation deferred to the retry queue)
+    pub fn for_subscription(subscription: Option<UserSubscription>) -> Self {
+        match subscription {
+            Some(sub) => EventResult::Success {
+                user_id: sub.user_id,
+                plan: sub.plan.key().to_string(),
+            },
+            None => EventResult::Processed,
+        }
+    }
+}
+
 impl IdempotencyStore {
     pub fn new(redis_url: Option<String>) -> Self {
         let redis_client = redis_url.and_then(|url| {
@@ -109,47 +763,208 @@ impl IdempotencyStore {
         Self {
             redis_client,
             processed_events_fallback: Arc::new(RwLock::new(HashMap::new())),
+            claims_fallback: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            claim_ttl: Duration::from_secs(env_parse("WEBHOOK_CLAIM_TTL_SECS", 60u64).max(1)),
         }
     }
 
-    /// O(1) - Check if event already processed
-    pub async fn is_processed(&self, event_id: &str) -> bool {
+    /// O(1) - Atomically take `event_id` for processing (Redis `SET NX EX`, or a
+    /// locked insert without Redis); false while another delivery holds it
+    pub async fn try_claim(&self, event_id: &str) -> bool {
         if let Some(client) = &self.redis_client {
             if let Ok(mut con) = client.get_multiplexed_async_connection().await {
-                let exists: bool = con
-                    .exists(format!("event:{}", event_id))
-                    .await
-                    .unwrap_or(false);
-                return exists;
+                let claimed: Result<Option<String>, _> = redis::cmd("SET")
+                    .arg(format!("claim:{}", event_id))
+                    .arg(1)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(self.claim_ttl.as_secs())
+                    .query_async(&mut con)
+                    .await;
+                match claimed {
+                    Ok(reply) => return reply.is_some(),
+                    Err(e) => println!(
+                        "[IDEMPOTENCY] ⚠️ Claim on Redis failed ({}); claiming {} locally",
+                        e, event_id
+                    ),
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let mut claims = self.claims_fallback.lock().unwrap();
+        claims.retain(|_, expires| *expires > now);
+        if claims.contains_key(event_id) {
+            return false;
+        }
+        claims.insert(event_id.to_string(), now + self.claim_ttl);
+        true
+    }
+
+    /// O(1) - Let the next delivery of `event_id` claim it
+    pub async fn release(&self, event_id: &str) {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let _: () = con.del(format!("claim:{}", event_id)).await.unwrap_or(());
+            }
+        }
+
+        self.claims_fallback.lock().unwrap().remove(event_id);
+    }
+
+    /// O(1) - Fetch the stored outcome of a processed event
+    pub async fn get(&self, event_id: &str) -> Option<ProcessedEvent> {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let json: Option<String> =
+                    con.get(format!("event:{}", event_id)).await.unwrap_or(None);
+                if let Some(record) = json.and_then(|j| serde_json::from_str(&j).ok()) {
+                    return Some(record);
+                }
+                // Markers kept locally after a failed Redis write
             }
         }
 
         let store = self.processed_events_fallback.read().await;
-        store.contains_key(event_id)
+        store.get(event_id).cloned()
     }
 
     /// O(1) - Mark event as processed with idempotency guarantee
-    pub async fn mark_processed(&self, event_id: String, result: EventResult) {
+    pub async fn mark_processed(
+        &self,
+        event_id: String,
+        result: EventResult,
+        api_version: Option<String>,
+    ) -> Result<(), StoreError> {
+        self.mark_processed_as(event_id.clone(), event_id, result, api_version)
+            .await
+    }
+
+    /// O(1) - Store an outcome under `key`, attributed to `event_id`. Without a
+    /// Redis connection the in-memory store is used; a failed Redis write is
+    /// returned so the caller can decide how to recover.
+    pub async fn mark_processed_as(
+        &self,
+        key: String,
+        event_id: String,
+        result: EventResult,
+        api_version: Option<String>,
+    ) -> Result<(), StoreError> {
+        let record = ProcessedEvent {
+            event_id,
+            processed_at: Utc::now(),
+            result,
+            api_version,
+        };
+
         if let Some(client) = &self.redis_client {
             if let Ok(mut con) = client.get_multiplexed_async_connection().await {
-                let json = serde_json::to_string(&result).unwrap();
-                let _: () = con
-                    .set_ex(format!("event:{}", event_id), json, 86400)
+                let json = serde_json::to_string(&record)
+                    .map_err(|e| StoreError::Serialize(e.to_string()))?;
+                return con
+                    .set_ex(format!("event:{}", key), json, 86400) // 24h expire
+                    .await
+                    .map_err(|e| StoreError::Redis(e.to_string()));
+            }
+        }
+
+        self.mark_processed_local(key, record).await;
+        Ok(())
+    }
+
+    /// O(1) - In-memory marker only; the fallback after a failed Redis write
+    async fn mark_processed_local(&self, key: String, record: ProcessedEvent) {
+        let mut store = self.processed_events_fallback.write().await;
+        store.insert(key, record);
+    }
+
+    /// O(1) - `mark_processed_as`, keeping the marker in memory when Redis
+    /// refuses it so this instance at least won't reprocess the event. Returns
+    /// the Redis error in that case.
+    pub async fn mark_processed_with_fallback(
+        &self,
+        key: String,
+        event_id: String,
+        result: EventResult,
+        api_version: Option<String>,
+    ) -> Option<StoreError> {
+        let fallback = ProcessedEvent {
+            event_id: event_id.clone(),
+            processed_at: Utc::now(),
+            result: result.clone(),
+            api_version: api_version.clone(),
+        };
+        if let Err(e) = self
+            .mark_processed_as(key.clone(), event_id, result, api_version)
+            .await
+        {
+            println!("[IDEMPOTENCY] ⚠️ {}; keeping {} in memory", e, key);
+            self.mark_processed_local(key, fallback).await;
+            return Some(e);
+        }
+        None
+    }
+
+    /// O(1) - `mark_processed_with_fallback` under the event's own id
+    pub async fn record(
+        &self,
+        event: &impl ProviderEvent,
+        result: EventResult,
+    ) -> Option<StoreError> {
+        self.mark_processed_with_fallback(
+            event.id().to_string(),
+            event.id().to_string(),
+            result,
+            event.api_version().map(|v| v.to_string()),
+        )
+        .await
+    }
+
+    /// O(1) - Drop the marker so the event can be processed again (replays)
+    pub async fn forget(&self, event_id: &str) {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let _: () = con.del(format!("event:{}", event_id)).await.unwrap_or(());
+            }
+        }
+
+        // Also clears markers kept locally after a failed Redis write
+        let mut store = self.processed_events_fallback.write().await;
+        store.remove(event_id);
+    }
+
+    /// O(n) - Drop every marker (Redis `event:*` and the in-memory fallback) so
+    /// any redelivered or replayed event is processed again; returns how many went
+    pub async fn forget_all(&self) -> Result<usize, String> {
+        let mut cleared = 0;
+        if let Some(client) = &self.redis_client {
+            let mut con = client
+                .get_multiplexed_async_connection()
+                .await
+                .map_err(|e| format!("Redis unavailable: {}", e))?;
+            let mut keys: Vec<String> = Vec::new();
+            let mut scan_con = con.clone();
+            let mut iter = scan_con
+                .scan_match::<_, String>("event:*")
+                .await
+                .map_err(|e| format!("Redis scan failed: {}", e))?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            drop(iter);
+            for key in keys {
+                let removed: usize = con
+                    .del(&key)
                     .await
-                    .unwrap_or(()); // 24h expire
-                return;
+                    .map_err(|e| format!("Redis delete failed: {}", e))?;
+                cleared += removed;
             }
         }
 
         let mut store = self.processed_events_fallback.write().await;
-        store.insert(
-            event_id.clone(),
-            ProcessedEvent {
-                event_id,
-                processed_at: Utc::now(),
-                result,
-            },
-        );
+        cleared += store.len();
+        store.clear();
+        Ok(cleared)
     }
 }
 
@@ -157,11 +972,62 @@ impl IdempotencyStore {
 // SUBSCRIPTION MANAGER
 // ═══════════════════════════════════════════════════════════════════════════════
 
+type SubscriptionShard = RwLock<HashMap<String, UserSubscription>>;
+
 #[derive(Clone)]
 pub struct SubscriptionManager {
-    // In production, use DB. For now, in-memory is fine for demo,
-    // or Redis could also be used here. Keeping in-memory/Redis simplicity.
-    subscriptions: Arc<RwLock<HashMap<String, UserSubscription>>>,
+    // Working set in memory, sharded by email hash so writes to different
+    // customers don't serialize. With Redis every change is written through
+    // to `subscription:{email}` and loaded back at boot.
+    shards: Arc<[SubscriptionShard]>,
+    redis_client: Option<redis::Client>,
+    cancel_downgrade: CancelDowngradePolicy,
+}
+
+/// When a canceled subscription's plan drops to Free (`CANCEL_DOWNGRADE_POLICY`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CancelDowngradePolicy {
+    /// Keep the paid plan on record until `current_period_end` (default)
+    KeepUntilPeriodEnd,
+    /// Free as soon as the subscription is canceled
+    Immediate,
+}
+
+impl CancelDowngradePolicy {
+    /// O(1) - `keep_until_period_end` / `immediate` (case-insensitive)
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "keep_until_period_end" => Some(CancelDowngradePolicy::KeepUntilPeriodEnd),
+            "immediate" => Some(CancelDowngradePolicy::Immediate),
+            _ => None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        match std::env::var("CANCEL_DOWNGRADE_POLICY") {
+            Ok(raw) => Self::parse(&raw).unwrap_or_else(|| {
+                println!(
+                    "[CONFIG] ⚠️ Unknown CANCEL_DOWNGRADE_POLICY '{}', using keep_until_period_end",
+                    raw
+                );
+                CancelDowngradePolicy::KeepUntilPeriodEnd
+            }),
+            Err(_) => CancelDowngradePolicy::KeepUntilPeriodEnd,
+        }
+    }
+
+    /// O(1) - True when `sub` is canceled, still on a paid plan, and the policy
+    /// says that plan has run out (no known period end counts as run out)
+    pub fn downgrade_due(self, sub: &UserSubscription, now: DateTime<Utc>) -> bool {
+        sub.status == SubscriptionStatus::Canceled
+            && sub.plan != SubscriptionPlan::Free
+            && match self {
+                CancelDowngradePolicy::Immediate => true,
+                CancelDowngradePolicy::KeepUntilPeriodEnd => {
+                    sub.current_period_end.is_none_or(|end| end <= now)
+                }
+            }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -174,6 +1040,39 @@ pub struct UserSubscription {
     pub status: SubscriptionStatus,
     pub activated_at: DateTime<Utc>,
     pub current_period_end: Option<DateTime<Utc>>,
+    /// End of the free trial while `Trialing`
+    #[serde(default)]
+    pub trial_end: Option<DateTime<Utc>>,
+    /// When the subscription entered `PastDue`; starts the dunning grace period
+    #[serde(default)]
+    pub past_due_since: Option<DateTime<Utc>>,
+    /// UTM / referrer tags of the checkout that created the subscription
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub attribution: HashMap<String, String>,
+    /// Card saved via a SetupIntent (`pm_...`), usable for off-session charges
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved_payment_method: Option<String>,
+}
+
+impl UserSubscription {
+    /// O(1) - Paid tier applies; PastDue keeps access during the dunning grace period
+    pub fn grants_access(&self) -> bool {
+        matches!(
+            self.status,
+            SubscriptionStatus::Active | SubscriptionStatus::Trialing | SubscriptionStatus::PastDue
+        )
+    }
+
+    /// O(1) - Change status, keeping `past_due_since` in step
+    pub fn set_status(&mut self, status: SubscriptionStatus) {
+        match status {
+            SubscriptionStatus::PastDue => {
+                self.past_due_since.get_or_insert_with(Utc::now);
+            }
+            _ => self.past_due_since = None,
+        }
+        self.status = status;
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -183,20 +1082,211 @@ pub enum SubscriptionPlan {
     Enterprise { monthly: bool },
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-pub enum SubscriptionStatus {
-    Active,
-    Trialing,
-    PastDue,
-    Canceled,
-    Unpaid,
-}
+impl SubscriptionPlan {
+    /// O(1) - Tier name used as the JWT role
+    pub fn role(&self) -> &'static str {
+        match self {
+            SubscriptionPlan::Free => "free",
+            SubscriptionPlan::Pro { .. } => "pro",
+            SubscriptionPlan::Enterprise { .. } => "enterprise",
+        }
+    }
+
+    /// O(1) - Canonical plan key; `parse_key` maps it back to the same plan
+    pub fn key(&self) -> &'static str {
+        match self {
+            SubscriptionPlan::Free => "free",
+            SubscriptionPlan::Pro { monthly: true } => "pro_monthly",
+            SubscriptionPlan::Pro { monthly: false } => "pro_annual",
+            SubscriptionPlan::Enterprise { monthly: true } => "enterprise_monthly",
+            SubscriptionPlan::Enterprise { monthly: false } => "enterprise_annual",
+        }
+    }
+
+    /// O(1) - Resolve a plan key (checkout metadata) to a plan
+    pub fn from_key(plan_name: &str) -> Self {
+        Self::parse_key(plan_name).unwrap_or(SubscriptionPlan::Free)
+    }
+
+    /// O(1) - Strict variant of `from_key`: unknown keys are `None`
+    pub fn parse_key(plan_name: &str) -> Option<Self> {
+        match plan_name {
+            "free" => Some(SubscriptionPlan::Free),
+            "pro_monthly" => Some(SubscriptionPlan::Pro { monthly: true }),
+            "pro_annual" => Some(SubscriptionPlan::Pro { monthly: false }),
+            "enterprise_monthly" => Some(SubscriptionPlan::Enterprise { monthly: true }),
+            "enterprise_annual" => Some(SubscriptionPlan::Enterprise { monthly: false }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum SubscriptionStatus {
+    Active,
+    Trialing,
+    PastDue,
+    Canceled,
+    Unpaid,
+}
+
+impl SubscriptionStatus {
+    /// O(1) - Parse Stripe's snake_case subscription status
+    pub fn from_stripe(status: &str) -> Option<Self> {
+        match status {
+            "active" => Some(SubscriptionStatus::Active),
+            "trialing" => Some(SubscriptionStatus::Trialing),
+            "past_due" => Some(SubscriptionStatus::PastDue),
+            "canceled" => Some(SubscriptionStatus::Canceled),
+            "unpaid" => Some(SubscriptionStatus::Unpaid),
+            _ => None,
+        }
+    }
+}
+
+/// O(1) - The one form subscriptions are keyed by: trimmed and lowercased, so
+/// `Foo@x.com` from checkout and `foo@x.com` from a receipt are the same record
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
 
 impl SubscriptionManager {
+    /// `SUBSCRIPTION_SHARDS` (default 16) and `CANCEL_DOWNGRADE_POLICY`
     pub fn new() -> Self {
+        let count = env_parse("SUBSCRIPTION_SHARDS", 16usize).max(1);
         Self {
-            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            shards: (0..count).map(|_| RwLock::new(HashMap::new())).collect(),
+            redis_client: None,
+            cancel_downgrade: CancelDowngradePolicy::from_env(),
+        }
+    }
+
+    /// Write every change through to `subscription:{email}`; in-memory only
+    /// without `redis_url`
+    pub fn with_redis(mut self, redis_url: Option<String>) -> Self {
+        self.redis_client = redis_url.and_then(|url| {
+            redis::Client::open(url)
+                .map_err(|e| println!("❌ Redis connect error: {}", e))
+                .ok()
+        });
+        self
+    }
+
+    /// O(1) - Write one record through to Redis; on failure the in-memory copy
+    /// stands and the next change to it retries the write. Activations pass the
+    /// error on (retry queue); other changes are best-effort.
+    async fn persist(&self, subscription: &UserSubscription) -> Result<(), String> {
+        let Some(client) = &self.redis_client else {
+            return Ok(());
+        };
+        let result = match client.get_multiplexed_async_connection().await {
+            Ok(mut con) => match serde_json::to_string(subscription) {
+                Ok(json) => con
+                    .set::<_, _, ()>(format!("subscription:{}", subscription.email), json)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = &result {
+            println!(
+                "[SUBSCRIPTION] ⚠️ Redis write for {} failed: {}",
+                subscription.email, e
+            );
+        }
+        result.map_err(|e| format!("Redis write failed: {}", e))
+    }
+
+    /// O(1) - Drop a record's Redis key (after it moved to another email)
+    async fn forget_persisted(&self, email: &str) {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let _: () = con
+                    .del(format!("subscription:{}", email))
+                    .await
+                    .unwrap_or(());
+            }
+        }
+    }
+
+    /// O(n) - Load every `subscription:*` record from Redis into memory (boot);
+    /// returns how many were loaded
+    pub async fn load_persisted(&self) -> usize {
+        let Some(client) = &self.redis_client else {
+            return 0;
+        };
+        let mut con = match client.get_multiplexed_async_connection().await {
+            Ok(con) => con,
+            Err(e) => {
+                println!("[SUBSCRIPTION] ❌ Redis unavailable, starting empty: {}", e);
+                return 0;
+            }
+        };
+
+        let mut keys: Vec<String> = Vec::new();
+        match con.clone().scan_match::<_, String>("subscription:*").await {
+            Ok(mut iter) => {
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+            }
+            Err(e) => {
+                println!("[SUBSCRIPTION] ❌ Redis scan failed: {}", e);
+                return 0;
+            }
+        }
+
+        let mut loaded = 0;
+        let mut rekeyed = Vec::new();
+        for key in keys {
+            let json: Option<String> = con.get(&key).await.unwrap_or(None);
+            match json.map(|j| serde_json::from_str::<UserSubscription>(&j)) {
+                Some(Ok(mut sub)) => {
+                    // Records written before emails were normalized move to the
+                    // lowercase key; a record already under that key wins
+                    let email = normalize_email(&sub.email);
+                    let moved = email != sub.email;
+                    if moved {
+                        rekeyed.push((sub.email.clone(), email.clone()));
+                        sub.email = email.clone();
+                    }
+                    let mut store = self.shard(&email).write().await;
+                    let taken = store.contains_key(&email);
+                    if !(moved && taken) {
+                        store.insert(email, sub);
+                    }
+                    if !taken {
+                        loaded += 1;
+                    }
+                }
+                Some(Err(e)) => println!("[SUBSCRIPTION] ⚠️ Skipping unreadable {}: {}", key, e),
+                None => {}
+            }
+        }
+
+        for (old, email) in rekeyed {
+            let Some(sub) = self.shard(&email).read().await.get(&email).cloned() else {
+                continue;
+            };
+            if self.persist(&sub).await.is_ok() {
+                self.forget_persisted(&old).await;
+                println!("[SUBSCRIPTION] 🔑 Re-keyed {} as {}", old, email);
+            }
         }
+        loaded
+    }
+
+    /// O(1) - Index of the shard owning `email`
+    fn shard_index(&self, email: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        email.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// O(1) - Shard owning `email`
+    fn shard(&self, email: &str) -> &SubscriptionShard {
+        &self.shards[self.shard_index(email)]
     }
 
     /// Activate subscription after successful payment
@@ -207,14 +1297,32 @@ impl SubscriptionManager {
         stripe_subscription_id: Option<String>,
         plan_name: &str,
     ) -> UserSubscription {
+        let subscription = self
+            .insert_activation(
+                email,
+                stripe_customer_id,
+                stripe_subscription_id,
+                plan_name,
+                HashMap::new(),
+            )
+            .await;
+        self.persist(&subscription).await.ok();
+        subscription
+    }
+
+    /// O(1) - Record a fresh active subscription in memory, replacing any
+    /// previous one for the email
+    async fn insert_activation(
+        &self,
+        email: &str,
+        stripe_customer_id: Option<String>,
+        stripe_subscription_id: Option<String>,
+        plan_name: &str,
+        attribution: HashMap<String, String>,
+    ) -> UserSubscription {
+        let email = &normalize_email(email);
         let user_id = Uuid::new_v4();
-        let plan = match plan_name {
-            "pro_monthly" => SubscriptionPlan::Pro { monthly: true },
-            "pro_annual" => SubscriptionPlan::Pro { monthly: false },
-            "enterprise_monthly" => SubscriptionPlan::Enterprise { monthly: true },
-            "enterprise_annual" => SubscriptionPlan::Enterprise { monthly: false },
-            _ => SubscriptionPlan::Free,
-        };
+        let plan = SubscriptionPlan::from_key(plan_name);
 
         let subscription = UserSubscription {
             user_id,
@@ -225,33 +1333,404 @@ impl SubscriptionManager {
             status: SubscriptionStatus::Active,
             activated_at: Utc::now(),
             current_period_end: None,
+            trial_end: None,
+            past_due_since: None,
+            attribution,
+            saved_payment_method: None,
         };
 
-        let mut store = self.subscriptions.write().await;
-        store.insert(email.to_string(), subscription.clone());
+        self.shard(email)
+            .write()
+            .await
+            .insert(email.to_string(), subscription.clone());
 
         println!("[SUBSCRIPTION] ✅ Activated {} for {}", plan_name, email);
 
         subscription
     }
 
-    /// Get subscription by email
-    pub async fn _get_by_email(&self, email: &str) -> Option<Uuid> {
-        let store = self.subscriptions.read().await;
-        store.get(email).map(|sub| sub.user_id)
+    /// O(1) - Persisting activation step: a failed Redis write is returned, and
+    /// callers send the activation to the retry queue
+    pub async fn try_activate(
+        &self,
+        activation: &PendingActivation,
+    ) -> Result<UserSubscription, String> {
+        let subscription = self
+            .insert_activation(
+                &activation.email,
+                activation.stripe_customer_id.clone(),
+                activation.stripe_subscription_id.clone(),
+                &activation.plan,
+                activation.attribution.clone(),
+            )
+            .await;
+        // Access is granted in memory either way; Err means it isn't durable yet
+        self.persist(&subscription).await?;
+        Ok(subscription)
+    }
+
+    /// O(1) - Insert or replace by email, keeping the original user id, activation time
+    /// and (unless replaced) attribution. Returns true when the record was newly created.
+    pub async fn upsert_subscription(&self, mut subscription: UserSubscription) -> bool {
+        subscription.email = normalize_email(&subscription.email);
+        let created = {
+            let mut store = self.shard(&subscription.email).write().await;
+            let created = match store.get(&subscription.email) {
+                Some(existing) => {
+                    subscription.user_id = existing.user_id;
+                    subscription.activated_at = existing.activated_at;
+                    if subscription.attribution.is_empty() {
+                        subscription.attribution = existing.attribution.clone();
+                    }
+                    if subscription.saved_payment_method.is_none() {
+                        subscription.saved_payment_method = existing.saved_payment_method.clone();
+                    }
+                    false
+                }
+                None => true,
+            };
+            store.insert(subscription.email.clone(), subscription.clone());
+            created
+        };
+        self.persist(&subscription).await.ok();
+        created
     }
 
-    /// Cancel subscription
-    pub async fn cancel_subscription(&self, email: &str) -> bool {
-        let mut store = self.subscriptions.write().await;
-        if let Some(sub) = store.get_mut(email) {
-            sub.status = SubscriptionStatus::Canceled;
-            println!("[SUBSCRIPTION] ❌ Canceled subscription for {}", email);
-            true
+    /// O(1) - Insert unless `email` already has a record (snapshot restore on top
+    /// of what Redis loaded, which is newer). Returns true when inserted.
+    pub async fn insert_if_absent(&self, mut subscription: UserSubscription) -> bool {
+        subscription.email = normalize_email(&subscription.email);
+        {
+            let mut store = self.shard(&subscription.email).write().await;
+            if store.contains_key(&subscription.email) {
+                return false;
+            }
+            store.insert(subscription.email.clone(), subscription.clone());
+        }
+        self.persist(&subscription).await.ok();
+        true
+    }
+
+    /// O(1) - Full subscription record by email. Redis is authoritative, so writes
+    /// from other instances are seen; the in-memory copy answers when Redis is not
+    /// configured, fails, or does not hold the record yet.
+    pub async fn get(&self, email: &str) -> Option<UserSubscription> {
+        let email = &normalize_email(email);
+        if let Some(client) = &self.redis_client {
+            match Self::fetch_persisted(client, email).await {
+                Ok(Some(sub)) => {
+                    self.shard(email)
+                        .write()
+                        .await
+                        .insert(email.to_string(), sub.clone());
+                    return Some(sub);
+                }
+                // Not written through yet (Redis was down at the time)
+                Ok(None) => {}
+                Err(e) => println!(
+                    "[SUBSCRIPTION] ⚠️ Redis read for {} failed ({}); using the local copy",
+                    email, e
+                ),
+            }
+        }
+
+        self.shard(email).read().await.get(email).cloned()
+    }
+
+    /// O(1) - `subscription:{email}` from Redis; Ok(None) when the key is absent
+    async fn fetch_persisted(
+        client: &redis::Client,
+        email: &str,
+    ) -> Result<Option<UserSubscription>, String> {
+        let mut con = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let json: Option<String> = con
+            .get(format!("subscription:{}", email))
+            .await
+            .map_err(|e| e.to_string())?;
+        json.map(|j| serde_json::from_str(&j).map_err(|e| e.to_string()))
+            .transpose()
+    }
+
+    /// O(n) - Email of the subscription holding these Stripe ids
+    pub async fn email_for_stripe(
+        &self,
+        customer_id: Option<&str>,
+        subscription_id: &str,
+    ) -> Option<String> {
+        for shard in self.shards.iter() {
+            let store = shard.read().await;
+            let found = store.values().find(|sub| {
+                sub.stripe_subscription_id.as_deref() == Some(subscription_id)
+                    || (customer_id.is_some() && sub.stripe_customer_id.as_deref() == customer_id)
+            });
+            if let Some(sub) = found {
+                return Some(sub.email.clone());
+            }
+        }
+        None
+    }
+
+    /// O(n) - Email of the subscription belonging to a Stripe customer
+    pub async fn email_for_customer(&self, customer_id: &str) -> Option<String> {
+        for shard in self.shards.iter() {
+            let store = shard.read().await;
+            let found = store
+                .values()
+                .find(|sub| sub.stripe_customer_id.as_deref() == Some(customer_id));
+            if let Some(sub) = found {
+                return Some(sub.email.clone());
+            }
+        }
+        None
+    }
+
+    /// O(1) - Re-key a record after the customer changed their email, keeping
+    /// everything else. False when nothing is under `old` or `new` is taken.
+    pub async fn change_email(&self, old: &str, new: &str) -> bool {
+        let (old, new) = (&normalize_email(old), &normalize_email(new));
+        let (from, to) = (self.shard_index(old), self.shard_index(new));
+        let moved = if from == to {
+            let mut store = self.shards[from].write().await;
+            move_record(None, &mut store, old, new)
         } else {
-            false
+            // Locks always taken in shard order so concurrent moves can't deadlock
+            let (mut source, mut target) = if from < to {
+                let source = self.shards[from].write().await;
+                (source, self.shards[to].write().await)
+            } else {
+                let target = self.shards[to].write().await;
+                (self.shards[from].write().await, target)
+            };
+            move_record(Some(&mut source), &mut target, old, new)
+        };
+        let Some(moved) = moved else {
+            return false;
+        };
+        self.persist(&moved).await.ok();
+        self.forget_persisted(old).await;
+        true
+    }
+
+    /// O(1) - Mirror a Stripe subscription object onto an existing record
+    pub async fn apply_stripe_subscription(
+        &self,
+        email: &str,
+        subscription: &StripeSubscription,
+    ) -> bool {
+        let email = &normalize_email(email);
+        let updated = {
+            let mut store = self.shard(email).write().await;
+            let Some(sub) = store.get_mut(email) else {
+                return false;
+            };
+            if let Some(status) = SubscriptionStatus::from_stripe(&subscription.status) {
+                sub.set_status(status);
+            }
+            sub.stripe_subscription_id = Some(subscription.id.clone());
+            sub.trial_end = subscription.trial_end.and_then(from_unix);
+            if let Some(end) = subscription.current_period_end.and_then(from_unix) {
+                sub.current_period_end = Some(end);
+            }
+            println!(
+                "[SUBSCRIPTION] 🔄 {} is {:?} (trial_end: {:?})",
+                email, sub.status, sub.trial_end
+            );
+            sub.clone()
+        };
+        self.persist(&updated).await.ok();
+        true
+    }
+
+    /// O(n) - Move subscriptions past due for longer than `grace` to `Unpaid`
+    /// (no access); returns the affected emails
+    pub async fn expire_past_due(&self, grace: chrono::Duration) -> Vec<String> {
+        let cutoff = Utc::now() - grace;
+        let mut expired = Vec::new();
+        for shard in self.shards.iter() {
+            let mut store = shard.write().await;
+            for sub in store.values_mut() {
+                if sub.status == SubscriptionStatus::PastDue
+                    && sub.past_due_since.is_some_and(|since| since <= cutoff)
+                {
+                    sub.set_status(SubscriptionStatus::Unpaid);
+                    expired.push(sub.clone());
+                }
+            }
+        }
+        for sub in &expired {
+            self.persist(sub).await.ok();
+        }
+        expired.into_iter().map(|sub| sub.email).collect()
+    }
+
+    /// O(n) - Drop canceled subscriptions whose paid period is over (per
+    /// `CANCEL_DOWNGRADE_POLICY`) to the Free plan; returns the affected emails
+    pub async fn downgrade_canceled(&self) -> Vec<String> {
+        let now = Utc::now();
+        let mut downgraded = Vec::new();
+        for shard in self.shards.iter() {
+            let mut store = shard.write().await;
+            for sub in store.values_mut() {
+                if self.cancel_downgrade.downgrade_due(sub, now) {
+                    sub.plan = SubscriptionPlan::Free;
+                    downgraded.push(sub.clone());
+                }
+            }
+        }
+        for sub in &downgraded {
+            self.persist(sub).await.ok();
+        }
+        downgraded.into_iter().map(|sub| sub.email).collect()
+    }
+
+    /// O(n) - Copy of every subscription (snapshots)
+    pub async fn all(&self) -> Vec<UserSubscription> {
+        let mut all = Vec::new();
+        for index in 0..self.shard_count() {
+            all.extend(self.shard_records(index).await);
+        }
+        all
+    }
+
+    /// O(1) - Number of shards, for callers walking the store one shard at a time
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// O(k) - Copy of one shard's subscriptions (empty past the last shard)
+    pub async fn shard_records(&self, index: usize) -> Vec<UserSubscription> {
+        match self.shards.get(index) {
+            Some(shard) => shard.read().await.values().cloned().collect(),
+            None => Vec::new(),
         }
     }
+
+    /// O(1) - Set the status of an existing subscription
+    pub async fn update_status(&self, email: &str, status: SubscriptionStatus) -> bool {
+        let email = &normalize_email(email);
+        let updated = {
+            let mut store = self.shard(email).write().await;
+            let Some(sub) = store.get_mut(email) else {
+                return false;
+            };
+            println!(
+                "[SUBSCRIPTION] 🔄 Status {:?} -> {:?} for {}",
+                sub.status, status, email
+            );
+            sub.set_status(status);
+            sub.clone()
+        };
+        self.persist(&updated).await.ok();
+        true
+    }
+
+    /// O(1) - Remember the payment method a SetupIntent saved for off-session charges
+    pub async fn save_payment_method(&self, email: &str, payment_method: &str) -> bool {
+        let email = &normalize_email(email);
+        let updated = {
+            let mut store = self.shard(email).write().await;
+            let Some(sub) = store.get_mut(email) else {
+                return false;
+            };
+            sub.saved_payment_method = Some(payment_method.to_string());
+            sub.clone()
+        };
+        self.persist(&updated).await.ok();
+        true
+    }
+
+    /// O(1) - A won chargeback returns the funds: revoked access comes back,
+    /// anything else (canceled, refunded, still active) is left alone
+    pub async fn restore_after_dispute(&self, email: &str) -> bool {
+        let email = &normalize_email(email);
+        let updated = {
+            let mut store = self.shard(email).write().await;
+            let Some(sub) = store.get_mut(email) else {
+                return false;
+            };
+            if sub.status != SubscriptionStatus::Unpaid {
+                return false;
+            }
+            println!(
+                "[SUBSCRIPTION] ⚖️ Dispute won, restoring access for {}",
+                email
+            );
+            sub.set_status(SubscriptionStatus::Active);
+            sub.clone()
+        };
+        self.persist(&updated).await.ok();
+        true
+    }
+
+    /// O(1) - Switch an existing subscription to another plan
+    pub async fn change_plan(&self, email: &str, plan: SubscriptionPlan) -> bool {
+        let email = &normalize_email(email);
+        let updated = {
+            let mut store = self.shard(email).write().await;
+            let Some(sub) = store.get_mut(email) else {
+                return false;
+            };
+            println!(
+                "[SUBSCRIPTION] 🔄 Plan {:?} -> {:?} for {}",
+                sub.plan, plan, email
+            );
+            sub.plan = plan;
+            sub.clone()
+        };
+        self.persist(&updated).await.ok();
+        true
+    }
+
+    /// Cancel subscription; the plan drops to Free now or at period end
+    /// (`downgrade_canceled`) depending on `CANCEL_DOWNGRADE_POLICY`
+    pub async fn cancel_subscription(&self, email: &str) -> bool {
+        let email = &normalize_email(email);
+        let updated = {
+            let mut store = self.shard(email).write().await;
+            let Some(sub) = store.get_mut(email) else {
+                return false;
+            };
+            sub.set_status(SubscriptionStatus::Canceled);
+            if self.cancel_downgrade.downgrade_due(sub, Utc::now()) {
+                sub.plan = SubscriptionPlan::Free;
+                println!(
+                    "[SUBSCRIPTION] ❌ Canceled subscription for {} (now on Free)",
+                    email
+                );
+            } else {
+                println!(
+                    "[SUBSCRIPTION] ❌ Canceled subscription for {} ({:?} until {:?})",
+                    email, sub.plan, sub.current_period_end
+                );
+            }
+            sub.clone()
+        };
+        self.persist(&updated).await.ok();
+        true
+    }
+}
+
+/// O(1) - Move `old` to `new` within `target`, or from `source` when the keys
+/// live in different shards; returns the moved record
+fn move_record(
+    source: Option<&mut HashMap<String, UserSubscription>>,
+    target: &mut HashMap<String, UserSubscription>,
+    old: &str,
+    new: &str,
+) -> Option<UserSubscription> {
+    if target.contains_key(new) {
+        return None;
+    }
+    let mut sub = match source {
+        Some(source) => source.remove(old),
+        None => target.remove(old),
+    }?;
+    sub.email = new.to_string();
+    target.insert(new.to_string(), sub.clone());
+    Some(sub)
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -260,12 +1739,42 @@ impl SubscriptionManager {
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Accepted clock skew between Stripe's `t=` and ours, unless `STRIPE_WEBHOOK_TOLERANCE` says otherwise
+const DEFAULT_SIGNATURE_TOLERANCE_SECS: i64 = 300;
+
+/// 9999-12-31T23:59:59Z; anything later is not a real signing time
+const MAX_WEBHOOK_TIMESTAMP: i64 = 253_402_300_799;
+
+/// Unix seconds from the `t=` element, range-checked before any arithmetic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WebhookTimestamp(i64);
+
+impl WebhookTimestamp {
+    /// O(1) - Positive and no later than year 9999
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let ts: i64 = raw
+            .trim()
+            .parse()
+            .map_err(|_| "Malformed timestamp".to_string())?;
+        if !(1..=MAX_WEBHOOK_TIMESTAMP).contains(&ts) {
+            return Err("Malformed timestamp".to_string());
+        }
+        Ok(Self(ts))
+    }
+
+    /// O(1) - Absolute distance from `now`, None if it does not fit
+    pub fn skew_from(&self, now: i64) -> Option<i64> {
+        now.checked_sub(self.0)?.checked_abs()
+    }
+}
+
 /// Verify Stripe webhook signature
 /// Big O: O(n) where n is payload size
 pub fn verify_webhook_signature(
     payload: &[u8],
     signature_header: &str,
     webhook_secret: &str,
+    tolerance_secs: i64,
 ) -> Result<(), String> {
     // Parse signature header: t=timestamp,v1=signature
     let parts: HashMap<&str, &str> = signature_header
@@ -279,26 +1788,142 @@ pub fn verify_webhook_signature(
     let timestamp = parts.get("t").ok_or("Missing timestamp")?;
     let expected_sig = parts.get("v1").ok_or("Missing signature")?;
 
-    // Check timestamp (5 minute tolerance)
-    let ts: i64 = timestamp.parse().map_err(|_| "Invalid timestamp")?;
-    let now = Utc::now().timestamp();
-    if (now - ts).abs() > 300 {
-        return Err("Webhook timestamp too old".to_string());
+    // Check timestamp (STRIPE_WEBHOOK_TOLERANCE, 5 minutes by default)
+    let ts = WebhookTimestamp::parse(timestamp)?;
+    match ts.skew_from(Utc::now().timestamp()) {
+        Some(skew) if skew <= tolerance_secs => {}
+        _ => return Err("Webhook timestamp too old".to_string()),
     }
 
-    // Compute expected signature
-    let signed_payload = format!("{}.{}", timestamp, String::from_utf8_lossy(payload));
+    // Compute expected signature over `{timestamp}.{payload}`, payload as raw bytes
     let mut mac = HmacSha256::new_from_slice(webhook_secret.as_bytes())
         .map_err(|_| "Invalid webhook secret")?;
-    mac.update(signed_payload.as_bytes());
-    let computed_sig = hex::encode(mac.finalize().into_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+
+    // Constant-time comparison on the raw bytes; undecodable hex is just a mismatch
+    let expected = hex::decode(expected_sig).map_err(|_| "Invalid webhook signature")?;
+    mac.verify_slice(&expected)
+        .map_err(|_| "Invalid webhook signature".to_string())
+}
+
+/// O(n) - Build a `Stripe-Signature` header the way Stripe does (t=...,v1=...),
+/// accepted by `verify_webhook_signature` for the same secret; for test deliveries
+#[cfg(test)]
+pub fn sign_stripe_payload(payload: &[u8], secret: &str, timestamp: i64) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(payload);
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Known-answer delivery for the self-test. The header was computed outside this
+/// code (HMAC-SHA256 of `1700000000.` + payload under the secret below), so a
+/// pass shows verification implements Stripe's scheme, not just that it agrees
+/// with `sign_stripe_payload`.
+const SELFTEST_VECTOR_SECRET: &str = "whsec_selftest_vector";
+const SELFTEST_VECTOR_PAYLOAD: &str = r#"{"id":"evt_selftest_vector","type":"selftest"}"#;
+const SELFTEST_VECTOR_HEADER: &str =
+    "t=1700000000,v1=90d8adbf4a256a47d7d4372f234f9f91bb846abc98d1ae45d59d118e05a23f5a";
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestReport {
+    pub ok: bool,
+    pub secret_configured: bool,
+    pub secret_format_ok: bool,
+    /// `supplied_delivery` (the configured secret was checked against a real
+    /// Stripe delivery) or `known_vector` (only the verification code was)
+    pub checked: &'static str,
+    pub signature_verified: bool,
+    pub signature_enforced: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
 
-    // Constant-time comparison
-    if computed_sig != *expected_sig {
-        return Err("Invalid webhook signature".to_string());
+/// GET|POST /stripe/webhook/selftest - Verification only, no side effects.
+/// POST a delivery captured from Stripe (body plus its `Stripe-Signature`) to
+/// check the configured secret against it; its age is not held against it.
+/// Without one, a known-answer vector checks the verification code, which says
+/// nothing about whether the configured secret is Stripe's.
+pub async fn webhook_selftest(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
     }
 
-    Ok(())
+    let secret = &state.config.webhook_secret;
+    let (checked, verification) = if body.is_empty() {
+        let verification = verify_webhook_signature(
+            SELFTEST_VECTOR_PAYLOAD.as_bytes(),
+            SELFTEST_VECTOR_HEADER,
+            SELFTEST_VECTOR_SECRET,
+            i64::MAX,
+        )
+        .and_then(|()| {
+            // A verifier accepting everything would pass the vector too
+            let tampered = SELFTEST_VECTOR_PAYLOAD.replace("selftest", "se1ftest");
+            match verify_webhook_signature(
+                tampered.as_bytes(),
+                SELFTEST_VECTOR_HEADER,
+                SELFTEST_VECTOR_SECRET,
+                i64::MAX,
+            ) {
+                Ok(()) => Err("Tampered vector accepted".to_string()),
+                Err(_) => Ok(()),
+            }
+        });
+        ("known_vector", verification)
+    } else {
+        let Some(signature) = headers
+            .get("stripe-signature")
+            .and_then(|v| v.to_str().ok())
+        else {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Stripe-Signature required with a body",
+            )
+                .into_response();
+        };
+        (
+            "supplied_delivery",
+            verify_webhook_signature(&body, signature, secret, i64::MAX),
+        )
+    };
+
+    let mut report = SelfTestReport {
+        ok: false,
+        secret_configured: !secret.is_empty() && secret != "whsec_placeholder",
+        secret_format_ok: secret.starts_with("whsec_"),
+        checked,
+        signature_verified: verification.is_ok(),
+        signature_enforced: !state.config.dev_skip_signature,
+        error: verification.err(),
+    };
+    report.ok = report.secret_configured
+        && report.secret_format_ok
+        && report.signature_verified
+        && report.signature_enforced;
+
+    println!(
+        "[WEBHOOK] 🩺 Self-test ({}) {}",
+        checked,
+        if report.ok { "passed" } else { "FAILED" }
+    );
+    let status = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
 }
 
 // ═══════════════════════════════════════════════════════════════════════════════
@@ -310,44 +1935,182 @@ pub struct StripeWebhookState {
     pub config: StripeConfig,
     pub idempotency: IdempotencyStore,
     pub subscriptions: SubscriptionManager,
+    pub notifications: NotificationHook,
+    /// Stdout plus `AUDIT_LOG_PATH` / `DATABASE_URL`; shared with the PayPal handler
+    pub audit: Arc<AuditTrail>,
+    pub catalog: Arc<PricingCatalog>,
+    pub checkout_limits: CheckoutRateLimits,
+    pub http: UpstreamClient,
+    pub license: LicenseIssuer,
+    pub licenses: LicenseRegistry,
+    pub tokens: TokenIssuer,
+    /// Activations whose persist step failed, retried in the background
+    pub activations: ActivationQueue,
+    /// Events that keep failing, with their raw payload
+    pub dead_letters: DeadLetterStore,
+    /// Frontends that checkout and portal sessions may return to
+    pub domains: SiteDomains,
+    /// Signs and redeems expiring campaign links (`/stripe/checkout/link`)
+    pub checkout_links: CheckoutLinkSigner,
+    /// When set, verified events are acked at once and processed by workers
+    pub webhook_queue: Option<WebhookQueue>,
+    /// Guards the admin routes (`ADMIN_API_TOKEN`)
+    pub admin: AdminAuth,
 }
 
 impl StripeWebhookState {
     pub fn new() -> Self {
         let config = StripeConfig::from_env();
+        let audit = Arc::new(AuditTrail::from_env());
+        // A queued event is acked before it is audited, so the 5xx that makes
+        // Stripe redeliver an unaudited event could never be sent
+        let webhook_queue = if audit.requires_durability() && env_flag("STRIPE_ASYNC_WEBHOOKS") {
+            println!("[CONFIG] ❌ STRIPE_ASYNC_WEBHOOKS refused with REQUIRE_AUDIT_DURABILITY; webhooks are processed before they are acked");
+            None
+        } else {
+            WebhookQueue::from_env("stripe", config.redis_url.clone())
+        };
         Self {
             idempotency: IdempotencyStore::new(config.redis_url.clone()),
+            dead_letters: DeadLetterStore::new("stripe", config.redis_url.clone()),
+            webhook_queue,
+            license: LicenseIssuer::from_env(config.is_live()),
+            subscriptions: SubscriptionManager::new().with_redis(config.redis_url.clone()),
+            licenses: LicenseRegistry::new(config.redis_url.clone()),
+            http: UpstreamClient::from_env("stripe", config.is_live()),
+            checkout_limits: CheckoutRateLimits::from_env(config.redis_url.clone()),
             config,
-            subscriptions: SubscriptionManager::new(),
+            notifications: NotificationHook::from_env(),
+            audit,
+            catalog: Arc::new(PricingCatalog::from_env()),
+            tokens: TokenIssuer::from_env(),
+            activations: ActivationQueue::from_env(),
+            domains: SiteDomains::from_env(),
+            checkout_links: CheckoutLinkSigner::from_env(),
+            admin: AdminAuth::from_env(),
+        }
+    }
+
+    /// O(1) - Authenticated request to `path` under the Stripe API, pinned to the
+    /// configured API version
+    fn stripe_api(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .http
+            .client()
+            .request(method, format!("{}{}", self.config.api_base, path))
+            .basic_auth(&self.config.secret_key, None::<&str>);
+        match &self.config.api_version {
+            Some(version) => request.header("Stripe-Version", version),
+            None => request,
+        }
+    }
+
+    /// O(1) - Cheapest authenticated call (`GET /v1/balance`) to prove the key works
+    pub async fn ping(&self) -> Result<(), String> {
+        let request = self.stripe_api(Method::GET, "/v1/balance");
+        let res = self.http.send("stripe_ping", request).await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(UpstreamError::status("Stripe", status, body).into());
+        }
+        Ok(())
+    }
+}
+
+/// Why an event handler failed, which decides whether Stripe redelivers
+#[derive(Debug, Clone)]
+pub enum WebhookError {
+    /// Infrastructure (audit sink, Redis, Stripe API, notification hook): answered
+    /// with 500 and left unmarked so Stripe's redelivery runs the event again
+    Transient(String),
+    /// Bad data or a business rule: acked with `business_error_status`
+    Permanent(String),
+}
+
+impl WebhookError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient(_))
+    }
+}
+
+impl std::fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transient(e) | Self::Permanent(e) => f.write_str(e),
+        }
+    }
+}
+
+/// Plain errors are permanent unless they came from the audit sink
+impl From<String> for WebhookError {
+    fn from(e: String) -> Self {
+        if audit::is_failure(&e) {
+            Self::Transient(e)
+        } else {
+            Self::Permanent(e)
+        }
+    }
+}
+
+/// Upstream calls worth retrying (transport failure, 429, 5xx) are transient
+impl From<UpstreamError> for WebhookError {
+    fn from(e: UpstreamError) -> Self {
+        if e.is_transient() {
+            Self::Transient(e.to_string())
+        } else {
+            Self::Permanent(e.to_string())
         }
     }
 }
 
+impl From<&str> for WebhookError {
+    fn from(e: &str) -> Self {
+        e.to_string().into()
+    }
+}
+
 /// Main webhook handler
 pub async fn stripe_webhook_handler(
     State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
     headers: HeaderMap,
-    body: String,
+    body: Bytes,
 ) -> impl IntoResponse {
-    // Get signature header
-    let signature = match headers.get("stripe-signature") {
-        Some(sig) => sig.to_str().unwrap_or(""),
-        None => {
-            println!("[WEBHOOK] ❌ Missing Stripe-Signature header");
-            return (StatusCode::BAD_REQUEST, "Missing signature").into_response();
-        }
-    };
+    if !state.config.webhook_sources.allows(client_ip) {
+        return (StatusCode::FORBIDDEN, "Source not allowed").into_response();
+    }
 
-    // Verify signature (0x4121 Security Gate)
-    if let Err(e) =
-        verify_webhook_signature(body.as_bytes(), signature, &state.config.webhook_secret)
-    {
-        println!("[WEBHOOK] ❌ Signature verification failed: {}", e);
-        return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+    if state.config.dev_skip_signature {
+        println!(
+            "[WEBHOOK] 🚧 DEV_SKIP_SIGNATURE: accepting payload WITHOUT signature verification"
+        );
+    } else {
+        // Get signature header
+        let signature = match headers.get("stripe-signature") {
+            Some(sig) => sig.to_str().unwrap_or(""),
+            None => {
+                println!("[WEBHOOK] ❌ Missing Stripe-Signature header");
+                metrics::counter!("webhook_signature_failures_total").increment(1);
+                return (StatusCode::BAD_REQUEST, "Missing signature").into_response();
+            }
+        };
+
+        // Verify signature (0x4121 Security Gate) over the bytes exactly as received
+        if let Err(e) = verify_webhook_signature(
+            &body,
+            signature,
+            &state.config.webhook_secret,
+            state.config.webhook_tolerance_secs,
+        ) {
+            println!("[WEBHOOK] ❌ Signature verification failed: {}", e);
+            metrics::counter!("webhook_signature_failures_total").increment(1);
+            return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+        }
     }
 
-    // Parse event
-    let event: StripeEvent = match serde_json::from_str(&body) {
+    // Parse event (depth-checked first: the body is attacker-controlled)
+    let event: StripeEvent = match parse_bounded(&body, state.config.max_json_depth) {
         Ok(e) => e,
         Err(e) => {
             println!("[WEBHOOK] ❌ Failed to parse event: {}", e);
@@ -355,91 +2118,665 @@ pub async fn stripe_webhook_handler(
         }
     };
 
-    println!("[WEBHOOK] 📬 Received: {} ({})", event.event_type, event.id);
+    if let Err(e) = event.validate() {
+        println!("[WEBHOOK] ❌ Event {} failed schema check: {}", event.id, e);
+        return (StatusCode::BAD_REQUEST, format!("Invalid event: {}", e)).into_response();
+    }
+
+    if let Err(e) = state.config.check_account(&event) {
+        println!("[WEBHOOK] ❌ Event {} rejected: {}", event.id, e);
+        return (StatusCode::BAD_REQUEST, "Event is for another account").into_response();
+    }
 
-    // Idempotency check - prevent double processing
-    if state.idempotency.is_processed(&event.id).await {
+    // Even with the bypass on, a live event must carry a real signature
+    if state.config.dev_skip_signature && event.livemode {
         println!(
-            "[WEBHOOK] ⚡ Event {} already processed (idempotent)",
+            "[WEBHOOK] ❌ Unsigned live event {} rejected (DEV_SKIP_SIGNATURE)",
             event.id
         );
-        return (StatusCode::OK, "Already processed").into_response();
+        metrics::counter!("webhook_signature_failures_total").increment(1);
+        return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
     }
 
-    // Process based on event type
-    let result = match event.event_type.as_str() {
-        "checkout.session.completed" => handle_checkout_completed(&state, &event).await,
-        "invoice.paid" => handle_invoice_paid(&state, &event).await,
-        "invoice.payment_failed" => handle_payment_failed(&state, &event).await,
-        "customer.subscription.deleted" => handle_subscription_deleted(&state, &event).await,
-        _ => {
-            println!("[WEBHOOK] ℹ️ Unhandled event type: {}", event.event_type);
-            Ok(())
-        }
-    };
+    println!(
+        "[WEBHOOK] 📬 Received: {} ({}, api {})",
+        event.event_type,
+        event.id,
+        event.api_version.as_deref().unwrap_or("unknown")
+    );
+    metrics::counter!(
+        "webhooks_received_total",
+        "provider" => "stripe",
+        "type" => event.event_type.clone()
+    )
+    .increment(1);
 
-    // Mark as processed with result
-    let event_result = match &result {
-        Ok(_) => EventResult::Success {
-            user_id: Uuid::new_v4(),
-            plan: "processed".to_string(),
-        },
-        Err(e) => EventResult::Failed { error: e.clone() },
-    };
-    state
-        .idempotency
-        .mark_processed(event.id, event_result)
-        .await;
+    if let (Some(expected), Some(actual)) = (&state.config.api_version, &event.api_version) {
+        if expected != actual {
+            println!(
+                "[WEBHOOK] ⚠️ Event {} uses API version {} but {} is pinned; object shapes may differ",
+                event.id, actual, expected
+            );
+        }
+    }
 
-    match result {
-        Ok(_) => (StatusCode::OK, "Success").into_response(),
-        Err(e) => {
-            println!("[WEBHOOK] ❌ Processing error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
+    if let Some(allowlist) = &state.config.event_allowlist {
+        if !allowlist.contains(&event.event_type) {
+            println!(
+                "[WEBHOOK] 🙈 Event type {} not in STRIPE_EVENT_ALLOWLIST, ignoring {}",
+                event.event_type, event.id
+            );
+            metrics::counter!("stripe_events_ignored_total", "type" => event.event_type.clone())
+                .increment(1);
+            return (StatusCode::OK, "Ignored").into_response();
         }
     }
-}
 
-// ═══════════════════════════════════════════════════════════════════════════════
-// EVENT HANDLERS
-// ═══════════════════════════════════════════════════════════════════════════════
+    // Parsed as JSON above, so this is valid UTF-8 and the conversion is lossless
+    let body = String::from_utf8_lossy(&body);
+
+    if let Some(queue) = &state.webhook_queue {
+        return match queue.enqueue(&event.id, &body).await {
+            Ok(outcome) => {
+                println!("[WEBHOOK] 📥 Event {} queued ({:?})", event.id, outcome);
+                (StatusCode::OK, "Queued").into_response()
+            }
+            Err(e) => {
+                // Not acked, so Stripe redelivers once the backlog clears
+                println!("[WEBHOOK] ❌ Could not queue {}: {}", event.id, e);
+                (StatusCode::SERVICE_UNAVAILABLE, "Queue full").into_response()
+            }
+        };
+    }
+
+    match process_event(&state, event, &body).await {
+        Ok(message) => (StatusCode::OK, message).into_response(),
+        Err(WebhookError::Transient(e)) if audit::is_failure(&e) => {
+            println!("[WEBHOOK] ❌ {}, asking Stripe to retry", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Audit unavailable").into_response()
+        }
+        Err(WebhookError::Transient(e)) => {
+            println!(
+                "[WEBHOOK] ❌ Transient error, asking Stripe to retry: {}",
+                e
+            );
+            (StatusCode::INTERNAL_SERVER_ERROR, "Temporarily unavailable").into_response()
+        }
+        Err(WebhookError::Permanent(e)) => {
+            println!("[WEBHOOK] ❌ Processing error: {}", e);
+            (state.config.business_error_status, "Processed with error").into_response()
+        }
+    }
+}
+
+/// Start the queue workers (`STRIPE_ASYNC_WEBHOOKS`); no-op when disabled
+pub async fn spawn_webhook_workers(state: Arc<StripeWebhookState>) {
+    let Some(queue) = state.webhook_queue.clone() else {
+        return;
+    };
+    queue
+        .spawn_workers(move |item: QueuedEvent| {
+            let state = state.clone();
+            async move {
+                // Verified before it was queued; only the shape is re-read here
+                let event: StripeEvent =
+                    match parse_bounded(item.body.as_bytes(), state.config.max_json_depth) {
+                        Ok(e) => e,
+                        Err(e) => {
+                            println!(
+                                "[QUEUE] ❌ Queued event {} unreadable: {}",
+                                item.event_id, e
+                            );
+                            return ProcessOutcome::Done;
+                        }
+                    };
+                match process_event(&state, event, &item.body).await {
+                    Ok(_) => ProcessOutcome::Done,
+                    // Already acked, so Stripe won't redeliver: retry it ourselves
+                    Err(e) if e.is_transient() => {
+                        println!("[QUEUE] ❌ Event {} not handled: {}", item.event_id, e);
+                        ProcessOutcome::Retry
+                    }
+                    Err(e) => {
+                        println!("[QUEUE] ❌ Event {} failed: {}", item.event_id, e);
+                        ProcessOutcome::Done
+                    }
+                }
+            }
+        })
+        .await;
+}
+
+/// Idempotency, routing, dead letters and the processed marker for a verified
+/// event; shared by the inline path and the queue workers. Concurrent deliveries
+/// of one event are serialized by a claim, so only one of them runs the handler.
+async fn process_event(
+    state: &StripeWebhookState,
+    event: StripeEvent,
+    body: &str,
+) -> Result<&'static str, WebhookError> {
+    process_event_reporting_marker(state, event, body).await.0
+}
+
+/// `process_event`, plus the Redis error when the processed marker could only
+/// be kept in memory (admin retries report it)
+async fn process_event_reporting_marker(
+    state: &StripeWebhookState,
+    event: StripeEvent,
+    body: &str,
+) -> (Result<&'static str, WebhookError>, Option<StoreError>) {
+    if !state.idempotency.try_claim(&event.id).await {
+        println!(
+            "[WEBHOOK] 🔒 Event {} is being processed by another delivery",
+            event.id
+        );
+        // Not acked: if the other delivery fails, this one's redelivery retries it
+        let error =
+            WebhookError::Transient(format!("Event {} is already being processed", event.id));
+        return (Err(error), None);
+    }
+
+    let event_id = event.id.clone();
+    let outcome = process_claimed(state, event, body).await;
+    // The marker (if any) is written by now, so later deliveries see it
+    state.idempotency.release(&event_id).await;
+    outcome
+}
+
+/// `process_event` once the event is claimed
+async fn process_claimed(
+    state: &StripeWebhookState,
+    event: StripeEvent,
+    body: &str,
+) -> (Result<&'static str, WebhookError>, Option<StoreError>) {
+    // Idempotency check - skip prior successes, let prior failures retry
+    if let Some(prior) = state.idempotency.get(&event.id).await {
+        if !prior.result.is_failure() {
+            println!(
+                "[WEBHOOK] ⚡ Event {} already processed (idempotent)",
+                event.id
+            );
+            return (Ok("Already processed"), None);
+        }
+        println!(
+            "[WEBHOOK] 🔁 Event {} previously failed at {} ({:?}), reprocessing",
+            event.id, prior.processed_at, prior.result
+        );
+    }
+
+    let request_key = event
+        .request_dedupe_key()
+        .filter(|_| state.config.dedupe_by_request_key);
+    if let Some(key) = &request_key {
+        if let Some(prior) = state.idempotency.get(key).await {
+            if !prior.result.is_failure() {
+                println!(
+                    "[WEBHOOK] ⚡ Event {} duplicates {} (same request idempotency key)",
+                    event.id, prior.event_id
+                );
+                let marker_error = state
+                    .idempotency
+                    .record(&event, EventResult::Duplicate)
+                    .await;
+                return (Ok("Already processed"), marker_error);
+            }
+        }
+    }
+
+    let result = route_event(state, &event).await;
+    match &result {
+        Ok(_) => state.dead_letters.remove(&event.id).await,
+        Err(e) => {
+            // A permanent failure is acked, so nothing redelivers it: dead-letter it
+            // at once. Transient ones are retried and count toward the threshold.
+            state
+                .dead_letters
+                .record_failure(
+                    &event.id,
+                    &event.event_type,
+                    body,
+                    &e.to_string(),
+                    !e.is_transient(),
+                )
+                .await;
+        }
+    }
+    if let Err(e) = &result {
+        if e.is_transient() {
+            // Not handled (or not durably audited): leave it unmarked so the
+            // redelivery runs again instead of counting as a duplicate
+            return (Err(e.clone()), None);
+        }
+    }
+
+    // Mark as processed with the subscription the handler left behind
+    let event_result = match &result {
+        Ok(handled) => handled.clone(),
+        Err(e) => EventResult::Failed {
+            error: e.to_string(),
+        },
+    };
+    if let Some(key) = request_key {
+        // Recorded under the original event id so duplicates can name it
+        state
+            .idempotency
+            .mark_processed_with_fallback(
+                key,
+                event.id.clone(),
+                event_result.clone(),
+                event.api_version.clone(),
+            )
+            .await;
+    }
+    let marker_error = state.idempotency.record(&event, event_result).await;
+
+    (result.map(|_| "Success"), marker_error)
+}
+
+/// Dispatch a Stripe event to its handler
+async fn route_event(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    match event.event_type.as_str() {
+        "checkout.session.completed" => handle_checkout_completed(state, event).await,
+        "checkout.session.expired" => handle_checkout_expired(state, event).await,
+        "checkout.session.async_payment_succeeded" => {
+            handle_async_payment_succeeded(state, event).await
+        }
+        "checkout.session.async_payment_failed" => handle_async_payment_failed(state, event).await,
+        "payment_intent.succeeded" => handle_payment_intent_succeeded(state, event).await,
+        // Older accounts send the alias instead of (or as well as) invoice.paid
+        "invoice.paid" | "invoice.payment_succeeded" => handle_invoice_paid(state, event).await,
+        "invoice.payment_failed" => handle_payment_failed(state, event).await,
+        "customer.subscription.created" => handle_subscription_created(state, event).await,
+        "customer.subscription.updated" => handle_subscription_updated(state, event).await,
+        "customer.subscription.deleted" => handle_subscription_deleted(state, event).await,
+        "customer.updated" => handle_customer_updated(state, event).await,
+        "setup_intent.succeeded" => handle_setup_intent_succeeded(state, event).await,
+        "charge.dispute.closed" => handle_dispute_closed(state, event).await,
+        "charge.refunded" => handle_charge_refunded(state, event).await,
+        "charge.refund.updated" => handle_charge_refund_updated(state, event).await,
+        _ => {
+            println!("[WEBHOOK] ℹ️ Unhandled event type: {}", event.event_type);
+            Ok(EventResult::Processed)
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// EVENT HANDLERS
+// ═══════════════════════════════════════════════════════════════════════════════
 
 async fn handle_checkout_completed(
     state: &StripeWebhookState,
     event: &StripeEvent,
-) -> Result<(), String> {
+) -> Result<EventResult, WebhookError> {
     let session: CheckoutSession = serde_json::from_value(event.data.object.clone())
         .map_err(|e| format!("Failed to parse session: {}", e))?;
 
-    let email = session.customer_email.unwrap_or_default();
-    let plan = session
-        .metadata
-        .as_ref()
-        .and_then(|m| m.get("plan"))
-        .map(|s| s.as_str())
-        .unwrap_or("pro_monthly");
+    if session.is_payment_pending() {
+        println!(
+            "[CHECKOUT] ⏳ Session {} completed, awaiting delayed payment for {:?}",
+            session.id,
+            session.email()
+        );
+        log_payment_event(
+            &state.audit,
+            event,
+            session.email().unwrap_or_default(),
+            "checkout.pending",
+            session.amount_total,
+        )
+        .await?;
+        return Ok(EventResult::Processed);
+    }
+
+    activate_session(state, event, session, "checkout.completed").await
+}
+
+/// Delayed payment cleared: activate like a synchronous completion
+async fn handle_async_payment_succeeded(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let session: CheckoutSession = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse session: {}", e))?;
+
+    activate_session(state, event, session, "checkout.async_payment_succeeded").await
+}
+
+/// O(1) - Activate the session's subscription; a failed persist is retried after we ack
+async fn activate_session(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+    session: CheckoutSession,
+    audit_event: &str,
+) -> Result<EventResult, WebhookError> {
+    let email = session.email().unwrap_or_default().to_string();
+    let plan = state
+        .catalog
+        .plan_key(session.plan().unwrap_or(DEFAULT_PLAN_KEY))
+        .to_string();
+    let attribution = session.attribution();
 
     println!(
         "[CHECKOUT] ✅ Session completed for: {} (Plan: {})",
         email, plan
     );
 
-    // Activate subscription
+    let amount = session
+        .amount_total
+        .zip(session.currency.as_deref())
+        .map(|(minor, currency)| Money {
+            minor,
+            currency: currency.to_ascii_uppercase(),
+        });
+    let mut external_ids = serde_json::Map::new();
+    external_ids.insert("session".to_string(), session.id.clone().into());
+    external_ids.insert("customer".to_string(), session.customer.clone().into());
+    external_ids.insert(
+        "subscription".to_string(),
+        session.subscription.clone().into(),
+    );
+
+    let mut activation =
+        PendingActivation::new(&email, session.customer, session.subscription, &plan);
+    activation.attribution = attribution.clone();
+    let activated = match state.subscriptions.try_activate(&activation).await {
+        Ok(subscription) => Some(subscription),
+        Err(e) => {
+            println!("[CHECKOUT] ⚠️ Activation for {} failed: {}", email, e);
+            if !state.activations.enqueue(activation, e.clone()).await {
+                return Err(WebhookError::Transient(format!(
+                    "Activation failed and retry queue is full: {}",
+                    e
+                )));
+            }
+            None
+        }
+    };
+
+    // Log to immutable audit trail
+    let mut log_entry = payment_event_entry(event, &email, audit_event, session.amount_total);
+    if !attribution.is_empty() {
+        log_entry["attribution"] = serde_json::json!(attribution);
+    }
+    state.audit.record("AUDIT", log_entry).await?;
+    provider_event::record_activation(
+        &state.audit,
+        event,
+        Activation {
+            email: &email,
+            plan: &plan,
+            amount,
+            external_ids,
+            queued: activated.is_none(),
+        },
+    )
+    .await?;
+    Ok(EventResult::for_subscription(activated))
+}
+
+/// Delayed payment bounced: nothing was activated, tell the customer to retry
+async fn handle_async_payment_failed(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let session: CheckoutSession = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse session: {}", e))?;
+
+    let Some(email) = session.email() else {
+        println!(
+            "[CHECKOUT] ⚠️ Delayed payment failed for session {} (no email)",
+            session.id
+        );
+        return Ok(EventResult::Processed);
+    };
+    println!(
+        "[CHECKOUT] ❌ Delayed payment failed for {} (Session {})",
+        email, session.id
+    );
+
+    let plan = session.plan().unwrap_or(DEFAULT_PLAN_KEY);
     state
-        .subscriptions
-        .activate_subscription(&email, session.customer, session.subscription, plan)
+        .notifications
+        .notify(
+            "checkout.payment_failed",
+            email,
+            serde_json::json!({
+                "plan": plan,
+                "session_id": session.id,
+                "checkout_url": format!("{}/stripe/checkout/{}", public_api_url(&state.domains), plan),
+            }),
+        )
+        .await
+        .map_err(WebhookError::Transient)?;
+
+    log_payment_event(
+        &state.audit,
+        event,
+        email,
+        "checkout.async_payment_failed",
+        session.amount_total,
+    )
+    .await?;
+
+    Ok(EventResult::Processed)
+}
+
+/// Abandonment recovery: re-send a fresh checkout link for the same plan
+async fn handle_checkout_expired(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    if !state.config.abandonment_recovery {
+        return Ok(EventResult::Processed);
+    }
+
+    let session: CheckoutSession = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse session: {}", e))?;
+
+    let (Some(email), Some(plan)) = (session.email(), session.plan()) else {
+        println!(
+            "[CHECKOUT] ℹ️ Session {} expired without email/plan, no recovery",
+            session.id
+        );
+        return Ok(EventResult::Processed);
+    };
+
+    let Some(base_url) = &state.config.recovery_base_url else {
+        println!(
+            "[CHECKOUT] ⚠️ Session {} expired but CHECKOUT_RECOVERY_URL is unset, no recovery",
+            session.id
+        );
+        return Ok(EventResult::Processed);
+    };
+    let recovery_url = format!("{}/stripe/checkout/{}", base_url, plan);
+    println!(
+        "[CHECKOUT] 🔁 Session {} expired, sending recovery link to {} (Plan: {})",
+        session.id, email, plan
+    );
+
+    state
+        .notifications
+        .notify(
+            "checkout.recovery",
+            email,
+            serde_json::json!({ "plan": plan, "checkout_url": recovery_url }),
+        )
+        .await
+        .map_err(WebhookError::Transient)?;
+
+    log_payment_event(
+        &state.audit,
+        event,
+        email,
+        "checkout.expired",
+        session.amount_total,
+    )
+    .await?;
+
+    Ok(EventResult::Processed)
+}
+
+/// One-off payments made outside Checkout (API/Elements). Subscription
+/// invoices and Checkout-created intents are handled by their own events.
+async fn handle_payment_intent_succeeded(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let intent: PaymentIntent = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse payment intent: {}", e))?;
+
+    if let Some(invoice) = &intent.invoice {
+        println!(
+            "[PAYMENT] ℹ️ Intent {} pays invoice {}, left to invoice.paid",
+            intent.id, invoice
+        );
+        return Ok(EventResult::Processed);
+    }
+
+    let Some(plan) = intent.plan() else {
+        println!(
+            "[PAYMENT] ℹ️ Intent {} has no plan metadata (Checkout or unrelated), skipping",
+            intent.id
+        );
+        return Ok(EventResult::Processed);
+    };
+    let email = intent
+        .email()
+        .ok_or_else(|| format!("Payment intent {} has no receipt email", intent.id))?;
+
+    println!(
+        "[PAYMENT] ✅ Intent {} succeeded for: {} (Plan: {})",
+        intent.id, email, plan
+    );
+
+    let activation = PendingActivation::new(
+        email,
+        intent.customer.clone(),
+        None,
+        state.catalog.plan_key(plan),
+    );
+    let activated = match state.subscriptions.try_activate(&activation).await {
+        Ok(subscription) => Some(subscription),
+        Err(e) => {
+            println!("[PAYMENT] ⚠️ Activation for {} failed: {}", email, e);
+            if !state.activations.enqueue(activation, e.clone()).await {
+                return Err(WebhookError::Transient(format!(
+                    "Activation failed and retry queue is full: {}",
+                    e
+                )));
+            }
+            None
+        }
+    };
+
+    let license_key = state
+        .license
+        .generate_license_key(LicenseProvider::Stripe, &intent.id)
+        .map_err(|e| e.to_string())?;
+    state
+        .licenses
+        .register(
+            &license_key,
+            LicenseProvider::Stripe,
+            &intent.id,
+            email,
+            plan,
+        )
         .await;
+    // The payment and license already stand; a redelivery would re-run the
+    // activation just to retry an email
+    if let Err(e) = state
+        .notifications
+        .notify(
+            "license.issued",
+            email,
+            serde_json::json!({
+                "plan": plan,
+                "license_key": license_key,
+                "payment_intent": intent.id,
+            }),
+        )
+        .await
+    {
+        println!(
+            "[PAYMENT] ⚠️ License notification for intent {} failed: {}",
+            intent.id, e
+        );
+    }
 
-    // Log to immutable audit trail
-    log_payment_event(&email, "checkout.completed", session.amount_total);
+    log_payment_event(
+        &state.audit,
+        event,
+        email,
+        "payment_intent.succeeded",
+        intent.amount_received,
+    )
+    .await?;
 
-    Ok(())
+    Ok(EventResult::for_subscription(activated))
 }
 
+/// Both `invoice.paid` and `invoice.payment_succeeded` land here; the invoice id
+/// is recorded so whichever arrives second is a no-op.
 async fn handle_invoice_paid(
-    _state: &StripeWebhookState,
+    state: &StripeWebhookState,
     event: &StripeEvent,
-) -> Result<(), String> {
+) -> Result<EventResult, WebhookError> {
+    let Some(key) = event.data.object["id"]
+        .as_str()
+        .map(|id| format!("invoice_paid:{}", id))
+    else {
+        return record_invoice_paid(state, event).await;
+    };
+
+    // invoice.paid and invoice.payment_succeeded for one invoice arrive together:
+    // claim the invoice so only one of them records the renewal
+    if !state.idempotency.try_claim(&key).await {
+        println!(
+            "[INVOICE] 🔒 {} ({}) is being handled by another event",
+            key, event.event_type
+        );
+        return Err(WebhookError::Transient(format!(
+            "{} is already being processed",
+            key
+        )));
+    }
+    if let Some(prior) = state.idempotency.get(&key).await {
+        if !prior.result.is_failure() {
+            println!(
+                "[INVOICE] ⚡ {} ({}) already handled via {}",
+                key, event.event_type, prior.event_id
+            );
+            state.idempotency.release(&key).await;
+            return Ok(EventResult::Processed);
+        }
+    }
+
+    let result = record_invoice_paid(state, event).await;
+    if let Ok(handled) = &result {
+        state
+            .idempotency
+            .mark_processed_with_fallback(
+                key.clone(),
+                event.id.clone(),
+                handled.clone(),
+                event.api_version.clone(),
+            )
+            .await;
+    }
+    state.idempotency.release(&key).await;
+    result
+}
+
+/// `handle_invoice_paid` once the invoice is claimed: sync the plan and audit
+async fn record_invoice_paid(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
     let customer_email = event
         .data
         .object
@@ -455,20 +2792,32 @@ async fn handle_invoice_paid(
         .unwrap_or(0);
 
     println!(
-        "[INVOICE] 💰 Paid: {} (€{})",
+        "[INVOICE] 💰 Paid: {} ({})",
         customer_email,
-        amount as f64 / 100.0
+        event_money(event, amount).localized()
     );
+    // Invoices carry the billed price, not our plan metadata
+    let price_id = event.data.object["lines"]["data"][0]["price"]["id"].as_str();
+    sync_plan_from_price(state, customer_email, price_id).await;
 
-    log_payment_event(customer_email, "invoice.paid", Some(amount));
+    log_payment_event(
+        &state.audit,
+        event,
+        customer_email,
+        "invoice.paid",
+        Some(amount),
+    )
+    .await?;
 
-    Ok(())
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(customer_email).await,
+    ))
 }
 
 async fn handle_payment_failed(
-    _state: &StripeWebhookState,
+    state: &StripeWebhookState,
     event: &StripeEvent,
-) -> Result<(), String> {
+) -> Result<EventResult, WebhookError> {
     let customer_email = event
         .data
         .object
@@ -479,164 +2828,4701 @@ async fn handle_payment_failed(
     println!("[PAYMENT] ❌ Failed for: {}", customer_email);
 
     // TODO: Send notification email, retry logic, etc.
-    log_payment_event(customer_email, "payment.failed", None);
+    log_payment_event(&state.audit, event, customer_email, "payment.failed", None).await?;
 
-    Ok(())
+    Ok(EventResult::Processed)
 }
 
-async fn handle_subscription_deleted(
+/// Subscriptions created outside our checkout (dashboard, API) have no local
+/// record yet: resolve the customer's email and plan, then activate.
+async fn handle_subscription_created(
     state: &StripeWebhookState,
     event: &StripeEvent,
-) -> Result<(), String> {
-    let customer_email = event
-        .data
-        .object
-        .get("customer_email")
-        .and_then(|v| v.as_str());
+) -> Result<EventResult, WebhookError> {
+    let subscription: StripeSubscription = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse subscription: {}", e))?;
 
-    if let Some(email) = customer_email {
-        state.subscriptions.cancel_subscription(email).await;
-        log_payment_event(email, "subscription.deleted", None);
+    let known = state
+        .subscriptions
+        .email_for_stripe(subscription.customer.as_deref(), &subscription.id)
+        .await;
+    if known.is_some() {
+        return handle_subscription_updated(state, event).await;
     }
 
-    Ok(())
-}
-
-// ═══════════════════════════════════════════════════════════════════════════════
-// IMMUTABLE AUDIT LOG
-// ═══════════════════════════════════════════════════════════════════════════════
-
-fn log_payment_event(email: &str, event_type: &str, amount: Option<i64>) {
-    let log_entry = serde_json::json!({
-        "timestamp": Utc::now().to_rfc3339(),
-        "event": event_type,
-        "email": email,
-        "amount_cents": amount,
-        "veritas_hash": format!("0x4121:{:x}", rand::random::<u64>()),
-    });
-
-    println!("[AUDIT] 📝 {}", log_entry);
-    // TODO: Append to immutable log file or PostgreSQL
-}
+    let email = match (subscription.metadata.get("email"), &subscription.customer) {
+        (Some(email), _) => email.clone(),
+        (None, Some(customer)) => fetch_customer_email(state, customer)
+            .await?
+            .ok_or_else(|| format!("Customer {} has no email", customer))?,
+        (None, None) => {
+            return Err(format!("Subscription {} has no customer", subscription.id).into())
+        }
+    };
+    // A known price wins over metadata; neither means the default plan
+    let plan = match (
+        subscription
+            .price_id()
+            .and_then(|price| state.catalog.find_by_stripe_price(price)),
+        subscription.metadata.get("plan"),
+    ) {
+        (Some(offer), _) => offer.key.clone(),
+        (None, Some(plan)) => plan.clone(),
+        (None, None) => {
+            println!(
+                "[SUBSCRIPTION] ⚠️ Unknown price {:?} on {}, assuming {}",
+                subscription.price_id(),
+                subscription.id,
+                DEFAULT_PLAN_KEY
+            );
+            state
+                .catalog
+                .plan_for_price(subscription.price_id())
+                .to_string()
+        }
+    };
 
-// ═══════════════════════════════════════════════════════════════════════════════
-// CUSTOMER PORTAL
-// ═══════════════════════════════════════════════════════════════════════════════
+    println!(
+        "[SUBSCRIPTION] 🆕 {} created outside checkout for {} (Plan: {})",
+        subscription.id, email, plan
+    );
+    let activation = PendingActivation::new(
+        &email,
+        subscription.customer.clone(),
+        Some(subscription.id.clone()),
+        state.catalog.plan_key(&plan),
+    );
+    if let Err(e) = state.subscriptions.try_activate(&activation).await {
+        println!("[SUBSCRIPTION] ⚠️ Activation for {} failed: {}", email, e);
+        if !state.activations.enqueue(activation, e.clone()).await {
+            return Err(WebhookError::Transient(format!(
+                "Activation failed and retry queue is full: {}",
+                e
+            )));
+        }
+        return Ok(EventResult::Processed);
+    }
+    // Carry over status, trial and period from the Stripe object
+    state
+        .subscriptions
+        .apply_stripe_subscription(&email, &subscription)
+        .await;
+    log_payment_event(&state.audit, event, &email, "subscription.created", None).await?;
 
-#[derive(Serialize)]
-pub struct PortalSessionResponse {
-    pub url: String,
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(&email).await,
+    ))
 }
 
-/// Create Stripe Customer Portal session
-pub async fn create_portal_session(
-    State(_state): State<Arc<StripeWebhookState>>,
-    Json(payload): Json<serde_json::Value>,
-) -> impl IntoResponse {
-    let customer_id = payload["customer_id"].as_str().unwrap_or("");
+/// Keep status, trial end and period end in step with Stripe
+async fn handle_subscription_updated(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let subscription: StripeSubscription = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse subscription: {}", e))?;
 
-    // In production: Call Stripe API to create portal session
-    let portal_url = format!(
-        "https://billing.stripe.com/p/session/test_portal_{}",
-        customer_id
-    );
+    let email = match subscription.metadata.get("email") {
+        Some(email) => Some(email.clone()),
+        None => {
+            state
+                .subscriptions
+                .email_for_stripe(subscription.customer.as_deref(), &subscription.id)
+                .await
+        }
+    };
+    let Some(email) = email else {
+        // created may arrive before checkout.session.completed links the customer
+        println!(
+            "[SUBSCRIPTION] ℹ️ No local subscription for {} yet, skipping",
+            subscription.id
+        );
+        return Ok(EventResult::Processed);
+    };
 
-    println!("[PORTAL] 🔗 Created portal session for: {}", customer_id);
+    if !state
+        .subscriptions
+        .apply_stripe_subscription(&email, &subscription)
+        .await
+    {
+        // Metadata can name a customer whose checkout has not landed yet
+        println!(
+            "[SUBSCRIPTION] ℹ️ No local subscription for {} ({}) yet, skipping",
+            email, subscription.id
+        );
+        return Ok(EventResult::Processed);
+    }
+    sync_plan_from_price(state, &email, subscription.price_id()).await;
+    log_payment_event(&state.audit, event, &email, "subscription.updated", None).await?;
 
-    Json(PortalSessionResponse { url: portal_url })
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(&email).await,
+    ))
 }
 
-/// ═══════════════════════════════════════════════════════════════════════════════
-// CHECKOUT HANDLERS
-// ═══════════════════════════════════════════════════════════════════════════════
+/// O(1) - Email on a Stripe customer object
+async fn fetch_customer_email(
+    state: &StripeWebhookState,
+    customer_id: &str,
+) -> Result<Option<String>, UpstreamError> {
+    let request = state.stripe_api(Method::GET, &format!("/v1/customers/{}", customer_id));
+    let res = state.http.send("fetch_customer", request).await?;
 
-/// O(1) - Initiates Stripe Checkout for Basic Plan
-pub async fn start_checkout_basic(State(state): State<Arc<StripeWebhookState>>) -> Redirect {
-    create_checkout_redirect(&state, "basic").await
+    let status = res.status();
+    let body: serde_json::Value = res.json().await.map_err(|e| format!("JSON error: {}", e))?;
+    if !status.is_success() {
+        return Err(UpstreamError::status("Stripe", status, body));
+    }
+    Ok(body["email"]
+        .as_str()
+        .filter(|e| !e.is_empty())
+        .map(|e| e.to_string()))
 }
 
-/// O(1) - Initiates Stripe Checkout for Premium Plan
-pub async fn start_checkout_premium(State(state): State<Arc<StripeWebhookState>>) -> Redirect {
-    create_checkout_redirect(&state, "premium").await
+/// O(1) - Follow plan changes made outside checkout (portal, dashboard) by the
+/// price Stripe now bills; unknown prices leave the stored plan alone
+async fn sync_plan_from_price(state: &StripeWebhookState, email: &str, price_id: Option<&str>) {
+    let Some(offer) = price_id.and_then(|price| state.catalog.find_by_stripe_price(price)) else {
+        return;
+    };
+    let plan = offer.grants.clone();
+    if state
+        .subscriptions
+        .get(email)
+        .await
+        .is_some_and(|sub| sub.plan != plan)
+    {
+        state.subscriptions.change_plan(email, plan).await;
+    }
 }
 
-/// O(log n) - Internal helper to create session via Stripe API
-async fn create_checkout_redirect(state: &Arc<StripeWebhookState>, plan_type: &str) -> Redirect {
-    let client = reqwest::Client::new();
-    let domain = std::env::var("DOMAIN").unwrap_or_else(|_| "https://veritras.website".to_string());
+async fn handle_subscription_deleted(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let customer_email = event
+        .data
+        .object
+        .get("customer_email")
+        .and_then(|v| v.as_str());
 
-    let price_id = match plan_type {
-        "basic" => {
-            std::env::var("STRIPE_PRICE_BASIC").unwrap_or_else(|_| "price_1OtH...".to_string())
-        }
-        "premium" => {
-            std::env::var("STRIPE_PRICE_PREMIUM").unwrap_or_else(|_| "price_1OtH...".to_string())
-        }
-        _ => return Redirect::to("/error"),
+    if let Some(email) = customer_email {
+        state.subscriptions.cancel_subscription(email).await;
+        state.licenses.revoke_email(email).await;
+        log_payment_event(&state.audit, event, email, "subscription.deleted", None).await?;
+        return Ok(EventResult::for_subscription(
+            state.subscriptions.get(email).await,
+        ));
+    }
+
+    Ok(EventResult::Processed)
+}
+
+/// Card saved without a charge: remember it on the customer's subscription so
+/// later off-session charges can use it
+async fn handle_setup_intent_succeeded(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let intent: StripeSetupIntent = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse setup intent: {}", e))?;
+    let Some(payment_method) = intent.payment_method.as_deref() else {
+        println!("[SETUP] ⚠️ SetupIntent {} has no payment method", intent.id);
+        return Ok(EventResult::Processed);
     };
 
-    // Stripe expects x-www-form-urlencoded for nested values
-    let mut params = HashMap::new();
+    let email = match intent.customer.as_deref() {
+        Some(customer) => state.subscriptions.email_for_customer(customer).await,
+        None => None,
+    }
+    .or_else(|| intent.metadata.get("email").cloned());
+    let Some(email) = email else {
+        println!(
+            "[SETUP] ⚠️ No subscription for SetupIntent {} (customer {:?})",
+            intent.id, intent.customer
+        );
+        return Ok(EventResult::Processed);
+    };
+
+    if !state
+        .subscriptions
+        .save_payment_method(&email, payment_method)
+        .await
+    {
+        println!("[SETUP] ℹ️ {} has no local subscription", email);
+        return Ok(EventResult::Processed);
+    }
+    println!(
+        "[SETUP] 💳 Saved {} for {} (usage: {})",
+        payment_method,
+        email,
+        intent.usage.as_deref().unwrap_or("unknown")
+    );
+    log_payment_event(&state.audit, event, &email, "payment_method.saved", None).await?;
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(&email).await,
+    ))
+}
+
+/// Customer changed their email (portal, dashboard): move their subscription and
+/// license keys to the new address so lookups by email keep working
+async fn handle_customer_updated(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let customer: StripeCustomer = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse customer: {}", e))?;
+    let new_email = customer.email.map(|e| e.trim().to_lowercase());
+    let Some(new_email) = new_email.filter(|e| !e.is_empty()) else {
+        return Ok(EventResult::Processed);
+    };
+
+    // previous_attributes only lists changed fields; otherwise compare with what we
+    // hold for this customer in case an earlier update was missed
+    let previous = event
+        .data
+        .previous_attributes
+        .as_ref()
+        .and_then(|prev| prev["email"].as_str())
+        .map(str::to_string);
+    let old_email = match previous {
+        Some(old) => old,
+        None => match state.subscriptions.email_for_customer(&customer.id).await {
+            Some(old) => old,
+            None => return Ok(EventResult::Processed),
+        },
+    };
+    if old_email == new_email {
+        return Ok(EventResult::Processed);
+    }
 
-    // Ensure domain has https://
-    let mut validated_domain = domain.clone();
-    if !validated_domain.starts_with("http") {
-        validated_domain = format!("https://{}", validated_domain);
+    if !state
+        .subscriptions
+        .change_email(&old_email, &new_email)
+        .await
+    {
         println!(
-            "[CHECKOUT] ⚠️ Auto-correcting DOMAIN to: {}",
-            validated_domain
+            "[CUSTOMER] ⚠️ Could not move {} to {} (no record, or {} already has one)",
+            old_email, new_email, new_email
         );
+        return Ok(EventResult::Processed);
     }
+    let moved_keys = state.licenses.reassign_email(&old_email, &new_email).await;
+    println!(
+        "[CUSTOMER] ✉️ {} is now {} ({} license key(s) moved)",
+        old_email, new_email, moved_keys
+    );
 
-    params.insert(
-        "success_url",
-        format!(
-            "{}/validator.html?session_id={{CHECKOUT_SESSION_ID}}&status=success",
-            validated_domain
-        ),
+    let mut log_entry = payment_event_entry(event, &new_email, "customer.email_changed", None);
+    log_entry["previous_email"] = old_email.into();
+    state.audit.record("AUDIT", log_entry).await?;
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(&new_email).await,
+    ))
+}
+
+/// O(1) - Email of the disputed customer: the evidence field if filled in,
+/// else the charge's receipt / billing email, else its customer's email
+async fn dispute_email(
+    state: &StripeWebhookState,
+    dispute: &StripeDispute,
+) -> Result<Option<String>, UpstreamError> {
+    if let Some(email) = dispute.evidence["customer_email_address"]
+        .as_str()
+        .filter(|e| !e.is_empty())
+    {
+        return Ok(Some(email.to_string()));
+    }
+    let Some(charge_id) = &dispute.charge else {
+        return Ok(None);
+    };
+    let charge = fetch_charge(state, charge_id).await?;
+    charge_email(state, &charge).await
+}
+
+/// O(1) - GET /v1/charges/{id}
+async fn fetch_charge(
+    state: &StripeWebhookState,
+    charge_id: &str,
+) -> Result<StripeCharge, UpstreamError> {
+    let request = state.stripe_api(Method::GET, &format!("/v1/charges/{}", charge_id));
+    let res = state.http.send("fetch_charge", request).await?;
+    let status = res.status();
+    let charge: serde_json::Value = res.json().await.map_err(|e| format!("JSON error: {}", e))?;
+    if !status.is_success() {
+        return Err(UpstreamError::status("Stripe", status, charge));
+    }
+    serde_json::from_value(charge).map_err(|e| format!("Failed to parse charge: {}", e).into())
+}
+
+/// O(1) - The charge's receipt / billing email, else its customer's email
+async fn charge_email(
+    state: &StripeWebhookState,
+    charge: &StripeCharge,
+) -> Result<Option<String>, UpstreamError> {
+    let email = charge
+        .receipt_email
+        .as_deref()
+        .or_else(|| charge.billing_details["email"].as_str())
+        .filter(|e| !e.is_empty());
+    match (email, charge.customer.as_deref()) {
+        (Some(email), _) => Ok(Some(email.to_string())),
+        (None, Some(customer)) => fetch_customer_email(state, customer).await,
+        (None, None) => Ok(None),
+    }
+}
+
+/// Chargeback decided: `won` restores access, `lost` keeps it revoked
+async fn handle_dispute_closed(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let dispute: StripeDispute = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse dispute: {}", e))?;
+    let Some(email) = dispute_email(state, &dispute).await? else {
+        println!("[DISPUTE] ⚠️ No customer email for dispute {}", dispute.id);
+        return Ok(EventResult::Processed);
+    };
+
+    // An inquiry closed without escalating (`warning_closed`) is as good as a win
+    let won = matches!(dispute.status.as_str(), "won" | "warning_closed");
+    if !won {
+        println!(
+            "[DISPUTE] ⚖️ Dispute {} lost, {} stays revoked",
+            dispute.id, email
+        );
+    } else if !state.subscriptions.restore_after_dispute(&email).await {
+        println!(
+            "[DISPUTE] ℹ️ Dispute {} won, {} has no revoked subscription to restore",
+            dispute.id, email
+        );
+    }
+    let audit_event = if won { "dispute.won" } else { "dispute.lost" };
+    log_payment_event(&state.audit, event, &email, audit_event, dispute.amount).await?;
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(&email).await,
+    ))
+}
+
+/// Refund issued outside this backend (dashboard, API): a full refund cancels
+/// the subscription, a partial one is only recorded
+async fn handle_charge_refunded(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let charge: StripeCharge = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse charge: {}", e))?;
+    apply_charge_refund(state, event, &charge).await
+}
+
+/// A refund's status changed; once it succeeds, reconcile its charge the same
+/// way as charge.refunded
+async fn handle_charge_refund_updated(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+) -> Result<EventResult, WebhookError> {
+    let refund: StripeRefund = serde_json::from_value(event.data.object.clone())
+        .map_err(|e| format!("Failed to parse refund: {}", e))?;
+    if refund.status != "succeeded" {
+        println!(
+            "[REFUND] ℹ️ Refund {} is {}, nothing to reconcile",
+            refund.id, refund.status
+        );
+        return Ok(EventResult::Processed);
+    }
+    let Some(charge_id) = &refund.charge else {
+        println!("[REFUND] ⚠️ Refund {} has no charge", refund.id);
+        return Ok(EventResult::Processed);
+    };
+    let charge = fetch_charge(state, charge_id).await?;
+    apply_charge_refund(state, event, &charge).await
+}
+
+async fn apply_charge_refund(
+    state: &StripeWebhookState,
+    event: &StripeEvent,
+    charge: &StripeCharge,
+) -> Result<EventResult, WebhookError> {
+    let Some(email) = charge_email(state, charge).await? else {
+        println!("[REFUND] ⚠️ No customer email for charge {}", charge.id);
+        return Ok(EventResult::Processed);
+    };
+
+    if !charge.refunded {
+        println!(
+            "[REFUND] ↩️ Charge {} partially refunded for {}, subscription kept",
+            charge.id, email
+        );
+        log_payment_event(
+            &state.audit,
+            event,
+            &email,
+            "charge.partially_refunded",
+            charge.amount_refunded,
+        )
+        .await?;
+        return Ok(EventResult::for_subscription(
+            state.subscriptions.get(&email).await,
+        ));
+    }
+
+    if state.subscriptions.cancel_subscription(&email).await {
+        let revoked = state.licenses.revoke_email(&email).await;
+        println!(
+            "[REFUND] ↩️ Charge {} fully refunded, canceled {} ({} license key(s) revoked)",
+            charge.id, email, revoked
+        );
+    } else {
+        println!(
+            "[REFUND] ℹ️ Charge {} refunded for {}, no local subscription",
+            charge.id, email
+        );
+    }
+    log_payment_event(
+        &state.audit,
+        event,
+        &email,
+        "charge.refunded",
+        charge.amount_refunded,
+    )
+    .await?;
+    Ok(EventResult::for_subscription(
+        state.subscriptions.get(&email).await,
+    ))
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// IMMUTABLE AUDIT LOG
+// ═══════════════════════════════════════════════════════════════════════════════
+
+async fn log_payment_event(
+    audit: &AuditTrail,
+    event: &StripeEvent,
+    email: &str,
+    event_type: &str,
+    amount: Option<i64>,
+) -> Result<(), String> {
+    audit
+        .record(
+            "AUDIT",
+            payment_event_entry(event, email, event_type, amount),
+        )
+        .await
+}
+
+fn payment_event_entry(
+    event: &StripeEvent,
+    email: &str,
+    event_type: &str,
+    amount: Option<i64>,
+) -> serde_json::Value {
+    let mut entry = provider_event::audit_entry(event, event_type);
+    entry["api_version"] = event.api_version.clone().into();
+    entry["email"] = email.into();
+    entry["amount_cents"] = amount.into();
+    if let Some(minor) = amount {
+        entry["amount_display"] = event_money(event, minor).localized().into();
+    }
+    entry
+}
+
+/// O(1) - `minor` in the currency of the event's object (Stripe sends it lower
+/// case); EUR when the object has none
+fn event_money(event: &StripeEvent, minor: i64) -> Money {
+    Money {
+        minor,
+        currency: event.data.object["currency"]
+            .as_str()
+            .unwrap_or("eur")
+            .to_ascii_uppercase(),
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// SESSION VERIFICATION & LICENSE
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    pub session_id: String,
+    /// Previously issued key to re-validate (accepted under any rotated secret)
+    pub license_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+    pub plan: Option<String>,
+    pub email: Option<String>,
+    pub license_key: Option<String>,
+}
+
+/// O(1) - Retrieve a Checkout Session from Stripe
+async fn fetch_checkout_session(
+    state: &StripeWebhookState,
+    session_id: &str,
+) -> Result<CheckoutSession, String> {
+    let request = state.stripe_api(
+        Method::GET,
+        &format!("/v1/checkout/sessions/{}", session_id),
     );
-    params.insert(
-        "cancel_url",
-        format!("{}/validator.html?status=cancel", validated_domain),
+    let res = state.http.send("verify_session", request).await?;
+
+    let status = res.status();
+    let body = res.text().await.map_err(|e| format!("Body error: {}", e))?;
+    if !status.is_success() {
+        return Err(UpstreamError::status("Stripe", status, body).into());
+    }
+    serde_json::from_str(&body).map_err(|e| format!("Failed to parse session: {}", e))
+}
+
+/// GET /stripe/verify?session_id= - Confirm a paid session and hand out its license key
+pub async fn verify_session(
+    State(state): State<Arc<StripeWebhookState>>,
+    Query(query): Query<VerifyQuery>,
+) -> Response {
+    if !query.session_id.starts_with("cs_") {
+        return (StatusCode::BAD_REQUEST, "Invalid session id").into_response();
+    }
+
+    let session = match fetch_checkout_session(&state, &query.session_id).await {
+        Ok(s) => s,
+        Err(e) => {
+            println!("[VERIFY] ❌ Lookup failed for {}: {}", query.session_id, e);
+            return (StatusCode::BAD_GATEWAY, "Session lookup failed").into_response();
+        }
+    };
+
+    let mut response = VerifyResponse {
+        valid: session.is_paid(),
+        plan: session.plan().map(|p| p.to_string()),
+        email: session.email().map(|e| e.to_string()),
+        license_key: None,
+    };
+    if !response.valid {
+        println!("[VERIFY] ⚠️ Session {} is not paid", session.id);
+        return Json(response).into_response();
+    }
+
+    let issued = state.license.issue_checked(
+        LicenseProvider::Stripe,
+        &session.id,
+        query.license_key.as_deref(),
     );
-    params.insert("line_items[0][price]", price_id.clone());
-    params.insert("line_items[0][quantity]", "1".to_string());
 
-    // Auto-detect mode or use override from ENV
-    let mode = std::env::var("STRIPE_PAYMENT_MODE").unwrap_or_else(|_| "payment".to_string());
-    params.insert("mode", mode);
+    match issued {
+        Ok((key, presented_ok)) => {
+            let registered = match session.email() {
+                Some(email) => {
+                    state
+                        .licenses
+                        .register(
+                            &key,
+                            LicenseProvider::Stripe,
+                            &session.id,
+                            email,
+                            session.plan().unwrap_or_default(),
+                        )
+                        .await
+                }
+                None => false,
+            };
+            if !registered {
+                println!(
+                    "[VERIFY] ❌ Session {} is paid but has no email",
+                    session.id
+                );
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(serde_json::json!({ "valid": false, "error": "Purchase has no email" })),
+                )
+                    .into_response();
+            }
+            response.valid = presented_ok;
+            response.license_key = Some(key);
+        }
+        Err(e) => {
+            println!("[VERIFY] ❌ License refused for {}: {}", session.id, e);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "valid": false, "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    }
 
-    match client
-        .post("https://api.stripe.com/v1/checkout/sessions")
-        .basic_auth(&state.config.secret_key, None::<&str>)
-        .form(&params)
-        .send()
-        .await
-    {
-        Ok(res) => {
-            let status = res.status();
-            let body = res
-                .text()
-                .await
-                .unwrap_or_else(|_| "<unreadable body>".to_string());
+    println!(
+        "[VERIFY] ✅ Session {} verified for {:?}",
+        session.id, response.email
+    );
+    Json(response).into_response()
+}
 
-            if status.is_success() {
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
-                    if let Some(url) = json.get("url").and_then(|u| u.as_str()) {
-                        println!("[CHECKOUT] 🔗 Redirecting to: {}", url);
-                        return Redirect::to(url);
-                    }
+// ═══════════════════════════════════════════════════════════════════════════════
+// PLAN LIMITS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct LimitsQuery {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitsResponse {
+    pub email: String,
+    /// Tier the limits come from: `free`, `pro`, `enterprise`
+    pub plan: &'static str,
+    pub limits: PlanLimits,
+}
+
+/// GET /stripe/limits?email= - Admin: quotas of the user's current tier (Free when unknown)
+pub async fn get_limits(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Query(query): Query<LimitsQuery>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let email = query.email.trim().to_lowercase();
+    let plan = match state.subscriptions.get(&email).await {
+        Some(sub) if sub.grants_access() => sub.plan.role(),
+        _ => SubscriptionPlan::Free.role(),
+    };
+    Json(LimitsResponse {
+        limits: state.catalog.limits_for(plan),
+        email,
+        plan,
+    })
+    .into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// SUBSCRIPTION LOOKUP
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct SubscriptionQuery {
+    pub email: String,
+}
+
+/// GET /stripe/subscription?email= - Admin: the user's subscription record (404 when none)
+pub async fn get_subscription(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Query(query): Query<SubscriptionQuery>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    match state.subscriptions.get(&query.email).await {
+        Some(subscription) => Json(subscription).into_response(),
+        None => (StatusCode::NOT_FOUND, "Subscription not found").into_response(),
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ENTITLEMENT TOKENS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Either a paid checkout session, or an email with admin auth
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub session_id: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub token_type: &'static str,
+    pub expires_at: i64,
+    pub claims: EntitlementClaims,
+}
+
+/// POST /stripe/token - Short-lived JWT carrying the subscriber's plan and entitlements
+pub async fn issue_token(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Json(request): Json<TokenRequest>,
+) -> Response {
+    if !state.tokens.enabled() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Token issuance disabled").into_response();
+    }
+
+    // Who is asking, and the plan their proof of purchase carries
+    let (email, purchased_plan) = match (&request.session_id, &request.email) {
+        (Some(session_id), _) => {
+            if !session_id.starts_with("cs_") {
+                return (StatusCode::BAD_REQUEST, "Invalid session id").into_response();
+            }
+            let session = match fetch_checkout_session(&state, session_id).await {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("[TOKEN] ❌ Lookup failed for {}: {}", session_id, e);
+                    return (StatusCode::BAD_GATEWAY, "Session lookup failed").into_response();
                 }
-            } else {
-                println!("[CHECKOUT] ❌ STRIPE API ERROR ({}): {}", status, body);
-                println!("[CHECKOUT] 💡 ARCHITECT: Check if your STRIPE_SECRET_KEY is valid and has 'Checkout Sessions' permissions.");
+            };
+            match session.email() {
+                Some(email) if session.is_paid() => (
+                    email.to_string(),
+                    session
+                        .plan()
+                        .map(|key| state.catalog.resolve(key).unwrap_or(SubscriptionPlan::Free)),
+                ),
+                _ => return (StatusCode::FORBIDDEN, "Session is not paid").into_response(),
             }
         }
-        Err(e) => println!("[CHECKOUT] ❌ Stripe API Request Failed: {}", e),
+        (None, Some(email)) => {
+            if let Err(denied) = state.admin.require(&headers) {
+                return denied.into_response();
+            }
+            (email.trim().to_lowercase(), None)
+        }
+        (None, None) => {
+            return (StatusCode::BAD_REQUEST, "session_id or email required").into_response()
+        }
+    };
+
+    // The stored subscription wins; a fresh session may arrive before its webhook
+    let role = match state.subscriptions.get(&email).await {
+        Some(sub) if sub.grants_access() => sub.plan.role(),
+        Some(_) => SubscriptionPlan::Free.role(),
+        None => purchased_plan
+            .as_ref()
+            .unwrap_or(&SubscriptionPlan::Free)
+            .role(),
+    };
+
+    match state.tokens.issue(&email, role) {
+        Ok((token, claims)) => {
+            println!("[TOKEN] 🎫 Issued {} token for {}", role, email);
+            Json(TokenResponse {
+                token,
+                token_type: "Bearer",
+                expires_at: claims.exp,
+                claims,
+            })
+            .into_response()
+        }
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
     }
+}
 
-    // Fallback if API fails
-    println!("[CHECKOUT] ⚠️ API failed, redirecting to frontend error handler");
-    let error_redirect = format!("{}/validator.html?error=gateway_failure", validated_domain);
-    Redirect::to(&error_redirect)
+// ═══════════════════════════════════════════════════════════════════════════════
+// CUSTOMER PORTAL
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Serialize)]
+pub struct PortalSessionResponse {
+    pub url: String,
+}
+
+/// Window in which repeated portal requests for one customer share a session
+const PORTAL_IDEMPOTENCY_WINDOW_SECS: i64 = 60;
+
+/// O(n) - The client's `Idempotency-Key`, validated to what Stripe accepts
+fn client_idempotency_key(headers: &HeaderMap) -> Result<Option<String>, String> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .map_err(|_| "Idempotency-Key must be ASCII".to_string())?
+        .trim();
+    if key.is_empty() || key.len() > 255 {
+        return Err("Idempotency-Key must be 1-255 characters".to_string());
+    }
+    Ok(Some(key.to_string()))
+}
+
+/// O(n) - The client's `Idempotency-Key` if sent, else one derived from the
+/// customer, return URL and current minute so a double-click reuses the session
+fn portal_idempotency_key(
+    headers: &HeaderMap,
+    customer_id: &str,
+    return_url: &str,
+) -> Result<String, String> {
+    if let Some(key) = client_idempotency_key(headers)? {
+        return Ok(key);
+    }
+
+    let window = Utc::now().timestamp() / PORTAL_IDEMPOTENCY_WINDOW_SECS;
+    let digest = Sha256::digest(format!("{}|{}|{}", customer_id, return_url, window));
+    Ok(format!("portal_{}", hex::encode(&digest[..16])))
+}
+
+/// O(1) - Admin: create Stripe Customer Portal session returning to the caller's site
+pub async fn create_portal_session(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let Some(customer_id) = payload["customer_id"].as_str().filter(|c| !c.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "customer_id required").into_response();
+    };
+    let return_url = format!(
+        "{}/validator.html?status=portal",
+        state.domains.for_request(&headers)
+    );
+
+    let mut form = vec![
+        ("customer", customer_id),
+        ("return_url", return_url.as_str()),
+    ];
+    if let Some(configuration) = &state.config.portal_configuration {
+        form.push(("configuration", configuration.as_str()));
+    }
+
+    let idempotency_key = match portal_idempotency_key(&headers, customer_id, &return_url) {
+        Ok(key) => key,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    // Same key on every attempt, so a retried POST cannot create a second session
+    let sent = retry_async(&state.http.retry, UpstreamError::is_transient, || async {
+        let request = state
+            .stripe_api(Method::POST, "/v1/billing_portal/sessions")
+            .header("Idempotency-Key", &idempotency_key)
+            .form(&form);
+        let res = state.http.send("create_portal", request).await?;
+        let status = res.status();
+        let body: serde_json::Value = res.json().await.unwrap_or_default();
+        if status.is_server_error() || status.as_u16() == 429 {
+            return Err(UpstreamError::status("Stripe", status, body));
+        }
+        Ok((status, body))
+    })
+    .await;
+    let (status, body) = match sent {
+        Ok(sent) => sent,
+        Err(e) => {
+            println!("[PORTAL] ❌ Stripe API Request Failed: {}", e);
+            return (StatusCode::BAD_GATEWAY, "Portal unavailable").into_response();
+        }
+    };
+
+    match body["url"].as_str() {
+        Some(url) if status.is_success() => {
+            println!(
+                "[PORTAL] 🔗 Created portal session for: {} (return {})",
+                customer_id, return_url
+            );
+            Json(PortalSessionResponse {
+                url: url.to_string(),
+            })
+            .into_response()
+        }
+        _ => {
+            println!("[PORTAL] ❌ STRIPE API ERROR ({}): {}", status, body);
+            (StatusCode::BAD_GATEWAY, "Portal unavailable").into_response()
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// REFUNDS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Reasons Stripe accepts on a refund
+const REFUND_REASONS: [&str; 3] = ["duplicate", "fraudulent", "requested_by_customer"];
+
+#[derive(Debug, Deserialize)]
+pub struct RefundRequest {
+    pub payment_intent: String,
+    /// Minor units for a partial refund; the whole remaining charge when omitted
+    pub amount: Option<i64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RefundResponse {
+    pub id: String,
+    pub status: String,
+    pub amount: Option<i64>,
+    pub currency: Option<String>,
+}
+
+/// POST /stripe/refund - Refund a PaymentIntent in full or in part (admin only)
+pub async fn create_refund(
+    State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    Json(request): Json<RefundRequest>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let payment_intent = request.payment_intent.trim();
+    if !payment_intent.starts_with("pi_") {
+        return (StatusCode::BAD_REQUEST, "payment_intent must be a pi_ id").into_response();
+    }
+    if request.amount.is_some_and(|amount| amount <= 0) {
+        return (StatusCode::BAD_REQUEST, "amount must be positive").into_response();
+    }
+    if let Some(reason) = &request.reason {
+        if !REFUND_REASONS.contains(&reason.as_str()) {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("reason must be one of {}", REFUND_REASONS.join(", ")),
+            )
+                .into_response();
+        }
+    }
+
+    let amount = request.amount.map(|amount| amount.to_string());
+    let mut form = vec![("payment_intent", payment_intent)];
+    if let Some(amount) = &amount {
+        form.push(("amount", amount.as_str()));
+    }
+    if let Some(reason) = &request.reason {
+        form.push(("reason", reason.as_str()));
+    }
+
+    // Without a client key each call is its own refund; retries below still share one
+    let idempotency_key = match client_idempotency_key(&headers) {
+        Ok(key) => key.unwrap_or_else(|| format!("refund_{}", Uuid::new_v4().simple())),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let sent = retry_async(&state.http.retry, UpstreamError::is_transient, || async {
+        let request = state
+            .stripe_api(Method::POST, "/v1/refunds")
+            .header("Idempotency-Key", &idempotency_key)
+            .form(&form);
+        let res = state.http.send("create_refund", request).await?;
+        let status = res.status();
+        let body: serde_json::Value = res.json().await.unwrap_or_default();
+        if status.is_server_error() || status.as_u16() == 429 {
+            return Err(UpstreamError::status("Stripe", status, body));
+        }
+        Ok((status, body))
+    })
+    .await;
+    let (status, body) = match sent {
+        Ok(sent) => sent,
+        Err(e) => {
+            println!("[REFUND] ❌ Stripe API Request Failed: {}", e);
+            return (StatusCode::BAD_GATEWAY, "Refund unavailable").into_response();
+        }
+    };
+
+    let refund_id = match body["id"].as_str() {
+        Some(id) if status.is_success() => id.to_string(),
+        _ => {
+            println!("[REFUND] ❌ STRIPE API ERROR ({}): {}", status, body);
+            // Stripe's own message (already refunded, amount too large...) is what the operator needs
+            return match body["error"]["message"].as_str() {
+                Some(message) if status.is_client_error() => {
+                    (StatusCode::BAD_REQUEST, message.to_string()).into_response()
+                }
+                _ => (StatusCode::BAD_GATEWAY, "Refund unavailable").into_response(),
+            };
+        }
+    };
+    let refund = RefundResponse {
+        id: refund_id,
+        status: body["status"].as_str().unwrap_or("unknown").to_string(),
+        amount: body["amount"].as_i64(),
+        currency: body["currency"]
+            .as_str()
+            .map(|currency| currency.to_ascii_uppercase()),
+    };
+    let display = match (refund.amount, &refund.currency) {
+        (Some(minor), Some(currency)) => Money {
+            minor,
+            currency: currency.clone(),
+        }
+        .localized(),
+        _ => "full".to_string(),
+    };
+    println!(
+        "[REFUND] 💸 {} refunded {} on {} ({}) by {}",
+        refund.id, display, payment_intent, refund.status, client_ip
+    );
+
+    let livemode = body["livemode"]
+        .as_bool()
+        .unwrap_or_else(|| state.config.is_live());
+    let entry = serde_json::json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "provider": "stripe",
+        "event": "refund.created",
+        "livemode": livemode,
+        "mode": audit::mode_label(livemode),
+        "payment_intent": payment_intent,
+        "refund_id": refund.id,
+        "refund_status": refund.status,
+        "amount_cents": refund.amount,
+        "currency": refund.currency,
+        "amount_display": display,
+        "reason": request.reason,
+        "requested_by": client_ip.to_string(),
+    });
+    // The refund already went through at Stripe, so a failed audit only gets reported
+    if let Err(e) = state.audit.record("AUDIT", entry).await {
+        println!("[REFUND] ⚠️ {} not audited: {}", refund.id, e);
+    }
+
+    Json(refund).into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// CHECKOUT HANDLERS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CheckoutQuery {
+    /// Optional; prefills Checkout and keys the per-email rate limit
+    pub email: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub referrer: Option<String>,
+}
+
+impl CheckoutQuery {
+    /// O(n) - Non-empty attribution tags, sanitized and length-limited
+    fn attribution(&self) -> Vec<(String, String)> {
+        let values = [
+            &self.utm_source,
+            &self.utm_medium,
+            &self.utm_campaign,
+            &self.referrer,
+        ];
+        ATTRIBUTION_KEYS
+            .iter()
+            .zip(values)
+            .filter_map(|(key, value)| {
+                let value = sanitize_value(value.as_deref()?.trim(), ATTRIBUTION_VALUE_MAX, key);
+                (!value.is_empty()).then(|| (key.to_string(), value))
+            })
+            .collect()
+    }
+}
+
+/// O(1) - Initiates Stripe Checkout for Basic Plan
+pub async fn start_checkout_basic(
+    State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    Query(query): Query<CheckoutQuery>,
+) -> Response {
+    start_checkout(&state, client_ip, &headers, query, "basic").await
+}
+
+/// O(1) - Initiates Stripe Checkout for Premium Plan
+pub async fn start_checkout_premium(
+    State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    Query(query): Query<CheckoutQuery>,
+) -> Response {
+    start_checkout(&state, client_ip, &headers, query, "premium").await
+}
+
+/// O(1) - Rate-limit gate shared by the checkout routes
+async fn start_checkout(
+    state: &Arc<StripeWebhookState>,
+    client_ip: IpAddr,
+    headers: &HeaderMap,
+    query: CheckoutQuery,
+    plan_type: &str,
+) -> Response {
+    let email = query.email.as_deref().filter(|e| e.contains('@'));
+    let ip = client_ip.to_string();
+
+    if let Err(limit) = state.checkout_limits.check(&ip, email).await {
+        println!(
+            "[CHECKOUT] 🚫 Rate limited ({} limit) for {} / {:?}",
+            limit, ip, email
+        );
+        metrics::counter!("rate_limit_blocked_total", "limit" => limit).increment(1);
+        return (StatusCode::TOO_MANY_REQUESTS, "Too many checkout attempts").into_response();
+    }
+
+    // Stripe would reject the session anyway; fail with a readable reason first
+    if let Some(plan) = state.catalog.get(plan_type) {
+        if let Err(e) = plan.price().check_minimum() {
+            println!("[CHECKOUT] ❌ Plan '{}' not chargeable: {}", plan_type, e);
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    }
+
+    let domain = state.domains.for_request(headers);
+    let attribution = query.attribution();
+    create_checkout_redirect(state, domain, plan_type, email, attribution)
+        .await
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckoutLinkRequest {
+    pub plan: String,
+    /// Defaults to `CHECKOUT_LINK_TTL_SECS`; capped at `CHECKOUT_LINK_MAX_TTL_SECS`
+    pub expires_in_secs: Option<i64>,
+    pub email: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckoutLinkResponse {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// POST /stripe/checkout/link - Admin: signed checkout URL that stops working at expiry
+pub async fn create_checkout_link(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Json(request): Json<CheckoutLinkRequest>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+    if !state.checkout_links.enabled() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Checkout links disabled").into_response();
+    }
+    if state
+        .catalog
+        .get(&request.plan)
+        .and_then(|p| p.stripe_price_id.as_ref())
+        .is_none()
+    {
+        return (StatusCode::BAD_REQUEST, "Unknown Stripe plan").into_response();
+    }
+
+    let ttl = request
+        .expires_in_secs
+        .unwrap_or(state.checkout_links.default_ttl_secs);
+    if ttl <= 0 || ttl > state.checkout_links.max_ttl_secs {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "expires_in_secs must be between 1 and {}",
+                state.checkout_links.max_ttl_secs
+            ),
+        )
+            .into_response();
+    }
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(ttl);
+    let claims = CheckoutLinkClaims {
+        plan: request.plan,
+        exp: expires_at.timestamp(),
+        email: request.email.map(|e| e.trim().to_lowercase()),
+        utm_source: request.utm_source,
+        utm_medium: request.utm_medium,
+        utm_campaign: request.utm_campaign,
+    };
+    let token = match state.checkout_links.sign(&claims) {
+        Ok(t) => t,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    println!(
+        "[CHECKOUT_LINK] 🔗 Issued {} link, expires {}",
+        claims.plan, expires_at
+    );
+
+    Json(CheckoutLinkResponse {
+        url: format!(
+            "{}/stripe/checkout/go?token={}",
+            public_api_url(&state.domains),
+            token
+        ),
+        expires_at,
+    })
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckoutLinkQuery {
+    pub token: String,
+}
+
+/// GET /stripe/checkout/go?token= - Redeem a signed link into a fresh Checkout Session
+pub async fn follow_checkout_link(
+    State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    Query(query): Query<CheckoutLinkQuery>,
+) -> Response {
+    if !state.checkout_links.enabled() {
+        return (StatusCode::NOT_FOUND, "Checkout links disabled").into_response();
+    }
+    let claims = match state.checkout_links.verify(&query.token) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("[CHECKOUT_LINK] 🚫 Rejected link from {}: {}", client_ip, e);
+            let status = match e {
+                LinkError::Expired => StatusCode::GONE,
+                LinkError::Malformed => StatusCode::BAD_REQUEST,
+                LinkError::Tampered => StatusCode::FORBIDDEN,
+            };
+            return (status, e.to_string()).into_response();
+        }
+    };
+
+    let query = CheckoutQuery {
+        email: claims.email,
+        utm_source: claims.utm_source,
+        utm_medium: claims.utm_medium,
+        utm_campaign: claims.utm_campaign,
+        referrer: None,
+    };
+    start_checkout(&state, client_ip, &headers, query, &claims.plan).await
+}
+
+/// O(1) - Public base URL of this backend (for links back into checkout)
+fn public_api_url(domains: &SiteDomains) -> String {
+    std::env::var("PUBLIC_API_URL").unwrap_or_else(|_| domains.primary.clone())
+}
+
+/// O(1) - Create a throwaway customer attached to a Stripe Test Clock
+async fn create_test_clock_customer(
+    state: &StripeWebhookState,
+    clock: &str,
+) -> Result<String, String> {
+    let request = state
+        .stripe_api(Method::POST, "/v1/customers")
+        .form(&[("test_clock", clock)]);
+    let res = state.http.send("create_test_customer", request).await?;
+
+    let status = res.status();
+    let body: serde_json::Value = res.json().await.map_err(|e| format!("JSON error: {}", e))?;
+    if !status.is_success() {
+        return Err(UpstreamError::status("Stripe", status, body).into());
+    }
+
+    body["id"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| "Customer id missing from Stripe response".to_string())
+}
+
+/// O(log n) - Internal helper to create session via Stripe API
+async fn create_checkout_redirect(
+    state: &Arc<StripeWebhookState>,
+    validated_domain: &str,
+    plan_type: &str,
+    customer_email: Option<&str>,
+    attribution: Vec<(String, String)>,
+) -> Redirect {
+    let price_id = match state
+        .catalog
+        .get(plan_type)
+        .and_then(|p| p.stripe_price_id.clone())
+    {
+        Some(price_id) => price_id,
+        None => return Redirect::to("/error"),
+    };
+
+    // Stripe expects x-www-form-urlencoded for nested values
+    let mut params: HashMap<String, String> = HashMap::new();
+
+    params.insert(
+        "success_url".to_string(),
+        format!(
+            "{}/validator.html?session_id={{CHECKOUT_SESSION_ID}}&status=success",
+            validated_domain
+        ),
+    );
+    params.insert(
+        "cancel_url".to_string(),
+        format!("{}/validator.html?status=cancel", validated_domain),
+    );
+    params.insert("line_items[0][price]".to_string(), price_id.clone());
+    params.insert("line_items[0][quantity]".to_string(), "1".to_string());
+    if let Some(email) = customer_email {
+        params.insert("customer_email".to_string(), email.to_string());
+    }
+
+    // Metadata is validated against Stripe's limits before it can be rejected upstream
+    let mut metadata = vec![("plan".to_string(), plan_type.to_string())];
+    metadata.extend(attribution);
+    for (key, value) in sanitize_metadata(metadata, STRIPE_METADATA_LIMITS) {
+        params.insert(format!("metadata[{}]", key), value);
+    }
+
+    // Auto-detect mode or use override from ENV
+    let mode = std::env::var("STRIPE_PAYMENT_MODE").unwrap_or_else(|_| "payment".to_string());
+    params.insert("mode".to_string(), mode);
+
+    // QA: bind the session to a Test Clock customer so billing can be fast-forwarded
+    if let Some(clock) = &state.config.test_clock {
+        match create_test_clock_customer(state, clock).await {
+            Ok(customer_id) => {
+                println!(
+                    "[CHECKOUT] ⏱️ Using test clock {} (customer {})",
+                    clock, customer_id
+                );
+                params.insert("customer".to_string(), customer_id);
+            }
+            Err(e) => println!("[CHECKOUT] ⚠️ Test clock customer failed: {}", e),
+        }
+    }
+
+    let request = state
+        .stripe_api(Method::POST, "/v1/checkout/sessions")
+        .form(&params);
+    match state.http.send("create_checkout", request).await {
+        Ok(res) => {
+            let status = res.status();
+            let body = res
+                .text()
+                .await
+                .unwrap_or_else(|_| "<unreadable body>".to_string());
+
+            if status.is_success() {
+                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
+                    if let Some(url) = json.get("url").and_then(|u| u.as_str()) {
+                        println!("[CHECKOUT] 🔗 Redirecting to: {}", url);
+                        metrics::counter!(
+                            "checkout_sessions_created_total",
+                            "provider" => "stripe",
+                            "plan" => plan_type.to_string()
+                        )
+                        .increment(1);
+                        return Redirect::to(url);
+                    }
+                }
+            } else {
+                println!("[CHECKOUT] ❌ STRIPE API ERROR ({}): {}", status, body);
+                println!("[CHECKOUT] 💡 ARCHITECT: Check if your STRIPE_SECRET_KEY is valid and has 'Checkout Sessions' permissions.");
+            }
+        }
+        Err(e) => println!("[CHECKOUT] ❌ Stripe API Request Failed: {}", e),
+    }
+
+    // Fallback if API fails
+    println!("[CHECKOUT] ⚠️ API failed, redirecting to frontend error handler");
+    let error_redirect = format!("{}/validator.html?error=gateway_failure", validated_domain);
+    Redirect::to(&error_redirect)
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ADMIN: BULK IMPORT
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct ImportRecord {
+    pub email: String,
+    pub plan: String,
+    /// Stripe-style status (`active`, `trialing`, ...); defaults to active
+    pub status: Option<String>,
+    pub stripe_customer_id: Option<String>,
+    pub stripe_subscription_id: Option<String>,
+    pub current_period_end: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub trial_end: Option<DateTime<Utc>>,
+    /// UTM / referrer tags carried over from the previous system
+    #[serde(default)]
+    pub attribution: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportOutcome {
+    pub email: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub failed: usize,
+    pub results: Vec<ImportOutcome>,
+}
+
+/// O(1) - Validate one record into a subscription ready for upsert
+fn validate_import(
+    catalog: &PricingCatalog,
+    record: &ImportRecord,
+) -> Result<UserSubscription, String> {
+    // Casing is left to `upsert_subscription`, which keys every record
+    let email = record.email.trim();
+    if email.is_empty() || !email.contains('@') || email.contains(char::is_whitespace) {
+        return Err("invalid email".to_string());
+    }
+
+    let plan = catalog
+        .resolve(&record.plan)
+        .ok_or_else(|| format!("unknown plan '{}'", record.plan))?;
+    let status = match record.status.as_deref() {
+        None => SubscriptionStatus::Active,
+        Some(raw) => SubscriptionStatus::from_stripe(raw)
+            .ok_or_else(|| format!("unknown status '{}'", raw))?,
+    };
+
+    let mut subscription = UserSubscription {
+        user_id: Uuid::new_v4(),
+        email: email.to_string(),
+        stripe_customer_id: record.stripe_customer_id.clone(),
+        stripe_subscription_id: record.stripe_subscription_id.clone(),
+        plan,
+        status: SubscriptionStatus::Active,
+        activated_at: Utc::now(),
+        current_period_end: record.current_period_end,
+        trial_end: record.trial_end,
+        past_due_since: None,
+        attribution: record.attribution.clone(),
+        saved_payment_method: None,
+    };
+    subscription.set_status(status);
+    Ok(subscription)
+}
+
+/// POST /stripe/admin/import - Seed subscriptions from another system (idempotent by email)
+pub async fn import_subscriptions(
+    State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    Json(records): Json<Vec<ImportRecord>>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let mut results = Vec::with_capacity(records.len());
+    for record in &records {
+        let outcome = match validate_import(&state.catalog, record) {
+            Ok(subscription) => {
+                let email = subscription.email.clone();
+                let created = state.subscriptions.upsert_subscription(subscription).await;
+                ImportOutcome {
+                    email,
+                    ok: true,
+                    action: Some(if created { "created" } else { "updated" }),
+                    error: None,
+                }
+            }
+            Err(e) => ImportOutcome {
+                email: record.email.clone(),
+                ok: false,
+                action: None,
+                error: Some(e),
+            },
+        };
+        results.push(outcome);
+    }
+
+    let imported = results.iter().filter(|r| r.ok).count();
+    let summary = ImportSummary {
+        imported,
+        failed: results.len() - imported,
+        results,
+    };
+    println!(
+        "[ADMIN] 📥 Imported {} subscription(s), {} failed (from {})",
+        summary.imported, summary.failed, client_ip
+    );
+
+    Json(summary).into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ADMIN: LIFECYCLE SIMULATION (sandbox only)
+// ═══════════════════════════════════════════════════════════════════════════════
+
+const SIMULATION_MAX_STEPS: usize = 20;
+const SIMULATION_MAX_DELAY_MS: u64 = 60_000;
+
+#[derive(Debug, Deserialize)]
+pub struct SimulateRequest {
+    pub email: String,
+    /// `activate`, then any Stripe status: `active`, `past_due`, `canceled`, ...
+    pub sequence: Vec<String>,
+    #[serde(default = "default_simulation_plan")]
+    pub plan: String,
+    #[serde(default = "default_simulation_delay_ms")]
+    pub delay_ms: u64,
+}
+
+fn default_simulation_plan() -> String {
+    "basic".to_string()
+}
+
+fn default_simulation_delay_ms() -> u64 {
+    2000
+}
+
+#[derive(Debug, Clone)]
+enum SimulationStep {
+    Activate,
+    Status(SubscriptionStatus),
+}
+
+/// O(n) - Reject the whole script up front if any step is unknown
+fn parse_simulation(sequence: &[String]) -> Result<Vec<SimulationStep>, String> {
+    if sequence.is_empty() || sequence.len() > SIMULATION_MAX_STEPS {
+        return Err(format!(
+            "sequence must have 1..={} steps",
+            SIMULATION_MAX_STEPS
+        ));
+    }
+    sequence
+        .iter()
+        .map(|step| match step.as_str() {
+            "activate" => Ok(SimulationStep::Activate),
+            other => SubscriptionStatus::from_stripe(other)
+                .map(SimulationStep::Status)
+                .ok_or_else(|| format!("unknown step '{}'", other)),
+        })
+        .collect()
+}
+
+/// POST /stripe/admin/simulate - Script subscription transitions for demos (never live)
+pub async fn simulate_lifecycle(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Json(request): Json<SimulateRequest>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+    if state.config.is_live() {
+        println!("[SIMULATE] 🚫 Refused: live Stripe keys configured");
+        return (StatusCode::FORBIDDEN, "Simulation disabled in live mode").into_response();
+    }
+
+    let email = request.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return (StatusCode::BAD_REQUEST, "Invalid email").into_response();
+    }
+    if state.catalog.resolve(&request.plan).is_none() {
+        return (StatusCode::BAD_REQUEST, "Unknown plan").into_response();
+    }
+    let steps = match parse_simulation(&request.sequence) {
+        Ok(steps) => steps,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let delay = std::time::Duration::from_millis(request.delay_ms.min(SIMULATION_MAX_DELAY_MS));
+    let subscriptions = state.subscriptions.clone();
+    let plan = state.catalog.plan_key(&request.plan);
+    let response = serde_json::json!({
+        "email": email,
+        "plan": request.plan,
+        "sequence": request.sequence,
+        "delay_ms": delay.as_millis() as u64,
+    });
+
+    println!(
+        "[SIMULATE] 🎬 {} step(s) for {} every {:?}",
+        steps.len(),
+        email,
+        delay
+    );
+    tokio::spawn(async move {
+        for (i, step) in steps.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(delay).await;
+            }
+            match step {
+                SimulationStep::Activate => {
+                    subscriptions
+                        .activate_subscription(&email, None, None, plan)
+                        .await;
+                }
+                SimulationStep::Status(status) => {
+                    if !subscriptions.update_status(&email, status).await {
+                        println!(
+                            "[SIMULATE] ⚠️ No subscription for {}, stopping (start with 'activate')",
+                            email
+                        );
+                        return;
+                    }
+                }
+            }
+        }
+        println!("[SIMULATE] 🏁 Sequence finished for {}", email);
+    });
+
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ADMIN: NDJSON EXPORT
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// GET /stripe/admin/subscriptions.ndjson - One `UserSubscription` per line.
+/// Streams shard by shard, so only one shard is copied in memory at a time.
+pub async fn export_subscriptions_ndjson(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let subscriptions = state.subscriptions.clone();
+    let chunks = stream::unfold(0usize, move |index| {
+        let subscriptions = subscriptions.clone();
+        async move {
+            if index >= subscriptions.shard_count() {
+                return None;
+            }
+            let mut chunk = String::new();
+            for record in subscriptions.shard_records(index).await {
+                match serde_json::to_string(&record) {
+                    Ok(line) => {
+                        chunk.push_str(&line);
+                        chunk.push('\n');
+                    }
+                    Err(e) => println!("[EXPORT] ⚠️ Skipping {}: {}", record.email, e),
+                }
+            }
+            Some((Ok::<_, Infallible>(chunk), index + 1))
+        }
+    });
+
+    println!("[EXPORT] 📤 Streaming subscriptions as NDJSON");
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ADMIN: IDEMPOTENCY
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Deserialize)]
+pub struct ClearIdempotencyQuery {
+    /// One event's marker; without it every marker is cleared
+    pub event_id: Option<String>,
+}
+
+/// POST /stripe/admin/idempotency/clear[?event_id=] - Forget processed markers so
+/// redelivered (or dead-letter retried) events run again
+pub async fn clear_idempotency(
+    State(state): State<Arc<StripeWebhookState>>,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    headers: HeaderMap,
+    Query(query): Query<ClearIdempotencyQuery>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    if let Some(event_id) = query.event_id.as_deref().map(str::trim) {
+        if event_id.is_empty() {
+            return (StatusCode::BAD_REQUEST, "event_id is empty").into_response();
+        }
+        let existed = state.idempotency.get(event_id).await.is_some();
+        state.idempotency.forget(event_id).await;
+        println!(
+            "[IDEMPOTENCY] 🧹 ADMIN CLEAR by {}: marker for {} {}",
+            client_ip,
+            event_id,
+            if existed { "removed" } else { "was not set" }
+        );
+        return Json(serde_json::json!({
+            "event_id": event_id,
+            "cleared": usize::from(existed),
+        }))
+        .into_response();
+    }
+
+    match state.idempotency.forget_all().await {
+        Ok(cleared) => {
+            println!(
+                "[IDEMPOTENCY] 🧹 ADMIN CLEAR by {}: ALL {} processed marker(s) removed; every redelivery will be reprocessed",
+                client_ip, cleared
+            );
+            Json(serde_json::json!({ "cleared": cleared })).into_response()
+        }
+        Err(e) => {
+            println!(
+                "[IDEMPOTENCY] ❌ ADMIN CLEAR by {} failed: {}",
+                client_ip, e
+            );
+            (StatusCode::SERVICE_UNAVAILABLE, e).into_response()
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// ADMIN: DEAD LETTERS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// GET /stripe/admin/dead-letter - Events that failed permanently or past DEAD_LETTER_AFTER_ATTEMPTS
+pub async fn list_dead_letters(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+    Json(state.dead_letters.list_dead().await).into_response()
+}
+
+/// POST /stripe/admin/dead-letter/:id/retry - Reprocess the stored payload
+pub async fn retry_dead_letter(
+    State(state): State<Arc<StripeWebhookState>>,
+    headers: HeaderMap,
+    Path(event_id): Path<String>,
+) -> Response {
+    if let Err(denied) = state.admin.require(&headers) {
+        return denied.into_response();
+    }
+
+    let Some(entry) = state.dead_letters.get(&event_id).await else {
+        return (StatusCode::NOT_FOUND, "No dead letter for this event").into_response();
+    };
+    let event: StripeEvent = match serde_json::from_str(&entry.payload) {
+        Ok(e) => e,
+        Err(e) => {
+            println!(
+                "[DEAD_LETTER] ❌ Stored payload for {} unreadable: {}",
+                event_id, e
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Stored payload unreadable",
+            )
+                .into_response();
+        }
+    };
+
+    println!(
+        "[DEAD_LETTER] 🔁 Retrying {} ({}) after {} attempts",
+        event.id, event.event_type, entry.attempts
+    );
+    // Same path as a delivery: claimed against concurrent redeliveries and queue
+    // workers, and a transient failure leaves the dead letter and marker as they were
+    let event_id = event.id.clone();
+    let event_type = event.event_type.clone();
+    let (result, marker_error) =
+        process_event_reporting_marker(&state, event, &entry.payload).await;
+
+    // Admin call: report a lost marker instead of hiding it
+    Json(serde_json::json!({
+        "event_id": event_id,
+        "event_type": event_type,
+        "retried": true,
+        "outcome": result.as_ref().ok(),
+        "error": result.err().map(|e| e.to_string()),
+        "marker_error": marker_error.map(|e| e.to_string()),
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockRequest, MockResponse, MockServer, RecordingAuditLog};
+
+    fn stripe_event(id: &str, event_type: &str, object: serde_json::Value) -> StripeEvent {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "type": event_type,
+            "created": 1_700_000_000,
+            "livemode": false,
+            "data": { "object": object },
+        }))
+        .unwrap()
+    }
+
+    async fn subscribed(email: &str) -> SubscriptionManager {
+        let subscriptions = SubscriptionManager::new();
+        subscriptions
+            .activate_subscription(email, Some("cus_1".into()), None, "pro_monthly")
+            .await;
+        subscriptions
+    }
+
+    async fn status_of(subscriptions: &SubscriptionManager, email: &str) -> SubscriptionStatus {
+        subscriptions.get(email).await.unwrap().status
+    }
+
+    /// Fresh state whose admin routes accept `admin_headers()`
+    fn test_state() -> StripeWebhookState {
+        StripeWebhookState {
+            admin: AdminAuth::new(Some("test-admin-token".to_string())),
+            ..StripeWebhookState::new()
+        }
+    }
+
+    fn admin_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "test-admin-token".parse().unwrap());
+        headers
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    async fn limits(state: &Arc<StripeWebhookState>, email: &str) -> serde_json::Value {
+        let query = LimitsQuery {
+            email: email.to_string(),
+        };
+        body_json(get_limits(State(state.clone()), admin_headers(), Query(query)).await).await
+    }
+
+    #[tokio::test]
+    async fn premium_user_gets_enterprise_limits() {
+        let state = Arc::new(test_state());
+        state
+            .subscriptions
+            .activate_subscription("vip@x.com", None, None, state.catalog.plan_key("premium"))
+            .await;
+
+        let body = limits(&state, "vip@X.com").await;
+        assert_eq!(body["email"], "vip@x.com");
+        assert_eq!(body["plan"], "enterprise");
+        assert_eq!(body["limits"]["api_calls_per_month"], 1_000_000);
+        assert_eq!(body["limits"]["seats"], 50);
+    }
+
+    #[tokio::test]
+    async fn unknown_user_gets_free_limits() {
+        let state = Arc::new(test_state());
+        let body = limits(&state, "nobody@x.com").await;
+        assert_eq!(body["plan"], "free");
+        assert_eq!(body["limits"]["api_calls_per_month"], 1_000);
+        assert_eq!(body["limits"]["seats"], 1);
+    }
+
+    const TEST_WEBHOOK_SECRET: &str = "whsec_test";
+
+    /// Signature enforced under `TEST_WEBHOOK_SECRET`, processed inline
+    fn webhook_state() -> StripeWebhookState {
+        let mut state = test_state();
+        state.config.webhook_secret = TEST_WEBHOOK_SECRET.to_string();
+        state.config.dev_skip_signature = false;
+        state.webhook_queue = None;
+        state
+    }
+
+    /// A signed delivery of `event` to the webhook route
+    async fn deliver(state: &Arc<StripeWebhookState>, event: &serde_json::Value) -> Response {
+        deliver_signed_at(state, event, Utc::now().timestamp()).await
+    }
+
+    /// `deliver`, with the signature dated `timestamp`
+    async fn deliver_signed_at(
+        state: &Arc<StripeWebhookState>,
+        event: &serde_json::Value,
+        timestamp: i64,
+    ) -> Response {
+        let body = event.to_string();
+        let signature =
+            sign_stripe_payload(body.as_bytes(), &state.config.webhook_secret, timestamp);
+        let mut headers = HeaderMap::new();
+        headers.insert("stripe-signature", signature.parse().unwrap());
+        stripe_webhook_handler(
+            State(state.clone()),
+            Extension(ClientIp("127.0.0.1".parse().unwrap())),
+            headers,
+            Bytes::from(body),
+        )
+        .await
+        .into_response()
+    }
+
+    fn event_json(id: &str, event_type: &str, object: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "type": event_type,
+            "created": Utc::now().timestamp(),
+            "livemode": false,
+            "data": { "object": object },
+        })
+    }
+
+    #[tokio::test]
+    async fn business_error_status_follows_the_setting() {
+        assert_eq!(parse_business_error_status(None), StatusCode::OK);
+        assert_eq!(
+            parse_business_error_status(Some(" 500 ")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(parse_business_error_status(Some("404")), StatusCode::OK);
+
+        // A session that does not parse is bad data, not an outage
+        let malformed = |id: &str| {
+            event_json(
+                id,
+                "checkout.session.completed",
+                serde_json::json!({ "id": "cs_bad", "status": "complete", "currency": 5 }),
+            )
+        };
+        for status in [StatusCode::OK, StatusCode::INTERNAL_SERVER_ERROR] {
+            let mut state = webhook_state();
+            state.config.business_error_status = status;
+            let state = Arc::new(state);
+            let response = deliver(
+                &state,
+                &malformed(&format!("evt_business_{}", status.as_u16())),
+            )
+            .await;
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_checkout_sends_a_recovery_link_for_its_plan() {
+        let hook = MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+        let mut state = webhook_state();
+        state.config.abandonment_recovery = true;
+        state.config.recovery_base_url = Some("https://api.veritas.test".to_string());
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let state = Arc::new(state);
+
+        let expired = event_json(
+            "evt_checkout_expired",
+            "checkout.session.expired",
+            serde_json::json!({
+                "id": "cs_1",
+                "status": "expired",
+                "customer_details": { "email": "late@x.com" },
+                "metadata": { "plan": "premium" },
+            }),
+        );
+        assert_eq!(deliver(&state, &expired).await.status(), StatusCode::OK);
+
+        let sent = hook.requests();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            (sent[0].method.as_str(), sent[0].path.as_str()),
+            ("POST", "/")
+        );
+        assert!(sent[0].headers["content-type"].contains("json"));
+        let notification = sent[0].json();
+        assert_eq!(notification["kind"], "checkout.recovery");
+        assert_eq!(notification["email"], "late@x.com");
+        assert_eq!(notification["data"]["plan"], "premium");
+        assert_eq!(
+            notification["data"]["checkout_url"],
+            "https://api.veritas.test/stripe/checkout/premium"
+        );
+    }
+
+    #[tokio::test]
+    async fn checkout_recovery_needs_its_own_url() {
+        let hook = MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+        let mut state = webhook_state();
+        state.config.abandonment_recovery = true;
+        state.config.recovery_base_url = None;
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let state = Arc::new(state);
+
+        let expired = event_json(
+            "evt_checkout_expired_no_url",
+            "checkout.session.expired",
+            serde_json::json!({
+                "id": "cs_3",
+                "status": "expired",
+                "customer_email": "late@x.com",
+                "metadata": { "plan": "basic" },
+            }),
+        );
+        assert_eq!(deliver(&state, &expired).await.status(), StatusCode::OK);
+        assert!(hook.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn checkout_recovery_is_opt_in() {
+        let hook = MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+        let mut state = webhook_state();
+        state.config.abandonment_recovery = false;
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let state = Arc::new(state);
+
+        let expired = event_json(
+            "evt_checkout_expired_off",
+            "checkout.session.expired",
+            serde_json::json!({
+                "id": "cs_2",
+                "status": "expired",
+                "customer_email": "late@x.com",
+                "metadata": { "plan": "basic" },
+            }),
+        );
+        assert_eq!(deliver(&state, &expired).await.status(), StatusCode::OK);
+        assert!(hook.requests().is_empty());
+    }
+
+    /// Stripe stand-in: customers come back as `cus_clock`, sessions as a URL
+    fn stripe_api(request: &MockRequest) -> MockResponse {
+        match request.path.as_str() {
+            "/v1/customers" => MockResponse::json(200, serde_json::json!({ "id": "cus_clock" })),
+            "/v1/checkout/sessions" => MockResponse::json(
+                200,
+                serde_json::json!({ "id": "cs_1", "url": "https://checkout.test/cs_1" }),
+            ),
+            _ => MockResponse::json(404, serde_json::json!({ "error": {} })),
+        }
+    }
+
+    /// Where checkout for `basic` redirects to
+    async fn checkout_against(api: &MockServer, test_clock: Option<&str>) -> String {
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        state.config.test_clock = test_clock.map(str::to_string);
+        let state = Arc::new(state);
+        let redirect =
+            create_checkout_redirect(&state, "https://app.test", "basic", None, Vec::new()).await;
+        redirect.into_response().headers()["location"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_clock_customer_is_attached_in_sandbox() {
+        let api = MockServer::start(stripe_api).await;
+        assert_eq!(
+            checkout_against(&api, Some("clock_1")).await,
+            "https://checkout.test/cs_1"
+        );
+
+        let requests = api.requests();
+        assert_eq!(requests[0].path, "/v1/customers");
+        assert_eq!(requests[0].form()["test_clock"], "clock_1");
+        let session = requests[1].form();
+        assert_eq!(session["customer"], "cus_clock");
+        assert_eq!(session["metadata[plan]"], "basic");
+    }
+
+    #[tokio::test]
+    async fn no_test_clock_means_no_customer_param() {
+        let api = MockServer::start(stripe_api).await;
+        assert_eq!(
+            checkout_against(&api, None).await,
+            "https://checkout.test/cs_1"
+        );
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].form().contains_key("customer"));
+    }
+
+    #[test]
+    fn test_clock_is_refused_with_live_keys() {
+        let mut config = test_state().config;
+        config.test_clock = Some("clock_1".to_string());
+        config.secret_key = "sk_test_x".to_string();
+        assert!(config.clone().validated().test_clock.is_some());
+
+        config.secret_key = "sk_live_x".to_string();
+        config.dev_skip_signature = false;
+        assert!(config.validated().test_clock.is_none());
+    }
+
+    fn with_webhook_secret(secret: &str) -> Arc<StripeWebhookState> {
+        let mut state = test_state();
+        state.config.webhook_secret = secret.to_string();
+        state.config.dev_skip_signature = false;
+        Arc::new(state)
+    }
+
+    async fn selftest(
+        state: &Arc<StripeWebhookState>,
+        delivery: Option<(&str, String)>,
+    ) -> serde_json::Value {
+        let mut headers = admin_headers();
+        let body = match delivery {
+            Some((payload, signature)) => {
+                headers.insert("stripe-signature", signature.parse().unwrap());
+                Bytes::from(payload.to_string())
+            }
+            None => Bytes::new(),
+        };
+        body_json(webhook_selftest(State(state.clone()), headers, body).await).await
+    }
+
+    #[tokio::test]
+    async fn selftest_checks_the_known_vector_without_a_delivery() {
+        let report = selftest(&with_webhook_secret("whsec_live_one"), None).await;
+        assert_eq!(report["checked"], "known_vector");
+        assert_eq!(report["signature_verified"], true);
+        assert_eq!(report["ok"], true);
+        // The vector's header really is what `sign_stripe_payload` produces
+        assert_eq!(
+            sign_stripe_payload(
+                SELFTEST_VECTOR_PAYLOAD.as_bytes(),
+                SELFTEST_VECTOR_SECRET,
+                1_700_000_000
+            ),
+            SELFTEST_VECTOR_HEADER
+        );
+    }
+
+    #[tokio::test]
+    async fn selftest_checks_a_supplied_delivery_against_the_configured_secret() {
+        let state = with_webhook_secret("whsec_configured");
+        let payload = r#"{"id":"evt_captured","type":"invoice.paid"}"#;
+        // Captured a while ago: age is not what this checks
+        let signed_at = Utc::now().timestamp() - 86_400;
+
+        let matching = sign_stripe_payload(payload.as_bytes(), "whsec_configured", signed_at);
+        let report = selftest(&state, Some((payload, matching))).await;
+        assert_eq!(report["checked"], "supplied_delivery");
+        assert_eq!(report["ok"], true);
+
+        let other = sign_stripe_payload(payload.as_bytes(), "whsec_someone_else", signed_at);
+        let report = selftest(&state, Some((payload, other))).await;
+        assert_eq!(report["signature_verified"], false);
+        assert_eq!(report["ok"], false);
+    }
+
+    struct FailingAuditLog;
+
+    impl crate::audit::AuditLog for FailingAuditLog {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn append<'a>(
+            &'a self,
+            _entry: &'a crate::audit::AuditEntry,
+        ) -> futures_util::future::BoxFuture<'a, Result<(), String>> {
+            Box::pin(async { Err("disk full".to_string()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn audit_failure_under_required_durability_is_a_5xx() {
+        let mut state = webhook_state();
+        state.audit = Arc::new(AuditTrail::with_sinks(
+            vec![Box::new(FailingAuditLog)],
+            true,
+        ));
+        state
+            .subscriptions
+            .activate_subscription("a@x.com", None, None, "basic")
+            .await;
+        let state = Arc::new(state);
+
+        let event = event_json(
+            "evt_audit_down",
+            "invoice.paid",
+            serde_json::json!({ "id": "in_1", "customer_email": "a@x.com", "amount_paid": 900 }),
+        );
+        assert!(deliver(&state, &event).await.status().is_server_error());
+        // Unmarked, so Stripe's redelivery is processed again
+        assert!(state.idempotency.get("evt_audit_down").await.is_none());
+    }
+
+    /// charge.dispute.closed for a customer whose access was revoked
+    fn dispute_closed(id: &str, status: &str) -> StripeEvent {
+        stripe_event(
+            id,
+            "charge.dispute.closed",
+            serde_json::json!({
+                "id": "dp_1",
+                "status": status,
+                "amount": 4900,
+                "evidence": { "customer_email_address": "a@x.com" },
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn won_dispute_restores_access() {
+        let state = test_state();
+        state
+            .subscriptions
+            .activate_subscription("a@x.com", None, None, "premium")
+            .await;
+        state
+            .subscriptions
+            .update_status("a@x.com", SubscriptionStatus::Unpaid)
+            .await;
+
+        process_event(&state, dispute_closed("evt_dispute_won", "won"), "{}")
+            .await
+            .unwrap();
+        assert_eq!(
+            status_of(&state.subscriptions, "a@x.com").await,
+            SubscriptionStatus::Active
+        );
+    }
+
+    #[tokio::test]
+    async fn lost_dispute_keeps_access_revoked() {
+        let state = test_state();
+        state
+            .subscriptions
+            .activate_subscription("a@x.com", None, None, "premium")
+            .await;
+        state
+            .subscriptions
+            .update_status("a@x.com", SubscriptionStatus::Unpaid)
+            .await;
+
+        process_event(&state, dispute_closed("evt_dispute_lost", "lost"), "{}")
+            .await
+            .unwrap();
+        assert_eq!(
+            status_of(&state.subscriptions, "a@x.com").await,
+            SubscriptionStatus::Unpaid
+        );
+    }
+
+    #[tokio::test]
+    async fn won_dispute_does_not_resurrect_a_canceled_subscription() {
+        let subscriptions = subscribed("a@x.com").await;
+        assert!(subscriptions.cancel_subscription("a@x.com").await);
+
+        assert!(!subscriptions.restore_after_dispute("a@x.com").await);
+        assert_eq!(
+            status_of(&subscriptions, "a@x.com").await,
+            SubscriptionStatus::Canceled
+        );
+    }
+
+    #[tokio::test]
+    async fn emails_are_keyed_case_insensitively() {
+        let subscriptions = subscribed(" Foo@X.com").await;
+        assert_eq!(
+            subscriptions.get("foo@x.com").await.unwrap().email,
+            "foo@x.com"
+        );
+
+        // An import naming another casing finds the same record
+        let mut imported = subscriptions.get("foo@x.com").await.unwrap();
+        imported.email = "FOO@X.COM".to_string();
+        assert!(!subscriptions.upsert_subscription(imported).await);
+        assert_eq!(subscriptions.all().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refund_reaches_a_mixed_case_checkout_email() {
+        let state = test_state();
+        // Checkout activates with the email exactly as the customer typed it
+        let activation = PendingActivation::new("Buyer@Example.com", None, None, "basic");
+        state.subscriptions.try_activate(&activation).await.unwrap();
+
+        let event = stripe_event(
+            "evt_refund_mixed_case",
+            "charge.refunded",
+            serde_json::json!({
+                "id": "ch_1",
+                "receipt_email": "buyer@example.COM",
+                "refunded": true,
+            }),
+        );
+        process_event(&state, event, "{}").await.unwrap();
+        assert_eq!(
+            status_of(&state.subscriptions, "Buyer@Example.com").await,
+            SubscriptionStatus::Canceled
+        );
+    }
+
+    #[tokio::test]
+    async fn held_claim_turns_a_concurrent_delivery_away_unmarked() {
+        let state = test_state();
+        let event = stripe_event(
+            "evt_claim_held",
+            "customer.subscription.deleted",
+            serde_json::json!({ "id": "sub_1" }),
+        );
+
+        // An admin retry or queue worker holds the event
+        assert!(state.idempotency.try_claim(&event.id).await);
+        let turned_away = process_event(&state, event.clone(), "{}").await;
+        assert!(turned_away.is_err_and(|e| e.is_transient()));
+        assert!(state.idempotency.get(&event.id).await.is_none());
+
+        state.idempotency.release(&event.id).await;
+        assert_eq!(
+            process_event(&state, event.clone(), "{}").await.unwrap(),
+            "Success"
+        );
+        assert_eq!(
+            process_event(&state, event, "{}").await.unwrap(),
+            "Already processed"
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_failure_counts_an_attempt_and_leaves_the_marker_alone() {
+        // The recovery hook is down: transient
+        let hook = MockServer::start(|_| MockResponse::json(503, serde_json::json!({}))).await;
+        let mut state = test_state();
+        state.config.abandonment_recovery = true;
+        state.config.recovery_base_url = Some("https://api.veritas.test".to_string());
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let event = stripe_event(
+            "evt_dead_transient",
+            "checkout.session.expired",
+            serde_json::json!({
+                "id": "cs_dead",
+                "status": "expired",
+                "customer_details": { "email": "a@x.com" },
+                "metadata": { "plan": "premium" },
+            }),
+        );
+        state
+            .dead_letters
+            .record_failure(&event.id, &event.event_type, "{}", "earlier failure", false)
+            .await;
+        let earlier = state.idempotency.get(&event.id).await;
+
+        let result = process_event(&state, event.clone(), "{}").await;
+        assert!(result.is_err_and(|e| e.is_transient()));
+        let dead = state.dead_letters.get(&event.id).await.unwrap();
+        assert_eq!(dead.attempts, 2);
+        assert!(!dead.permanent);
+        assert!(state.dead_letters.list_dead().await.is_empty());
+        assert_eq!(
+            format!(
+                "{:?}",
+                state.idempotency.get(&event.id).await.map(|m| m.result)
+            ),
+            format!("{:?}", earlier.map(|m| m.result))
+        );
+    }
+
+    #[tokio::test]
+    async fn permanent_failure_is_dead_lettered_and_retried_once_fixed() {
+        let email_on_file = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = email_on_file.clone();
+        let api = MockServer::start(move |_| {
+            let email = flag
+                .load(std::sync::atomic::Ordering::SeqCst)
+                .then_some("dead@x.io");
+            MockResponse::json(200, serde_json::json!({ "id": "cus_dead", "email": email }))
+        })
+        .await;
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        let state = Arc::new(state);
+        let event = event_json(
+            "evt_dead_permanent",
+            "customer.subscription.created",
+            serde_json::json!({
+                "id": "sub_dead",
+                "status": "active",
+                "customer": "cus_dead",
+            }),
+        );
+
+        // The customer has no email yet: a business error, acked and not redelivered
+        let response = deliver(&state, &event).await;
+        assert_eq!(response.status(), state.config.business_error_status);
+        assert_eq!(body_text(response).await, "Processed with error");
+        let listed =
+            body_json(list_dead_letters(State(state.clone()), admin_headers()).await).await;
+        assert_eq!(listed[0]["event_id"], "evt_dead_permanent");
+        assert_eq!(listed[0]["attempts"], 1);
+
+        email_on_file.store(true, std::sync::atomic::Ordering::SeqCst);
+        let response = retry_dead_letter(
+            State(state.clone()),
+            admin_headers(),
+            Path("evt_dead_permanent".to_string()),
+        )
+        .await;
+        let retried = body_json(response).await;
+        assert_eq!(retried["retried"], true);
+        assert!(retried["error"].is_null(), "{}", retried);
+        assert!(state.dead_letters.get("evt_dead_permanent").await.is_none());
+        let listed =
+            body_json(list_dead_letters(State(state.clone()), admin_headers()).await).await;
+        assert_eq!(listed, serde_json::json!([]));
+        let marker = state.idempotency.get("evt_dead_permanent").await.unwrap();
+        assert!(!marker.result.is_failure());
+    }
+
+    /// Dead-letters an event with no handler, so a retry of it succeeds
+    async fn dead_lettered(state: &StripeWebhookState, event_id: &str) {
+        let payload = event_json(event_id, "test.event.unrouted", serde_json::json!({}));
+        state
+            .dead_letters
+            .record_failure(
+                event_id,
+                "test.event.unrouted",
+                &payload.to_string(),
+                "boom",
+                true,
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn dead_letter_retry_reports_a_marker_redis_refused() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(crate::test_support::serve_fake_redis(
+            listener,
+            b"-ERR write refused\r\n",
+        ));
+        let mut state = test_state();
+        state.idempotency = IdempotencyStore::new(Some(url));
+        dead_lettered(&state, "evt_dead_marker").await;
+        let state = Arc::new(state);
+
+        let response = retry_dead_letter(
+            State(state.clone()),
+            admin_headers(),
+            Path("evt_dead_marker".to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let retried = body_json(response).await;
+        assert_eq!(retried["retried"], true);
+        assert!(retried["error"].is_null(), "{}", retried);
+        assert!(retried["marker_error"]
+            .as_str()
+            .is_some_and(|e| e.contains("write refused")));
+    }
+
+    #[tokio::test]
+    async fn dead_letter_retry_reports_a_transient_failure_with_200() {
+        let state = test_state();
+        dead_lettered(&state, "evt_dead_held").await;
+        let state = Arc::new(state);
+        // A redelivery holds the event
+        assert!(state.idempotency.try_claim("evt_dead_held").await);
+
+        let response = retry_dead_letter(
+            State(state.clone()),
+            admin_headers(),
+            Path("evt_dead_held".to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let retried = body_json(response).await;
+        assert_eq!(retried["retried"], true);
+        assert!(retried["error"]
+            .as_str()
+            .is_some_and(|e| e.contains("already being processed")));
+        assert!(retried["marker_error"].is_null());
+        assert!(state.dead_letters.get("evt_dead_held").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn process_event_reports_a_customer_without_email_as_permanent() {
+        let api = MockServer::start(|_| {
+            MockResponse::json(200, serde_json::json!({ "id": "cus_dead", "email": null }))
+        })
+        .await;
+        let mut state = test_state();
+        state.config.api_base = api.url.clone();
+        let event = stripe_event(
+            "evt_dead_direct",
+            "customer.subscription.created",
+            serde_json::json!({ "id": "sub_dead", "status": "active", "customer": "cus_dead" }),
+        );
+
+        let result = process_event(&state, event, "{}").await;
+        assert!(result.is_err_and(|e| !e.is_transient()));
+        let dead = state.dead_letters.get("evt_dead_direct").await.unwrap();
+        assert!(dead.permanent);
+    }
+
+    #[tokio::test]
+    async fn concurrent_deliveries_run_the_handler_once() {
+        let state = test_state();
+        let event = stripe_event(
+            "evt_claim_race",
+            "customer.subscription.deleted",
+            serde_json::json!({ "id": "sub_1" }),
+        );
+
+        let (first, second) = tokio::join!(
+            process_event(&state, event.clone(), "{}"),
+            process_event(&state, event.clone(), "{}"),
+        );
+        let ran = [&first, &second]
+            .iter()
+            .filter(|r| matches!(r, Ok("Success")))
+            .count();
+        assert_eq!(ran, 1, "{:?} / {:?}", first, second);
+        // The other one was either turned away (retryable) or saw the marker
+        assert!([first, second].iter().all(|r| match r {
+            Ok(outcome) => matches!(*outcome, "Success" | "Already processed"),
+            Err(e) => e.is_transient(),
+        }));
+    }
+
+    #[tokio::test]
+    async fn event_api_version_is_parsed_and_recorded() {
+        let mut event = event_json("evt_versioned", "customer.created", serde_json::json!({}));
+        event["api_version"] = "2023-10-16".into();
+        let parsed: StripeEvent = serde_json::from_value(event.clone()).unwrap();
+        assert_eq!(parsed.api_version.as_deref(), Some("2023-10-16"));
+        let entry = payment_event_entry(&parsed, "a@x.io", "payment_succeeded", Some(500));
+        assert_eq!(entry["api_version"], "2023-10-16");
+
+        let mut state = webhook_state();
+        state.config.api_version = Some("2024-04-10".into());
+        let state = Arc::new(state);
+        assert_eq!(deliver(&state, &event).await.status(), StatusCode::OK);
+        let record = state.idempotency.get("evt_versioned").await.unwrap();
+        assert_eq!(record.api_version.as_deref(), Some("2023-10-16"));
+    }
+
+    #[tokio::test]
+    async fn signature_bypass_works_in_sandbox_and_is_refused_live() {
+        let unsigned = |state: &Arc<StripeWebhookState>, event: serde_json::Value| {
+            stripe_webhook_handler(
+                State(state.clone()),
+                Extension(ClientIp("127.0.0.1".parse().unwrap())),
+                HeaderMap::new(),
+                Bytes::from(event.to_string()),
+            )
+        };
+        let sample = |id: &str, livemode: bool| {
+            let mut event = event_json(id, "customer.created", serde_json::json!({}));
+            event["livemode"] = livemode.into();
+            event
+        };
+
+        let mut state = webhook_state();
+        state.config.secret_key = "sk_test_x".to_string();
+        state.config.dev_skip_signature = true;
+        state.config = state.config.validated();
+        let state = Arc::new(state);
+        let response = unsigned(&state, sample("evt_dev_unsigned", false)).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+        let response = unsigned(&state, sample("evt_live_unsigned", true)).await;
+        assert_eq!(response.into_response().status(), StatusCode::UNAUTHORIZED);
+
+        let mut state = webhook_state();
+        state.config.secret_key = "sk_live_x".to_string();
+        state.config.dev_skip_signature = true;
+        state.config = state.config.validated();
+        assert!(!state.config.dev_skip_signature);
+        let response = unsigned(&Arc::new(state), sample("evt_prod_unsigned", false)).await;
+        assert_eq!(response.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn import_reports_each_record_of_a_mixed_batch() {
+        let state = Arc::new(test_state());
+        let import = |headers: HeaderMap, records: serde_json::Value| {
+            import_subscriptions(
+                State(state.clone()),
+                Extension(ClientIp("127.0.0.1".parse().unwrap())),
+                headers,
+                Json(serde_json::from_value(records).unwrap()),
+            )
+        };
+        let batch = serde_json::json!([
+            { "email": "Ok@x.io", "plan": "premium", "status": "trialing" },
+            { "email": "not-an-email", "plan": "basic" },
+            { "email": "b@x.io", "plan": "platinum" },
+            { "email": "c@x.io", "plan": "basic", "status": "frozen" },
+        ]);
+
+        let admin = admin_headers();
+        let denied = import(HeaderMap::new(), batch.clone()).await;
+        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.subscriptions.get("ok@x.io").await.is_none());
+
+        let summary = body_json(import(admin.clone(), batch.clone()).await).await;
+        assert_eq!(summary["imported"], 1);
+        assert_eq!(summary["failed"], 3);
+        let results = summary["results"].as_array().unwrap();
+        assert_eq!(results[0]["action"], "created");
+        assert_eq!(results[1]["error"], "invalid email");
+        assert_eq!(results[2]["error"], "unknown plan 'platinum'");
+        assert_eq!(results[3]["error"], "unknown status 'frozen'");
+        assert_eq!(
+            status_of(&state.subscriptions, "ok@x.io").await,
+            SubscriptionStatus::Trialing
+        );
+
+        let again = body_json(import(admin, batch).await).await;
+        assert_eq!(again["results"][0]["action"], "updated");
+    }
+
+    #[tokio::test]
+    async fn import_matches_records_whatever_the_casing() {
+        let state = Arc::new(test_state());
+        let import = |email: &str| {
+            import_subscriptions(
+                State(state.clone()),
+                Extension(ClientIp("127.0.0.1".parse().unwrap())),
+                admin_headers(),
+                Json(
+                    serde_json::from_value(serde_json::json!([
+                        { "email": email, "plan": "basic" }
+                    ]))
+                    .unwrap(),
+                ),
+            )
+        };
+
+        let first = body_json(import(" Mixed@X.io").await).await;
+        assert_eq!(first["results"][0]["action"], "created");
+        let second = body_json(import("mixed@x.io").await).await;
+        assert_eq!(second["results"][0]["action"], "updated");
+        assert_eq!(
+            state.subscriptions.get("MIXED@x.io").await.unwrap().email,
+            "mixed@x.io"
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_routes_refuse_every_token_when_none_is_configured() {
+        let state = Arc::new(StripeWebhookState {
+            admin: AdminAuth::new(None),
+            ..StripeWebhookState::new()
+        });
+        let response = import_subscriptions(
+            State(state),
+            Extension(ClientIp("127.0.0.1".parse().unwrap())),
+            admin_headers(),
+            Json(Vec::new()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn payment_intent_outside_checkout_activates_and_issues_a_license() {
+        let state = test_state();
+        let intent = stripe_event(
+            "evt_pi_1",
+            "payment_intent.succeeded",
+            serde_json::json!({
+                "id": "pi_elements_1",
+                "amount_received": 4900,
+                "currency": "eur",
+                "customer": "cus_pi",
+                "receipt_email": "buyer@x.io",
+                "metadata": { "plan": "premium" },
+            }),
+        );
+
+        let result = handle_payment_intent_succeeded(&state, &intent)
+            .await
+            .unwrap();
+        assert!(matches!(result, EventResult::Success { .. }));
+        let subscription = state.subscriptions.get("buyer@x.io").await.unwrap();
+        assert_eq!(subscription.stripe_customer_id.as_deref(), Some("cus_pi"));
+
+        let key = state
+            .license
+            .generate_license_key(LicenseProvider::Stripe, "pi_elements_1")
+            .unwrap();
+        let record = state.licenses.get(&key).await.unwrap();
+        assert_eq!(record.email, "buyer@x.io");
+        assert_eq!(record.purchase_id, "pi_elements_1");
+
+        // Subscription invoices are left to invoice.paid
+        let invoice_intent = stripe_event(
+            "evt_pi_2",
+            "payment_intent.succeeded",
+            serde_json::json!({
+                "id": "pi_invoice_1",
+                "invoice": "in_1",
+                "receipt_email": "sub@x.io",
+                "metadata": { "plan": "basic" },
+            }),
+        );
+        let result = handle_payment_intent_succeeded(&state, &invoice_intent)
+            .await
+            .unwrap();
+        assert!(matches!(result, EventResult::Processed));
+        assert!(state.subscriptions.get("sub@x.io").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn payment_intent_license_survives_a_failed_notification() {
+        let hook = MockServer::start(|_| MockResponse::json(503, serde_json::json!({}))).await;
+        let mut state = test_state();
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let intent = stripe_event(
+            "evt_pi_notify",
+            "payment_intent.succeeded",
+            serde_json::json!({
+                "id": "pi_notify_1",
+                "amount_received": 4900,
+                "currency": "eur",
+                "receipt_email": "notify@x.io",
+                "metadata": { "plan": "premium" },
+            }),
+        );
+
+        let result = handle_payment_intent_succeeded(&state, &intent).await;
+        assert!(matches!(result, Ok(EventResult::Success { .. })));
+        assert_eq!(hook.requests().len(), 1);
+        assert!(state.subscriptions.get("notify@x.io").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn outbound_calls_carry_the_pinned_api_version() {
+        let api = MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        for version in [Some("2024-06-20"), None] {
+            state.config.api_version = version.map(str::to_string);
+            let request = state.stripe_api(Method::GET, "/v1/balance");
+            state.http.send("api_version", request).await.unwrap();
+        }
+
+        let requests = api.requests();
+        assert_eq!(requests[0].path, "/v1/balance");
+        assert_eq!(requests[0].headers["stripe-version"], "2024-06-20");
+        assert!(!requests[1].headers.contains_key("stripe-version"));
+
+        let mut config = webhook_state().config;
+        config.api_version = Some("june-2024".into());
+        assert_eq!(config.validated().api_version, None);
+        assert!(is_valid_api_version("2024-06-20.acacia"));
+    }
+
+    #[tokio::test]
+    async fn duplicate_of_a_success_is_skipped_and_of_a_failure_reprocessed() {
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("dup@x.io").await;
+        let state = Arc::new(state);
+        let deleted = |id: &str| {
+            event_json(
+                id,
+                "customer.subscription.deleted",
+                serde_json::json!({ "id": "sub_1", "customer_email": "dup@x.io" }),
+            )
+        };
+
+        let prior = EventResult::Success {
+            user_id: Uuid::new_v4(),
+            plan: "basic".into(),
+        };
+        state
+            .idempotency
+            .mark_processed("evt_dup_ok".into(), prior, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            body_text(deliver(&state, &deleted("evt_dup_ok")).await).await,
+            "Already processed"
+        );
+        assert_eq!(
+            status_of(&state.subscriptions, "dup@x.io").await,
+            SubscriptionStatus::Active
+        );
+
+        let prior = EventResult::Failed {
+            error: "customer lookup failed".into(),
+        };
+        state
+            .idempotency
+            .mark_processed("evt_dup_failed".into(), prior, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            body_text(deliver(&state, &deleted("evt_dup_failed")).await).await,
+            "Success"
+        );
+        assert_eq!(
+            status_of(&state.subscriptions, "dup@x.io").await,
+            SubscriptionStatus::Canceled
+        );
+        let record = state.idempotency.get("evt_dup_failed").await.unwrap();
+        assert!(!record.result.is_failure());
+    }
+
+    #[tokio::test]
+    async fn process_event_skips_a_duplicate_of_a_success_and_reprocesses_a_failure() {
+        let state = subscribed("dupdirect@x.io").await;
+        let state = StripeWebhookState {
+            subscriptions: state,
+            ..test_state()
+        };
+        let deleted = |id: &str| {
+            stripe_event(
+                id,
+                "customer.subscription.deleted",
+                serde_json::json!({ "id": "sub_1", "customer_email": "dupdirect@x.io" }),
+            )
+        };
+
+        let prior = EventResult::Success {
+            user_id: Uuid::new_v4(),
+            plan: "basic".into(),
+        };
+        state
+            .idempotency
+            .mark_processed("evt_dup_ok_direct".into(), prior, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            process_event(&state, deleted("evt_dup_ok_direct"), "{}")
+                .await
+                .unwrap(),
+            "Already processed"
+        );
+        assert_eq!(
+            status_of(&state.subscriptions, "dupdirect@x.io").await,
+            SubscriptionStatus::Active
+        );
+
+        let prior = EventResult::Failed {
+            error: "customer lookup failed".into(),
+        };
+        state
+            .idempotency
+            .mark_processed("evt_dup_failed_direct".into(), prior, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            process_event(&state, deleted("evt_dup_failed_direct"), "{}")
+                .await
+                .unwrap(),
+            "Success"
+        );
+        assert_eq!(
+            status_of(&state.subscriptions, "dupdirect@x.io").await,
+            SubscriptionStatus::Canceled
+        );
+        let record = state
+            .idempotency
+            .get("evt_dup_failed_direct")
+            .await
+            .unwrap();
+        assert!(!record.result.is_failure());
+    }
+
+    #[tokio::test]
+    async fn schema_accepts_a_valid_event_and_rejects_missing_nested_fields() {
+        let valid = event_json(
+            "evt_schema_ok",
+            "checkout.session.completed",
+            serde_json::json!({ "id": "cs_1", "status": "complete", "amount_total": 4900 }),
+        );
+        let parsed: StripeEvent = serde_json::from_value(valid).unwrap();
+        assert_eq!(parsed.validate(), Ok(()));
+
+        let missing = stripe_event(
+            "evt_schema_missing",
+            "checkout.session.completed",
+            serde_json::json!({ "id": "cs_1" }),
+        );
+        assert_eq!(
+            missing.validate(),
+            Err("data.object.status is required".to_string())
+        );
+        let mistyped = stripe_event(
+            "evt_schema_type",
+            "checkout.session.completed",
+            serde_json::json!({ "id": "cs_1", "status": "complete", "amount_total": "4900" }),
+        );
+        assert_eq!(
+            mistyped.validate(),
+            Err("data.object.amount_total must be an integer".to_string())
+        );
+
+        let state = Arc::new(webhook_state());
+        let mut event = event_json(
+            "evt_schema_http",
+            "checkout.session.expired",
+            serde_json::json!({}),
+        );
+        event["data"]["object"]["id"] = "cs_1".into();
+        let response = deliver(&state, &event).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(state.idempotency.get("evt_schema_http").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn simulated_sequence_ends_in_its_last_state() {
+        let request = |email: &str| {
+            Json(
+                serde_json::from_value(serde_json::json!({
+                    "email": email,
+                    "sequence": ["activate", "past_due", "active", "canceled"],
+                    "delay_ms": 10,
+                }))
+                .unwrap(),
+            )
+        };
+        let state = Arc::new(webhook_state());
+        let admin = admin_headers();
+        let response =
+            simulate_lifecycle(State(state.clone()), admin.clone(), request("Demo@x.io")).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let mut status = None;
+        for _ in 0..100 {
+            status = state.subscriptions.get("demo@x.io").await.map(|s| s.status);
+            if status == Some(SubscriptionStatus::Canceled) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(status, Some(SubscriptionStatus::Canceled));
+
+        let mut live = webhook_state();
+        live.config.secret_key = "sk_live_x".to_string();
+        let response = simulate_lifecycle(State(Arc::new(live)), admin, request("live@x.io")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn only_allowlisted_event_types_are_routed() {
+        assert_eq!(parse_event_allowlist(Some(" , ")), None);
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("allow@x.io").await;
+        state.config.event_allowlist =
+            parse_event_allowlist(Some("customer.subscription.deleted, invoice.paid"));
+        let state = Arc::new(state);
+
+        let deleted = event_json(
+            "evt_allowed",
+            "customer.subscription.deleted",
+            serde_json::json!({ "id": "sub_1", "customer_email": "allow@x.io" }),
+        );
+        assert_eq!(deliver(&state, &deleted).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "allow@x.io").await,
+            SubscriptionStatus::Canceled
+        );
+
+        let completed = event_json(
+            "evt_not_allowed",
+            "checkout.session.completed",
+            serde_json::json!({ "id": "cs_1", "status": "complete", "customer_email": "allow@x.io" }),
+        );
+        let response = deliver(&state, &completed).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"Ignored");
+        assert_eq!(
+            status_of(&state.subscriptions, "allow@x.io").await,
+            SubscriptionStatus::Canceled
+        );
+        assert!(state.idempotency.get("evt_not_allowed").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn token_for_a_known_subscription_carries_its_plan() {
+        let mut state = webhook_state();
+        state.tokens = TokenIssuer::with_secret("jwt-secret");
+        state.subscriptions = subscribed("jwt@x.io").await;
+        let state = Arc::new(state);
+
+        let request = serde_json::from_value(serde_json::json!({ "email": "JWT@x.io" })).unwrap();
+        let response = issue_token(State(state.clone()), admin_headers(), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_json(response).await;
+        assert_eq!(body["token_type"], "Bearer");
+
+        let claims = state
+            .tokens
+            .verify(body["token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims["sub"], "jwt@x.io");
+        assert_eq!(claims["role"], body["claims"]["role"]);
+        assert_eq!(claims["exp"], body["expires_at"]);
+        assert_ne!(claims["role"], "free");
+    }
+
+    #[tokio::test]
+    async fn events_sharing_a_request_key_run_once_when_opted_in() {
+        let retried = |id: &str| {
+            let mut event = event_json(
+                id,
+                "customer.subscription.deleted",
+                serde_json::json!({ "id": "sub_1", "customer_email": "reqkey@x.io" }),
+            );
+            event["request"] = serde_json::json!({ "id": "req_1", "idempotency_key": "ik_1" });
+            event
+        };
+
+        for opted_in in [true, false] {
+            let mut state = webhook_state();
+            state.subscriptions = subscribed("reqkey@x.io").await;
+            state.config.dedupe_by_request_key = opted_in;
+            let state = Arc::new(state);
+
+            assert_eq!(
+                body_text(deliver(&state, &retried("evt_req_a")).await).await,
+                "Success"
+            );
+            state
+                .subscriptions
+                .update_status("reqkey@x.io", SubscriptionStatus::Active)
+                .await;
+            let second = body_text(deliver(&state, &retried("evt_req_b")).await).await;
+
+            let status = status_of(&state.subscriptions, "reqkey@x.io").await;
+            let record = state.idempotency.get("evt_req_b").await.unwrap();
+            if opted_in {
+                assert_eq!(second, "Already processed");
+                assert_eq!(status, SubscriptionStatus::Active);
+                assert!(matches!(record.result, EventResult::Duplicate));
+            } else {
+                assert_eq!(second, "Success");
+                assert_eq!(status, SubscriptionStatus::Canceled);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn process_event_runs_events_sharing_a_request_key_once_when_opted_in() {
+        let retried = |id: &str| {
+            let mut event = event_json(
+                id,
+                "customer.subscription.deleted",
+                serde_json::json!({ "id": "sub_1", "customer_email": "reqkey@x.io" }),
+            );
+            event["request"] = serde_json::json!({ "id": "req_1", "idempotency_key": "ik_1" });
+            serde_json::from_value::<StripeEvent>(event).unwrap()
+        };
+
+        for opted_in in [true, false] {
+            let mut state = webhook_state();
+            state.subscriptions = subscribed("reqkey@x.io").await;
+            state.config.dedupe_by_request_key = opted_in;
+
+            assert_eq!(
+                process_event(&state, retried("evt_req_a"), "{}")
+                    .await
+                    .unwrap(),
+                "Success"
+            );
+            state
+                .subscriptions
+                .update_status("reqkey@x.io", SubscriptionStatus::Active)
+                .await;
+            let second = process_event(&state, retried("evt_req_b"), "{}")
+                .await
+                .unwrap();
+
+            let status = status_of(&state.subscriptions, "reqkey@x.io").await;
+            let record = state.idempotency.get("evt_req_b").await.unwrap();
+            if opted_in {
+                assert_eq!(second, "Already processed");
+                assert_eq!(status, SubscriptionStatus::Active);
+                assert!(matches!(record.result, EventResult::Duplicate));
+            } else {
+                assert_eq!(second, "Success");
+                assert_eq!(status, SubscriptionStatus::Canceled);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn subscription_update_for_an_unknown_email_is_skipped() {
+        let state = Arc::new(webhook_state());
+        let orphan = event_json(
+            "evt_sub_orphan",
+            "customer.subscription.updated",
+            serde_json::json!({
+                "id": "sub_orphan",
+                "status": "active",
+                "metadata": { "email": "orphan@x.io" },
+            }),
+        );
+        let response = deliver(&state, &orphan).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.subscriptions.get("orphan@x.io").await.is_none());
+        let record = state.idempotency.get("evt_sub_orphan").await.unwrap();
+        assert!(!record.result.is_failure());
+    }
+
+    #[tokio::test]
+    async fn subscription_update_mirrors_a_past_due_status() {
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("pastdue@x.io").await;
+        let state = Arc::new(state);
+        let updated = event_json(
+            "evt_sub_past_due",
+            "customer.subscription.updated",
+            serde_json::json!({ "id": "sub_1", "customer": "cus_1", "status": "past_due" }),
+        );
+        assert_eq!(deliver(&state, &updated).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "pastdue@x.io").await,
+            SubscriptionStatus::PastDue
+        );
+    }
+
+    #[tokio::test]
+    async fn trialing_subscription_stores_and_surfaces_trial_end() {
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("trial@x.io").await;
+        let state = Arc::new(state);
+        let trialing = event_json(
+            "evt_trialing",
+            "customer.subscription.updated",
+            serde_json::json!({
+                "id": "sub_1",
+                "customer": "cus_1",
+                "status": "trialing",
+                "trial_end": 1_800_000_000,
+            }),
+        );
+        assert_eq!(deliver(&state, &trialing).await.status(), StatusCode::OK);
+
+        let stored = state.subscriptions.get("trial@x.io").await.unwrap();
+        assert_eq!(stored.status, SubscriptionStatus::Trialing);
+        assert_eq!(stored.trial_end, DateTime::from_timestamp(1_800_000_000, 0));
+
+        let body = serde_json::to_value(stored).unwrap();
+        assert_eq!(body["trial_end"], "2027-01-15T08:00:00Z");
+    }
+
+    /// GET /stripe/subscription?email=
+    async fn query_subscription(
+        state: &Arc<StripeWebhookState>,
+        headers: HeaderMap,
+        email: &str,
+    ) -> Response {
+        let query = SubscriptionQuery {
+            email: email.to_string(),
+        };
+        get_subscription(State(state.clone()), headers, Query(query)).await
+    }
+
+    #[tokio::test]
+    async fn get_subscription_returns_the_record_to_an_admin() {
+        let state = Arc::new(test_state());
+        state
+            .subscriptions
+            .activate_subscription("sub@x.io", Some("cus_sub".into()), None, "premium")
+            .await;
+
+        let response = query_subscription(&state, admin_headers(), "Sub@x.io").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_json(response).await;
+        assert_eq!(body["email"], "sub@x.io");
+        assert_eq!(body["stripe_customer_id"], "cus_sub");
+
+        let response = query_subscription(&state, admin_headers(), "nobody@x.io").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn get_subscription_requires_the_admin_token() {
+        let state = Arc::new(test_state());
+        state
+            .subscriptions
+            .activate_subscription("sub@x.io", None, None, "premium")
+            .await;
+
+        let response = query_subscription(&state, HeaderMap::new(), "sub@x.io").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "wrong".parse().unwrap());
+        let response = query_subscription(&state, headers, "sub@x.io").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn past_due_loses_access_only_once_the_grace_period_is_over() {
+        let subscriptions = subscribed("dunning@x.io").await;
+        subscriptions
+            .update_status("dunning@x.io", SubscriptionStatus::PastDue)
+            .await;
+        let grace = chrono::Duration::days(7);
+
+        let during = subscriptions.get("dunning@x.io").await.unwrap();
+        assert!(during.past_due_since.is_some());
+        assert_eq!(during.status, SubscriptionStatus::PastDue);
+        assert!(during.grants_access());
+        assert!(subscriptions.expire_past_due(grace).await.is_empty());
+
+        let mut lapsed = during;
+        lapsed.past_due_since = Some(Utc::now() - chrono::Duration::days(8));
+        subscriptions.upsert_subscription(lapsed).await;
+        assert_eq!(subscriptions.expire_past_due(grace).await, ["dunning@x.io"]);
+        let after = subscriptions.get("dunning@x.io").await.unwrap();
+        assert_eq!(after.status, SubscriptionStatus::Unpaid);
+        assert!(!after.grants_access());
+    }
+
+    #[tokio::test]
+    async fn delayed_payment_activates_on_success_and_notifies_on_failure() {
+        let hook = MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+        let mut state = webhook_state();
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let state = Arc::new(state);
+        let sepa_session = |id: &str, email: &str, payment_status: &str| {
+            serde_json::json!({
+                "id": id,
+                "status": "complete",
+                "payment_status": payment_status,
+                "customer": "cus_sepa",
+                "customer_details": { "email": email },
+                "amount_total": 4900,
+                "metadata": { "plan": "basic" },
+            })
+        };
+
+        let succeeded = event_json(
+            "evt_async_ok",
+            "checkout.session.async_payment_succeeded",
+            sepa_session("cs_sepa_ok", "sepa@x.com", "paid"),
+        );
+        assert_eq!(deliver(&state, &succeeded).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "sepa@x.com").await,
+            SubscriptionStatus::Active
+        );
+
+        let failed = event_json(
+            "evt_async_failed",
+            "checkout.session.async_payment_failed",
+            sepa_session("cs_sepa_failed", "bounced@x.com", "unpaid"),
+        );
+        assert_eq!(deliver(&state, &failed).await.status(), StatusCode::OK);
+        assert!(state.subscriptions.get("bounced@x.com").await.is_none());
+        let failure = hook
+            .requests()
+            .into_iter()
+            .map(|r| r.json())
+            .find(|n| n["kind"] == "checkout.payment_failed")
+            .unwrap();
+        assert_eq!(failure["email"], "bounced@x.com");
+        assert_eq!(failure["data"]["session_id"], "cs_sepa_failed");
+        assert!(failure["data"]["checkout_url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/stripe/checkout/basic"));
+    }
+
+    #[tokio::test]
+    async fn utm_tags_reach_the_session_metadata_and_the_subscription() {
+        let query: CheckoutQuery = serde_json::from_value(serde_json::json!({
+            "utm_source": " newsletter ",
+            "utm_medium": "",
+            "utm_campaign": "x".repeat(ATTRIBUTION_VALUE_MAX + 50),
+            "referrer": "https://blog.test/post",
+        }))
+        .unwrap();
+        let attribution = query.attribution();
+        assert_eq!(
+            attribution
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>(),
+            ["utm_source", "utm_campaign", "referrer"]
+        );
+        assert_eq!(attribution[0].1, "newsletter");
+        assert_eq!(attribution[1].1.len(), ATTRIBUTION_VALUE_MAX);
+
+        let api = MockServer::start(stripe_api).await;
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        let state = Arc::new(state);
+        let redirect =
+            create_checkout_redirect(&state, "https://app.test", "basic", None, attribution).await;
+        assert_eq!(
+            redirect.into_response().headers()["location"],
+            "https://checkout.test/cs_1"
+        );
+        let session = api.requests()[0].form();
+        assert_eq!(session["metadata[plan]"], "basic");
+        assert_eq!(session["metadata[utm_source]"], "newsletter");
+        assert_eq!(session["metadata[referrer]"], "https://blog.test/post");
+
+        let completed = event_json(
+            "evt_checkout_utm",
+            "checkout.session.completed",
+            serde_json::json!({
+                "id": "cs_utm",
+                "status": "complete",
+                "payment_status": "paid",
+                "customer_email": "utm@x.com",
+                "metadata": {
+                    "plan": "basic",
+                    "utm_source": "newsletter",
+                    "referrer": "https://blog.test/post",
+                },
+            }),
+        );
+        assert_eq!(deliver(&state, &completed).await.status(), StatusCode::OK);
+
+        let stored = serde_json::to_value(state.subscriptions.get("utm@x.com").await).unwrap();
+        assert_eq!(stored["attribution"]["utm_source"], "newsletter");
+        assert_eq!(stored["attribution"]["referrer"], "https://blog.test/post");
+        assert!(stored["attribution"].get("utm_medium").is_none());
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_malformed_not_skewed() {
+        let payload = br#"{"id":"evt_ts"}"#;
+        let signed_at = |t: &str| {
+            let mut mac = HmacSha256::new_from_slice(b"whsec_ts").unwrap();
+            mac.update(format!("{}.", t).as_bytes());
+            mac.update(payload);
+            format!("t={},v1={}", t, hex::encode(mac.finalize().into_bytes()))
+        };
+        let verify = |t: &str| verify_webhook_signature(payload, &signed_at(t), "whsec_ts", 300);
+
+        assert_eq!(verify("-1"), Err("Malformed timestamp".to_string()));
+        assert_eq!(
+            verify(&i64::MAX.to_string()),
+            Err("Malformed timestamp".to_string())
+        );
+        assert!(WebhookTimestamp::parse(&i64::MIN.to_string()).is_err());
+        assert_eq!(
+            WebhookTimestamp::parse("253402300799")
+                .unwrap()
+                .skew_from(i64::MIN),
+            None
+        );
+
+        assert_eq!(verify(&Utc::now().timestamp().to_string()), Ok(()));
+        assert_eq!(
+            verify("1700000000"),
+            Err("Webhook timestamp too old".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_land_and_do_not_wait_on_other_shards() {
+        let subscriptions = SubscriptionManager::new();
+        let writers: Vec<_> = (0..200)
+            .map(|i| {
+                let subscriptions = subscriptions.clone();
+                tokio::spawn(async move {
+                    let email = format!("load{}@x.com", i);
+                    subscriptions
+                        .activate_subscription(&email, None, None, "basic")
+                        .await;
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        assert_eq!(subscriptions.all().await.len(), 200);
+        let used = (0..subscriptions.shard_count()).filter(|&i| {
+            (0..200).any(|n| subscriptions.shard_index(&format!("load{}@x.com", n)) == i)
+        });
+        assert!(used.count() > 1);
+
+        // A write held on one shard leaves customers on the others untouched
+        let blocked = subscriptions.shard_index("load0@x.com");
+        let other = (1..200)
+            .map(|n| format!("load{}@x.com", n))
+            .find(|email| subscriptions.shard_index(email) != blocked)
+            .unwrap();
+        let _held = subscriptions.shards[blocked].write().await;
+        let write = subscriptions.update_status(&other, SubscriptionStatus::PastDue);
+        let done = tokio::time::timeout(std::time::Duration::from_millis(500), write).await;
+        assert_eq!(done, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn ndjson_export_parses_back_into_one_record_per_line() {
+        let state = Arc::new(test_state());
+        for (email, plan) in [("a@x.com", "basic"), ("b@x.com", "premium")] {
+            state
+                .subscriptions
+                .activate_subscription(email, Some(format!("cus_{}", &email[..1])), None, plan)
+                .await;
+        }
+
+        let response = export_subscriptions_ndjson(State(state.clone()), admin_headers()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.ends_with('\n'));
+        let mut records: Vec<UserSubscription> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        records.sort_by(|a, b| a.email.cmp(&b.email));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].email, "a@x.com");
+        assert_eq!(records[0].stripe_customer_id.as_deref(), Some("cus_a"));
+        assert_eq!(records[1].email, "b@x.com");
+
+        let response = export_subscriptions_ndjson(State(state), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn subscription_created_outside_checkout_is_activated_for_its_price() {
+        let api = MockServer::start(|request| match request.path.as_str() {
+            "/v1/customers/cus_dash" => MockResponse::json(
+                200,
+                serde_json::json!({ "id": "cus_dash", "email": "dash@x.com" }),
+            ),
+            _ => MockResponse::json(404, serde_json::json!({ "error": {} })),
+        })
+        .await;
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        state.catalog = Arc::new(
+            PricingCatalog::from_env()
+                .with_stripe_prices(&[("basic", "price_basic"), ("premium", "price_premium")]),
+        );
+        let state = Arc::new(state);
+
+        let created = event_json(
+            "evt_sub_created_dash",
+            "customer.subscription.created",
+            serde_json::json!({
+                "id": "sub_dash",
+                "object": "subscription",
+                "customer": "cus_dash",
+                "status": "active",
+                "current_period_end": 1_800_000_000,
+                "items": { "object": "list", "data": [{ "price": { "id": "price_premium" } }] },
+            }),
+        );
+        assert_eq!(deliver(&state, &created).await.status(), StatusCode::OK);
+
+        let stored = state.subscriptions.get("dash@x.com").await.unwrap();
+        assert_eq!(stored.plan, state.catalog.resolve("premium").unwrap());
+        assert_eq!(stored.status, SubscriptionStatus::Active);
+        assert_eq!(stored.stripe_customer_id.as_deref(), Some("cus_dash"));
+        assert_eq!(stored.stripe_subscription_id.as_deref(), Some("sub_dash"));
+        assert!(stored.current_period_end.is_some());
+        assert_eq!(api.requests()[0].path, "/v1/customers/cus_dash");
+    }
+
+    #[tokio::test]
+    async fn deeply_nested_webhook_body_is_a_400() {
+        let state = Arc::new(webhook_state());
+        let mut event = event_json("evt_deep", "invoice.paid", serde_json::json!({}));
+        let mut deep = serde_json::json!(1);
+        for _ in 0..(DEFAULT_MAX_JSON_DEPTH + 8) {
+            deep = serde_json::json!([deep]);
+        }
+        event["data"]["object"]["lines"] = deep;
+
+        let response = deliver(&state, &event).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(state.idempotency.get("evt_deep").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn checkout_links_redeem_until_expiry_and_refuse_tampering() {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+
+        let api = MockServer::start(stripe_api).await;
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        state.checkout_links = CheckoutLinkSigner::with_secret("link-secret");
+        state.catalog = Arc::new(
+            PricingCatalog::from_env().with_stripe_prices(&[("premium", "price_premium")]),
+        );
+        let state = Arc::new(state);
+        let follow = |token: String| {
+            follow_checkout_link(
+                State(state.clone()),
+                Extension(ClientIp("198.51.100.7".parse().unwrap())),
+                HeaderMap::new(),
+                Query(CheckoutLinkQuery { token }),
+            )
+        };
+
+        let request = CheckoutLinkRequest {
+            plan: "premium".to_string(),
+            expires_in_secs: Some(3600),
+            email: Some("Campaign@X.com".to_string()),
+            utm_source: Some("spring_mail".to_string()),
+            utm_medium: None,
+            utm_campaign: None,
+        };
+        let issued = body_json(
+            create_checkout_link(State(state.clone()), admin_headers(), Json(request)).await,
+        )
+        .await;
+        let url = issued["url"].as_str().unwrap();
+        let token = url.split_once("/stripe/checkout/go?token=").unwrap().1;
+
+        let response = follow(token.to_string()).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "https://checkout.test/cs_1");
+        let session = api.requests()[0].form();
+        assert_eq!(session["line_items[0][price]"], "price_premium");
+        assert_eq!(session["metadata[plan]"], "premium");
+        assert_eq!(session["metadata[utm_source]"], "spring_mail");
+
+        let expired = state
+            .checkout_links
+            .sign(&CheckoutLinkClaims {
+                plan: "premium".to_string(),
+                exp: Utc::now().timestamp() - 1,
+                email: None,
+                utm_source: None,
+                utm_medium: None,
+                utm_campaign: None,
+            })
+            .unwrap();
+        assert_eq!(follow(expired).await.status(), StatusCode::GONE);
+
+        // Swap in a payload for another plan, keeping the original signature
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(r#"{"plan":"basic","exp":9999999999}"#);
+        let tampered = format!("{}.{}", forged, signature);
+        assert_eq!(follow(tampered).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            follow("not-a-token".to_string()).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(api.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invoice_payment_succeeded_keeps_the_subscription_active_once() {
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("renew@x.com").await;
+        let state = Arc::new(state);
+        let invoice = serde_json::json!({
+            "id": "in_renewal",
+            "object": "invoice",
+            "customer": "cus_1",
+            "customer_email": "renew@x.com",
+            "amount_paid": 900,
+            "currency": "eur",
+            "billing_reason": "subscription_cycle",
+        });
+
+        let succeeded = event_json(
+            "evt_inv_succeeded",
+            "invoice.payment_succeeded",
+            invoice.clone(),
+        );
+        assert_eq!(deliver(&state, &succeeded).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "renew@x.com").await,
+            SubscriptionStatus::Active
+        );
+
+        // The twin invoice.paid for the same invoice is not counted again
+        let paid = event_json("evt_inv_paid", "invoice.paid", invoice);
+        assert_eq!(deliver(&state, &paid).await.status(), StatusCode::OK);
+        let handled = state
+            .idempotency
+            .get("invoice_paid:in_renewal")
+            .await
+            .unwrap();
+        assert_eq!(handled.event_id, "evt_inv_succeeded");
+        assert_eq!(
+            status_of(&state.subscriptions, "renew@x.com").await,
+            SubscriptionStatus::Active
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_invoice_twins_record_one_renewal() {
+        let sink = RecordingAuditLog::default();
+        let mut state = webhook_state();
+        state.audit = Arc::new(AuditTrail::with_sinks(vec![Box::new(sink.clone())], false));
+        state.subscriptions = subscribed("twins@x.com").await;
+        let invoice = serde_json::json!({
+            "id": "in_twins",
+            "object": "invoice",
+            "customer": "cus_1",
+            "customer_email": "twins@x.com",
+            "amount_paid": 900,
+            "currency": "eur",
+        });
+        let state = Arc::new(state);
+        let paid = event_json("evt_twin_paid", "invoice.paid", invoice.clone());
+        let succeeded = event_json("evt_twin_succeeded", "invoice.payment_succeeded", invoice);
+
+        let (first, second) = tokio::join!(deliver(&state, &paid), deliver(&state, &succeeded));
+        assert!(first.status() == StatusCode::OK || second.status() == StatusCode::OK);
+        let renewals = sink
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| {
+                e["event"] == "invoice.paid"
+                    && ["evt_twin_paid", "evt_twin_succeeded"]
+                        .contains(&e["stripe_event_id"].as_str().unwrap_or_default())
+            })
+            .count();
+        assert_eq!(renewals, 1);
+    }
+
+    #[tokio::test]
+    async fn process_event_records_one_renewal_for_concurrent_invoice_twins() {
+        let sink = RecordingAuditLog::default();
+        let mut state = webhook_state();
+        state.audit = Arc::new(AuditTrail::with_sinks(vec![Box::new(sink.clone())], false));
+        state.subscriptions = subscribed("twins@x.com").await;
+        let invoice = serde_json::json!({
+            "id": "in_twins",
+            "object": "invoice",
+            "customer": "cus_1",
+            "customer_email": "twins@x.com",
+            "amount_paid": 900,
+            "currency": "eur",
+        });
+        let paid = stripe_event("evt_twin_paid", "invoice.paid", invoice.clone());
+        let succeeded = stripe_event("evt_twin_succeeded", "invoice.payment_succeeded", invoice);
+
+        let (first, second) = tokio::join!(
+            process_event(&state, paid, "{}"),
+            process_event(&state, succeeded, "{}"),
+        );
+        assert!(first.is_ok() || second.is_ok());
+        let renewals = sink
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| {
+                e["event"] == "invoice.paid"
+                    && ["evt_twin_paid", "evt_twin_succeeded"]
+                        .contains(&e["stripe_event_id"].as_str().unwrap_or_default())
+            })
+            .count();
+        assert_eq!(renewals, 1);
+    }
+
+    #[tokio::test]
+    async fn refused_redis_write_is_reported_and_kept_in_memory() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(crate::test_support::serve_fake_redis(
+            listener,
+            b"-READONLY You can't write against a read only replica.\r\n",
+        ));
+        let store = IdempotencyStore::new(Some(url));
+
+        let refused = store
+            .mark_processed("evt_readonly".to_string(), EventResult::Processed, None)
+            .await;
+        assert!(
+            matches!(&refused, Err(StoreError::Redis(e)) if e.contains("read only")),
+            "{:?}",
+            refused
+        );
+        assert!(store.get("evt_readonly").await.is_none());
+
+        store
+            .mark_processed_with_fallback(
+                "evt_readonly".to_string(),
+                "evt_readonly".to_string(),
+                EventResult::Processed,
+                None,
+            )
+            .await;
+        let kept = store.get("evt_readonly").await.unwrap();
+        assert_eq!(kept.event_id, "evt_readonly");
+    }
+
+    #[tokio::test]
+    async fn verify_refuses_a_license_for_a_paid_session_without_an_email() {
+        let api = MockServer::start(|request| {
+            let session = |id: &str, email: Option<&str>| {
+                serde_json::json!({
+                    "id": id,
+                    "customer_email": email,
+                    "status": "complete",
+                    "payment_status": "paid",
+                    "metadata": { "plan": "premium" },
+                })
+            };
+            match request.path.as_str() {
+                "/v1/checkout/sessions/cs_mail" => {
+                    MockResponse::json(200, session("cs_mail", Some("buyer@x.io")))
+                }
+                "/v1/checkout/sessions/cs_nomail" => {
+                    MockResponse::json(200, session("cs_nomail", None))
+                }
+                _ => MockResponse::json(404, serde_json::json!({ "error": {} })),
+            }
+        })
+        .await;
+        let mut state = test_state();
+        state.config.api_base = api.url.clone();
+        let state = Arc::new(state);
+        let verify = |session_id: &str| {
+            let query = VerifyQuery {
+                session_id: session_id.to_string(),
+                license_key: None,
+            };
+            verify_session(State(state.clone()), Query(query))
+        };
+
+        let response = verify("cs_mail").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_json(response).await;
+        let key = body["license_key"].as_str().unwrap();
+        assert_eq!(state.licenses.get(key).await.unwrap().email, "buyer@x.io");
+
+        let response = verify("cs_nomail").await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = body_json(response).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["license_key"], serde_json::Value::Null);
+        let key = state
+            .license
+            .generate_license_key(LicenseProvider::Stripe, "cs_nomail")
+            .unwrap();
+        assert!(state.licenses.get(&key).await.is_none());
+    }
+
+    /// Stripe stand-in for `/v1/billing_portal/sessions`
+    fn portal_api(request: &MockRequest) -> MockResponse {
+        match request.path.as_str() {
+            "/v1/billing_portal/sessions" => MockResponse::json(
+                200,
+                serde_json::json!({ "id": "bps_1", "url": "https://billing.test/p/bps_1" }),
+            ),
+            _ => MockResponse::json(404, serde_json::json!({ "error": {} })),
+        }
+    }
+
+    async fn open_portal(state: &Arc<StripeWebhookState>) -> Response {
+        create_portal_session(
+            State(state.clone()),
+            admin_headers(),
+            Json(serde_json::json!({ "customer_id": "cus_portal" })),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn portal_configuration_is_forwarded_only_when_set() {
+        for configuration in [Some("bpc_1PortalCfg"), None] {
+            let api = MockServer::start(portal_api).await;
+            let mut state = webhook_state();
+            state.config.api_base = api.url.clone();
+            state.config.portal_configuration = configuration.map(str::to_string);
+            let state = Arc::new(state);
+
+            let response = open_portal(&state).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let form = api.requests()[0].form();
+            assert_eq!(form["customer"], "cus_portal");
+            assert_eq!(form.get("configuration").map(String::as_str), configuration);
+        }
+
+        // Ids that aren't bpc_ fall back to Stripe's default portal
+        let mut config = webhook_state().config;
+        config.portal_configuration = Some("pc_123".to_string());
+        assert_eq!(config.validated().portal_configuration, None);
+        assert!(is_valid_portal_configuration("bpc_1PortalCfg"));
+        assert!(!is_valid_portal_configuration("bpc_"));
+        assert!(!is_valid_portal_configuration("bpc_1&x=1"));
+    }
+
+    /// Portal endpoint answering with `statuses` in turn (the last one repeats)
+    async fn portal_api_answering(statuses: &'static [u16]) -> MockServer {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        MockServer::start(move |_| {
+            let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let status = statuses[call.min(statuses.len() - 1)];
+            match status {
+                200 => MockResponse::json(
+                    200,
+                    serde_json::json!({ "id": "bps_1", "url": "https://billing.test/p/bps_1" }),
+                ),
+                _ => MockResponse::json(status, serde_json::json!({ "error": {} })),
+            }
+        })
+        .await
+    }
+
+    fn portal_state(api: &MockServer) -> Arc<StripeWebhookState> {
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        state.http.retry = crate::retry::RetryPolicy {
+            max_attempts: 3,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(5),
+        };
+        Arc::new(state)
+    }
+
+    #[tokio::test]
+    async fn portal_retries_5xx_under_one_idempotency_key() {
+        let api = portal_api_answering(&[503, 200]).await;
+        let state = portal_state(&api);
+        assert_eq!(open_portal(&state).await.status(), StatusCode::OK);
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 2);
+        let key = &requests[0].headers["idempotency-key"];
+        assert!(key.starts_with("portal_"));
+        assert_eq!(&requests[1].headers["idempotency-key"], key);
+
+        // A client-supplied key is passed through as-is
+        let mut headers = admin_headers();
+        headers.insert("idempotency-key", "click-42".parse().unwrap());
+        let payload = Json(serde_json::json!({ "customer_id": "cus_portal" }));
+        create_portal_session(State(state), headers, payload).await;
+        assert_eq!(api.requests()[2].headers["idempotency-key"], "click-42");
+    }
+
+    #[tokio::test]
+    async fn portal_requires_the_admin_token() {
+        let api = MockServer::start(portal_api).await;
+        let state = portal_state(&api);
+        let payload = serde_json::json!({ "customer_id": "cus_portal" });
+
+        let response = create_portal_session(
+            State(state.clone()),
+            HeaderMap::new(),
+            Json(payload.clone()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "wrong".parse().unwrap());
+        let response = create_portal_session(State(state), headers, Json(payload)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(api.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn portal_does_not_retry_a_4xx() {
+        let api = portal_api_answering(&[400]).await;
+        let state = portal_state(&api);
+        assert_eq!(open_portal(&state).await.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(api.requests().len(), 1);
+
+        let api = portal_api_answering(&[500]).await;
+        let state = portal_state(&api);
+        assert_eq!(open_portal(&state).await.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(api.requests().len(), 3);
+    }
+
+    /// Stripe refunds stand-in for a $49.00 charge; more than that is refused
+    fn refunds_api(request: &MockRequest) -> MockResponse {
+        let form = request.form();
+        let amount = form.get("amount").map_or(4900, |a| a.parse().unwrap());
+        if amount > 4900 {
+            return MockResponse::json(
+                400,
+                serde_json::json!({ "error": {
+                    "code": "amount_too_large",
+                    "message": "Refund amount ($60.00) is greater than charge amount ($49.00)",
+                } }),
+            );
+        }
+        MockResponse::json(
+            200,
+            serde_json::json!({
+                "id": "re_1",
+                "status": "succeeded",
+                "amount": amount,
+                "currency": "usd",
+                "livemode": false,
+            }),
+        )
+    }
+
+    /// Refunds against `api`, audited into the returned sink
+    fn refund_state(api: &MockServer) -> (Arc<StripeWebhookState>, RecordingAuditLog) {
+        let sink = RecordingAuditLog::default();
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        state.audit = Arc::new(AuditTrail::with_sinks(vec![Box::new(sink.clone())], false));
+        (Arc::new(state), sink)
+    }
+
+    async fn refund(
+        state: &Arc<StripeWebhookState>,
+        headers: HeaderMap,
+        request: serde_json::Value,
+    ) -> Response {
+        create_refund(
+            State(state.clone()),
+            Extension(ClientIp("127.0.0.1".parse().unwrap())),
+            headers,
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn full_refund_sends_no_amount_and_is_audited() {
+        let api = MockServer::start(refunds_api).await;
+        let (state, sink) = refund_state(&api);
+        let request =
+            serde_json::json!({ "payment_intent": "pi_1", "reason": "requested_by_customer" });
+
+        let response = refund(&state, admin_headers(), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_json(response).await;
+        assert_eq!(body["id"], "re_1");
+        assert_eq!(body["status"], "succeeded");
+        assert_eq!(body["amount"], 4900);
+        assert_eq!(body["currency"], "USD");
+
+        let requests = api.requests();
+        assert_eq!(requests[0].path, "/v1/refunds");
+        let form = requests[0].form();
+        assert_eq!(form["payment_intent"], "pi_1");
+        assert_eq!(form["reason"], "requested_by_customer");
+        assert!(!form.contains_key("amount"));
+        assert!(requests[0].headers["idempotency-key"].starts_with("refund_"));
+
+        let entries = sink.entries.lock().unwrap().clone();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["event"], "refund.created");
+        assert_eq!(entries[0]["refund_id"], "re_1");
+        assert_eq!(entries[0]["payment_intent"], "pi_1");
+        assert_eq!(entries[0]["amount_cents"], 4900);
+    }
+
+    #[tokio::test]
+    async fn partial_refund_sends_the_amount() {
+        let api = MockServer::start(refunds_api).await;
+        let (state, sink) = refund_state(&api);
+        let request = serde_json::json!({ "payment_intent": "pi_1", "amount": 1500 });
+
+        let response = refund(&state, admin_headers(), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["amount"], 1500);
+        assert_eq!(api.requests()[0].form()["amount"], "1500");
+        assert_eq!(sink.entries.lock().unwrap()[0]["amount_cents"], 1500);
+    }
+
+    #[tokio::test]
+    async fn over_refund_returns_stripes_message_unaudited() {
+        let api = MockServer::start(refunds_api).await;
+        let (state, sink) = refund_state(&api);
+        let request = serde_json::json!({ "payment_intent": "pi_1", "amount": 6000 });
+
+        let response = refund(&state, admin_headers(), request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_text(response).await,
+            "Refund amount ($60.00) is greater than charge amount ($49.00)"
+        );
+        // A 4xx is Stripe's answer, not worth a retry
+        assert_eq!(api.requests().len(), 1);
+        assert!(sink.entries.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refund_requires_the_admin_token() {
+        let api = MockServer::start(refunds_api).await;
+        let (state, sink) = refund_state(&api);
+        let request = serde_json::json!({ "payment_intent": "pi_1" });
+
+        let response = refund(&state, HeaderMap::new(), request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(api.requests().is_empty());
+        assert!(sink.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn audit_entries_are_tagged_live_or_test() {
+        for livemode in [true, false] {
+            let mut event = event_json(
+                "evt_mode",
+                "invoice.paid",
+                serde_json::json!({ "id": "in_1" }),
+            );
+            event["livemode"] = livemode.into();
+            let event: StripeEvent = serde_json::from_value(event).unwrap();
+
+            let entry = payment_event_entry(&event, "mode@x.io", "invoice.paid", Some(900));
+            assert_eq!(entry["livemode"], livemode);
+            assert_eq!(entry["mode"], if livemode { "live" } else { "test" });
+        }
+    }
+
+    #[tokio::test]
+    async fn non_ascii_body_verifies_over_its_raw_bytes() {
+        let state = Arc::new(webhook_state());
+        let event = event_json(
+            "evt_unicode",
+            "customer.updated",
+            serde_json::json!({ "id": "cus_1", "name": "Zoë Ørsted — 東京 €9" }),
+        );
+        assert_eq!(deliver(&state, &event).await.status(), StatusCode::OK);
+
+        // Invalid UTF-8 must still be signed and checked byte for byte
+        let mut body = br#"{"id":"evt_bytes","note":""#.to_vec();
+        body.extend_from_slice(&[0xff, 0xfe, b'"', b'}']);
+        let now = Utc::now().timestamp();
+        let header = sign_stripe_payload(&body, TEST_WEBHOOK_SECRET, now);
+        assert_eq!(
+            verify_webhook_signature(&body, &header, TEST_WEBHOOK_SECRET, 300),
+            Ok(())
+        );
+        let lossy = String::from_utf8_lossy(&body).into_owned();
+        assert_eq!(
+            verify_webhook_signature(lossy.as_bytes(), &header, TEST_WEBHOOK_SECRET, 300),
+            Err("Invalid webhook signature".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn ten_second_tolerance_rejects_a_minute_old_signature() {
+        let body = br#"{"id":"evt_tolerance"}"#;
+        let minute_ago =
+            sign_stripe_payload(body, TEST_WEBHOOK_SECRET, Utc::now().timestamp() - 60);
+        assert_eq!(
+            verify_webhook_signature(body, &minute_ago, TEST_WEBHOOK_SECRET, 10),
+            Err("Webhook timestamp too old".to_string())
+        );
+        assert_eq!(
+            verify_webhook_signature(body, &minute_ago, TEST_WEBHOOK_SECRET, 300),
+            Ok(())
+        );
+
+        // The handler passes the configured window through
+        let mut state = webhook_state();
+        state.config.webhook_tolerance_secs = 10;
+        let state = Arc::new(state);
+        let event = event_json(
+            "evt_tolerance",
+            "customer.updated",
+            serde_json::json!({ "id": "cus_1" }),
+        );
+        let response = deliver_signed_at(&state, &event, Utc::now().timestamp() - 60).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.idempotency.get("evt_tolerance").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn customer_email_change_moves_the_record_and_its_keys() {
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("old@x.com").await;
+        state
+            .subscriptions
+            .update_status("old@x.com", SubscriptionStatus::PastDue)
+            .await;
+        let before = state.subscriptions.get("old@x.com").await.unwrap();
+        state
+            .licenses
+            .register(
+                "VRT-KEY",
+                LicenseProvider::Stripe,
+                "cs_1",
+                "old@x.com",
+                "basic",
+            )
+            .await;
+        let state = Arc::new(state);
+
+        let mut updated = event_json(
+            "evt_email_change",
+            "customer.updated",
+            serde_json::json!({ "id": "cus_1", "object": "customer", "email": "New@X.com" }),
+        );
+        updated["data"]["previous_attributes"] = serde_json::json!({ "email": "old@x.com" });
+        assert_eq!(deliver(&state, &updated).await.status(), StatusCode::OK);
+
+        assert!(state.subscriptions.get("old@x.com").await.is_none());
+        let moved = state.subscriptions.get("new@x.com").await.unwrap();
+        assert_eq!(moved.user_id, before.user_id);
+        assert_eq!(moved.status, SubscriptionStatus::PastDue);
+        assert_eq!(moved.stripe_customer_id.as_deref(), Some("cus_1"));
+        assert_eq!(
+            state
+                .subscriptions
+                .email_for_customer("cus_1")
+                .await
+                .as_deref(),
+            Some("new@x.com")
+        );
+        assert_eq!(
+            state.licenses.get("VRT-KEY").await.unwrap().email,
+            "new@x.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn without_redis_subscriptions_live_in_memory() {
+        let subscriptions = SubscriptionManager::new();
+        assert_eq!(subscriptions.load_persisted().await, 0);
+
+        let activated = subscriptions
+            .activate_subscription("Mem@X.com", Some("cus_mem".into()), None, "premium")
+            .await;
+        assert_eq!(activated.email, "mem@x.com");
+        assert_eq!(
+            subscriptions.get("mem@x.com").await.unwrap().user_id,
+            activated.user_id
+        );
+
+        assert!(
+            subscriptions
+                .update_status("mem@x.com", SubscriptionStatus::PastDue)
+                .await
+        );
+        assert_eq!(
+            status_of(&subscriptions, "MEM@x.com").await,
+            SubscriptionStatus::PastDue
+        );
+        assert!(subscriptions.cancel_subscription("mem@x.com").await);
+        assert_eq!(
+            status_of(&subscriptions, "mem@x.com").await,
+            SubscriptionStatus::Canceled
+        );
+        assert!(
+            !subscriptions
+                .update_status("nobody@x.com", SubscriptionStatus::Active)
+                .await
+        );
+        assert!(subscriptions.get("nobody@x.com").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn get_prefers_the_record_another_instance_wrote_to_redis() {
+        let local = subscribed("shared@x.com").await;
+        let mut elsewhere = local.get("shared@x.com").await.unwrap();
+        elsewhere.set_status(SubscriptionStatus::Canceled);
+        let json = serde_json::to_string(&elsewhere).unwrap();
+        let reply: &'static [u8] = Box::leak(
+            format!("${}\r\n{}\r\n", json.len(), json)
+                .into_bytes()
+                .into(),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(crate::test_support::serve_fake_redis(listener, reply));
+        let subscriptions = SubscriptionManager::new().with_redis(Some(url));
+        // A stale local copy, as cached before the other instance canceled
+        assert!(
+            subscriptions
+                .insert_if_absent(local.get("shared@x.com").await.unwrap())
+                .await
+        );
+
+        assert_eq!(
+            status_of(&subscriptions, "shared@x.com").await,
+            SubscriptionStatus::Canceled
+        );
+    }
+
+    #[tokio::test]
+    async fn get_falls_back_to_memory_when_redis_has_no_record() {
+        // Answers every command with a nil bulk string, as for a missing key
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(crate::test_support::serve_fake_redis(listener, b"$-1\r\n"));
+        let subscriptions = SubscriptionManager::new().with_redis(Some(url));
+        let local = subscribed("unsynced@x.com").await;
+        assert!(
+            subscriptions
+                .insert_if_absent(local.get("unsynced@x.com").await.unwrap())
+                .await
+        );
+
+        assert_eq!(
+            status_of(&subscriptions, "unsynced@x.com").await,
+            SubscriptionStatus::Active
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_keeps_the_in_memory_copy() {
+        // Nothing can listen on port 0, so every Redis call is refused
+        let subscriptions =
+            SubscriptionManager::new().with_redis(Some("redis://127.0.0.1:0".to_string()));
+        assert_eq!(subscriptions.load_persisted().await, 0);
+
+        // The failed write is reported (for the retry queue) but the record stands
+        let activation = PendingActivation::new("down@x.com", None, None, "basic");
+        assert!(subscriptions.try_activate(&activation).await.is_err());
+        assert!(
+            subscriptions
+                .update_status("down@x.com", SubscriptionStatus::PastDue)
+                .await
+        );
+        assert_eq!(
+            status_of(&subscriptions, "down@x.com").await,
+            SubscriptionStatus::PastDue
+        );
+    }
+
+    #[tokio::test]
+    async fn setup_intent_saves_the_payment_method_on_the_subscription() {
+        let mut state = webhook_state();
+        state.subscriptions = subscribed("saver@x.com").await;
+        let state = Arc::new(state);
+
+        let succeeded = event_json(
+            "evt_setup_succeeded",
+            "setup_intent.succeeded",
+            serde_json::json!({
+                "id": "seti_1",
+                "object": "setup_intent",
+                "customer": "cus_1",
+                "payment_method": "pm_card_visa",
+                "status": "succeeded",
+                "usage": "off_session",
+            }),
+        );
+        assert_eq!(deliver(&state, &succeeded).await.status(), StatusCode::OK);
+        let stored = state.subscriptions.get("saver@x.com").await.unwrap();
+        assert_eq!(stored.saved_payment_method.as_deref(), Some("pm_card_visa"));
+        assert_eq!(stored.status, SubscriptionStatus::Active);
+
+        // A customer we don't know is acknowledged without creating anything
+        let stranger = event_json(
+            "evt_setup_stranger",
+            "setup_intent.succeeded",
+            serde_json::json!({
+                "id": "seti_2",
+                "customer": "cus_unknown",
+                "payment_method": "pm_card_mastercard",
+                "status": "succeeded",
+            }),
+        );
+        assert_eq!(deliver(&state, &stranger).await.status(), StatusCode::OK);
+        assert_eq!(state.subscriptions.all().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn events_for_another_account_or_mode_are_a_400() {
+        let mut state = webhook_state();
+        state.config.account_id = Some("acct_1Ours".to_string());
+        let state = Arc::new(state);
+        let event = |id: &str, account: Option<&str>, livemode: bool| {
+            let mut event =
+                event_json(id, "customer.updated", serde_json::json!({ "id": "cus_1" }));
+            event["livemode"] = livemode.into();
+            if let Some(account) = account {
+                event["account"] = account.into();
+            }
+            event
+        };
+
+        let ours = event("evt_acct_ours", Some("acct_1Ours"), false);
+        assert_eq!(deliver(&state, &ours).await.status(), StatusCode::OK);
+        let platform = event("evt_acct_platform", None, false);
+        assert_eq!(deliver(&state, &platform).await.status(), StatusCode::OK);
+
+        let other = event("evt_acct_other", Some("acct_1Other"), false);
+        assert_eq!(
+            deliver(&state, &other).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let live = event("evt_acct_live", Some("acct_1Ours"), true);
+        assert_eq!(
+            deliver(&state, &live).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert!(state.idempotency.get("evt_acct_other").await.is_none());
+
+        // Without STRIPE_ACCOUNT_ID the guard is off
+        let unguarded = Arc::new(webhook_state());
+        let other = event("evt_acct_unguarded", Some("acct_1Other"), false);
+        assert_eq!(deliver(&unguarded, &other).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn deferred_activation_is_audited_as_queued() {
+        let sink = RecordingAuditLog::default();
+        let mut state = webhook_state();
+        state.audit = Arc::new(AuditTrail::with_sinks(vec![Box::new(sink.clone())], true));
+        // Nothing can listen on port 0, so the activation can't be persisted
+        state.subscriptions =
+            SubscriptionManager::new().with_redis(Some("redis://127.0.0.1:0".to_string()));
+        let state = Arc::new(state);
+
+        let completed = event_json(
+            "evt_activation_queued",
+            "checkout.session.completed",
+            serde_json::json!({
+                "id": "cs_queued",
+                "status": "complete",
+                "payment_status": "paid",
+                "customer_email": "queued@x.com",
+                "metadata": { "plan": "basic" },
+            }),
+        );
+        assert_eq!(deliver(&state, &completed).await.status(), StatusCode::OK);
+        assert_eq!(state.activations.snapshot().await.len(), 1);
+
+        let entries = sink.entries.lock().unwrap().clone();
+        let events: Vec<_> = entries.iter().map(|e| e["event"].clone()).collect();
+        assert!(events.contains(&"subscription.activation_queued".into()));
+        assert!(!events.contains(&"subscription.activated".into()));
+    }
+
+    #[tokio::test]
+    async fn both_providers_record_activations_in_one_shape() {
+        use crate::paypal_handler::{paypal_webhook_handler, PayPalState};
+
+        let sink = RecordingAuditLog::default();
+        let mut state = webhook_state();
+        state.audit = Arc::new(AuditTrail::with_sinks(vec![Box::new(sink.clone())], true));
+        let mut paypal = PayPalState::new(
+            state.subscriptions.clone(),
+            state.domains.clone(),
+            state.license.clone(),
+            state.licenses.clone(),
+            state.audit.clone(),
+            state.catalog.clone(),
+        );
+        paypal.config.dev_skip_signature = true;
+        paypal
+            .config
+            .plan_map
+            .insert("P-BASIC".to_string(), "pro_monthly".to_string());
+        let state = Arc::new(state);
+
+        let completed = event_json(
+            "evt_activation_shape",
+            "checkout.session.completed",
+            serde_json::json!({
+                "id": "cs_shape",
+                "status": "complete",
+                "payment_status": "paid",
+                "customer": "cus_shape",
+                "subscription": "sub_shape",
+                "customer_email": "stripe@x.com",
+                "amount_total": 4900,
+                "currency": "usd",
+                "metadata": { "plan": "basic" },
+            }),
+        );
+        assert_eq!(deliver(&state, &completed).await.status(), StatusCode::OK);
+
+        let activated = serde_json::json!({
+            "id": "WH-ACTIVATION-SHAPE",
+            "event_type": "BILLING.SUBSCRIPTION.ACTIVATED",
+            "create_time": "2024-01-01T00:00:00Z",
+            "resource_type": "subscription",
+            "resource": {
+                "id": "I-SHAPE",
+                "plan_id": "P-BASIC",
+                "subscriber": { "email_address": "paypal@x.com" },
+                "billing_info": {
+                    "last_payment": { "amount": { "currency_code": "USD", "value": "49.00" } },
+                },
+            },
+        });
+        let response = paypal_webhook_handler(
+            State(Arc::new(paypal)),
+            Extension(ClientIp("127.0.0.1".parse().unwrap())),
+            HeaderMap::new(),
+            Bytes::from(activated.to_string()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let entries = sink.entries.lock().unwrap().clone();
+        let activations: Vec<_> = entries
+            .iter()
+            .filter(|e| {
+                e["event"] == "subscription.activated"
+                    && (e["stripe_event_id"] == "evt_activation_shape"
+                        || e["paypal_event_id"] == "WH-ACTIVATION-SHAPE")
+            })
+            .collect();
+        assert_eq!(activations.len(), 2, "{:?}", entries);
+        // Everything but the provider-named event id field is common
+        let shape = |entry: &serde_json::Value| {
+            let event_id = format!("{}_event_id", entry["provider"].as_str().unwrap());
+            let mut keys: Vec<_> = entry
+                .as_object()
+                .unwrap()
+                .keys()
+                .filter(|k| **k != event_id)
+                .cloned()
+                .collect();
+            keys.sort();
+            keys
+        };
+        let (stripe, paypal) = (activations[0], activations[1]);
+        assert_eq!(
+            (&stripe["provider"], &paypal["provider"]),
+            (&"stripe".into(), &"paypal".into())
+        );
+        assert_eq!(shape(stripe), shape(paypal));
+        assert_eq!(stripe["plan"], paypal["plan"]);
+        assert_eq!(
+            stripe["amount"],
+            serde_json::json!({ "minor": 4900, "currency": "USD" })
+        );
+        assert_eq!(stripe["amount"], paypal["amount"]);
+        assert_eq!(stripe["external_ids"]["subscription"], "sub_shape");
+        assert_eq!(paypal["external_ids"]["subscription"], "I-SHAPE");
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_and_business_errors_acked() {
+        let hook = MockServer::start(|_| MockResponse::json(503, serde_json::json!({}))).await;
+        let mut state = webhook_state();
+        state.notifications = NotificationHook::with_url(&hook.url);
+        let state = Arc::new(state);
+
+        // Hook down: 500 so Stripe redelivers, and unmarked so that is not a duplicate
+        let bounced = event_json(
+            "evt_branch_transient",
+            "checkout.session.async_payment_failed",
+            serde_json::json!({
+                "id": "cs_bounced",
+                "status": "complete",
+                "customer_email": "bounced@x.com",
+            }),
+        );
+        let response = deliver(&state, &bounced).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(state
+            .idempotency
+            .get("evt_branch_transient")
+            .await
+            .is_none());
+        assert_eq!(
+            deliver(&state, &bounced).await.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(hook.requests().len(), 2);
+
+        // No subscriber to attach the subscription to: retrying can't help
+        let orphan = event_json(
+            "evt_branch_permanent",
+            "customer.subscription.created",
+            serde_json::json!({ "id": "sub_orphan", "status": "active" }),
+        );
+        let response = deliver(&state, &orphan).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"Processed with error");
+        assert!(state
+            .idempotency
+            .get("evt_branch_permanent")
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn processed_marker_names_the_activated_user_and_plan() {
+        let state = Arc::new(webhook_state());
+        let completed = event_json(
+            "evt_marker_plan",
+            "checkout.session.completed",
+            serde_json::json!({
+                "id": "cs_marker",
+                "status": "complete",
+                "payment_status": "paid",
+                "customer_email": "marker@x.com",
+                "metadata": { "plan": "premium" },
+            }),
+        );
+        assert_eq!(deliver(&state, &completed).await.status(), StatusCode::OK);
+
+        let subscription = state.subscriptions.get("marker@x.com").await.unwrap();
+        let marker = state.idempotency.get("evt_marker_plan").await.unwrap();
+        match marker.result {
+            EventResult::Success { user_id, plan } => {
+                assert_eq!(user_id, subscription.user_id);
+                // Stored under the plan's canonical key, not the checkout alias
+                assert_eq!(plan, "enterprise_monthly");
+                assert_eq!(plan, subscription.plan.key());
+            }
+            other => panic!("expected Success, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn cleared_marker_lets_its_event_run_again() {
+        let state = Arc::new(webhook_state());
+        state
+            .subscriptions
+            .activate_subscription("clear@x.com", None, None, "basic")
+            .await;
+        let canceled = event_json(
+            "evt_clear_one",
+            "customer.subscription.deleted",
+            serde_json::json!({ "id": "sub_clear", "customer_email": "clear@x.com" }),
+        );
+        let other = event_json("evt_clear_kept", "customer.created", serde_json::json!({}));
+        assert_eq!(deliver(&state, &canceled).await.status(), StatusCode::OK);
+        assert_eq!(deliver(&state, &other).await.status(), StatusCode::OK);
+
+        // Reactivated since; while marked, a redelivery is a duplicate
+        state
+            .subscriptions
+            .activate_subscription("clear@x.com", None, None, "basic")
+            .await;
+        assert_eq!(deliver(&state, &canceled).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "clear@x.com").await,
+            SubscriptionStatus::Active
+        );
+
+        let clear = |headers: HeaderMap, event_id: &str| {
+            clear_idempotency(
+                State(state.clone()),
+                Extension(ClientIp("127.0.0.1".parse().unwrap())),
+                headers,
+                Query(ClearIdempotencyQuery {
+                    event_id: Some(event_id.to_string()),
+                }),
+            )
+        };
+        let denied = clear(HeaderMap::new(), "evt_clear_one").await;
+        assert!(denied.status().is_client_error());
+        let cleared = body_json(clear(admin_headers(), "evt_clear_one").await).await;
+        assert_eq!(cleared["cleared"], 1);
+        assert!(state.idempotency.get("evt_clear_one").await.is_none());
+        assert!(state.idempotency.get("evt_clear_kept").await.is_some());
+
+        assert_eq!(deliver(&state, &canceled).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "clear@x.com").await,
+            SubscriptionStatus::Canceled
+        );
+        assert!(state.idempotency.get("evt_clear_one").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn canceled_plans_drop_to_free_per_downgrade_policy() {
+        assert_eq!(
+            CancelDowngradePolicy::parse(" Immediate "),
+            Some(CancelDowngradePolicy::Immediate)
+        );
+        assert_eq!(CancelDowngradePolicy::parse("never"), None);
+
+        let plan_of = |subscriptions: SubscriptionManager| async move {
+            subscriptions.get("late@x.com").await.unwrap().plan.key()
+        };
+
+        let mut immediate = subscribed("late@x.com").await;
+        immediate.cancel_downgrade = CancelDowngradePolicy::Immediate;
+        assert!(immediate.cancel_subscription("late@x.com").await);
+        assert_eq!(plan_of(immediate.clone()).await, "free");
+        assert!(immediate.downgrade_canceled().await.is_empty());
+
+        let mut keep = subscribed("late@x.com").await;
+        keep.cancel_downgrade = CancelDowngradePolicy::KeepUntilPeriodEnd;
+        let set_period_end = |end: DateTime<Utc>| {
+            let keep = keep.clone();
+            async move {
+                let mut store = keep.shard("late@x.com").write().await;
+                store.get_mut("late@x.com").unwrap().current_period_end = Some(end);
+            }
+        };
+        set_period_end(Utc::now() + chrono::Duration::days(3)).await;
+        assert!(keep.cancel_subscription("late@x.com").await);
+        assert_eq!(
+            status_of(&keep, "late@x.com").await,
+            SubscriptionStatus::Canceled
+        );
+        // Paid through the period already billed
+        assert_eq!(plan_of(keep.clone()).await, "pro_monthly");
+        assert!(keep.downgrade_canceled().await.is_empty());
+
+        set_period_end(Utc::now() - chrono::Duration::seconds(1)).await;
+        assert_eq!(keep.downgrade_canceled().await, ["late@x.com"]);
+        assert_eq!(plan_of(keep).await, "free");
+    }
+
+    #[test]
+    fn signed_payload_round_trips_through_verification() {
+        let payload = br#"{"id":"evt_round_trip","type":"invoice.paid"}"#;
+        let now = Utc::now().timestamp();
+        let header = sign_stripe_payload(payload, "whsec_round_trip", now);
+        assert!(header.starts_with(&format!("t={},v1=", now)));
+        assert_eq!(
+            verify_webhook_signature(payload, &header, "whsec_round_trip", 300),
+            Ok(())
+        );
+
+        let other_secret = verify_webhook_signature(payload, &header, "whsec_other", 300);
+        assert_eq!(other_secret, Err("Invalid webhook signature".to_string()));
+        let edited = br#"{"id":"evt_round_trip","type":"invoice.void"}"#;
+        assert!(verify_webhook_signature(edited, &header, "whsec_round_trip", 300).is_err());
+        let stale = sign_stripe_payload(payload, "whsec_round_trip", now - 600);
+        assert_eq!(
+            verify_webhook_signature(payload, &stale, "whsec_round_trip", 300),
+            Err("Webhook timestamp too old".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_and_pending_refunds_keep_access_until_a_full_refund_succeeds() {
+        let api = MockServer::start(|request: &MockRequest| match request.path.as_str() {
+            "/v1/charges/ch_full" => MockResponse::json(
+                200,
+                serde_json::json!({
+                    "id": "ch_full",
+                    "customer": "cus_refund",
+                    "amount_refunded": 4900,
+                    "refunded": true,
+                }),
+            ),
+            "/v1/customers/cus_refund" => MockResponse::json(
+                200,
+                serde_json::json!({ "id": "cus_refund", "email": "refund@x.com" }),
+            ),
+            _ => MockResponse::json(404, serde_json::json!({ "error": {} })),
+        })
+        .await;
+        let mut state = webhook_state();
+        state.config.api_base = api.url.clone();
+        state
+            .subscriptions
+            .activate_subscription("refund@x.com", Some("cus_refund".into()), None, "basic")
+            .await;
+        let state = Arc::new(state);
+
+        let partial = event_json(
+            "evt_refund_partial",
+            "charge.refunded",
+            serde_json::json!({
+                "id": "ch_partial",
+                "billing_details": { "email": "refund@x.com" },
+                "amount_refunded": 500,
+                "refunded": false,
+            }),
+        );
+        let pending = event_json(
+            "evt_refund_pending",
+            "charge.refund.updated",
+            serde_json::json!({ "id": "re_pending", "status": "pending", "charge": "ch_full" }),
+        );
+        for event in [&partial, &pending] {
+            assert_eq!(deliver(&state, event).await.status(), StatusCode::OK);
+            assert_eq!(
+                status_of(&state.subscriptions, "refund@x.com").await,
+                SubscriptionStatus::Active
+            );
+        }
+        // A pending refund is not looked up
+        assert!(api.requests().is_empty());
+
+        let succeeded = event_json(
+            "evt_refund_succeeded",
+            "charge.refund.updated",
+            serde_json::json!({ "id": "re_full", "status": "succeeded", "charge": "ch_full" }),
+        );
+        assert_eq!(deliver(&state, &succeeded).await.status(), StatusCode::OK);
+        assert_eq!(
+            status_of(&state.subscriptions, "refund@x.com").await,
+            SubscriptionStatus::Canceled
+        );
+        let paths: Vec<_> = api.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/v1/charges/ch_full", "/v1/customers/cus_refund"]);
+    }
 }
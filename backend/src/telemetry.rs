@@ -0,0 +1,209 @@
+// lwas_economy/src/payments/telemetry.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: BETA
+// Log subscriber plus optional OpenTelemetry traces (OTLP/HTTP, W3C traceparent)
+
+use std::sync::OnceLock;
+
+use axum::http::{HeaderMap, Request};
+use opentelemetry::global;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use reqwest::RequestBuilder;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::config::env_flag;
+
+/// Collector used when `OTEL_EXPORTER_OTLP_ENDPOINT` is unset (OTLP/HTTP port)
+const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318";
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// O(1) - True once `init` installed the OTLP exporter
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// O(1) - Install the log subscriber; with `OTEL_ENABLED`, also export spans over
+/// OTLP/HTTP to `OTEL_EXPORTER_OTLP_ENDPOINT` (`/v1/traces` is appended) under
+/// `OTEL_SERVICE_NAME` (default `qantum_payment_backend`)
+pub fn init() {
+    // Same default level `tracing_subscriber::fmt::init` used
+    let registry = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer());
+    if !env_flag("OTEL_ENABLED") {
+        registry.init();
+        ENABLED.get_or_init(|| false);
+        return;
+    }
+
+    let service_name = std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|n| !n.trim().is_empty())
+        .unwrap_or_else(|| "qantum_payment_backend".to_string());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(DEFAULT_OTLP_ENDPOINT),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                service_name.clone(),
+            )])),
+        )
+        .install_batch(runtime::Tokio);
+
+    match tracer {
+        Ok(tracer) => {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            registry
+                .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                .init();
+            ENABLED.get_or_init(|| true);
+            println!(
+                "[TELEMETRY] 🛰️ Exporting traces as {} (OTLP/HTTP)",
+                service_name
+            );
+        }
+        Err(e) => {
+            registry.init();
+            ENABLED.get_or_init(|| false);
+            println!(
+                "[TELEMETRY] ❌ OTLP exporter unavailable, tracing disabled: {}",
+                e
+            );
+        }
+    }
+}
+
+/// O(n) - Flush spans still buffered in the batch exporter (on shutdown)
+pub fn shutdown() {
+    if enabled() {
+        global::shutdown_tracer_provider();
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // An empty `tracestate` is allowed to be left out
+        if value.is_empty() {
+            return;
+        }
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// O(1) - Request span for `TraceLayer`, continuing the caller's trace when a
+/// valid `traceparent` header came in
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+    );
+    if enabled() {
+        span.set_parent(parent_context(request.headers()));
+    }
+    span
+}
+
+/// O(1) - Remote parent named by the `traceparent` header; an empty context
+/// (a new trace) when it is missing or malformed
+fn parent_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// O(1) - Add `traceparent` for `span` to an outbound call, so the provider
+/// request shows up under the webhook / checkout that made it
+pub fn inject_context(span: &Span, request: RequestBuilder) -> RequestBuilder {
+    if !enabled() {
+        return request;
+    }
+    let mut headers = reqwest::header::HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut HeaderInjector(&mut headers))
+    });
+    request.headers(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn request_with(traceparent: Option<&str>) -> Request<()> {
+        let mut request = Request::builder().uri("/stripe/webhook");
+        if let Some(value) = traceparent {
+            request = request.header("traceparent", value);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn request_span_continues_an_incoming_traceparent() {
+        // What `init` installs under OTEL_ENABLED, minus the exporter
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = trace::TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = request_with(Some(TRACEPARENT));
+            let span = make_request_span(&request);
+            assert_eq!(span.metadata().unwrap().name(), "request");
+            assert!(span.id().is_some());
+
+            let parent = parent_context(request.headers());
+            let remote = parent.span().span_context().clone();
+            assert!(remote.is_valid() && remote.is_remote() && remote.is_sampled());
+            assert_eq!(
+                remote.trace_id().to_string(),
+                "4bf92f3577b34da6a3ce929d0e0e4736"
+            );
+            assert_eq!(remote.span_id().to_string(), "00f067aa0ba902b7");
+
+            span.set_parent(parent);
+            assert_eq!(
+                span.context().span().span_context().trace_id(),
+                remote.trace_id()
+            );
+        });
+
+        for header in [None, Some("00-not-a-trace-01")] {
+            let parent = parent_context(request_with(header).headers());
+            assert!(!parent.span().span_context().is_valid(), "{:?}", header);
+        }
+    }
+}
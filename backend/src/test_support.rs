@@ -0,0 +1,232 @@
+// lwas_economy/src/payments/test_support.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: TEST
+// Local HTTP upstream standing in for Stripe, PayPal and notification hooks
+
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::audit::{AuditEntry, AuditLog};
+
+/// One request as the mock received it; header names are lowercase
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl MockRequest {
+    /// O(1) - Body parsed as JSON (`Value::Null` when it isn't)
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or_default()
+    }
+
+    /// O(n) - Body decoded as `application/x-www-form-urlencoded`
+    pub fn form(&self) -> HashMap<String, String> {
+        self.body
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+            .collect()
+    }
+}
+
+pub struct MockResponse {
+    status: u16,
+    body: String,
+    delay: Duration,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            body: body.to_string(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Answer only after `delay` (a slow or hanging upstream)
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+type Responder = Arc<dyn Fn(&MockRequest) -> MockResponse + Send + Sync>;
+
+/// Answers every request with whatever `respond` returns and remembers them
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    pub async fn start(
+        respond: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond: Responder = Arc::new(respond);
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, seen.clone(), respond.clone()));
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// O(n) - Every request so far, oldest first
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// One request per connection; the response closes it
+async fn serve(mut socket: TcpStream, seen: Arc<Mutex<Vec<MockRequest>>>, respond: Responder) {
+    let Some(request) = read_request(&mut socket).await else {
+        return;
+    };
+    let response = respond(&request);
+    seen.lock().unwrap().push(request);
+    tokio::time::sleep(response.delay).await;
+
+    let raw = format!(
+        "HTTP/1.1 {} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    );
+    let _ = socket.write_all(raw.as_bytes()).await;
+}
+
+/// O(n) - Head up to the blank line, then `Content-Length` bytes of body
+async fn read_request(socket: &mut TcpStream) -> Option<MockRequest> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        let n = socket.read(&mut buf).await.ok().filter(|n| *n > 0)?;
+        received.extend_from_slice(&buf[..n]);
+        if let Some(at) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+            break at + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&received[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut start = lines.next()?.split(' ');
+    let (method, path) = (start.next()?.to_string(), start.next()?.to_string());
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    while received.len() < head_end + length {
+        let n = socket.read(&mut buf).await.ok().filter(|n| *n > 0)?;
+        received.extend_from_slice(&buf[..n]);
+    }
+    Some(MockRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&received[head_end..head_end + length]).to_string(),
+    })
+}
+
+/// Audit sink that keeps every appended entry's fields
+#[derive(Clone, Default)]
+pub struct RecordingAuditLog {
+    pub entries: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl AuditLog for RecordingAuditLog {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn append<'a>(&'a self, entry: &'a AuditEntry) -> BoxFuture<'a, Result<(), String>> {
+        self.entries.lock().unwrap().push(entry.fields.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Answers every Redis command with the same raw RESP `reply` (`+OK` for a
+/// healthy `SET`, an `-ERR` line for a server that refuses writes)
+pub async fn serve_fake_redis(listener: TcpListener, reply: &'static [u8]) {
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            return;
+        };
+        tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => n,
+                };
+                received.extend_from_slice(&buf[..n]);
+                while let Some(len) = complete_command(&received) {
+                    received.drain(..len);
+                    if socket.write_all(reply).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// O(n) - Length of the first complete RESP command in `buf`, if any
+fn complete_command(buf: &[u8]) -> Option<usize> {
+    fn line(buf: &[u8], from: usize) -> Option<(usize, usize)> {
+        let end = buf[from..].windows(2).position(|w| w == b"\r\n")? + from;
+        let value = std::str::from_utf8(&buf[from + 1..end])
+            .ok()?
+            .parse()
+            .ok()?;
+        Some((value, end + 2))
+    }
+    let (args, mut at) = line(buf, 0)?;
+    for _ in 0..args {
+        let (len, start) = line(buf, at)?;
+        at = start + len + 2;
+        if at > buf.len() {
+            return None;
+        }
+    }
+    Some(at)
+}
+
+/// O(n) - `+` is a space, `%XX` a byte
+fn percent_decode(raw: &str) -> String {
+    let raw = raw.replace('+', " ");
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut rest = raw.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
@@ -0,0 +1,173 @@
+// lwas_economy/src/payments/token.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: BETA
+// Short-lived entitlement JWTs (HS256) for services gating features on claims
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+
+use crate::config::env_parse;
+
+const ISSUER: &str = "veritas-payments";
+
+/// `PLAN_ENTITLEMENTS` when unset
+const DEFAULT_ENTITLEMENTS: &str =
+    "free=;pro=verify,export;enterprise=verify,export,api,priority_support";
+
+#[derive(Debug, Serialize)]
+pub struct EntitlementClaims {
+    pub iss: &'static str,
+    /// Subscriber email
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// Plan tier: `free`, `pro`, `enterprise`
+    pub role: String,
+    pub entitlements: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct TokenIssuer {
+    /// `None` disables issuance
+    secret: Option<String>,
+    ttl_secs: i64,
+    /// role -> entitlements
+    entitlements: HashMap<String, Vec<String>>,
+}
+
+impl TokenIssuer {
+    /// `JWT_SIGNING_SECRET`, `JWT_TTL_SECS` (default 900), `PLAN_ENTITLEMENTS`
+    pub fn from_env() -> Self {
+        let secret = std::env::var("JWT_SIGNING_SECRET")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        if secret.is_none() {
+            println!("[TOKEN] ℹ️ JWT_SIGNING_SECRET not set, entitlement tokens disabled");
+        }
+        Self {
+            secret,
+            ttl_secs: env_parse("JWT_TTL_SECS", 900i64).max(1),
+            entitlements: parse_entitlements(
+                &std::env::var("PLAN_ENTITLEMENTS")
+                    .unwrap_or_else(|_| DEFAULT_ENTITLEMENTS.to_string()),
+            ),
+        }
+    }
+
+    /// Signing with `secret` and the default entitlements instead of the environment
+    #[cfg(test)]
+    pub fn with_secret(secret: &str) -> Self {
+        Self {
+            secret: Some(secret.to_string()),
+            ttl_secs: 900,
+            entitlements: parse_entitlements(DEFAULT_ENTITLEMENTS),
+        }
+    }
+
+    /// O(n) - Claims of a token this issuer signed; None for anything else
+    #[cfg(test)]
+    pub fn verify(&self, token: &str) -> Option<serde_json::Value> {
+        let secret = self.secret.as_ref()?;
+        let (signing_input, signature) = token.rsplit_once('.')?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+        mac.update(signing_input.as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?)
+            .ok()?;
+        let (header, payload) = signing_input.split_once('.')?;
+        let header: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+        (header["alg"] == "HS256").then_some(())?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// O(1) - Entitlements configured for a role (none when unmapped)
+    pub fn entitlements_for(&self, role: &str) -> Vec<String> {
+        self.entitlements.get(role).cloned().unwrap_or_default()
+    }
+
+    /// O(n) - Signed `header.claims.signature` for `email` holding `role`
+    pub fn issue(&self, email: &str, role: &str) -> Result<(String, EntitlementClaims), String> {
+        let secret = self.secret.as_ref().ok_or("Token issuance disabled")?;
+        let now = Utc::now().timestamp();
+        let claims = EntitlementClaims {
+            iss: ISSUER,
+            sub: email.to_string(),
+            iat: now,
+            exp: now + self.ttl_secs,
+            role: role.to_string(),
+            entitlements: self.entitlements_for(role),
+        };
+
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload =
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).map_err(|e| e.to_string())?);
+        let signing_input = format!("{}.{}", header, payload);
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .map_err(|_| "Invalid signing secret")?;
+        mac.update(signing_input.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        Ok((format!("{}.{}", signing_input, signature), claims))
+    }
+}
+
+/// O(n) - `role=ent1,ent2;role2=...`
+fn parse_entitlements(raw: &str) -> HashMap<String, Vec<String>> {
+    raw.split(';')
+        .filter_map(|pair| {
+            let (role, list) = pair.split_once('=')?;
+            let list = list
+                .split(',')
+                .map(|e| e.trim().to_string())
+                .filter(|e| !e.is_empty())
+                .collect();
+            Some((role.trim().to_lowercase(), list))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_signed_and_carries_the_role_claims() {
+        let issuer = TokenIssuer::with_secret("jwt-secret");
+        let (token, claims) = issuer.issue("ada@x.io", "pro").unwrap();
+
+        let decoded = issuer.verify(&token).unwrap();
+        assert_eq!(decoded["iss"], ISSUER);
+        assert_eq!(decoded["sub"], "ada@x.io");
+        assert_eq!(decoded["role"], "pro");
+        assert_eq!(
+            decoded["entitlements"],
+            serde_json::json!(["verify", "export"])
+        );
+        assert_eq!(decoded["exp"].as_i64().unwrap() - claims.iat, 900);
+        assert!(TokenIssuer::with_secret("other-secret")
+            .verify(&token)
+            .is_none());
+
+        let tampered = token.replacen(".", ".e30", 1);
+        assert!(issuer.verify(&tampered).is_none());
+    }
+
+    #[test]
+    fn disabled_issuer_refuses() {
+        let issuer = TokenIssuer {
+            secret: None,
+            ttl_secs: 900,
+            entitlements: HashMap::new(),
+        };
+        assert!(issuer.issue("ada@x.io", "pro").is_err());
+        assert!(issuer.entitlements_for("pro").is_empty());
+    }
+}
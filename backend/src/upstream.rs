@@ -0,0 +1,819 @@
+// lwas_economy/src/payments/upstream.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: PRODUCTION_READY
+// Shared outbound HTTP client per provider with a circuit breaker and concurrency cap
+
+use reqwest::{Client, Proxy, RequestBuilder, Response, ResponseBuilderExt, StatusCode};
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+use crate::config::{env_flag, env_parse};
+use crate::retry::RetryPolicy;
+use crate::telemetry;
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// CIRCUIT BREAKER
+// ═══════════════════════════════════════════════════════════════════════════════
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerPhase {
+    /// Requests flow normally
+    Closed,
+    /// Fast-failing until the cooldown has elapsed
+    Open { since: Instant },
+    /// Cooldown over; a single probe request is let through
+    HalfOpen { probe_in_flight: bool },
+}
+
+impl BreakerPhase {
+    /// Gauge value exported as `upstream_circuit_state`
+    fn gauge_value(&self) -> f64 {
+        match self {
+            BreakerPhase::Closed => 0.0,
+            BreakerPhase::Open { .. } => 1.0,
+            BreakerPhase::HalfOpen { .. } => 2.0,
+        }
+    }
+}
+
+struct BreakerState {
+    consecutive_failures: u32,
+    phase: BreakerPhase,
+}
+
+/// Opens after `failure_threshold` consecutive failures, fast-fails for
+/// `cooldown`, then half-opens to probe the upstream with one request.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    provider: &'static str,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(provider: &'static str, failure_threshold: u32, cooldown: Duration) -> Self {
+        let breaker = Self {
+            provider,
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Arc::new(Mutex::new(BreakerState {
+                consecutive_failures: 0,
+                phase: BreakerPhase::Closed,
+            })),
+        };
+        breaker.publish(BreakerPhase::Closed);
+        breaker
+    }
+
+    /// O(1) - Ask permission to call the upstream; `Ok(true)` means the
+    /// caller was handed the half-open probe
+    pub fn allow(&self) -> Result<bool, String> {
+        let mut state = self.state.lock().unwrap();
+        match state.phase {
+            BreakerPhase::Closed => Ok(false),
+            BreakerPhase::Open { since } if since.elapsed() >= self.cooldown => {
+                state.phase = BreakerPhase::HalfOpen {
+                    probe_in_flight: true,
+                };
+                self.publish(state.phase);
+                println!("[UPSTREAM] 🟡 {} circuit half-open, probing", self.provider);
+                Ok(true)
+            }
+            BreakerPhase::HalfOpen {
+                probe_in_flight: false,
+            } => {
+                state.phase = BreakerPhase::HalfOpen {
+                    probe_in_flight: true,
+                };
+                Ok(true)
+            }
+            _ => Err(format!("{} circuit open, failing fast", self.provider)),
+        }
+    }
+
+    /// O(1) - The probe ended without an outcome (its future was dropped); let
+    /// the next caller probe instead of staying half-open forever
+    fn release_probe(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.phase
+            == (BreakerPhase::HalfOpen {
+                probe_in_flight: true,
+            })
+        {
+            state.phase = BreakerPhase::HalfOpen {
+                probe_in_flight: false,
+            };
+        }
+    }
+
+    /// O(1) - A call succeeded; close the circuit
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.phase != BreakerPhase::Closed {
+            println!("[UPSTREAM] 🟢 {} circuit closed", self.provider);
+        }
+        state.consecutive_failures = 0;
+        state.phase = BreakerPhase::Closed;
+        self.publish(state.phase);
+    }
+
+    /// O(1) - A call failed; open once the threshold is hit (or the probe failed)
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+
+        let probe_failed = matches!(state.phase, BreakerPhase::HalfOpen { .. });
+        if probe_failed || state.consecutive_failures >= self.failure_threshold {
+            if !matches!(state.phase, BreakerPhase::Open { .. }) {
+                println!(
+                    "[UPSTREAM] 🔴 {} circuit open after {} consecutive failure(s)",
+                    self.provider, state.consecutive_failures
+                );
+            }
+            state.phase = BreakerPhase::Open {
+                since: Instant::now(),
+            };
+            self.publish(state.phase);
+        }
+    }
+
+    fn publish(&self, phase: BreakerPhase) {
+        metrics::gauge!("upstream_circuit_state", "provider" => self.provider)
+            .set(phase.gauge_value());
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// OUTBOUND PROXY
+// ═══════════════════════════════════════════════════════════════════════════════
+
+static OUTBOUND: OnceLock<Client> = OnceLock::new();
+
+/// O(1) - Process-wide reqwest client (one connection pool, one proxy setup)
+pub fn outbound_client() -> Client {
+    OUTBOUND.get_or_init(build_outbound_client).clone()
+}
+
+/// `OUTBOUND_PROXY_URL` (+ `OUTBOUND_PROXY_USERNAME` / `OUTBOUND_PROXY_PASSWORD`)
+/// routes every call through one proxy. Without it reqwest honors
+/// `HTTPS_PROXY` / `HTTP_PROXY` / `NO_PROXY` itself. An invalid explicit proxy
+/// aborts startup rather than silently going direct.
+fn build_outbound_client() -> Client {
+    for var in ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"] {
+        if let Ok(url) = std::env::var(var) {
+            if Proxy::all(&url).is_err() {
+                println!(
+                    "[UPSTREAM] ⚠️ {} is not a valid proxy URL and will be ignored",
+                    var
+                );
+            }
+        }
+    }
+
+    let Some(url) = std::env::var("OUTBOUND_PROXY_URL")
+        .ok()
+        .filter(|u| !u.trim().is_empty())
+    else {
+        return Client::new();
+    };
+    let auth = std::env::var("OUTBOUND_PROXY_USERNAME")
+        .ok()
+        .map(|username| {
+            let password = std::env::var("OUTBOUND_PROXY_PASSWORD").unwrap_or_default();
+            (username, password)
+        });
+    proxied_client(&url, auth)
+}
+
+/// O(1) - Client sending every request through `url`, optionally with basic auth
+fn proxied_client(url: &str, auth: Option<(String, String)>) -> Client {
+    let parsed = reqwest::Url::parse(url.trim())
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some())
+        .unwrap_or_else(|| panic!("OUTBOUND_PROXY_URL must be an http(s) URL with a host"));
+    let mut proxy = Proxy::all(parsed.as_str()).expect("OUTBOUND_PROXY_URL rejected by reqwest");
+    if let Some((username, password)) = auth {
+        proxy = proxy.basic_auth(&username, &password);
+    }
+
+    println!(
+        "[UPSTREAM] 🌐 Routing outbound calls via {}://{}:{}",
+        parsed.scheme(),
+        parsed.host_str().unwrap_or_default(),
+        parsed.port_or_known_default().unwrap_or_default()
+    );
+    Client::builder()
+        .proxy(proxy)
+        .build()
+        .expect("failed to build proxied HTTP client")
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// UPSTREAM CLIENT
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Keeps `upstream_in_flight` accurate even if the calling future is dropped
+struct InFlightGuard {
+    provider: &'static str,
+}
+
+impl InFlightGuard {
+    fn enter(provider: &'static str) -> Self {
+        metrics::gauge!("upstream_in_flight", "provider" => provider).increment(1.0);
+        Self { provider }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        metrics::gauge!("upstream_in_flight", "provider" => self.provider).decrement(1.0);
+    }
+}
+
+/// Hands the half-open probe back if the call is cancelled before it records
+/// a success or failure
+struct ProbeGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.release_probe();
+        }
+    }
+}
+
+/// Outbound deadline when neither `{PROVIDER}_HTTP_TIMEOUT_SECS` nor `HTTP_TIMEOUT_SECS` is set
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// One per provider so a Stripe outage never trips PayPal (and vice versa)
+#[derive(Clone)]
+pub struct UpstreamClient {
+    provider: &'static str,
+    client: Client,
+    pub breaker: CircuitBreaker,
+    /// Caps concurrent calls; excess callers wait for a permit
+    permits: Arc<Semaphore>,
+    /// Backoff for idempotent calls (`UPSTREAM_RETRY_ATTEMPTS`, `_BASE_MS`, `_MAX_MS`)
+    pub retry: RetryPolicy,
+    /// Per-request deadline, connect through body
+    timeout: Duration,
+    /// Log redacted request params and response bodies (`DEBUG_UPSTREAM_BODIES`, sandbox only)
+    debug_bodies: bool,
+}
+
+impl UpstreamClient {
+    /// `CIRCUIT_BREAKER_THRESHOLD` (default 5) / `CIRCUIT_BREAKER_COOLDOWN_SECS` (default 30),
+    /// `UPSTREAM_MAX_IN_FLIGHT` (default 32), `{PROVIDER}_HTTP_TIMEOUT_SECS` falling
+    /// back to `HTTP_TIMEOUT_SECS` (default 30), `DEBUG_UPSTREAM_BODIES` (refused when `live`)
+    pub fn from_env(provider: &'static str, live: bool) -> Self {
+        let shared_timeout = env_parse("HTTP_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS);
+        let timeout_secs = env_parse(
+            &format!("{}_HTTP_TIMEOUT_SECS", provider.to_ascii_uppercase()),
+            shared_timeout,
+        )
+        .max(1);
+        let debug_bodies = if !env_flag("DEBUG_UPSTREAM_BODIES") {
+            false
+        } else if live {
+            println!(
+                "[CONFIG] ❌ DEBUG_UPSTREAM_BODIES refused for live {} keys; bodies stay unlogged",
+                provider
+            );
+            false
+        } else {
+            println!(
+                "[CONFIG] 🚧 DEBUG_UPSTREAM_BODIES: logging redacted {} request/response bodies",
+                provider
+            );
+            true
+        };
+        metrics::gauge!("upstream_in_flight", "provider" => provider).set(0.0);
+        Self {
+            provider,
+            client: outbound_client(),
+            breaker: CircuitBreaker::new(
+                provider,
+                env_parse("CIRCUIT_BREAKER_THRESHOLD", 5),
+                Duration::from_secs(env_parse("CIRCUIT_BREAKER_COOLDOWN_SECS", 30)),
+            ),
+            permits: Arc::new(Semaphore::new(
+                env_parse("UPSTREAM_MAX_IN_FLIGHT", 32usize).max(1),
+            )),
+            retry: RetryPolicy::from_env("UPSTREAM_RETRY"),
+            timeout: Duration::from_secs(timeout_secs),
+            debug_bodies,
+        }
+    }
+
+    /// Underlying client for building requests
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// O(1) - Send through the breaker. Transport errors and 5xx count as
+    /// failures; 4xx are the caller's problem and leave the breaker alone.
+    /// Waits for a concurrency permit first, so a queued call sees the breaker's
+    /// state at the time it would actually go out. The call's wall time is
+    /// recorded in `upstream_request_duration_seconds{provider, operation}`.
+    /// Requests past the provider's timeout fail like transport errors.
+    pub async fn send(
+        &self,
+        operation: &'static str,
+        request: RequestBuilder,
+    ) -> Result<Response, UpstreamError> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| format!("{} upstream limiter closed", self.provider))?;
+        let _probe = ProbeGuard {
+            breaker: &self.breaker,
+            probe: self.breaker.allow()?,
+        };
+        let _in_flight = InFlightGuard::enter(self.provider);
+
+        let span = tracing::info_span!("upstream", provider = self.provider, operation);
+        let request = telemetry::inject_context(&span, request.timeout(self.timeout));
+        let started = Instant::now();
+        let result = if self.debug_bodies {
+            self.send_logged(operation, request).instrument(span).await
+        } else {
+            request.send().instrument(span).await
+        };
+        metrics::histogram!(
+            "upstream_request_duration_seconds",
+            "provider" => self.provider,
+            "operation" => operation
+        )
+        .record(started.elapsed().as_secs_f64());
+
+        match result {
+            Ok(res) if res.status().is_server_error() => {
+                self.breaker.record_failure();
+                Ok(res)
+            }
+            Ok(res) => {
+                self.breaker.record_success();
+                Ok(res)
+            }
+            Err(e) => {
+                self.breaker.record_failure();
+                Err(UpstreamError::Transport(e.to_string()))
+            }
+        }
+    }
+}
+
+impl UpstreamClient {
+    /// O(n) - `send` with the redacted request and response logged. The body is
+    /// read here for logging, so the caller gets a rebuilt response.
+    async fn send_logged(
+        &self,
+        operation: &'static str,
+        request: RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let mut url = request.url().clone();
+        if let Some(query) = url.query().map(redact_params) {
+            url.set_query(Some(&query));
+        }
+        let body = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| redact_body(&String::from_utf8_lossy(b)))
+            .unwrap_or_default();
+        println!(
+            "[UPSTREAM:DEBUG] ➡️ {} {} {} {} {}",
+            self.provider,
+            operation,
+            request.method(),
+            url,
+            body
+        );
+
+        let response = client.execute(request).await?;
+        let response_url = response.url().clone();
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let bytes = response.bytes().await?;
+        println!(
+            "[UPSTREAM:DEBUG] ⬅️ {} {} {} {}",
+            self.provider,
+            operation,
+            status,
+            redact_body(&String::from_utf8_lossy(&bytes))
+        );
+
+        // Rebuilt with the URL, or callers would see reqwest's placeholder
+        let mut rebuilt = http::Response::builder()
+            .url(response_url)
+            .body(bytes)
+            .expect("a builder without parts always builds");
+        *rebuilt.status_mut() = status;
+        *rebuilt.version_mut() = version;
+        *rebuilt.headers_mut() = headers;
+        Ok(Response::from(rebuilt))
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// REDACTION
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Field names whose values never reach the logs (matched case-insensitively, as substrings)
+const SENSITIVE_KEYS: &[&str] = &[
+    "secret",
+    "token",
+    "password",
+    "authorization",
+    "api_key",
+    "cvc",
+    "card[number]",
+];
+
+/// Values that are credentials whatever field they sit in
+const SECRET_PREFIXES: &[&str] = &[
+    "sk_live_", "sk_test_", "rk_live_", "rk_test_", "whsec_", "Bearer ", "Basic ",
+];
+
+const REDACTED: &str = "[REDACTED]";
+
+fn is_sensitive_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_KEYS.iter().any(|s| key.contains(s))
+}
+
+fn is_secret_value(value: &str) -> bool {
+    SECRET_PREFIXES.iter().any(|p| value.starts_with(p))
+}
+
+/// O(n) - JSON, form-encoded or free text with credentials replaced by `[REDACTED]`
+pub fn redact_body(body: &str) -> String {
+    if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) {
+        redact_json(&mut json);
+        return json.to_string();
+    }
+    if body.contains('=') && !body.contains(char::is_whitespace) {
+        return redact_params(body);
+    }
+    // Free text: also the word after an auth scheme (`Bearer <token>`)
+    let mut after_scheme = false;
+    body.split(' ')
+        .map(|word| {
+            let redact = after_scheme || is_secret_value(word);
+            after_scheme = matches!(word, "Bearer" | "Basic");
+            if redact {
+                REDACTED
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// O(n) - `a=1&client_secret=...` with sensitive values replaced
+fn redact_params(params: &str) -> String {
+    params
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if is_sensitive_key(key) || is_secret_value(value) => {
+                format!("{}={}", key, REDACTED)
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn redact_json(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if is_sensitive_key(key) {
+                    *field = REDACTED.into();
+                } else {
+                    redact_json(field);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_json),
+        serde_json::Value::String(s) if is_secret_value(s) => *s = REDACTED.to_string(),
+        _ => {}
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════════
+// UPSTREAM ERRORS
+// ═══════════════════════════════════════════════════════════════════════════════
+
+/// Why a call to a provider failed, which decides whether it is worth retrying
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpstreamError {
+    /// Transport failure or timeout; the request may never have arrived
+    Transport(String),
+    /// The provider answered with a non-success status
+    Status {
+        /// Display name, e.g. `Stripe`
+        provider: &'static str,
+        status: StatusCode,
+        body: String,
+    },
+    /// Anything else: open circuit, unreadable answer, missing configuration
+    Other(String),
+}
+
+impl UpstreamError {
+    /// O(1) - Non-success answer from `provider`
+    pub fn status(provider: &'static str, status: StatusCode, body: impl fmt::Display) -> Self {
+        Self::Status {
+            provider,
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    /// O(1) - Worth retrying: transport failures, 429 and 5xx. An open circuit
+    /// and other 4xx answers are not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Transport(_) => true,
+            Self::Status { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Self::Other(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "Request failed: {}", e),
+            Self::Status {
+                provider,
+                status,
+                body,
+            } => write!(f, "{} returned {}: {}", provider, status, body),
+            Self::Other(e) => f.write_str(e),
+        }
+    }
+}
+
+impl PartialEq<&str> for UpstreamError {
+    fn eq(&self, other: &&str) -> bool {
+        match self {
+            Self::Other(e) => e == other,
+            Self::Transport(e) => other.strip_prefix("Request failed: ") == Some(e.as_str()),
+            Self::Status { .. } => {
+                let shown = self.to_string();
+                shown == *other
+            }
+        }
+    }
+}
+
+impl From<String> for UpstreamError {
+    fn from(e: String) -> Self {
+        Self::Other(e)
+    }
+}
+
+impl From<&str> for UpstreamError {
+    fn from(e: &str) -> Self {
+        Self::Other(e.to_string())
+    }
+}
+
+impl From<UpstreamError> for String {
+    fn from(e: UpstreamError) -> Self {
+        e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockResponse, MockServer};
+
+    fn upstream(provider: &'static str, threshold: u32, cooldown: Duration) -> UpstreamClient {
+        UpstreamClient {
+            provider,
+            client: Client::new(),
+            breaker: CircuitBreaker::new(provider, threshold, cooldown),
+            permits: Arc::new(Semaphore::new(32)),
+            retry: RetryPolicy::from_env("UPSTREAM_RETRY"),
+            timeout: Duration::from_secs(5),
+            debug_bodies: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn breaker_opens_on_5xx_and_fails_fast_until_cooldown() {
+        let api = MockServer::start(|_| MockResponse::json(503, serde_json::json!({}))).await;
+        let stripe = upstream("stripe", 3, Duration::from_millis(200));
+        let paypal = upstream("paypal", 3, Duration::from_millis(200));
+
+        for _ in 0..3 {
+            let res = stripe.send("probe", stripe.client().get(&api.url)).await;
+            assert_eq!(res.unwrap().status(), 503);
+        }
+        let fast_fail = stripe.send("probe", stripe.client().get(&api.url)).await;
+        assert_eq!(fast_fail.unwrap_err(), "stripe circuit open, failing fast");
+        assert_eq!(api.requests().len(), 3);
+        assert!(paypal.breaker.allow().is_ok());
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(stripe.breaker.allow().is_ok());
+        assert!(stripe.breaker.allow().is_err());
+        stripe.breaker.record_success();
+        assert!(stripe.breaker.allow().is_ok());
+    }
+
+    #[tokio::test]
+    async fn logged_bodies_keep_the_response_status_headers_and_url() {
+        let api =
+            MockServer::start(|_| MockResponse::json(402, serde_json::json!({ "ok": 1 }))).await;
+        let mut stripe = upstream("stripe", 5, Duration::from_secs(30));
+        stripe.debug_bodies = true;
+
+        let url = format!("{}/v1/charges?limit=1", api.url);
+        let res = stripe
+            .send("probe", stripe.client().get(&url))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 402);
+        assert_eq!(res.url().as_str(), url);
+        assert!(res.headers().contains_key("content-type"));
+        assert_eq!(res.text().await.unwrap(), r#"{"ok":1}"#);
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_circuit() {
+        let breaker = CircuitBreaker::new("paypal", 1, Duration::ZERO);
+        breaker.record_failure();
+        assert!(breaker.allow().is_ok());
+        breaker.record_failure();
+        assert!(matches!(
+            breaker.state.lock().unwrap().phase,
+            BreakerPhase::Open { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancelled_probe_hands_the_probe_to_the_next_caller() {
+        let api = MockServer::start(|_| {
+            MockResponse::json(200, serde_json::json!({})).after(Duration::from_millis(500))
+        })
+        .await;
+        let stripe = upstream("stripe", 1, Duration::ZERO);
+        stripe.breaker.record_failure();
+
+        let probe = stripe.send("probe", stripe.client().get(&api.url));
+        assert!(tokio::time::timeout(Duration::from_millis(50), probe)
+            .await
+            .is_err());
+
+        assert!(stripe.breaker.allow().is_ok());
+        assert!(stripe.breaker.allow().is_err());
+    }
+
+    // Paused clock: the mock's 300ms only elapses once every task is waiting on it
+    #[tokio::test(start_paused = true)]
+    async fn excess_calls_queue_for_a_permit() {
+        let api = MockServer::start(|_| {
+            MockResponse::json(200, serde_json::json!({})).after(Duration::from_millis(300))
+        })
+        .await;
+        let mut stripe = upstream("stripe", 5, Duration::from_secs(30));
+        stripe.permits = Arc::new(Semaphore::new(2));
+
+        let calls: Vec<_> = (0..5)
+            .map(|_| {
+                let stripe = stripe.clone();
+                let url = api.url.clone();
+                tokio::spawn(async move { stripe.send("probe", stripe.client().get(url)).await })
+            })
+            .collect();
+        // Spinning keeps the runtime busy, so the clock stays put meanwhile
+        while api.requests().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(api.requests().len(), 2);
+        assert_eq!(stripe.permits.available_permits(), 0);
+
+        for call in calls {
+            assert_eq!(call.await.unwrap().unwrap().status(), 200);
+        }
+        assert_eq!(api.requests().len(), 5);
+        assert_eq!(stripe.permits.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn configured_proxy_carries_every_request_with_its_credentials() {
+        let proxy = MockServer::start(|_| MockResponse::json(200, serde_json::json!({}))).await;
+        let client = proxied_client(
+            &proxy.url,
+            Some(("egress".to_string(), "s3cret".to_string())),
+        );
+        let response = client
+            .get("http://api.stripe.invalid/v1/balance")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let seen = proxy.requests();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].path, "http://api.stripe.invalid/v1/balance");
+        assert_eq!(
+            seen[0].headers["proxy-authorization"],
+            "Basic ZWdyZXNzOnMzY3JldA=="
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "OUTBOUND_PROXY_URL must be an http(s) URL with a host")]
+    fn proxy_without_a_host_is_rejected_at_startup() {
+        proxied_client("socks5:nowhere", None);
+    }
+
+    #[tokio::test]
+    async fn each_provider_gives_up_after_its_own_timeout() {
+        // Provider names of their own, so no other test reads these variables
+        std::env::set_var("TIMEOUT_FAST_HTTP_TIMEOUT_SECS", "1");
+        std::env::set_var("TIMEOUT_SLOW_HTTP_TIMEOUT_SECS", "3");
+        let fast = UpstreamClient::from_env("timeout_fast", false);
+        let slow = UpstreamClient::from_env("timeout_slow", false);
+        assert_eq!(fast.timeout, Duration::from_secs(1));
+        assert_eq!(slow.timeout, Duration::from_secs(3));
+        assert_eq!(
+            UpstreamClient::from_env("timeout_unset", false).timeout,
+            Duration::from_secs(DEFAULT_TIMEOUT_SECS)
+        );
+
+        let api = MockServer::start(|_| {
+            MockResponse::json(200, serde_json::json!({})).after(Duration::from_millis(1500))
+        })
+        .await;
+        let (fast_result, slow_result) = tokio::join!(
+            fast.send(
+                "probe",
+                fast.client().get(format!("{}/v1/balance", api.url))
+            ),
+            slow.send(
+                "probe",
+                slow.client().get(format!("{}/v1/oauth2/token", api.url))
+            ),
+        );
+        assert!(matches!(
+            fast_result.unwrap_err(),
+            UpstreamError::Transport(_)
+        ));
+        assert_eq!(slow_result.unwrap().status(), 200);
+    }
+
+    #[test]
+    fn only_transport_failures_429_and_5xx_are_transient() {
+        assert!(UpstreamError::Transport("timed out".into()).is_transient());
+        assert!(UpstreamError::status("Stripe", StatusCode::TOO_MANY_REQUESTS, "").is_transient());
+        assert!(UpstreamError::status("PayPal", StatusCode::BAD_GATEWAY, "").is_transient());
+        assert!(!UpstreamError::status("Stripe", StatusCode::NOT_FOUND, "").is_transient());
+        // A 4xx body mentioning a 5xx code is still a 4xx
+        assert!(
+            !UpstreamError::status("Stripe", StatusCode::BAD_REQUEST, "upstream said 503")
+                .is_transient()
+        );
+        assert!(!UpstreamError::from("stripe circuit open, failing fast").is_transient());
+    }
+
+    #[test]
+    fn logged_bodies_carry_no_secrets() {
+        let json = redact_body(
+            r#"{"id":"cus_1","client_secret":"pi_1_secret_abc","nested":{"key":"sk_live_123"},"email":"a@b.c"}"#,
+        );
+        assert!(!json.contains("pi_1_secret_abc") && !json.contains("sk_live_123"));
+        assert!(json.contains("cus_1") && json.contains("a@b.c"));
+
+        let form = redact_body("grant_type=client_credentials&access_token=A21AA&amount=500");
+        assert_eq!(
+            form,
+            "grant_type=client_credentials&access_token=[REDACTED]&amount=500"
+        );
+
+        let text = redact_body("auth failed for Basic Y2xpZW50OnNlY3JldA== with sk_test_456");
+        assert!(!text.contains("Y2xpZW50OnNlY3JldA==") && !text.contains("sk_test_456"));
+        assert!(text.starts_with("auth failed for"));
+    }
+}
@@ -0,0 +1,396 @@
+// lwas_economy/src/payments/webhook_queue.rs
+// ARCHITECT: QANTUM AETERNA | STATUS: BETA
+// Ack-then-process: verified webhook bodies are journaled, queued and drained by workers
+
+use redis::AsyncCommands;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, RwLock};
+
+use crate::config::{env_flag, env_parse};
+use crate::retry::RetryPolicy;
+
+/// A verified event waiting for its worker
+#[derive(Clone, Debug)]
+pub struct QueuedEvent {
+    pub event_id: String,
+    /// Raw payload exactly as received
+    pub body: String,
+    /// Transient failures so far; drives the re-queue backoff
+    pub attempts: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EnqueueOutcome {
+    Queued,
+    /// Same event id is already waiting or being retried (provider redelivery)
+    AlreadyQueued,
+    /// Journaled by an earlier delivery but no longer queued; put back on the queue
+    Requeued,
+}
+
+/// Outcome of one worker pass over an event
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProcessOutcome {
+    /// Handled, or failed for good; the journal entry is dropped
+    Done,
+    /// Worth another try; re-queued after a backoff
+    Retry,
+}
+
+/// Bounded channel plus a journal (Redis hash, else in-memory) so events acked
+/// but not yet processed survive a restart when Redis is configured. An acked
+/// event is retried until it is done: providers don't redeliver what was acked.
+#[derive(Clone)]
+pub struct WebhookQueue {
+    /// Redis hash name, e.g. `webhookqueue:stripe`
+    hash: String,
+    capacity: usize,
+    workers: usize,
+    sender: mpsc::Sender<QueuedEvent>,
+    receiver: Arc<Mutex<mpsc::Receiver<QueuedEvent>>>,
+    redis_client: Option<redis::Client>,
+    fallback: Arc<RwLock<HashMap<String, String>>>,
+    /// Ids on the channel, with a worker, or waiting out a retry backoff
+    in_flight: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Backoff between transient failures; attempts are unlimited
+    retry: RetryPolicy,
+}
+
+impl WebhookQueue {
+    /// Enabled by `{PROVIDER}_ASYNC_WEBHOOKS`; `WEBHOOK_QUEUE_CAPACITY` (1000),
+    /// `WEBHOOK_QUEUE_WORKERS` (4), retry backoff `WEBHOOK_QUEUE_RETRY_BASE_MS`
+    /// (1000) doubling up to `WEBHOOK_QUEUE_RETRY_MAX_MS` (60000)
+    pub fn from_env(provider: &str, redis_url: Option<String>) -> Option<Self> {
+        if !env_flag(&format!("{}_ASYNC_WEBHOOKS", provider.to_ascii_uppercase())) {
+            return None;
+        }
+        let capacity = env_parse("WEBHOOK_QUEUE_CAPACITY", 1000usize).max(1);
+        let workers = env_parse("WEBHOOK_QUEUE_WORKERS", 4usize).max(1);
+        let redis_client = redis_url.and_then(|url| {
+            redis::Client::open(url)
+                .map_err(|e| println!("❌ Redis connect error: {}", e))
+                .ok()
+        });
+        if redis_client.is_none() {
+            println!(
+                "[QUEUE] ⚠️ {} webhook queue is in-memory only; set REDIS_URL to survive restarts",
+                provider
+            );
+        }
+        println!(
+            "[QUEUE] 📥 {} webhooks acked on receipt: capacity {}, {} worker(s)",
+            provider, capacity, workers
+        );
+
+        let retry = RetryPolicy {
+            max_attempts: u32::MAX,
+            base_delay: Duration::from_millis(env_parse("WEBHOOK_QUEUE_RETRY_BASE_MS", 1000u64)),
+            max_delay: Duration::from_millis(env_parse("WEBHOOK_QUEUE_RETRY_MAX_MS", 60_000u64)),
+        };
+        Some(Self::new(provider, capacity, workers, redis_client, retry))
+    }
+
+    fn new(
+        provider: &str,
+        capacity: usize,
+        workers: usize,
+        redis_client: Option<redis::Client>,
+        retry: RetryPolicy,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Self {
+            hash: format!("webhookqueue:{}", provider),
+            capacity,
+            workers,
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+            redis_client,
+            fallback: Arc::new(RwLock::new(HashMap::new())),
+            in_flight: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            retry,
+        }
+    }
+
+    /// O(1) - Journal first, then queue; Err when full (caller should not ack).
+    /// A redelivery of an event that is journaled but no longer queued (left by a
+    /// previous process, or dropped when the queue was full) is queued again.
+    pub async fn enqueue(&self, event_id: &str, body: &str) -> Result<EnqueueOutcome, String> {
+        let fresh = self.journal_insert(event_id, body).await?;
+        // Fresh in Redis can still be in flight when the first copy was journaled
+        // in memory during a Redis outage
+        if !self.mark_in_flight(event_id) {
+            return Ok(EnqueueOutcome::AlreadyQueued);
+        }
+        let item = QueuedEvent {
+            event_id: event_id.to_string(),
+            body: body.to_string(),
+            attempts: 0,
+        };
+        if self.sender.try_send(item).is_err() {
+            self.unmark_in_flight(event_id);
+            if fresh {
+                self.complete(event_id).await;
+            }
+            return Err(format!("Webhook queue full ({})", self.capacity));
+        }
+        metrics::gauge!("webhook_queue_depth", "queue" => self.hash.clone()).increment(1.0);
+        Ok(if fresh {
+            EnqueueOutcome::Queued
+        } else {
+            EnqueueOutcome::Requeued
+        })
+    }
+
+    /// O(1) - false when `event_id` is already in flight
+    fn mark_in_flight(&self, event_id: &str) -> bool {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(event_id.to_string())
+    }
+
+    fn unmark_in_flight(&self, event_id: &str) {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(event_id);
+    }
+
+    /// O(1) - Drop the journal entry once the worker is done with the event
+    pub async fn complete(&self, event_id: &str) {
+        // Also journaled in memory when a Redis write failed
+        self.fallback.write().await.remove(event_id);
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                let _: () = con.hdel(&self.hash, event_id).await.unwrap_or(());
+            }
+        }
+    }
+
+    /// O(1) - false when `event_id` is already journaled
+    async fn journal_insert(&self, event_id: &str, body: &str) -> Result<bool, String> {
+        if let Some(client) = &self.redis_client {
+            if let Ok(mut con) = client.get_multiplexed_async_connection().await {
+                return con
+                    .hset_nx(&self.hash, event_id, body)
+                    .await
+                    .map_err(|e| format!("Queue journal write failed: {}", e));
+            }
+        }
+
+        let mut journal = self.fallback.write().await;
+        if journal.contains_key(event_id) {
+            return Ok(false);
+        }
+        journal.insert(event_id.to_string(), body.to_string());
+        Ok(true)
+    }
+
+    /// O(n) - Journal entries not in flight: left by a previous process (Redis)
+    /// or kept in memory
+    async fn journaled(&self) -> Vec<QueuedEvent> {
+        let mut entries: HashMap<String, String> = match &self.redis_client {
+            Some(client) => match client.get_multiplexed_async_connection().await {
+                Ok(mut con) => con.hgetall(&self.hash).await.unwrap_or_default(),
+                Err(_) => HashMap::new(),
+            },
+            None => HashMap::new(),
+        };
+        for (event_id, body) in self.fallback.read().await.iter() {
+            entries
+                .entry(event_id.clone())
+                .or_insert_with(|| body.clone());
+        }
+        entries
+            .into_iter()
+            .filter(|(event_id, _)| self.mark_in_flight(event_id))
+            .map(|(event_id, body)| QueuedEvent {
+                event_id,
+                body,
+                attempts: 0,
+            })
+            .collect()
+    }
+
+    /// O(1) - Put `item` back after its backoff; waits for room instead of dropping it
+    fn requeue_later(&self, mut item: QueuedEvent) {
+        item.attempts += 1;
+        let delay = self.retry.delay(item.attempts);
+        println!(
+            "[QUEUE] 🔁 Event {} retrying in {:?} (attempt {})",
+            item.event_id,
+            delay,
+            item.attempts + 1
+        );
+        let queue = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if queue.sender.send(item).await.is_ok() {
+                metrics::gauge!("webhook_queue_depth", "queue" => queue.hash.clone())
+                    .increment(1.0);
+            }
+        });
+    }
+
+    /// Re-queue journaled events, then start the worker pool. `process` sees
+    /// each event once per queue entry; its own idempotency check still applies.
+    /// `ProcessOutcome::Retry` puts the event back after a backoff, so an acked
+    /// event is never left behind.
+    pub async fn spawn_workers<F, Fut>(self, process: F)
+    where
+        F: Fn(QueuedEvent) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = ProcessOutcome> + Send,
+    {
+        let recovered = self.journaled().await;
+        if !recovered.is_empty() {
+            println!(
+                "[QUEUE] ♻️ Recovered {} unprocessed event(s) from {}",
+                recovered.len(),
+                self.hash
+            );
+            // More than fit in the channel are fed in as workers make room
+            let queue = self.clone();
+            tokio::spawn(async move {
+                for item in recovered {
+                    if queue.sender.send(item).await.is_err() {
+                        break;
+                    }
+                    metrics::gauge!("webhook_queue_depth", "queue" => queue.hash.clone())
+                        .increment(1.0);
+                }
+            });
+        }
+
+        for _ in 0..self.workers {
+            let queue = self.clone();
+            let process = process.clone();
+            tokio::spawn(async move {
+                loop {
+                    let next = queue.receiver.lock().await.recv().await;
+                    let Some(item) = next else { break };
+                    metrics::gauge!("webhook_queue_depth", "queue" => queue.hash.clone())
+                        .decrement(1.0);
+                    match process(item.clone()).await {
+                        ProcessOutcome::Done => {
+                            queue.complete(&item.event_id).await;
+                            queue.unmark_in_flight(&item.event_id);
+                        }
+                        ProcessOutcome::Retry => queue.requeue_later(item),
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn in_memory_queue(capacity: usize) -> WebhookQueue {
+        let retry = RetryPolicy {
+            max_attempts: u32::MAX,
+            base_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(20),
+        };
+        WebhookQueue::new("test", capacity, 2, None, retry)
+    }
+
+    async fn wait_for(mut done: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not reached in time");
+    }
+
+    #[tokio::test]
+    async fn fast_ack_then_eventual_processing_after_transient_failures() {
+        let queue = in_memory_queue(8);
+        let calls = Arc::new(AtomicU32::new(0));
+        let handled = Arc::new(AtomicU32::new(0));
+        let (c, h) = (calls.clone(), handled.clone());
+        queue
+            .clone()
+            .spawn_workers(move |_item| {
+                let (c, h) = (c.clone(), h.clone());
+                async move {
+                    // Two transient failures (claim contention, audit down), then success
+                    if c.fetch_add(1, Ordering::SeqCst) < 2 {
+                        ProcessOutcome::Retry
+                    } else {
+                        h.fetch_add(1, Ordering::SeqCst);
+                        ProcessOutcome::Done
+                    }
+                }
+            })
+            .await;
+
+        // Acked before any processing happened
+        assert_eq!(
+            queue.enqueue("evt_1", "{}").await,
+            Ok(EnqueueOutcome::Queued)
+        );
+        wait_for(|| handled.load(Ordering::SeqCst) == 1).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(queue.fallback.read().await.is_empty());
+        assert!(queue.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn redelivery_while_retrying_is_not_queued_twice() {
+        let queue = in_memory_queue(8);
+        queue.enqueue("evt_1", "{}").await.unwrap();
+        assert_eq!(
+            queue.enqueue("evt_1", "{}").await,
+            Ok(EnqueueOutcome::AlreadyQueued)
+        );
+    }
+
+    #[tokio::test]
+    async fn journaled_but_idle_event_is_requeued_on_redelivery() {
+        let queue = in_memory_queue(8);
+        // Left in the journal, not on the channel (e.g. dropped by an older build)
+        queue
+            .fallback
+            .write()
+            .await
+            .insert("evt_1".to_string(), "{}".to_string());
+        assert_eq!(
+            queue.enqueue("evt_1", "{}").await,
+            Ok(EnqueueOutcome::Requeued)
+        );
+    }
+
+    #[tokio::test]
+    async fn recovered_backlog_larger_than_capacity_is_fully_processed() {
+        let queue = in_memory_queue(2);
+        for i in 0..5 {
+            queue
+                .fallback
+                .write()
+                .await
+                .insert(format!("evt_{}", i), "{}".to_string());
+        }
+        let handled = Arc::new(AtomicU32::new(0));
+        let h = handled.clone();
+        queue
+            .clone()
+            .spawn_workers(move |_item| {
+                let h = h.clone();
+                async move {
+                    h.fetch_add(1, Ordering::SeqCst);
+                    ProcessOutcome::Done
+                }
+            })
+            .await;
+        wait_for(|| handled.load(Ordering::SeqCst) == 5).await;
+        assert!(queue.fallback.read().await.is_empty());
+    }
+}